@@ -0,0 +1,393 @@
+//! **archive** contains the support to store replicated files compressed, keeping an
+//! index that maps every original path to its compressed counterpart.
+
+use crate::{
+    copy::{self, RateLimiter},
+    error::{IoResultExt, SyncError},
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+/// Name of the index file kept at the root of a compressed destination.
+pub const INDEX_FILE_NAME: &str = ".acsync_index";
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Compression format used to store files, backed by the `gzip` and `zstd` tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the extension appended to compressed file names.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Returns true if the tool of the format can be run.
+    pub fn is_available(&self) -> bool {
+        Command::new(self.to_string())
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    fn command(&self, decompress: bool) -> Command {
+        let mut command = match self {
+            Compression::Gzip => Command::new("gzip"),
+            Compression::Zstd => {
+                let mut command = Command::new("zstd");
+                command.arg("-q");
+                command
+            }
+        };
+        if decompress {
+            command.arg("-d");
+        }
+        command.arg("-c");
+        command
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "unknown compression {value:?}, expected one of zstd or gzip"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// Index of a compressed destination, mapping original relative paths to their sizes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveIndex {
+    pub compression: Compression,
+    entries: BTreeMap<PathBuf, u64>,
+}
+
+impl ArchiveIndex {
+    pub fn new(compression: Compression) -> Self {
+        ArchiveIndex {
+            compression,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the index of the compressed `directory`.
+    ///
+    /// Returns [`None`] if the directory has no index file.
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Option<Self>, SyncError> {
+        let index_path = directory.as_ref().join(INDEX_FILE_NAME);
+        if !index_path.is_file() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&index_path).with_path(&index_path)?;
+        let invalid_index = |reason: &str| SyncError::Io {
+            path: index_path.clone(),
+            source: std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string()),
+        };
+
+        let mut lines = content.lines();
+        let compression = lines
+            .next()
+            .and_then(|line| line.strip_prefix("compression\t"))
+            .ok_or_else(|| invalid_index("missing compression header"))?
+            .parse()
+            .map_err(|reason: String| invalid_index(&reason))?;
+        let mut index = ArchiveIndex::new(compression);
+        for line in lines {
+            let (path, size) = line
+                .rsplit_once('\t')
+                .ok_or_else(|| invalid_index("malformed entry"))?;
+            let size = size
+                .parse()
+                .map_err(|_| invalid_index("malformed entry size"))?;
+            index.entries.insert(PathBuf::from(unescape(path)), size);
+        }
+        Ok(Some(index))
+    }
+
+    /// Writes the index file into the compressed `directory`.
+    pub fn save<P: AsRef<Path>>(&self, directory: P) -> Result<(), SyncError> {
+        let index_path = directory.as_ref().join(INDEX_FILE_NAME);
+        let mut content = format!("compression\t{}\n", self.compression);
+        for (path, size) in &self.entries {
+            content += &format!("{}\t{size}\n", escape(&path.to_string_lossy()));
+        }
+        std::fs::write(&index_path, content).with_path(&index_path)
+    }
+
+    /// Returns the relative path where the file at the original `path` is stored.
+    pub fn stored_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut stored_path = path.as_ref().as_os_str().to_os_string();
+        stored_path.push(format!(".{}", self.compression.extension()));
+        PathBuf::from(stored_path)
+    }
+
+    /// Returns the original relative path and size of the file stored at `stored_path`.
+    ///
+    /// Returns [`None`] if the stored file is not part of the index.
+    pub fn original<P: AsRef<Path>>(&self, stored_path: P) -> Option<(&Path, u64)> {
+        let original_path = stored_path
+            .as_ref()
+            .to_str()?
+            .strip_suffix(&format!(".{}", self.compression.extension()))?;
+        self.entries
+            .get_key_value(Path::new(original_path))
+            .map(|(path, size)| (path.as_path(), *size))
+    }
+
+    /// Returns the original size recorded for the file at the original `path`.
+    pub fn size<P: AsRef<Path>>(&self, path: P) -> Option<u64> {
+        self.entries.get(path.as_ref()).copied()
+    }
+
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P, size: u64) {
+        self.entries.insert(path.as_ref().to_path_buf(), size);
+    }
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+pub(crate) fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(char) = chars.next() {
+        match (char, chars.clone().next()) {
+            ('\\', Some('t')) => {
+                unescaped.push('\t');
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(char),
+        }
+    }
+    unescaped
+}
+
+/// Compresses `data` in memory at the `level` of the format, or its default one.
+pub fn compress_bytes(
+    data: &[u8],
+    compression: Compression,
+    level: Option<u32>,
+) -> std::io::Result<Vec<u8>> {
+    let mut command = compression.command(false);
+    if let Some(level) = level {
+        command.arg(format!("-{level}"));
+    }
+    filter_bytes(data, command, None)
+}
+
+/// Decompresses `data` in memory.
+pub fn decompress_bytes(data: &[u8], compression: Compression) -> std::io::Result<Vec<u8>> {
+    filter_bytes(data, compression.command(true), None)
+}
+
+/// Decompresses `data` in memory as [`decompress_bytes`] does, failing as soon as it
+/// decompresses to more than `limit` bytes, so that a small but highly compressed input
+/// from an untrusted peer can't exhaust the memory.
+pub fn decompress_bytes_limited(
+    data: &[u8],
+    compression: Compression,
+    limit: usize,
+) -> std::io::Result<Vec<u8>> {
+    filter_bytes(data, compression.command(true), Some(limit))
+}
+
+/// Runs `data` through the `command`, reading at most `limit` bytes of its output, if
+/// any, and killing it past them.
+fn filter_bytes(
+    data: &[u8],
+    mut command: Command,
+    limit: Option<usize>,
+) -> std::io::Result<Vec<u8>> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut child_stdin = child.stdin.take().expect("child stdin is piped");
+    let mut child_stdout = child.stdout.take().expect("child stdout is piped");
+    // Writes from another thread, the tool blocking on a full output otherwise.
+    let mut output = vec![];
+    let (read, written) = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || child_stdin.write_all(data));
+        let read = match limit {
+            Some(limit) => (&mut child_stdout)
+                .take(limit as u64 + 1)
+                .read_to_end(&mut output),
+            None => child_stdout.read_to_end(&mut output),
+        };
+        let exceeded = limit.is_some_and(|limit| output.len() > limit);
+        if exceeded {
+            // Also ends the writer, on a broken pipe.
+            let _ = child.kill();
+        }
+        drop(child_stdout);
+        (read, writer.join().expect("writer doesn't panic"))
+    });
+    let status = child.wait()?;
+    read?;
+    if let Some(limit) = limit
+        && output.len() > limit
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{program} output more than {limit} bytes"),
+        ));
+    }
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "{program} exited with {status}"
+        )));
+    }
+    written?;
+    Ok(output)
+}
+
+/// Compresses `source` into `target`, returning the number of bytes read from `source`.
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+    compression: Compression,
+    limiter: Option<&mut RateLimiter>,
+) -> Result<u64, SyncError> {
+    filter_file(
+        source.as_ref(),
+        target.as_ref(),
+        compression.command(false),
+        limiter,
+    )
+}
+
+/// Decompresses `source` into `target`, returning the number of bytes read from `source`.
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+    compression: Compression,
+    limiter: Option<&mut RateLimiter>,
+) -> Result<u64, SyncError> {
+    filter_file(
+        source.as_ref(),
+        target.as_ref(),
+        compression.command(true),
+        limiter,
+    )
+}
+
+fn filter_file(
+    source: &Path,
+    target: &Path,
+    mut command: Command,
+    mut limiter: Option<&mut RateLimiter>,
+) -> Result<u64, SyncError> {
+    let mut source_file = copy::open_source(source)?;
+    let permissions = source_file.metadata().with_path(source)?.permissions();
+    let target_file = File::create(target).with_path(target)?;
+
+    let program = command.get_program().to_string_lossy().to_string();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(target_file.try_clone().with_path(target)?)
+        .spawn()
+        .with_path(&program)?;
+    let mut child_stdin = child.stdin.take().expect("child stdin is piped");
+
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut copied = 0;
+    loop {
+        let read = source_file.read(&mut buffer).with_path(source)?;
+        if read == 0 {
+            break;
+        }
+        if let Some(limiter) = limiter.as_mut() {
+            limiter.acquire(read as u64);
+        }
+        child_stdin.write_all(&buffer[..read]).with_path(&program)?;
+        copied += read as u64;
+    }
+    drop(child_stdin);
+
+    let status = child.wait().with_path(&program)?;
+    if !status.success() {
+        return Err(SyncError::Io {
+            path: source.to_path_buf(),
+            source: std::io::Error::other(format!("{program} exited with {status}")),
+        });
+    }
+    target_file.set_permissions(permissions).with_path(target)?;
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_maps_stored_paths_back_to_original_paths() {
+        let mut index = ArchiveIndex::new(Compression::Zstd);
+        index.insert("docs/notes.txt", 42);
+        assert_eq!(
+            index.stored_path("docs/notes.txt"),
+            PathBuf::from("docs/notes.txt.zst")
+        );
+        assert_eq!(
+            index.original("docs/notes.txt.zst"),
+            Some((Path::new("docs/notes.txt"), 42))
+        );
+        assert_eq!(index.original("docs/other.txt.zst"), None);
+    }
+
+    #[test]
+    fn it_saves_and_loads_the_index() {
+        let directory = testing::test_dir("it_saves_and_loads_the_index");
+        let mut index = ArchiveIndex::new(Compression::Gzip);
+        index.insert("a\tb.txt", 1);
+        index.insert("c\\d.txt", 2);
+        index.save(&directory).unwrap();
+        assert_eq!(ArchiveIndex::load(&directory).unwrap(), Some(index));
+    }
+}
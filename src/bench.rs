@@ -0,0 +1,285 @@
+//! **bench** contains the benchmark of the sync strategies on a pair of directories, so
+//! that users can pick the flags suiting their storage.
+
+use crate::{
+    copy,
+    error::{IoResultExt, SyncError},
+    info,
+    sync::Replicator,
+    verify::Verifier,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Name of the scratch directory created in the destination for the runs.
+pub const BENCH_DIR_NAME: &str = "acsync_bench";
+
+/// Strategies compared by a [`Bench`] run, in the order they are run.
+const STRATEGIES: [Strategy; 6] = [
+    Strategy::KernelCopy,
+    Strategy::BufferedCopy,
+    Strategy::SizeAndTime,
+    Strategy::Checksum,
+    Strategy::SerialHashing,
+    Strategy::ParallelHashing,
+];
+
+/// Way of syncing measured by a [`Bench`] run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Copying every file, in the kernel with `copy_file_range` where supported.
+    KernelCopy,
+    /// Copying every file through userspace buffers.
+    BufferedCopy,
+    /// Comparing the copies by size and modification date.
+    SizeAndTime,
+    /// Comparing the copies by content hash, without the checksum cache.
+    Checksum,
+    /// Storing every file into a content-addressed store, hashing them one by one.
+    SerialHashing,
+    /// Storing every file into a content-addressed store, hashing the upcoming ones
+    /// on background threads.
+    ParallelHashing,
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Strategy::KernelCopy => "copy, copy_file_range",
+            Strategy::BufferedCopy => "copy, read/write",
+            Strategy::SizeAndTime => "compare, size and time",
+            Strategy::Checksum => "compare, checksum",
+            Strategy::SerialHashing => "store, serial hashing",
+            Strategy::ParallelHashing => "store, parallel hashing",
+        })
+    }
+}
+
+/// Read and write system calls of the process and the bytes they transferred, as
+/// counted by Linux in `/proc/self/io`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IoCounters {
+    pub read_calls: u64,
+    pub write_calls: u64,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+}
+
+impl IoCounters {
+    /// Returns the counters of the process so far, or `None` where they are not
+    /// available.
+    pub fn current() -> Option<Self> {
+        let content = std::fs::read_to_string("/proc/self/io").ok()?;
+        let mut counters = IoCounters::default();
+        for line in content.lines() {
+            let Some((name, value)) = line.split_once(": ") else {
+                continue;
+            };
+            let value = value.trim().parse().ok()?;
+            match name {
+                "syscr" => counters.read_calls = value,
+                "syscw" => counters.write_calls = value,
+                "rchar" => counters.read_bytes = value,
+                "wchar" => counters.written_bytes = value,
+                _ => {}
+            }
+        }
+        Some(counters)
+    }
+
+    fn since(&self, start: &IoCounters) -> IoCounters {
+        IoCounters {
+            read_calls: self.read_calls.saturating_sub(start.read_calls),
+            write_calls: self.write_calls.saturating_sub(start.write_calls),
+            read_bytes: self.read_bytes.saturating_sub(start.read_bytes),
+            written_bytes: self.written_bytes.saturating_sub(start.written_bytes),
+        }
+    }
+}
+
+/// Measure of a [`Strategy`], the fastest of the runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measure {
+    pub strategy: Strategy,
+    pub elapsed: Duration,
+    /// Counters of the fastest run, `None` where they are not available.
+    pub counters: Option<IoCounters>,
+}
+
+/// Result of a [`Bench`] run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BenchReport {
+    pub file_count: u64,
+    pub total_file_size: u64,
+    pub measures: Vec<Measure>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", " Bench ")?;
+        writeln!(
+            f,
+            "Files: {} ({} KBs)",
+            self.file_count,
+            (self.total_file_size / 1024) as f64
+        )?;
+        writeln!(
+            f,
+            "{:<26}{:>12}{:>14}{:>14}{:>14}",
+            "Strategy", "Time", "Syscalls", "Read KBs", "Written KBs"
+        )?;
+        for measure in &self.measures {
+            let elapsed = format!("{:.3}s", measure.elapsed.as_secs_f64());
+            let (calls, read, written) = match &measure.counters {
+                Some(counters) => (
+                    (counters.read_calls + counters.write_calls).to_string(),
+                    (counters.read_bytes / 1024).to_string(),
+                    (counters.written_bytes / 1024).to_string(),
+                ),
+                None => ("-".to_string(), "-".to_string(), "-".to_string()),
+            };
+            writeln!(
+                f,
+                "{:<26}{elapsed:>12}{calls:>14}{read:>14}{written:>14}",
+                measure.strategy.to_string()
+            )?;
+        }
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+/// Runs the copier and the planner from an origin into a scratch directory of the
+/// destination with each [`Strategy`], measuring their time and system calls.
+///
+/// The origin is copied several times, so the destination needs room for a few copies
+/// of it. The scratch directory is removed once done.
+#[derive(Debug)]
+pub struct Bench {
+    origin: PathBuf,
+    destination: PathBuf,
+    runs: usize,
+}
+
+impl Bench {
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(origin: P, destination: Q) -> Self {
+        Bench {
+            origin: origin.as_ref().to_path_buf(),
+            destination: destination.as_ref().to_path_buf(),
+            runs: 1,
+        }
+    }
+
+    /// Runs each strategy `count` times, reporting the fastest run, 1 by default.
+    pub fn runs(mut self, count: usize) -> Self {
+        self.runs = count.max(1);
+        self
+    }
+
+    pub fn run(&self) -> Result<BenchReport, SyncError> {
+        let scratch = self.destination.join(BENCH_DIR_NAME);
+        if scratch.exists() {
+            return Err(SyncError::InvalidArgument {
+                name: "destination".to_string(),
+                reason: format!(
+                    "{} already exists, remove it or pick another destination",
+                    scratch.display()
+                ),
+            });
+        }
+        std::fs::create_dir_all(&scratch).with_path(&scratch)?;
+        let result = self.measure_all(&scratch);
+        copy::set_kernel_copy(true);
+        std::fs::remove_dir_all(&scratch).with_path(&scratch)?;
+        result
+    }
+
+    fn measure_all(&self, scratch: &Path) -> Result<BenchReport, SyncError> {
+        let mut report = BenchReport::default();
+        // The copy compared by the planner, kept across the strategies.
+        let reference = scratch.join("reference");
+        let stats = Replicator::new(&self.origin, &reference).run()?;
+        report.file_count = stats.file_count;
+        report.total_file_size = stats.total_file_size;
+
+        for strategy in STRATEGIES {
+            info!("Measuring {strategy} ...");
+            let mut best: Option<Measure> = None;
+            for _ in 0..self.runs {
+                let target = scratch.join("target");
+                let start_counters = IoCounters::current();
+                let start = Instant::now();
+                match strategy {
+                    Strategy::KernelCopy | Strategy::BufferedCopy => {
+                        copy::set_kernel_copy(strategy == Strategy::KernelCopy);
+                        Replicator::new(&self.origin, &target).run()?;
+                    }
+                    Strategy::SizeAndTime | Strategy::Checksum => {
+                        Verifier::new(&self.origin, &reference)
+                            .checksum(strategy == Strategy::Checksum)
+                            .ignore_cache(true)
+                            .run()?;
+                    }
+                    Strategy::SerialHashing | Strategy::ParallelHashing => {
+                        let hash_threads = (strategy == Strategy::SerialHashing).then_some(0);
+                        Replicator::new(&self.origin, &target)
+                            .store(true)
+                            .hash_threads(hash_threads)
+                            .ignore_cache(true)
+                            .run()?;
+                    }
+                }
+                let measure = Measure {
+                    strategy,
+                    elapsed: start.elapsed(),
+                    counters: IoCounters::current()
+                        .zip(start_counters)
+                        .map(|(counters, start_counters)| counters.since(&start_counters)),
+                };
+                copy::set_kernel_copy(true);
+                if target.exists() {
+                    std::fs::remove_dir_all(&target).with_path(&target)?;
+                }
+                if best
+                    .as_ref()
+                    .is_none_or(|best| measure.elapsed < best.elapsed)
+                {
+                    best = Some(measure);
+                }
+            }
+            report.measures.extend(best);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_measures_every_strategy_and_removes_its_scratch_directory() {
+        let root =
+            testing::test_dir("it_measures_every_strategy_and_removes_its_scratch_directory");
+        let (origin, destination) = (root.join("origin"), root.join("destination"));
+        std::fs::create_dir_all(origin.join("docs")).unwrap();
+        std::fs::write(origin.join("docs/notes.txt"), "notes").unwrap();
+        std::fs::write(origin.join("photo.jpg"), vec![7; 100_000]).unwrap();
+
+        let report = Bench::new(&origin, &destination).runs(2).run().unwrap();
+        assert_eq!((report.file_count, report.total_file_size), (2, 100_005));
+        let strategies: Vec<Strategy> = report
+            .measures
+            .iter()
+            .map(|measure| measure.strategy)
+            .collect();
+        assert_eq!(strategies, STRATEGIES);
+        #[cfg(target_os = "linux")]
+        assert!(report.measures[0].counters.is_some());
+        assert!(!destination.join(BENCH_DIR_NAME).exists());
+        assert!(destination.is_dir());
+    }
+}
@@ -0,0 +1,192 @@
+//! **cache** contains the checksum cache kept next to the state manifest of a
+//! destination, so that files unchanged since they were last hashed are not hashed
+//! again.
+
+use crate::{
+    archive::{escape, unescape},
+    error::{IoResultExt, SyncError},
+    hash, platform,
+};
+use std::{
+    collections::BTreeMap,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// Name of the checksum cache file kept at the root of a destination.
+pub const CACHE_FILE_NAME: &str = ".acsync_checksums";
+
+/// Attributes telling whether a file changed since it was hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileKey {
+    size: u64,
+    modified: u64,
+    modified_nanos: u32,
+    inode: u64,
+}
+
+impl From<&Metadata> for FileKey {
+    fn from(metadata: &Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        FileKey {
+            size: metadata.len(),
+            modified: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            inode: platform::inode(metadata),
+        }
+    }
+}
+
+/// Cache of the SHA-256 hashes of files, keyed by their absolute paths and invalidated
+/// when their size, modification time or inode change.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChecksumCache {
+    entries: BTreeMap<PathBuf, (FileKey, String)>,
+    changed: bool,
+}
+
+impl ChecksumCache {
+    pub fn new() -> Self {
+        ChecksumCache::default()
+    }
+
+    /// Loads the cache of the `directory`.
+    ///
+    /// Returns an empty cache if the directory has no cache file.
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Self, SyncError> {
+        let cache_path = directory.as_ref().join(CACHE_FILE_NAME);
+        let mut cache = ChecksumCache::new();
+        if !cache_path.is_file() {
+            return Ok(cache);
+        }
+        let content = std::fs::read_to_string(&cache_path).with_path(&cache_path)?;
+        for line in content.lines() {
+            let mut fields = line.rsplitn(6, '\t');
+            let entry = (|| {
+                let hash = fields.next()?.to_string();
+                let inode = fields.next()?.parse().ok()?;
+                let modified_nanos = fields.next()?.parse().ok()?;
+                let modified = fields.next()?.parse().ok()?;
+                let size = fields.next()?.parse().ok()?;
+                let path = PathBuf::from(unescape(fields.next()?));
+                let key = FileKey {
+                    size,
+                    modified,
+                    modified_nanos,
+                    inode,
+                };
+                Some((path, (key, hash)))
+            })()
+            .ok_or_else(|| SyncError::Io {
+                path: cache_path.clone(),
+                source: std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed entry"),
+            })?;
+            cache.entries.insert(entry.0, entry.1);
+        }
+        Ok(cache)
+    }
+
+    /// Writes the cache file into the `directory` if new hashes were computed, dropping
+    /// the entries of files that no longer exist.
+    pub fn save<P: AsRef<Path>>(&self, directory: P) -> Result<(), SyncError> {
+        if !self.changed {
+            return Ok(());
+        }
+        let cache_path = directory.as_ref().join(CACHE_FILE_NAME);
+        let mut content = String::new();
+        for (path, (key, hash)) in &self.entries {
+            if !path.is_file() {
+                continue;
+            }
+            content += &format!(
+                "{}\t{}\t{}\t{}\t{}\t{hash}\n",
+                escape(&path.to_string_lossy()),
+                key.size,
+                key.modified,
+                key.modified_nanos,
+                key.inode
+            );
+        }
+        std::fs::write(&cache_path, content).with_path(&cache_path)
+    }
+
+    /// Returns the SHA-256 digest of the file content, hashing it only if it changed
+    /// since it was cached.
+    pub fn hash_file<P: AsRef<Path>>(&mut self, path: P) -> Result<String, SyncError> {
+        let path = path.as_ref();
+        let metadata = path.metadata().with_path(path)?;
+        if let Some(hash) = self.get(path, &metadata) {
+            return Ok(hash);
+        }
+        let hash = hash::hash_file(path)?;
+        self.insert(path, &metadata, &hash)?;
+        Ok(hash)
+    }
+
+    /// Returns the cached digest of the `path` file, unless it changed since it was
+    /// hashed.
+    pub fn get<P: AsRef<Path>>(&self, path: P, metadata: &Metadata) -> Option<String> {
+        let absolute_path = std::path::absolute(path).ok()?;
+        match self.entries.get(&absolute_path) {
+            Some((cached_key, hash)) if *cached_key == FileKey::from(metadata) => {
+                Some(hash.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Caches the `hash` digest of the `path` file, computed elsewhere.
+    pub fn insert<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        metadata: &Metadata,
+        hash: &str,
+    ) -> Result<(), SyncError> {
+        let path = path.as_ref();
+        let absolute_path = std::path::absolute(path).with_path(path)?;
+        self.entries
+            .insert(absolute_path, (FileKey::from(metadata), hash.to_string()));
+        self.changed = true;
+        Ok(())
+    }
+}
+
+/// Returns the SHA-256 digest of the file content, through the `cache` if any.
+pub fn hash_file<P: AsRef<Path>>(
+    cache: &mut Option<ChecksumCache>,
+    path: P,
+) -> Result<String, SyncError> {
+    match cache {
+        Some(cache) => cache.hash_file(path),
+        None => hash::hash_file(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_reuses_the_hashes_of_unchanged_files() {
+        let directory = testing::test_dir("it_reuses_the_hashes_of_unchanged_files");
+        let file_path = directory.join("a\tb.txt");
+        std::fs::write(&file_path, "abc").unwrap();
+
+        let mut cache = ChecksumCache::new();
+        let hash = cache.hash_file(&file_path).unwrap();
+        cache.save(&directory).unwrap();
+        let mut cache = ChecksumCache::load(&directory).unwrap();
+        assert_eq!(cache.hash_file(&file_path).unwrap(), hash);
+        assert!(!cache.changed);
+
+        std::fs::write(&file_path, "abcd").unwrap();
+        assert_ne!(cache.hash_file(&file_path).unwrap(), hash);
+        assert!(cache.changed);
+    }
+}
@@ -0,0 +1,77 @@
+//! **cancel** contains the token an embedding application cancels a replicate run
+//! with, from another thread.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// Shared flag cancelling the runs it was given to, see
+/// [`crate::sync::Replicator::cancellation`]. Clones share the same flag.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cancel::CancellationToken;
+/// #
+/// let token = CancellationToken::new();
+/// let clone = token.clone();
+/// assert!(!clone.is_cancelled());
+/// token.cancel();
+/// assert!(clone.is_cancelled());
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken {
+    flag: Arc<AtomicBool>,
+    /// Token cancelling this one along with itself, see [`CancellationToken::child`].
+    parent: Option<Arc<CancellationToken>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a token cancelled along with this one, but that can be cancelled on its
+    /// own without cancelling this one, e.g. to stop a single run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::cancel::CancellationToken;
+    /// #
+    /// let token = CancellationToken::new();
+    /// let child = token.child();
+    /// child.cancel();
+    /// assert!(!token.is_cancelled());
+    /// let other = token.child();
+    /// token.cancel();
+    /// assert!(other.is_cancelled());
+    /// ```
+    pub fn child(&self) -> Self {
+        CancellationToken {
+            flag: Arc::default(),
+            parent: Some(Arc::new(self.clone())),
+        }
+    }
+
+    /// Cancels the runs given this token or one of its clones.
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_cancelled())
+    }
+}
+
+/// Wraps a flag of the embedding application, the runs being cancelled once it's set.
+impl From<Arc<AtomicBool>> for CancellationToken {
+    fn from(flag: Arc<AtomicBool>) -> Self {
+        CancellationToken { flag, parent: None }
+    }
+}
@@ -0,0 +1,202 @@
+//! **clean** contains the removal of the artifacts left in a destination directory by
+//! interrupted or outdated replicate runs.
+
+use crate::{
+    debug,
+    error::{IoResultExt, SyncError},
+    fs::{FileSearcher, SortOrder, TraversalOrder},
+    info,
+    state::{STATE_FILE_NAME, StateManifest},
+};
+use std::{
+    collections::HashSet,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Result of a [`Cleaner`] run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CleanReport {
+    pub temporary_file_count: u64,
+    pub total_temporary_file_size: u64,
+    pub state_file_count: u64,
+    pub stale_entry_count: u64,
+    pub directory_removed_count: u64,
+}
+
+impl fmt::Display for CleanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", " Clean ")?;
+        writeln!(
+            f,
+            "Temporary files removed: {} ({} KBs)",
+            self.temporary_file_count,
+            (self.total_temporary_file_size / 1024) as f64
+        )?;
+        writeln!(f, "Stale state files removed: {}", self.state_file_count)?;
+        writeln!(f, "Stale state entries removed: {}", self.stale_entry_count)?;
+        writeln!(
+            f,
+            "Empty directories removed: {}",
+            self.directory_removed_count
+        )?;
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+#[derive(Debug, Default)]
+struct CleanerOptions {
+    dryrun: bool,
+    prune_empty_dirs: bool,
+}
+
+/// Removes from a destination directory the temporary files of interrupted copies,
+/// the state manifests out of its root and the state entries of vanished files.
+#[derive(Debug)]
+pub struct Cleaner {
+    destination: PathBuf,
+    options: CleanerOptions,
+}
+
+impl Cleaner {
+    pub fn new<P: AsRef<Path>>(destination: P) -> Self {
+        Cleaner {
+            destination: destination.as_ref().to_path_buf(),
+            options: CleanerOptions::default(),
+        }
+    }
+
+    pub fn dryrun(mut self, flag: bool) -> Self {
+        self.options.dryrun = flag;
+        self
+    }
+
+    /// Also removes the directories left empty.
+    pub fn prune_empty_dirs(mut self, flag: bool) -> Self {
+        self.options.prune_empty_dirs = flag;
+        self
+    }
+
+    pub fn run(&self) -> Result<CleanReport, SyncError> {
+        let destination = &self.destination;
+        let dryrun = self.options.dryrun;
+        let mut report = CleanReport::default();
+        let mut removed_paths = HashSet::new();
+
+        let paths_iter = FileSearcher::new(destination)
+            .sort_by(SortOrder::Name)
+            .traversal_order(TraversalOrder::PostOrder);
+        for result in paths_iter {
+            let entry = result.map_err(|source| SyncError::WalkError {
+                path: destination.clone(),
+                source,
+            })?;
+            let (is_file, is_dir) = (entry.is_file(), entry.is_dir());
+            let file_size = entry.metadata().len();
+            let path = entry.into_path();
+            let file_name = path.file_name().unwrap_or_default();
+            if is_file && file_name.to_string_lossy().ends_with(".acsync_tmp") {
+                info!("Removing temporary file {} ...", path.display());
+                report.total_temporary_file_size += file_size;
+                if !dryrun {
+                    std::fs::remove_file(&path).with_path(&path)?;
+                }
+                report.temporary_file_count += 1;
+                removed_paths.insert(path);
+            } else if is_file && file_name == STATE_FILE_NAME && path.parent() != Some(destination)
+            {
+                info!("Removing stale state file {} ...", path.display());
+                if !dryrun {
+                    std::fs::remove_file(&path).with_path(&path)?;
+                }
+                report.state_file_count += 1;
+                removed_paths.insert(path);
+            } else if self.options.prune_empty_dirs
+                && is_dir
+                && path != *destination
+                && is_empty(&path, &removed_paths)?
+            {
+                info!("Removing empty directory {} ...", path.display());
+                if !dryrun {
+                    std::fs::remove_dir(&path).with_path(&path)?;
+                }
+                report.directory_removed_count += 1;
+                removed_paths.insert(path);
+            }
+        }
+
+        let mut state = StateManifest::load(destination)?;
+        let stale_paths: Vec<PathBuf> = state
+            .iter()
+            .map(|(path, _)| path.to_path_buf())
+            .filter(|path| !destination.join(path).is_file())
+            .collect();
+        if !stale_paths.is_empty() {
+            for path in &stale_paths {
+                debug!("Removing stale state entry {} ...", path.display());
+                state.remove(path);
+            }
+            if !dryrun {
+                state.save(destination)?;
+            }
+            report.stale_entry_count = stale_paths.len() as u64;
+        }
+
+        Ok(report)
+    }
+}
+
+/// Returns true if every entry of `directory` is in `removed_paths`.
+fn is_empty(directory: &Path, removed_paths: &HashSet<PathBuf>) -> Result<bool, SyncError> {
+    for entry in std::fs::read_dir(directory).with_path(directory)? {
+        let entry = entry.with_path(directory)?;
+        if !removed_paths.contains(&entry.path()) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_removes_artifacts_and_empty_directories() {
+        let root = testing::test_dir("it_removes_artifacts_and_empty_directories");
+        std::fs::create_dir_all(root.join("docs/empty")).unwrap();
+        std::fs::create_dir_all(root.join("partial")).unwrap();
+        std::fs::write(root.join("docs/notes.txt"), "notes").unwrap();
+        std::fs::write(root.join("partial/image.iso.acsync_tmp"), "half").unwrap();
+        std::fs::write(root.join("docs").join(STATE_FILE_NAME), "").unwrap();
+        let mut state = StateManifest::new();
+        state.insert("docs/notes.txt", 5);
+        state.insert("docs/deleted.txt", 7);
+        state.save(&root).unwrap();
+
+        let report = Cleaner::new(&root)
+            .dryrun(true)
+            .prune_empty_dirs(true)
+            .run()
+            .unwrap();
+        assert_eq!(report.directory_removed_count, 2);
+        assert!(root.join("partial/image.iso.acsync_tmp").exists());
+
+        let report = Cleaner::new(&root).prune_empty_dirs(true).run().unwrap();
+        assert_eq!(
+            report,
+            CleanReport {
+                temporary_file_count: 1,
+                total_temporary_file_size: 4,
+                state_file_count: 1,
+                stale_entry_count: 1,
+                directory_removed_count: 2,
+            }
+        );
+        assert!(!root.join("partial").exists());
+        assert!(!root.join("docs/empty").exists());
+        assert!(root.join("docs/notes.txt").exists());
+        assert_eq!(StateManifest::load(&root).unwrap().iter().count(), 1);
+    }
+}
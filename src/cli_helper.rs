@@ -1,7 +1,9 @@
 //! **cli_helper** contains simple and useful functions to support simple CLI software
 //! to work with command arguments and options.
 
-/// Returns [`Some<String>`] corresponding to the index argument.
+/// Returns [`Some<String>`] corresponding to the index argument, counting only the
+/// positional arguments, which can be interleaved with options given as
+/// `--name=value`. See [`argument_indexes`] for options given as `--name value`.
 ///
 /// Returns [`None`] if there is no argument value at the given index.
 ///
@@ -22,7 +24,55 @@
 /// );
 /// ```
 pub fn get_argument(index: usize, args: &[String]) -> Option<&String> {
-    args.get(index).filter(|value| !value.starts_with("--"))
+    argument_indexes(args, &[])
+        .get(index)
+        .map(|index| &args[*index])
+}
+
+/// Returns the indexes of the positional arguments of args: the values not starting
+/// with `--` nor following one of the `value_options` given as `--name value`, and
+/// every value following a `--` terminator, so that values such as `--weird-dir-name`
+/// can be given as arguments.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// #
+/// let args: Vec<String> = "command --debug foo --baz qux -- --bar"
+///                         .split_whitespace()
+///                         .map(|value| value.to_string())
+///                         .skip(1)
+///                         .collect();
+///
+/// assert_eq!(cli_helper::argument_indexes(&args, &["baz"]), vec![1, 5]);
+/// assert_eq!(
+///     cli_helper::get_argument(1, &args),
+///     Some(&String::from("qux"))
+/// );
+/// ```
+pub fn argument_indexes(args: &[String], value_options: &[&str]) -> Vec<usize> {
+    let options_end = options_end(args);
+    let mut indexes = vec![];
+    let mut index = 0;
+    while index < args.len() {
+        let value = &args[index];
+        if index > options_end || !value.starts_with("--") {
+            indexes.push(index);
+        } else if value_options.contains(&&value[2..]) && index + 1 < options_end {
+            index += 1;
+        }
+        index += 1;
+    }
+    indexes
+}
+
+/// Returns the index of the `--` terminator, after which no option is parsed, or the
+/// length of args if there is none.
+pub fn options_end(args: &[String]) -> usize {
+    args.iter()
+        .position(|value| value == "--")
+        .unwrap_or(args.len())
 }
 
 /// Returns ([`bool`], [`Some<usize>`]) if args contains the option name.
@@ -41,14 +91,15 @@ pub fn get_argument(index: usize, args: &[String]) -> Option<&String> {
 /// assert_eq!(cli_helper::has_option("debug", &args), (true, Some(3)));
 /// ```
 pub fn has_option(name: &str, args: &[String]) -> (bool, Option<usize>) {
-    let index = args
+    let index = args[..options_end(args)]
         .iter()
-        .position(|value| value.starts_with(&format!("--{name}")));
-    // let a = args.iter().enumerate().filter(|(index, ..)| index != index).map(|(.., value)| value);
+        .position(|value| is_option(name, value));
     (index.is_some(), index)
 }
 
-/// Returns ([`Some<&str>`], [`Some<usize>`]) corresponding to the option name.
+/// Returns ([`Some<&str>`], [`Some<usize>`]) corresponding to the option name, given
+/// as `--name=value` or as `--name value`. In the latter form, the index is the one of
+/// the value.
 ///
 /// Returns ([`None`], [`None`]) if there is no option value matching the option name.
 ///
@@ -69,32 +120,736 @@ pub fn has_option(name: &str, args: &[String]) -> (bool, Option<usize>) {
 /// );
 /// ```
 pub fn get_option_value<'a>(name: &str, args: &'a [String]) -> (Option<&'a str>, Option<usize>) {
-    let mut value_index = 0;
-    let mut arguments_iter = args.iter().enumerate();
-    (
-        arguments_iter
-            .find(|(.., value)| value.starts_with(&format!("--{name}")))
-            .inspect(|(index, ..)| value_index = *index)
-            .and_then(|(.., value)| value.strip_prefix(&format!("--{name}=")))
-            .or(arguments_iter
-                .take(1)
-                .find(|(.., value)| !value.starts_with("--"))
-                .inspect(|(index, ..)| value_index = *index)
-                .map(|(.., value)| &value[..])),
-        Some(value_index),
+    let (.., Some(index)) = has_option(name, args) else {
+        return (None, None);
+    };
+    if let Some(value) = args[index].strip_prefix(&format!("--{name}=")) {
+        return (Some(value), Some(index));
+    }
+    match args.get(index + 1) {
+        Some(value) if index + 1 < options_end(args) && !value.starts_with("--") => {
+            (Some(value), Some(index + 1))
+        }
+        _ => (None, Some(index)),
+    }
+}
+
+/// Returns the values of every occurrence of the option name, given as `--name=value`
+/// or as `--name value`, along with the indexes of the options and of their values.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// #
+/// let args: Vec<String> = "command --baz=qux foo --baz quux --debug"
+///                         .split_whitespace()
+///                         .map(|value| value.to_string())
+///                         .skip(1)
+///                         .collect();
+///
+/// assert_eq!(
+///     cli_helper::get_option_values("baz", &args),
+///     (vec!["qux", "quux"], vec![0, 2, 3])
+/// );
+/// ```
+pub fn get_option_values<'a>(name: &str, args: &'a [String]) -> (Vec<&'a str>, Vec<usize>) {
+    let options_end = options_end(args);
+    let mut values = vec![];
+    let mut indexes = vec![];
+    for (index, value) in args[..options_end].iter().enumerate() {
+        if !is_option(name, value) {
+            continue;
+        }
+        indexes.push(index);
+        if let Some(value) = value.strip_prefix(&format!("--{name}=")) {
+            values.push(value);
+        } else if let Some(value) = args.get(index + 1)
+            && index + 1 < options_end
+            && !value.starts_with("--")
+        {
+            values.push(value);
+            indexes.push(index + 1);
+        }
+    }
+    (values, indexes)
+}
+
+/// Returns true if the argument is the option name, alone or followed by `=value`,
+/// so that options sharing a prefix such as `--backup` and `--backup_dir` don't clash.
+fn is_option(name: &str, value: &str) -> bool {
+    value
+        .strip_prefix("--")
+        .and_then(|value| value.strip_prefix(name))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+}
+
+/// Returns [`Some<u64>`] with the amount of bytes described by a human readable size.
+///
+/// Sizes accept an optional `K`, `M`, `G` or `T` suffix (powers of 1024), optionally
+/// followed by `B` or `iB`. A plain number is read as bytes.
+///
+/// Returns [`None`] if the value is not a valid size.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// #
+/// assert_eq!(cli_helper::parse_size("500K"), Some(500 * 1024));
+/// assert_eq!(cli_helper::parse_size("10M"), Some(10 * 1024 * 1024));
+/// assert_eq!(cli_helper::parse_size("foo"), None);
+/// ```
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("iB")
+        .or_else(|| value.strip_suffix(['B', 'b']))
+        .unwrap_or(value);
+    let (number, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1024),
+        'M' => (&value[..value.len() - 1], 1024u64.pow(2)),
+        'G' => (&value[..value.len() - 1], 1024u64.pow(3)),
+        'T' => (&value[..value.len() - 1], 1024u64.pow(4)),
+        _ => (value, 1),
+    };
+    let number: f64 = number.trim().parse().ok()?;
+    if !number.is_finite() || number < 0.0 {
+        return None;
+    }
+    Some((number * multiplier as f64) as u64)
+}
+
+/// Returns [`Some<Duration>`] with the span of time described by a human readable duration.
+///
+/// Durations accept the `ms`, `s`, `m`, `h`, `d` and `w` suffixes. A plain number is
+/// read as seconds.
+///
+/// Returns [`None`] if the value is not a valid duration.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// # use std::time::Duration;
+/// #
+/// assert_eq!(cli_helper::parse_duration("30s"), Some(Duration::from_secs(30)));
+/// assert_eq!(cli_helper::parse_duration("7d"), Some(Duration::from_secs(7 * 86400)));
+/// assert_eq!(cli_helper::parse_duration("soon"), None);
+/// ```
+pub fn parse_duration(value: &str) -> Option<std::time::Duration> {
+    let value = value.trim();
+    let (number, seconds) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 0.001)
+    } else {
+        match value.chars().last()?.to_ascii_lowercase() {
+            's' => (&value[..value.len() - 1], 1.0),
+            'm' => (&value[..value.len() - 1], 60.0),
+            'h' => (&value[..value.len() - 1], 3600.0),
+            'd' => (&value[..value.len() - 1], 86400.0),
+            'w' => (&value[..value.len() - 1], 7.0 * 86400.0),
+            _ => (value, 1.0),
+        }
+    };
+    let number: f64 = number.trim().parse().ok()?;
+    std::time::Duration::try_from_secs_f64(number * seconds).ok()
+}
+
+/// Returns [`Some<SystemTime>`] with the point in time described either as an age
+/// relative to now (see [`parse_duration`]) or as an UTC date in the `YYYY-MM-DD` or
+/// `YYYY-MM-DDTHH:MM:SS` formats.
+///
+/// Returns [`None`] if the value is neither a valid age nor a valid date.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// #
+/// assert_eq!(
+///     cli_helper::parse_time("2024-01-01"),
+///     Some(UNIX_EPOCH + Duration::from_secs(1704067200))
+/// );
+/// assert!(cli_helper::parse_time("7d").is_some());
+/// ```
+pub fn parse_time(value: &str) -> Option<std::time::SystemTime> {
+    if let Some(age) = parse_duration(value) {
+        return std::time::SystemTime::now().checked_sub(age);
+    }
+
+    let value = value.trim();
+    let (date, time) = value.split_once(['T', ' ']).unwrap_or((value, "00:00:00"));
+    let mut date_parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+    let mut time_parts = time.splitn(3, ':').map(|part| part.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (
+        time_parts.next()??,
+        time_parts.next().unwrap_or(Some(0))?,
+        time_parts.next().unwrap_or(Some(0))?,
+    );
+
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if !(1..=days_in_month).contains(&day)
+        || !(0..24).contains(&hours)
+        || !(0..60).contains(&minutes)
+        || !(0..60).contains(&seconds)
+    {
+        return None;
+    }
+
+    // Days since the epoch of a proleptic Gregorian calendar date, counting years
+    // from March so that leap days fall at the end of each year.
+    let shifted_year = if month <= 2 { year - 1 } else { year };
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let timestamp = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    let offset = std::time::Duration::from_secs(timestamp.unsigned_abs());
+    if timestamp >= 0 {
+        std::time::UNIX_EPOCH.checked_add(offset)
+    } else {
+        std::time::UNIX_EPOCH.checked_sub(offset)
+    }
+}
+
+/// Returns the UTC date and time of `time` in the `YYYY-MM-DDTHH:MM:SS.mmmZ` format.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// #
+/// assert_eq!(
+///     cli_helper::format_time(UNIX_EPOCH + Duration::from_millis(1704067200250)),
+///     "2024-01-01T00:00:00.250Z"
+/// );
+/// ```
+pub fn format_time(time: std::time::SystemTime) -> String {
+    let (timestamp, millis) = match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(offset) => (offset.as_secs() as i64, offset.subsec_millis()),
+        Err(error) => {
+            let offset = error.duration();
+            let millis = (1000 - offset.subsec_millis()) % 1000;
+            let seconds = offset.as_secs() as i64 + i64::from(millis > 0);
+            (-seconds, millis)
+        }
+    };
+    let (days, seconds) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+
+    // Inverse of the days since the epoch computed by [`parse_time`].
+    let shifted_days = days + 719468;
+    let era = shifted_days.div_euclid(146097);
+    let day_of_era = shifted_days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
     )
 }
 
+/// Returns the name of the command matching `name` among `commands`, given with
+/// their aliases: the command named or aliased `name`, or else the only command whose
+/// name starts with `name`.
+///
+/// Returns the names of the matching commands otherwise, none if there is no match.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// #
+/// let commands = vec![
+///     ("replicate".to_string(), vec!["cp".to_string()]),
+///     ("restore".to_string(), vec![]),
+/// ];
+///
+/// assert_eq!(cli_helper::resolve_command("cp", &commands), Ok("replicate"));
+/// assert_eq!(cli_helper::resolve_command("rep", &commands), Ok("replicate"));
+/// assert_eq!(
+///     cli_helper::resolve_command("re", &commands),
+///     Err(vec!["replicate", "restore"])
+/// );
+/// ```
+pub fn resolve_command<'a>(
+    name: &str,
+    commands: &'a [(String, Vec<String>)],
+) -> Result<&'a str, Vec<&'a str>> {
+    if let Some((command_name, ..)) = commands.iter().find(|(command_name, aliases)| {
+        command_name == name || aliases.iter().any(|alias| alias == name)
+    }) {
+        return Ok(command_name);
+    }
+    let candidates: Vec<&str> = commands
+        .iter()
+        .map(|(command_name, ..)| command_name.as_str())
+        .filter(|command_name| !name.is_empty() && command_name.starts_with(name))
+        .collect();
+    match candidates[..] {
+        [command_name] => Ok(command_name),
+        _ => Err(candidates),
+    }
+}
+
+/// Shell supported by [`completion_script`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl std::str::FromStr for Shell {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!(
+                "unknown shell {value:?}, expected one of bash, zsh or fish"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Shell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shell::Bash => write!(f, "bash"),
+            Shell::Zsh => write!(f, "zsh"),
+            Shell::Fish => write!(f, "fish"),
+        }
+    }
+}
+
+/// Description of a command declared with [`create_args_parser!`], used to generate
+/// completion scripts. The default command has an empty name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub description: String,
+    pub options: Vec<OptionSpec>,
+}
+
+/// Description of an option of a [`CommandSpec`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionSpec {
+    pub name: String,
+    pub description: String,
+    pub takes_value: bool,
+}
+
+/// Returns the script completing the commands and options of `program` in `shell`.
+///
+/// Arguments are completed as file paths.
+pub fn completion_script(shell: Shell, program: &str, commands: &[CommandSpec]) -> String {
+    let function_name = format!("_{}", program.replace('-', "_"));
+    let command_names: Vec<&str> = commands
+        .iter()
+        .filter(|command| !command.name.is_empty())
+        .map(|command| command.name.as_str())
+        .collect();
+    fn command_and_aliases(command: &CommandSpec) -> Vec<&str> {
+        let mut names = vec![command.name.as_str()];
+        names.extend(command.aliases.iter().map(String::as_str));
+        names
+    }
+    let option_names = |command: &CommandSpec| {
+        command
+            .options
+            .iter()
+            .map(|option| format!("--{}", option.name))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let default_options = commands
+        .iter()
+        .find(|command| command.name.is_empty())
+        .map(option_names)
+        .unwrap_or_default();
+    let mut script = String::new();
+    match shell {
+        Shell::Bash => {
+            script += &format!("{function_name}() {{\n");
+            script += "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" options\n";
+            script += "    case \"${COMP_WORDS[1]}\" in\n";
+            for command in commands.iter().filter(|command| !command.name.is_empty()) {
+                script += &format!(
+                    "        {}) options=\"{}\" ;;\n",
+                    command_and_aliases(command).join("|"),
+                    option_names(command)
+                );
+            }
+            script += &format!("        *) options=\"{default_options}\" ;;\n");
+            script += "    esac\n";
+            script += "    if [[ \"$cur\" == -* ]]; then\n";
+            script += "        COMPREPLY=($(compgen -W \"$options\" -- \"$cur\"))\n";
+            script += "    elif [[ $COMP_CWORD -eq 1 ]]; then\n";
+            script += &format!(
+                "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+                command_names.join(" ")
+            );
+            script += "    fi\n";
+            script += "}\n";
+            script += &format!("complete -o default -F {function_name} {program}\n");
+        }
+        Shell::Zsh => {
+            script += &format!("#compdef {program}\n\n");
+            script += &format!("{function_name}() {{\n");
+            script += "    local -a options\n";
+            script += "    case $words[2] in\n";
+            for command in commands.iter().filter(|command| !command.name.is_empty()) {
+                script += &format!(
+                    "        {}) options=({}) ;;\n",
+                    command_and_aliases(command).join("|"),
+                    option_names(command)
+                );
+            }
+            script += &format!("        *) options=({default_options}) ;;\n");
+            script += "    esac\n";
+            script += "    if [[ $PREFIX == -* ]]; then\n";
+            script += "        compadd -- $options\n";
+            script += "    elif (( CURRENT == 2 )); then\n";
+            script += &format!("        compadd -- {}\n", command_names.join(" "));
+            script += "        _files\n";
+            script += "    else\n";
+            script += "        _files\n";
+            script += "    fi\n";
+            script += "}\n\n";
+            script += &format!("compdef {function_name} {program}\n");
+        }
+        Shell::Fish => {
+            let quote =
+                |value: &str| format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"));
+            for command in commands {
+                let condition = if command.name.is_empty() {
+                    "__fish_use_subcommand".to_string()
+                } else {
+                    script += &format!(
+                        "complete -c {program} -n __fish_use_subcommand -a {} -d {}\n",
+                        command.name,
+                        quote(&command.description)
+                    );
+                    format!(
+                        "__fish_seen_subcommand_from {}",
+                        command_and_aliases(command).join(" ")
+                    )
+                };
+                for option in &command.options {
+                    script += &format!(
+                        "complete -c {program} -n {} -l {}{} -d {}\n",
+                        quote(&condition),
+                        option.name,
+                        if option.takes_value { " -r" } else { "" },
+                        quote(&option.description)
+                    );
+                }
+            }
+        }
+    }
+    script
+}
+
+/// Validator of the [`create_args_parser!`] fields declared with
+/// `@validate(cli_helper::existing_directory)`, checking that the value is a readable directory.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// #
+/// assert!(cli_helper::existing_directory(".").is_ok());
+/// assert_eq!(
+///     cli_helper::existing_directory("/foo/bar"),
+///     Err("does not exist or is not a readable directory".to_string())
+/// );
+/// ```
+pub fn existing_directory(value: &str) -> Result<(), String> {
+    std::fs::read_dir(value)
+        .map(|_| ())
+        .map_err(|_| "does not exist or is not a readable directory".to_string())
+}
+
+/// Validator of the [`create_args_parser!`] fields declared with
+/// `@validate(cli_helper::location)`, accepting local paths, the `acsync://` URLs of
+/// `acsync serve`, the `s3://` URLs of buckets and the `dav://` URLs of WebDAV shares,
+/// but rejecting other URLs, which would otherwise be taken as relative directories.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// #
+/// assert!(cli_helper::location("/media/backup").is_ok());
+/// assert!(cli_helper::location("C:\\Backup").is_ok());
+/// assert!(cli_helper::location("acsync://nas.local/backup").is_ok());
+/// assert!(cli_helper::location("s3://bucket/prefix").is_ok());
+/// assert!(cli_helper::location("dav://cloud.local/remote.php/dav/files/ana").is_ok());
+/// assert!(cli_helper::location("davs://cloud.example.com/dav").is_err());
+/// assert!(cli_helper::location("https://cloud.example.com/dav").is_err());
+/// ```
+pub fn location(value: &str) -> Result<(), String> {
+    match value.split_once("://") {
+        Some(("acsync" | "s3" | "dav", _)) => Ok(()),
+        Some(("http", _)) => {
+            Err("is not a local path, write the URL of a WebDAV share as dav://HOST/PATH".to_string())
+        }
+        Some(("davs" | "https", _)) => Err(
+            "uses TLS, which acsync doesn't speak, go through a local TLS proxy such as stunnel with a dav:// URL"
+                .to_string(),
+        ),
+        Some((scheme, _))
+            if !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) =>
+        {
+            Err("is not a local path, mount the remote storage and use its mount point".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the version and build information of `program`, given the build metadata
+/// exposed by its build script: the git commit, the build UNIX timestamp and the
+/// comma-separated enabled features.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::cli_helper;
+/// #
+/// assert_eq!(
+///     cli_helper::describe_version("acsync", "0.1.0", Some("0123abc"), Some("0"), None),
+///     "acsync 0.1.0\ncommit: 0123abc\nbuilt: 1970-01-01T00:00:00.000Z\nfeatures: none"
+/// );
+/// ```
+pub fn describe_version(
+    program: &str,
+    version: &str,
+    commit: Option<&str>,
+    timestamp: Option<&str>,
+    features: Option<&str>,
+) -> String {
+    let built = timestamp
+        .and_then(|timestamp| timestamp.parse().ok())
+        .map(|seconds| format_time(std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds)))
+        .unwrap_or("unknown".to_string());
+    let features = features
+        .filter(|features| !features.is_empty())
+        .unwrap_or("none");
+    format!(
+        "{program} {version}\ncommit: {}\nbuilt: {built}\nfeatures: {features}",
+        commit.unwrap_or("unknown")
+    )
+}
+
+/// Optional positional argument.
 pub type Arg<T> = Option<T>;
 
+/// Required positional argument: parsing fails with the command usage when missing.
+pub type Req<T> = Option<T>;
+
+/// Returns true if the type of a field declared with [`create_args_parser!`] is a
+/// positional argument, i.e. an [`Arg`] or a [`Req`].
+#[doc(hidden)]
+pub fn is_argument_type(parameter_type: &str) -> bool {
+    parameter_type.starts_with("Arg") || parameter_type.starts_with("Req")
+}
+
+/// Returns the label of a field declared with [`create_args_parser!`] in the help:
+/// its name followed by the expected value type for options taking a value.
+#[doc(hidden)]
+pub fn option_label(name: &str, parameter_type: &str) -> String {
+    if is_argument_type(parameter_type) || parameter_type.contains("<bool>") {
+        return name.to_string();
+    }
+    let value_type = parameter_type
+        .split_once('<')
+        .and_then(|(_, value_type)| value_type.strip_suffix('>'))
+        .unwrap_or(parameter_type)
+        .trim();
+    let value_hint = match value_type.rsplit("::").next().unwrap_or(value_type) {
+        "String" | "str" => "TEXT".to_string(),
+        "PathBuf" | "Path" => "PATH".to_string(),
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => "INTEGER".to_string(),
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => "INTEGER".to_string(),
+        "f32" | "f64" => "NUMBER".to_string(),
+        other => other.to_uppercase(),
+    };
+    let repeatable = if parameter_type.starts_with("Vec") {
+        "..."
+    } else {
+        ""
+    };
+    format!("{name} <{value_hint}>{repeatable}")
+}
+
+/// Reasons why [`ArgsParser::try_parse_slice`] didn't return a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// `--help` was informed: holds the help of the command.
+    Help(String),
+    /// `--version` or `-V` was informed: holds the version information.
+    Version(String),
+    /// The command name is a prefix of several commands.
+    AmbiguousCommand {
+        name: String,
+        candidates: Vec<String>,
+    },
+    /// There is no command with the given name.
+    UnknownCommand(String),
+    /// A required argument was not informed.
+    MissingArgument { name: String, usage: String },
+    /// A value was rejected by the `@validate` hook of its field.
+    InvalidValue {
+        name: String,
+        value: String,
+        reason: String,
+    },
+    /// Arguments matching no argument nor option of the command.
+    UnrecognizedArguments(Vec<String>),
+}
+
+impl ParseError {
+    /// Returns the process exit code reporting this error: `0` for the help and the
+    /// version, `1` otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ParseError::Help(_) | ParseError::Version(_) => 0,
+            _ => 1,
+        }
+    }
+
+    /// Prints the help or the version to the standard output, or the error to the
+    /// standard error, and exits the process with [`ParseError::exit_code`].
+    pub fn exit(&self) -> ! {
+        match self {
+            ParseError::Help(_) | ParseError::Version(_) => println!("{self}"),
+            _ => eprintln!("ERROR: {self}"),
+        }
+        std::process::exit(self.exit_code())
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Help(text) | ParseError::Version(text) => write!(f, "{text}"),
+            ParseError::AmbiguousCommand { name, candidates } => write!(
+                f,
+                "Command {name:?} is ambiguous, candidates are: {}",
+                candidates.join(", ")
+            ),
+            ParseError::UnknownCommand(name) => write!(f, "Command {name:?} not found!"),
+            ParseError::MissingArgument { name, usage } => {
+                write!(f, "Argument {name} must be informed!\n\n{usage}")
+            }
+            ParseError::InvalidValue {
+                name,
+                value,
+                reason,
+            } => write!(f, "{name} {value:?} {reason}"),
+            ParseError::UnrecognizedArguments(arguments) => {
+                write!(f, "Not recognized arguments! {arguments:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Conversion of the values parsed for a field declared with [`create_args_parser!`]
+/// into the field: the first value for an [`Option`], every value for a [`Vec`] of a
+/// repeatable option. Values that fail to parse fall back to the type default.
+#[doc(hidden)]
+pub trait FromArgValues {
+    fn from_arg_values(values: Vec<String>) -> Self;
+}
+
+impl<T: std::str::FromStr + Default> FromArgValues for Option<T> {
+    fn from_arg_values(values: Vec<String>) -> Self {
+        values
+            .into_iter()
+            .next()
+            .map(|value| value.parse().unwrap_or_default())
+    }
+}
+
+impl<T: std::str::FromStr + Default> FromArgValues for Vec<T> {
+    fn from_arg_values(values: Vec<String>) -> Self {
+        values
+            .into_iter()
+            .map(|value| value.parse().unwrap_or_default())
+            .collect()
+    }
+}
+
 pub trait ArgsParser {
     fn debug(&self) -> bool;
 
+    /// Returns the names of the options taking a value, i.e. neither flags nor
+    /// positional arguments.
+    fn value_options() -> Vec<&'static str>;
+
     fn print_help(&self);
 
+    /// Returns the names of the options declared with `@env` and their environment
+    /// variable, used as fallback when the option is not in the command line.
+    fn env_options() -> Vec<(&'static str, &'static str)>;
+
+    /// Returns the completion script of the declared commands and options for `shell`.
+    fn completions(shell: Shell) -> String;
+
+    /// Returns the version and build information printed by `--version`.
+    fn version() -> String;
+
     fn describe(command_name: &str) -> String;
 
-    fn parse_slice(args: &[String]) -> Self;
+    /// Parses the command line `args`, without the program name.
+    fn try_parse_slice(args: &[String]) -> Result<Self, ParseError>
+    where
+        Self: Sized;
+
+    /// Parses the command line `args`, without the program name, exiting the process
+    /// with the help, the version or the error when they can't be parsed.
+    fn parse_slice(args: &[String]) -> Self
+    where
+        Self: Sized,
+    {
+        Self::try_parse_slice(args).unwrap_or_else(|error| error.exit())
+    }
 
     fn parse() -> Self
     where
@@ -111,15 +866,23 @@ macro_rules! create_args_parser {
         $(#[doc = $doc_literal:literal])*
         $vis_enum:vis enum $ident_enum:ident {
             $($(#[doc = $literal_command_description:literal])*
+            $(@alias($($alias_command:ident),+ $(,)?))?
+            $(@examples($($example_command:literal),+ $(,)?))?
             $ident_command:ident {
                 $(
                     $(#[doc = $literal_parameter_description:literal])*
+                    $(@env $env_parameter:ident)?
+                    $(@validate($validate_parameter:expr))?
                     $ident_parameter:ident: $ty_parameter:ty
                 ),* $(,)?
             }),*  $(,)?
-           $(@default $ident_default_command:ident {
+           $(@default
+            $(@examples($($example_default_command:literal),+ $(,)?))?
+            $ident_default_command:ident {
                 $(
                     $(#[doc = $literal_default_parameter_description:literal])*
+                    $(@env $env_default_parameter:ident)?
+                    $(@validate($validate_default_parameter:expr))?
                     $ident_default_parameter:ident: $ty_default_parameter:ty
                 ),* $(,)?
             } $(,)? )?
@@ -145,6 +908,8 @@ macro_rules! create_args_parser {
             })?
         }
 
+        // The generated code is shared by commands with and without arguments/options.
+        #[allow(unused_mut, clippy::vec_init_then_push)]
         impl $crate::cli_helper::ArgsParser for $ident_enum {
 
             fn debug(&self) -> bool {
@@ -154,6 +919,95 @@ macro_rules! create_args_parser {
                 }
             }
 
+            fn value_options() -> Vec<&'static str> {
+                let parameters: Vec<(&'static str, &'static str)> = vec![
+                    $($((stringify!($ident_parameter), stringify!($ty_parameter)),)*)*
+                    $($((stringify!($ident_default_parameter), stringify!($ty_default_parameter)),)*)?
+                ];
+                parameters
+                    .into_iter()
+                    .filter(|(.., parameter_type)| {
+                        !cli_helper::is_argument_type(parameter_type) && !parameter_type.contains("<bool>")
+                    })
+                    .map(|(parameter_name, ..)| parameter_name)
+                    .collect()
+            }
+
+            fn completions(shell: cli_helper::Shell) -> String {
+                let mut commands: Vec<cli_helper::CommandSpec> = vec![];
+                let option_spec = |name: &str, descriptions: &[&str], parameter_type: &str| {
+                    let mut options = vec![];
+                    if !cli_helper::is_argument_type(parameter_type) {
+                        options.push(cli_helper::OptionSpec {
+                            name: name.to_string(),
+                            description: descriptions.join(" "),
+                            takes_value: !parameter_type.contains("<bool>"),
+                        });
+                    }
+                    if parameter_type.contains("<bool>") && name != "no" && !name.starts_with("no_") {
+                        options.push(cli_helper::OptionSpec {
+                            name: format!("no_{name}"),
+                            description: format!("Disable --{name}"),
+                            takes_value: false,
+                        });
+                    }
+                    options
+                };
+                let common_options = [("debug", "Enable debug mode"), ("help", "Print help")]
+                    .map(|(name, description)| cli_helper::OptionSpec {
+                        name: name.to_string(),
+                        description: description.to_string(),
+                        takes_value: false,
+                    });
+                $(
+                let command_descriptions: [&str; _] = [$($literal_command_description.trim_start(),)*];
+                let mut options: Vec<cli_helper::OptionSpec> = vec![];
+                $(
+                let parameter_descriptions: [&str; _] = [$($literal_parameter_description.trim_start(),)*];
+                options.extend(option_spec(stringify!($ident_parameter), &parameter_descriptions, stringify!($ty_parameter)));
+                )*
+                options.extend(common_options.clone());
+                commands.push(cli_helper::CommandSpec {
+                    name: stringify!($ident_command).to_lowercase(),
+                    aliases: vec![$($(stringify!($alias_command).to_string()),+)?],
+                    description: command_descriptions.join(" "),
+                    options,
+                });
+                )*
+                $(
+                let mut options: Vec<cli_helper::OptionSpec> = vec![];
+                $(
+                let parameter_descriptions: [&str; _] = [$($literal_default_parameter_description.trim_start(),)*];
+                options.extend(option_spec(stringify!($ident_default_parameter), &parameter_descriptions, stringify!($ty_default_parameter)));
+                )*
+                options.extend(common_options.clone());
+                commands.push(cli_helper::CommandSpec {
+                    name: String::new(),
+                    aliases: vec![],
+                    description: String::new(),
+                    options,
+                });
+                )?
+                cli_helper::completion_script(shell, env!("CARGO_PKG_NAME"), &commands)
+            }
+
+            fn env_options() -> Vec<(&'static str, &'static str)> {
+                vec![
+                    $($($((stringify!($ident_parameter), stringify!($env_parameter)),)?)*)*
+                    $($($((stringify!($ident_default_parameter), stringify!($env_default_parameter)),)?)*)?
+                ]
+            }
+
+            fn version() -> String {
+                cli_helper::describe_version(
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    option_env!("BUILD_GIT_COMMIT"),
+                    option_env!("BUILD_TIMESTAMP"),
+                    option_env!("BUILD_FEATURES"),
+                )
+            }
+
             fn print_help(&self) {
                 match &self {
                     $($ident_enum::$ident_command { .. } => {
@@ -169,15 +1023,16 @@ macro_rules! create_args_parser {
                 let crate_name = env!("CARGO_PKG_NAME");
                 let mut description = String::new();
 
-                let mut all_parameters = vec![
-                    $($(stringify!($ident_parameter),)*)*
-                    $($(stringify!($ident_default_parameter),)*)*
-                    "debug",
+                let all_parameters = vec![
+                    $($(cli_helper::option_label(stringify!($ident_parameter), stringify!($ty_parameter)),)*)*
+                    $($(cli_helper::option_label(stringify!($ident_default_parameter), stringify!($ty_default_parameter)),)*)*
+                    "debug".to_string(),
                 ];
                 let parameter_width = all_parameters.iter().map(|item| item.len()).max().unwrap() + 2;
 
                 let mut parameter_description_map = std::collections::HashMap::from([
                     ("debug", "Enable debug mode".to_string()),
+                    ("version", "Print version and build information".to_string()),
                 ]);
 
                 match command_name {
@@ -185,23 +1040,37 @@ macro_rules! create_args_parser {
                         let command_name = &stringify!($ident_command).to_lowercase();
 
                         let mut arg_parameters: Vec<&str> = vec![];
-                        let mut opt_parameters: Vec<&str> = vec![];
+                        let mut arg_usages: Vec<String> = vec!["[OPTIONS]".to_string()];
+                        let mut opt_parameters: Vec<(&str, String)> = vec![];
                         $(
                         let parameter_descriptions: [&str; _] = [$($literal_parameter_description.trim_start(),)*];
-                        parameter_description_map.insert(stringify!($ident_parameter), parameter_descriptions.join(" "));
-                        if stringify!($ty_parameter).starts_with("Arg") {
+                        parameter_description_map.insert(
+                            stringify!($ident_parameter),
+                            parameter_descriptions.join(" ") + concat!("" $(, " [env: ", stringify!($env_parameter), "]")?),
+                        );
+                        if cli_helper::is_argument_type(stringify!($ty_parameter)) {
                             arg_parameters.push(stringify!($ident_parameter));
+                            arg_usages.push(if stringify!($ty_parameter).starts_with("Req") {
+                                *parameter_description_map.get_mut(stringify!($ident_parameter)).unwrap() += " [required]";
+                                format!("<{}>", stringify!($ident_parameter))
+                            } else {
+                                format!("[{}]", stringify!($ident_parameter))
+                            });
                         }
                         else {
-                            opt_parameters.push(stringify!($ident_parameter));
+                            opt_parameters.push((
+                                stringify!($ident_parameter),
+                                cli_helper::option_label(stringify!($ident_parameter), stringify!($ty_parameter)),
+                            ));
                         }
                         )*
-                        opt_parameters.push("debug");
+                        opt_parameters.push(("debug", "debug".to_string()));
+                        let examples: Vec<&str> = vec![$($($example_command),+)?];
 
                         $(description += &format!("{}\n", $literal_command_description).trim_start();)*
                         description += "\n";
                         description += &format!(
-                            "Usage: {} {} [OPTIONS] [ARGS]...\n", crate_name, command_name,
+                            "Usage: {} {} {}\n", crate_name, command_name, arg_usages.join(" "),
                         ).as_str();
                         if !arg_parameters.is_empty() {
                             description += "\n";
@@ -215,11 +1084,18 @@ macro_rules! create_args_parser {
                         if !opt_parameters.is_empty() {
                             description += "\n";
                             description += "Options:\n";
-                            for opt_name in &opt_parameters {
-                                description += &format!("\t--{:<parameter_width$}", opt_name).as_str();
+                            for (opt_name, opt_label) in &opt_parameters {
+                                description += &format!("\t--{:<parameter_width$}", opt_label).as_str();
                                 description += format!("{}\n", parameter_description_map.get(opt_name).unwrap()).as_str();
                             }
                         }
+                        if !examples.is_empty() {
+                            description += "\n";
+                            description += "Examples:\n";
+                            for example in &examples {
+                                description += format!("\t{}\n", example).as_str();
+                            }
+                        }
 
                         description
                     },)*
@@ -227,23 +1103,36 @@ macro_rules! create_args_parser {
                         let command_names: Vec<&str> = vec![$(stringify!($ident_command)),*];
                         $(
                         let command_descriptions: [&str; _] = [$($literal_command_description.trim_start(),)*];
-                        parameter_description_map.insert(stringify!($ident_command), command_descriptions.join(" "));
+                        let command_aliases: [&str; _] = [$($(stringify!($alias_command)),+)?];
+                        let mut command_description = command_descriptions.join(" ");
+                        if !command_aliases.is_empty() {
+                            command_description += &format!(" [aliases: {}]", command_aliases.join(", "));
+                        }
+                        parameter_description_map.insert(stringify!($ident_command), command_description);
                         )*
                         let mut arg_parameters: Vec<&str> = vec![];
-                        let mut opt_parameters: Vec<&str> = vec![];
+                        let mut opt_parameters: Vec<(&str, String)> = vec![];
                         $($(
                         let default_parameter_descriptions: [&str; _] = [$($literal_default_parameter_description.trim_start(),)*];
-                        parameter_description_map.insert(stringify!($ident_default_parameter), default_parameter_descriptions.join(" "));
-                        if stringify!($ty_default_parameter).starts_with("Arg") {
+                        parameter_description_map.insert(
+                            stringify!($ident_default_parameter),
+                            default_parameter_descriptions.join(" ") + concat!("" $(, " [env: ", stringify!($env_default_parameter), "]")?),
+                        );
+                        if cli_helper::is_argument_type(stringify!($ty_default_parameter)) {
                             arg_parameters.push(stringify!($ident_default_parameter));
                         }
                         else {
-                            opt_parameters.push(stringify!($ident_default_parameter));
+                            opt_parameters.push((
+                                stringify!($ident_default_parameter),
+                                cli_helper::option_label(stringify!($ident_default_parameter), stringify!($ty_default_parameter)),
+                            ));
                         }
                         )*)*
-                        opt_parameters.push("debug");
+                        opt_parameters.push(("debug", "debug".to_string()));
+                        opt_parameters.push(("version", "version".to_string()));
+                        let examples: Vec<&str> = vec![$($($($example_default_command),+)?)?];
 
-                        $(description += &format!("{}\n", $doc_literal).trim_start())*;
+                        $(description += &format!("{}\n", $doc_literal).trim_start();)*
                         description += "\n";
                         description += &format!(
                             "Usage: {} [COMMAND] [OPTIONS] [ARGS]...\n", crate_name,
@@ -269,18 +1158,25 @@ macro_rules! create_args_parser {
                         if !opt_parameters.is_empty() {
                             description += "\n";
                             description += "Options:\n";
-                            for opt_name in &opt_parameters {
-                                description += &format!("\t--{:<parameter_width$}", opt_name).as_str();
+                            for (opt_name, opt_label) in &opt_parameters {
+                                description += &format!("\t--{:<parameter_width$}", opt_label).as_str();
                                 description += format!("{}\n", parameter_description_map.get(opt_name).unwrap()).as_str();
                             }
                         }
+                        if !examples.is_empty() {
+                            description += "\n";
+                            description += "Examples:\n";
+                            for example in &examples {
+                                description += format!("\t{}\n", example).as_str();
+                            }
+                        }
 
                         description
                     }
                 }
             }
 
-            fn parse_slice(args: &[String]) -> Self {
+            fn try_parse_slice(args: &[String]) -> Result<Self, cli_helper::ParseError> {
                 let mut indexes_found: std::collections::HashSet<usize>  = std::collections::HashSet::new();
 
                 let debug = if let (has_option, Some(index)) = cli_helper::has_option("debug", &args) {
@@ -293,13 +1189,38 @@ macro_rules! create_args_parser {
                 let command_name_map: std::collections::HashMap<String, &str> = std::collections::HashMap::from([
                     $((stringify!($ident_command).to_lowercase(), stringify!($ident_command)),)*
                 ]);
-                let command_name = cli_helper::get_argument(0, &args);
+                let argument_indexes = cli_helper::argument_indexes(&args, &Self::value_options());
+                let command_aliases: Vec<(String, Vec<String>)> = vec![
+                    $((
+                        stringify!($ident_command).to_lowercase(),
+                        vec![$($(stringify!($alias_command).to_string()),+)?],
+                    ),)*
+                ];
+                let command_name = match argument_indexes.first().map(|index| &args[*index]) {
+                    Some(command_name) => match cli_helper::resolve_command(command_name, &command_aliases) {
+                        Ok(command_name) => Some(command_name.to_string()),
+                        Err(candidates) if candidates.len() > 1 => {
+                            return Err(cli_helper::ParseError::AmbiguousCommand {
+                                name: command_name.clone(),
+                                candidates: candidates.into_iter().map(String::from).collect(),
+                            });
+                        }
+                        Err(_) => Some(command_name.clone()),
+                    },
+                    None => None,
+                };
+                let command_name = command_name.as_ref();
 
+                let described_command = command_name
+                    .and_then(|command_name| command_name_map.get(command_name))
+                    .unwrap_or(&"__");
+                if cli_helper::has_option("version", &args).0
+                    || args[..cli_helper::options_end(&args)].iter().any(|value| value == "-V")
+                {
+                    return Err(cli_helper::ParseError::Version($ident_enum::version()));
+                }
                 if let (true, ..)  = cli_helper::has_option("help", &args) {
-                    println!("{}", $ident_enum::describe(
-                        command_name_map.get(command_name.unwrap_or(&"__".to_string())).unwrap_or(&"__")
-                    ));
-                    std::process::exit(0);
+                    return Err(cli_helper::ParseError::Help($ident_enum::describe(described_command)));
                 }
 
                 let mut argument_index = 0;
@@ -307,72 +1228,135 @@ macro_rules! create_args_parser {
                 let command_names: Vec<&str> = vec![$(stringify!($ident_command)),*];
                 if !command_names.is_empty() {
                     argument_index = 1;
+                    if let Some(index) = argument_indexes.first() {
+                        indexes_found.insert(*index);
+                    }
                 }
+                indexes_found.insert(cli_helper::options_end(&args));
 
-                let mut get = |field_name: &str, field_type: &str| {
+                let mut get = |
+                    field_name: &str,
+                    field_type: &str,
+                    env_name: Option<&str>,
+                    validate: Option<fn(&str) -> Result<(), String>>,
+                | {
                     let mut value = None;
-                    if (field_type.starts_with("Arg")) {
-                        value = cli_helper::get_argument(argument_index, &args).cloned();
-                        if value.is_some() {
-                            indexes_found.insert(argument_index);
+                    let mut values = vec![];
+                    let env_value = env_name.and_then(|env_name| std::env::var(env_name).ok());
+                    if (cli_helper::is_argument_type(field_type)) {
+                        if let Some(index) = argument_indexes.get(argument_index) {
+                            indexes_found.insert(*index);
+                            value = Some(args[*index].clone());
                         }
                         argument_index += 1;
+                        if value.is_none() {
+                            value = env_value;
+                        }
+                        if value.is_none() && field_type.starts_with("Req") {
+                            let description = $ident_enum::describe(described_command);
+                            let usage = description
+                                .lines()
+                                .find(|line| line.starts_with("Usage:"))
+                                .unwrap_or_default();
+                            return Err(cli_helper::ParseError::MissingArgument {
+                                name: field_name.to_string(),
+                                usage: usage.to_string(),
+                            });
+                        }
+                    }
+                    else if field_type.starts_with("Vec") {
+                        let (option_values, option_indexes) = cli_helper::get_option_values(field_name, &args);
+                        indexes_found.extend(option_indexes);
+                        values = option_values.into_iter().map(String::from).collect();
+                        if values.is_empty() {
+                            values.extend(env_value);
+                        }
                     }
                     else {
                         let (has_option, option_index) = cli_helper::has_option(field_name, &args);
-                        if has_option {
+                        let (has_negation, negation_index) = if field_type.contains("<bool>") {
+                            cli_helper::has_option(&format!("no_{field_name}"), &args)
+                        } else {
+                            (false, None)
+                        };
+                        if has_negation && negation_index > option_index {
+                            value = Some(false.to_string());
+                        } else if has_option && field_type.contains("<bool>") {
+                            value = option_index
+                                .and_then(|index| args[index].strip_prefix(&format!("--{field_name}=")))
+                                .map(String::from);
+                        } else if has_option {
                             let (option_value, option_index) = cli_helper::get_option_value(field_name, &args);
                             if let Some(option_index) = option_index {
                                 indexes_found.insert(option_index);
                             }
                             value = option_value.map(String::from);
                         }
-                        if value.is_none() && field_type.contains("<bool>") {
-                            value = Some(has_option.to_string());
+                        if !has_option && !has_negation && env_value.is_some() {
+                            value = env_value;
                         }
-                        if let Some(option_index) = option_index {
-                            indexes_found.insert(option_index);
+                        // Left unset when absent, so that flags can default to true.
+                        if value.is_none() && has_option && field_type.contains("<bool>") {
+                            value = Some(true.to_string());
+                        }
+                        indexes_found.extend(option_index);
+                        indexes_found.extend(negation_index);
+                    }
+                    values.extend(value);
+                    if let Some(validate) = validate {
+                        for value in &values {
+                            if let Err(reason) = validate(value) {
+                                return Err(cli_helper::ParseError::InvalidValue {
+                                    name: field_name.to_string(),
+                                    value: value.clone(),
+                                    reason,
+                                });
+                            }
                         }
                     }
-                    value
+                    Ok(values)
                 };
 
                 let command = match command_name {
                     $(Some(command_name) if command_name == &stringify!($ident_command).to_lowercase() => {
                         $ident_enum::$ident_command {
-                            $($ident_parameter: match get(stringify!($ident_parameter), stringify!($ty_parameter)) {
-                                Some(value) => Some(value.parse().unwrap_or_default()),
-                                None => Default::default()
-                            },)*
+                            $($ident_parameter: cli_helper::FromArgValues::from_arg_values(get(
+                                stringify!($ident_parameter),
+                                stringify!($ty_parameter),
+                                None $(.or(Some(stringify!($env_parameter))))?,
+                                None $(.or(Some($validate_parameter as fn(&str) -> Result<(), String>)))?,
+                            )?),)*
                             debug: Some(debug),
                         }
                     })*
                     $(_ if command_name.is_none() || command_names.is_empty() => $ident_enum::$ident_default_command {
-                        $($ident_default_parameter: match get(stringify!($ident_default_parameter), stringify!($ty_default_parameter)) {
-                            Some(value) => Some(value.parse().unwrap_or_default()),
-                            None => Default::default()
-                        },)*
+                        $($ident_default_parameter: cli_helper::FromArgValues::from_arg_values(get(
+                            stringify!($ident_default_parameter),
+                            stringify!($ty_default_parameter),
+                            None $(.or(Some(stringify!($env_default_parameter))))?,
+                            None $(.or(Some($validate_default_parameter as fn(&str) -> Result<(), String>)))?,
+                        )?),)*
                         debug: Some(debug),
                     },)?
                     _ => {
-                        eprintln!("ERROR: Command {:?} not found!", command_name.unwrap_or(&"None".to_string()));
-                        std::process::exit(1);
+                        return Err(cli_helper::ParseError::UnknownCommand(
+                            command_name.cloned().unwrap_or_default(),
+                        ));
                     }
                 };
 
-                indexes_found.insert(0);
-
                 let reaming: Vec<&String> = args
                                         .iter().enumerate()
                                         .filter(|(index, ..)| !indexes_found.contains(index))
                                         .map(|(.., value)| value)
                                         .collect();
                 if (!reaming.is_empty()) {
-                    eprintln!("ERROR: Not recognized arguments! {:?}", reaming);
-                    std::process::exit(1);
+                    return Err(cli_helper::ParseError::UnrecognizedArguments(
+                        reaming.into_iter().cloned().collect(),
+                    ));
                 }
 
-                command
+                Ok(command)
             }
         }
     };
@@ -431,4 +1415,316 @@ mod tests {
         let args: Vec<String> = parse("command foo bar --fred=qux --debug", 1);
         assert_ne!(get_option_value("baz", &args), (Some("qux"), Some(2)));
     }
+
+    #[test]
+    fn it_does_not_confuse_options_sharing_a_prefix() {
+        let args: Vec<String> = parse("command foo --backup_dir=old", 1);
+        assert_eq!(has_option("backup", &args), (false, None));
+        assert_eq!(
+            get_option_value("backup_dir", &args),
+            (Some("old"), Some(1))
+        );
+    }
+
+    #[test]
+    fn it_interleaves_arguments_and_options() {
+        let args: Vec<String> = parse("command --dryrun foo --baz=qux bar -- --qux=baz", 1);
+        assert_eq!(get_argument(0, &args), Some(&String::from("foo")));
+        assert_eq!(get_argument(1, &args), Some(&String::from("bar")));
+        assert_eq!(get_argument(2, &args), Some(&String::from("--qux=baz")));
+        assert_eq!(has_option("dryrun", &args), (true, Some(0)));
+        assert_eq!(has_option("qux", &args), (false, None));
+    }
+
+    #[test]
+    fn it_finds_space_separated_option_values() {
+        let args: Vec<String> = parse("command foo --baz qux --fred -- bar", 1);
+        assert_eq!(get_option_value("baz", &args), (Some("qux"), Some(2)));
+        assert_eq!(get_option_value("fred", &args), (None, Some(3)));
+        assert_eq!(argument_indexes(&args, &["baz", "fred"]), vec![0, 5]);
+    }
+
+    #[test]
+    fn it_generates_completion_scripts() {
+        let commands = vec![CommandSpec {
+            name: "replicate".to_string(),
+            aliases: vec!["cp".to_string()],
+            description: "Copy files".to_string(),
+            options: vec![
+                OptionSpec {
+                    name: "dryrun".to_string(),
+                    description: "Run without side effects".to_string(),
+                    takes_value: false,
+                },
+                OptionSpec {
+                    name: "bwlimit".to_string(),
+                    description: "Don't exceed RATE".to_string(),
+                    takes_value: true,
+                },
+            ],
+        }];
+        let bash = completion_script(Shell::Bash, "acsync", &commands);
+        assert!(bash.contains("replicate|cp) options=\"--dryrun --bwlimit\" ;;"));
+        assert!(bash.ends_with("complete -o default -F _acsync acsync\n"));
+        let fish = completion_script(Shell::Fish, "acsync", &commands);
+        assert!(fish.contains(
+            "complete -c acsync -n '__fish_seen_subcommand_from replicate cp' -l bwlimit -r \
+             -d 'Don\\'t exceed RATE'"
+        ));
+        assert_eq!("ZSH".parse(), Ok(Shell::Zsh));
+    }
+
+    #[test]
+    fn it_falls_back_to_environment_variables() {
+        use crate::cli_helper;
+
+        create_args_parser! {
+            enum TestCommand {
+                Copy {
+                    @env ACSYNC_TEST_ORIGIN
+                    origin: Arg<String>,
+                    @env ACSYNC_TEST_LIMIT
+                    limit: Option<String>,
+                },
+                @default Help {},
+            }
+        }
+
+        // SAFETY: no other test reads or writes these variables.
+        unsafe {
+            std::env::set_var("ACSYNC_TEST_ORIGIN", "env_origin");
+            std::env::set_var("ACSYNC_TEST_LIMIT", "10");
+        }
+        let TestCommand::Copy { origin, limit, .. } =
+            TestCommand::parse_slice(&parse("copy --limit=5", 0))
+        else {
+            panic!("copy command expected");
+        };
+        assert_eq!(
+            (origin.as_deref(), limit.as_deref()),
+            (Some("env_origin"), Some("5"))
+        );
+        let TestCommand::Copy { origin, limit, .. } =
+            TestCommand::parse_slice(&parse("copy cli_origin", 0))
+        else {
+            panic!("copy command expected");
+        };
+        assert_eq!(
+            (origin.as_deref(), limit.as_deref()),
+            (Some("cli_origin"), Some("10"))
+        );
+        assert_eq!(
+            TestCommand::env_options(),
+            vec![
+                ("origin", "ACSYNC_TEST_ORIGIN"),
+                ("limit", "ACSYNC_TEST_LIMIT")
+            ]
+        );
+    }
+
+    #[test]
+    fn it_describes_required_arguments_in_the_usage() {
+        use crate::cli_helper;
+
+        create_args_parser! {
+            @attr #[allow(dead_code)]
+            enum TestCommand {
+                Copy {
+                    origin: Req<String>,
+                    destination: Arg<String>,
+                },
+                @default Help {},
+            }
+        }
+
+        assert!(
+            TestCommand::describe("Copy")
+                .contains("Usage: acsync copy [OPTIONS] <origin> [destination]\n")
+        );
+    }
+
+    #[test]
+    fn it_describes_value_types_and_examples() {
+        use crate::cli_helper;
+
+        create_args_parser! {
+            @attr #[allow(dead_code)]
+            enum TestCommand {
+                /// Copy files
+                @examples("acsync copy a b --limit=10")
+                Copy {
+                    /// Origin directory
+                    origin: Req<String>,
+                    /// Maximum number of files
+                    limit: Option<usize>,
+                    /// Skip existing files
+                    skip: Option<bool>,
+                },
+                @default Help {},
+            }
+        }
+
+        let description = TestCommand::describe("Copy");
+        assert!(description.contains("\torigin             Origin directory [required]\n"));
+        assert!(description.contains("\t--limit <INTEGER>  Maximum number of files\n"));
+        assert!(description.contains("\t--skip             Skip existing files\n"));
+        assert!(description.ends_with("Examples:\n\tacsync copy a b --limit=10\n"));
+    }
+
+    #[test]
+    fn it_negates_flags_with_the_last_one_winning() {
+        use crate::cli_helper;
+
+        create_args_parser! {
+            @attr #[allow(dead_code)]
+            enum TestCommand {
+                Copy {
+                    skip: Option<bool>,
+                },
+                @default Help {},
+            }
+        }
+
+        let skip = |line: &str| {
+            let TestCommand::Copy { skip, .. } = TestCommand::parse_slice(&parse(line, 0)) else {
+                panic!("expected the copy command");
+            };
+            skip
+        };
+        assert_eq!(skip("copy"), None);
+        assert_eq!(skip("copy --skip"), Some(true));
+        assert_eq!(skip("copy --no_skip"), Some(false));
+        assert_eq!(skip("copy --skip --no_skip"), Some(false));
+        assert_eq!(skip("copy --no_skip --skip"), Some(true));
+    }
+
+    #[test]
+    fn it_collects_repeated_options() {
+        use crate::cli_helper;
+
+        create_args_parser! {
+            @attr #[allow(dead_code)]
+            enum TestCommand {
+                Copy {
+                    origin: Arg<String>,
+                    excludes: Vec<String>,
+                },
+                @default Help {},
+            }
+        }
+
+        let TestCommand::Copy {
+            origin, excludes, ..
+        } = TestCommand::parse_slice(&parse("copy --excludes=a foo --excludes b,c", 0))
+        else {
+            panic!("expected the copy command");
+        };
+        assert_eq!(origin, Some("foo".to_string()));
+        assert_eq!(excludes, vec!["a", "b,c"]);
+        assert!(TestCommand::describe("Copy").contains("--excludes <TEXT>...  "));
+    }
+
+    #[test]
+    fn it_returns_parse_errors() {
+        use crate::cli_helper;
+
+        create_args_parser! {
+            @attr #[allow(dead_code)]
+            enum TestCommand {
+                Copy {
+                    origin: Req<String>,
+                },
+                Clean {},
+                @default Help {},
+            }
+        }
+
+        let try_parse = |line: &str| TestCommand::try_parse_slice(&parse(line, 0));
+        assert!(matches!(
+            try_parse("copy foo"),
+            Ok(TestCommand::Copy { .. })
+        ));
+        assert_eq!(
+            try_parse("move foo").err(),
+            Some(ParseError::UnknownCommand("move".to_string()))
+        );
+        assert_eq!(
+            try_parse("c foo").err(),
+            Some(ParseError::AmbiguousCommand {
+                name: "c".to_string(),
+                candidates: vec!["copy".to_string(), "clean".to_string()],
+            })
+        );
+        assert_eq!(
+            try_parse("copy").err(),
+            Some(ParseError::MissingArgument {
+                name: "origin".to_string(),
+                usage: "Usage: acsync copy [OPTIONS] <origin>".to_string(),
+            })
+        );
+        assert_eq!(
+            try_parse("copy foo --bar").err(),
+            Some(ParseError::UnrecognizedArguments(vec!["--bar".to_string()]))
+        );
+        let Err(help) = try_parse("copy --help") else {
+            panic!("expected the help");
+        };
+        assert!(matches!(help, ParseError::Help(_)));
+        assert_eq!(help.exit_code(), 0);
+    }
+
+    #[test]
+    fn it_parses_sizes_with_suffixes() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("1.5K"), Some(1536));
+        assert_eq!(parse_size("2g"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("3MiB"), Some(3 * 1024 * 1024));
+    }
+
+    #[test]
+    fn it_parses_durations_with_suffixes() {
+        use std::time::Duration;
+        assert_eq!(parse_duration("45"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("15m"), Some(Duration::from_secs(900)));
+        assert_eq!(parse_duration("2w"), Some(Duration::from_secs(14 * 86400)));
+        assert_eq!(parse_duration("-1s"), None);
+    }
+
+    #[test]
+    fn it_parses_dates_as_utc_times() {
+        use std::time::{Duration, UNIX_EPOCH};
+        assert_eq!(parse_time("1970-01-01"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_time("2000-02-29T12:30:15"),
+            Some(UNIX_EPOCH + Duration::from_secs(951827415))
+        );
+        assert_eq!(
+            parse_time("1969-12-31"),
+            UNIX_EPOCH.checked_sub(Duration::from_secs(86400))
+        );
+        assert_eq!(parse_time("2001-02-29"), None);
+        assert_eq!(parse_time("2024-13-01"), None);
+    }
+
+    #[test]
+    fn it_formats_times_as_parsed() {
+        for date in [
+            "1970-01-01T00:00:00",
+            "2000-02-29T12:30:15",
+            "1969-12-31T23:59:59",
+        ] {
+            assert_eq!(
+                format_time(parse_time(date).unwrap()),
+                format!("{date}.000Z")
+            );
+        }
+    }
+
+    #[test]
+    fn it_does_not_parse_invalid_sizes() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("-1M"), None);
+    }
 }
@@ -0,0 +1,364 @@
+//! **config** contains the support to named sync profiles stored in a TOML config file,
+//! which are merged with the command-line arguments.
+//!
+//! Only the subset of TOML needed by profiles is understood: `[profiles.<name>]` tables
+//! with string, integer, boolean and string array values.
+
+use crate::{
+    cli_helper,
+    error::{IoResultExt, SyncError},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Value of a profile key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// Returns the value as written in a command-line option.
+    fn to_arg(&self) -> String {
+        match self {
+            Value::String(value) => value.clone(),
+            Value::Integer(value) => value.to_string(),
+            Value::Boolean(value) => value.to_string(),
+            Value::Array(values) => values
+                .iter()
+                .map(Value::to_arg)
+                .collect::<Vec<_>>()
+                .join(","),
+        }
+    }
+}
+
+/// Named set of arguments, as defined by a `[profiles.<name>]` table.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    values: BTreeMap<String, Value>,
+}
+
+impl Profile {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    pub fn insert<K: Into<String>>(&mut self, key: K, value: Value) -> Option<Value> {
+        self.values.insert(key.into(), value)
+    }
+
+    /// Returns `args` completed with the profile values.
+    ///
+    /// The `origin` and `destination` keys are inserted as the positional arguments
+    /// following the command name when `args` has none. Every other key becomes a
+    /// `--key=value` option, unless `args` already has it or its `--no_key` negation, so
+    /// the command line always overrides the profile. Boolean keys become flags, omitted when `false`. The
+    /// `value_options` are the options of `args` that may be given as `--name value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::config::Config;
+    /// #
+    /// let config: Config = "[profiles.work]\norigin = \"/home/user/work\"\n\
+    ///                       destination = \"/media/backup/work\"\ndryrun = true\n\
+    ///                       bwlimit = \"10M\""
+    ///     .parse()
+    ///     .unwrap();
+    /// let args: Vec<String> = vec!["replicate".into(), "--bwlimit".into(), "1M".into()];
+    ///
+    /// assert_eq!(
+    ///     config.profile("work").unwrap().merge_args(&args, &["bwlimit"]),
+    ///     vec![
+    ///         "replicate",
+    ///         "/home/user/work",
+    ///         "/media/backup/work",
+    ///         "--bwlimit",
+    ///         "1M",
+    ///         "--dryrun"
+    ///     ]
+    /// );
+    /// ```
+    pub fn merge_args(&self, args: &[String], value_options: &[&str]) -> Vec<String> {
+        let mut options = vec![];
+        for (key, value) in &self.values {
+            if key == "origin"
+                || key == "destination"
+                || cli_helper::has_option(key, args).0
+                || cli_helper::has_option(&format!("no_{key}"), args).0
+            {
+                continue;
+            }
+            match value {
+                Value::Boolean(true) => options.push(format!("--{key}")),
+                Value::Boolean(false) => {}
+                value => options.push(format!("--{key}={}", value.to_arg())),
+            }
+        }
+        let mut merged_args = args.to_vec();
+        let options_end = cli_helper::options_end(args);
+        merged_args.splice(options_end..options_end, options);
+        let argument_indexes = cli_helper::argument_indexes(args, value_options);
+        if argument_indexes.len() < 2 {
+            let positionals = ["origin", "destination"]
+                .iter()
+                .filter_map(|key| self.values.get(*key))
+                .map(Value::to_arg);
+            let command_index = argument_indexes.first().map_or(0, |index| index + 1);
+            merged_args.splice(command_index..command_index, positionals);
+        }
+        merged_args
+    }
+}
+
+/// Content of a config file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Returns the default config file path, `$XDG_CONFIG_HOME/acsync/config.toml` or
+    /// `~/.config/acsync/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|config_home| config_home.join("acsync").join("config.toml"))
+    }
+
+    /// Loads the config file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SyncError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).with_path(path)?;
+        content
+            .parse()
+            .map_err(|(line, reason)| SyncError::InvalidConfig {
+                path: path.to_path_buf(),
+                line,
+                reason,
+            })
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+}
+
+impl std::str::FromStr for Config {
+    /// Line number and reason of the parsing failure.
+    type Err = (usize, String);
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let mut config = Config::default();
+        let mut current_profile: Option<&mut Profile> = None;
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(table) = line.strip_prefix('[') {
+                let name = table
+                    .strip_suffix(']')
+                    .and_then(|table| table.trim().strip_prefix("profiles."))
+                    .map(|name| name.trim().trim_matches('"'))
+                    .filter(|name| !name.is_empty())
+                    .ok_or((line_number, format!("unexpected table {line}")))?;
+                current_profile = Some(config.profiles.entry(name.to_string()).or_default());
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or((line_number, format!("expected key = value, found {line}")))?;
+            let profile = current_profile.as_deref_mut().ok_or((
+                line_number,
+                "key outside of a [profiles.<name>] table".into(),
+            ))?;
+            let value = parse_value(value.trim()).map_err(|reason| (line_number, reason))?;
+            profile
+                .values
+                .insert(key.trim().trim_matches('"').to_string(), value);
+        }
+        Ok(config)
+    }
+}
+
+/// Returns the line without its trailing `#` comment, ignoring `#` inside strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, char) in line.char_indices() {
+        match (char, quote) {
+            ('\\', Some('"')) if !escaped => {
+                escaped = true;
+                continue;
+            }
+            ('"' | '\'', None) => quote = Some(char),
+            (char, Some(quoted)) if char == quoted && !escaped => quote = None,
+            ('#', None) => return &line[..index],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(items) = value.strip_prefix('[') {
+        let items = items
+            .strip_suffix(']')
+            .ok_or(format!("unterminated array {value}"))?;
+        return split_items(items)
+            .into_iter()
+            .filter(|item| !item.trim().is_empty())
+            .map(|item| parse_value(item.trim()))
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+    if let Some(string) = value.strip_prefix('"') {
+        let string = string
+            .strip_suffix('"')
+            .ok_or(format!("unterminated string {value}"))?;
+        return unescape(string).map(Value::String);
+    }
+    if let Some(string) = value.strip_prefix('\'') {
+        return string
+            .strip_suffix('\'')
+            .map(|string| Value::String(string.to_string()))
+            .ok_or(format!("unterminated string {value}"));
+    }
+    match value {
+        "true" => Ok(Value::Boolean(true)),
+        "false" => Ok(Value::Boolean(false)),
+        _ => value
+            .replace('_', "")
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| format!("unsupported value {value}")),
+    }
+}
+
+/// Splits the items of an array on the commas outside of strings.
+fn split_items(items: &str) -> Vec<&str> {
+    let mut split_items = vec![];
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, char) in items.char_indices() {
+        match (char, quote) {
+            ('\\', Some('"')) if !escaped => {
+                escaped = true;
+                continue;
+            }
+            ('"' | '\'', None) => quote = Some(char),
+            (char, Some(quoted)) if char == quoted && !escaped => quote = None,
+            (',', None) => {
+                split_items.push(&items[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    split_items.push(&items[start..]);
+    split_items
+}
+
+fn unescape(value: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            Some('"') => unescaped.push('"'),
+            escape => return Err(format!("unsupported escape \\{}", escape.unwrap_or(' '))),
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_profiles() {
+        let config: Config = r#"
+            # Backups of the work documents
+            [profiles.work]
+            origin = "/home/user/work"   # trailing comment
+            destination = '/media/backup/#work'
+            excludes = ["target", ".tmp", "a,b"]
+            bwlimit = 10_000
+            hidden = true
+
+            [profiles."photos"]
+            origin = "/home/user/Pictures"
+        "#
+        .parse()
+        .unwrap();
+        let work = config.profile("work").unwrap();
+        assert_eq!(
+            work.get("destination"),
+            Some(&Value::String("/media/backup/#work".into()))
+        );
+        assert_eq!(
+            work.get("excludes"),
+            Some(&Value::Array(vec![
+                Value::String("target".into()),
+                Value::String(".tmp".into()),
+                Value::String("a,b".into()),
+            ]))
+        );
+        assert_eq!(work.get("bwlimit"), Some(&Value::Integer(10000)));
+        assert_eq!(work.get("hidden"), Some(&Value::Boolean(true)));
+        assert!(config.profile("photos").is_some());
+        assert!(config.profile("music").is_none());
+    }
+
+    #[test]
+    fn it_reports_the_line_of_invalid_content() {
+        assert_eq!(
+            "[profiles.work]\norigin = /home".parse::<Config>(),
+            Err((2, "unsupported value /home".to_string()))
+        );
+        assert!("origin = \"/home\"".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn it_keeps_command_line_arguments_over_profile_values() {
+        let config: Config = "[profiles.work]\norigin = \"a\"\ndestination = \"b\"\nhidden = false"
+            .parse()
+            .unwrap();
+        let args: Vec<String> = vec!["replicate".into(), "c".into(), "d".into()];
+        assert_eq!(
+            config.profile("work").unwrap().merge_args(&args, &[]),
+            vec!["replicate", "c", "d"]
+        );
+
+        let config: Config = "[profiles.work]\ndryrun = true".parse().unwrap();
+        let args: Vec<String> = vec![
+            "replicate".into(),
+            "c".into(),
+            "d".into(),
+            "--no_dryrun".into(),
+        ];
+        assert_eq!(
+            config.profile("work").unwrap().merge_args(&args, &[]),
+            vec!["replicate", "c", "d", "--no_dryrun"]
+        );
+    }
+}
@@ -0,0 +1,759 @@
+//! **copy** contains the file copy routines used by the replicate command.
+
+use crate::{
+    cancel::CancellationToken,
+    error::{IoResultExt, SyncError},
+    hash::Sha256,
+};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    cell::RefCell,
+    fmt,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{
+    ffi::{CString, c_char, c_int, c_ulong},
+    os::{
+        fd::AsRawFd,
+        unix::{ffi::OsStrExt, fs::MetadataExt},
+    },
+};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Size of the blocks compared by [`patch_file`].
+pub const DELTA_BLOCK_SIZE: usize = 128 * 1024;
+
+/// Size from which an overridden file is patched with [`patch_file`] rather than
+/// copied again, smaller files being cheaper to copy whole.
+pub const DELTA_MIN_SIZE: u64 = 1024 * 1024;
+
+#[cfg(unix)]
+const SEEK_DATA: c_int = 3;
+#[cfg(unix)]
+const SEEK_HOLE: c_int = 4;
+#[cfg(unix)]
+const EINVAL: i32 = 22;
+#[cfg(unix)]
+const ENXIO: i32 = 6;
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn mknod(path: *const c_char, mode: u32, dev: u64) -> c_int;
+    fn lseek(fd: c_int, offset: i64, whence: c_int) -> i64;
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+    fn copy_file_range(
+        fd_in: c_int,
+        off_in: *mut i64,
+        fd_out: c_int,
+        off_out: *mut i64,
+        len: usize,
+        flags: u32,
+    ) -> isize;
+}
+
+/// Whether `copy_file_range` is used, cleared on kernels older than 4.5 lacking it.
+#[cfg(target_os = "linux")]
+static KERNEL_COPY: AtomicBool = AtomicBool::new(true);
+
+/// Largest length of a single `copy_file_range` call, the limited copies using
+/// [`BUFFER_SIZE`] instead.
+#[cfg(target_os = "linux")]
+const KERNEL_COPY_SIZE: u64 = 1 << 30;
+
+/// Largest length of a single `copy_file_range` call under a [`CancellationToken`],
+/// for the cancellation to be noticed within a fraction of a second.
+#[cfg(target_os = "linux")]
+const CANCELLABLE_COPY_SIZE: u64 = 64 << 20;
+
+thread_local! {
+    /// Token cancelling the copies of the current thread, see [`with_cancellation`].
+    static CANCELLATION: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// `FICLONE` ioctl request, sharing the extents of a file with another one.
+#[cfg(unix)]
+const FICLONE: c_ulong = 0x40049409;
+
+/// Whether files are cloned, sharing their extents on copy-on-write filesystems such as
+/// Btrfs or XFS, instead of copied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Reflink {
+    /// Clone files when the filesystem supports it, copying them otherwise.
+    #[default]
+    Auto,
+    /// Always clone files, failing when the filesystem doesn't support it.
+    Always,
+    /// Never clone files.
+    Never,
+}
+
+impl FromStr for Reflink {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "auto" => Ok(Reflink::Auto),
+            "always" => Ok(Reflink::Always),
+            "never" => Ok(Reflink::Never),
+            _ => Err(format!(
+                "unknown reflink mode {value:?}, expected one of auto, always or never"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Reflink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reflink::Auto => write!(f, "auto"),
+            Reflink::Always => write!(f, "always"),
+            Reflink::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// Token bucket used to throttle the byte throughput of file copies.
+///
+/// The bucket is refilled with `bytes_per_second` tokens every second and holds
+/// at most one second worth of tokens, so bursts never exceed the configured rate.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        RateLimiter {
+            bytes_per_second: bytes_per_second.max(1),
+            tokens: bytes_per_second.max(1) as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes `bytes` tokens from the bucket, sleeping until the bucket is refilled
+    /// when there are not enough tokens available.
+    pub fn acquire(&mut self, bytes: u64) {
+        self.refill();
+        self.tokens -= bytes as f64;
+        if self.tokens < 0.0 {
+            std::thread::sleep(Duration::from_secs_f64(
+                -self.tokens / self.bytes_per_second as f64,
+            ));
+            self.refill();
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.bytes_per_second as f64)
+            .min(self.bytes_per_second as f64);
+    }
+}
+
+/// Whether file data is copied in the kernel with `copy_file_range` on Linux, enabled
+/// by default. Disabling it copies through userspace buffers instead, e.g. to compare
+/// both with `acsync bench`.
+pub fn set_kernel_copy(flag: bool) {
+    #[cfg(target_os = "linux")]
+    KERNEL_COPY.store(flag, Ordering::Relaxed);
+    #[cfg(not(target_os = "linux"))]
+    let _ = flag;
+}
+
+/// Runs `copy` with the copies of the current thread checking `token` between chunks,
+/// failing with [`SyncError::Interrupted`] once it's cancelled rather than going
+/// through the rest of a large file.
+///
+/// Applies to [`copy_file`], [`copy_file_hashed`], [`copy_file_sparse`] and
+/// [`patch_file`], not to the clones of [`reflink_file`] done in a single call.
+pub fn with_cancellation<T>(token: Option<CancellationToken>, copy: impl FnOnce() -> T) -> T {
+    let previous = CANCELLATION.replace(token);
+    let result = copy();
+    CANCELLATION.set(previous);
+    result
+}
+
+/// Whether the token of [`with_cancellation`] was cancelled.
+fn cancelled() -> bool {
+    CANCELLATION.with_borrow(|token| token.as_ref().is_some_and(CancellationToken::is_cancelled))
+}
+
+/// Opens the `source` file of a copy, failing if the token of [`with_cancellation`] was
+/// cancelled while the open hung, before the target gets truncated.
+pub(crate) fn open_source(source: &Path) -> Result<File, SyncError> {
+    let file = File::open(source).with_path(source)?;
+    if cancelled() {
+        return Err(SyncError::Interrupted);
+    }
+    Ok(file)
+}
+
+/// Returns the temporary path a file is written to before being renamed to `path`.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::copy;
+/// # use std::path::PathBuf;
+/// #
+/// assert_eq!(
+///     copy::temporary_path("docs/notes.txt"),
+///     PathBuf::from("docs/notes.txt.acsync_tmp")
+/// );
+/// ```
+pub fn temporary_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut file_name = path.as_ref().as_os_str().to_os_string();
+    file_name.push(".acsync_tmp");
+    PathBuf::from(file_name)
+}
+
+/// Moves `source` to `target`, falling back to a copy followed by a removal when they
+/// are on different filesystems.
+pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> Result<(), SyncError> {
+    let (source, target) = (source.as_ref(), target.as_ref());
+    match std::fs::rename(source, target) {
+        Err(error) if error.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_file(source, target, None)?;
+            std::fs::remove_file(source).with_path(source)
+        }
+        result => result.with_path(source),
+    }
+}
+
+/// Copies the contents and permissions of `source` into `target`, returning the
+/// number of bytes copied.
+///
+/// When a [`RateLimiter`] is given the copy is done chunk by chunk, waiting on the
+/// limiter before each write.
+pub fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+    limiter: Option<&mut RateLimiter>,
+) -> Result<u64, SyncError> {
+    copy_file_with(source.as_ref(), target.as_ref(), limiter, None)
+}
+
+/// Copies `source` into `target` as [`copy_file`] does, but always through userspace
+/// buffers, returning along with the number of bytes copied the SHA-256 digest of the
+/// data read from `source`, as [`crate::hash::hash_file`] would return it.
+pub fn copy_file_hashed<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+    limiter: Option<&mut RateLimiter>,
+) -> Result<(u64, String), SyncError> {
+    let mut hasher = Sha256::new();
+    let copied = copy_file_with(source.as_ref(), target.as_ref(), limiter, Some(&mut hasher))?;
+    Ok((copied, hasher.finalize_hex()))
+}
+
+fn copy_file_with(
+    source: &Path,
+    target: &Path,
+    mut limiter: Option<&mut RateLimiter>,
+    hasher: Option<&mut Sha256>,
+) -> Result<u64, SyncError> {
+    let mut source_file = open_source(source)?;
+    let permissions = source_file.metadata().with_path(source)?.permissions();
+    let mut target_file = File::create(target).with_path(target)?;
+
+    let copied = copy_data(
+        (&mut source_file, source),
+        (&mut target_file, target),
+        u64::MAX,
+        &mut limiter,
+        hasher,
+    )?;
+    target_file.set_permissions(permissions).with_path(target)?;
+
+    Ok(copied)
+}
+
+/// Clones `source` into `target`, sharing their extents so no data is copied, and
+/// copies the permissions of `source`.
+///
+/// Fails when the files are on different filesystems or the filesystem doesn't support
+/// cloning.
+pub fn reflink_file<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> Result<(), SyncError> {
+    let (source, target) = (source.as_ref(), target.as_ref());
+    let source_file = open_source(source)?;
+    let permissions = source_file.metadata().with_path(source)?.permissions();
+    let target_file = File::create(target).with_path(target)?;
+    clone_file(&source_file, &target_file).with_path(target)?;
+    target_file.set_permissions(permissions).with_path(target)
+}
+
+/// Shares the extents of `source_file` with `target_file`.
+#[cfg(unix)]
+fn clone_file(source_file: &File, target_file: &File) -> std::io::Result<()> {
+    // SAFETY: both file descriptors stay open while their files are alive.
+    if unsafe { ioctl(target_file.as_raw_fd(), FICLONE, source_file.as_raw_fd()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Fails, Windows having no file cloning reachable without a dependency.
+#[cfg(not(unix))]
+fn clone_file(_source_file: &File, _target_file: &File) -> std::io::Result<()> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+/// Copies `source` into `target` like [`copy_file`], but only the data regions of
+/// `source` are written, so its holes stay holes in `target`.
+///
+/// Falls back to [`copy_file`] on filesystems unable to report the holes of a file.
+#[cfg(unix)]
+pub fn copy_file_sparse<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+    mut limiter: Option<&mut RateLimiter>,
+) -> Result<u64, SyncError> {
+    let (source, target) = (source.as_ref(), target.as_ref());
+    let mut source_file = open_source(source)?;
+    let metadata = source_file.metadata().with_path(source)?;
+    let mut target_file = File::create(target).with_path(target)?;
+
+    let mut copied = 0;
+    let mut offset = 0;
+    while offset < metadata.len() {
+        // SAFETY: the file descriptor stays open while `source_file` is alive.
+        let data_offset = unsafe { lseek(source_file.as_raw_fd(), offset as i64, SEEK_DATA) };
+        if data_offset < 0 {
+            let error = std::io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(ENXIO) => break,
+                Some(EINVAL) if offset == 0 => {
+                    drop((source_file, target_file));
+                    return copy_file(source, target, limiter);
+                }
+                _ => return Err(error).with_path(source),
+            }
+        }
+        // SAFETY: as above.
+        let hole_offset = unsafe { lseek(source_file.as_raw_fd(), data_offset, SEEK_HOLE) };
+        if hole_offset < 0 {
+            return Err(std::io::Error::last_os_error()).with_path(source);
+        }
+        let (data_offset, hole_offset) = (data_offset as u64, hole_offset as u64);
+
+        source_file
+            .seek(SeekFrom::Start(data_offset))
+            .with_path(source)?;
+        target_file
+            .seek(SeekFrom::Start(data_offset))
+            .with_path(target)?;
+        copied += copy_data(
+            (&mut source_file, source),
+            (&mut target_file, target),
+            hole_offset - data_offset,
+            &mut limiter,
+            None,
+        )?;
+        offset = hole_offset;
+    }
+    target_file.set_len(metadata.len()).with_path(target)?;
+    target_file
+        .set_permissions(metadata.permissions())
+        .with_path(target)?;
+
+    Ok(copied)
+}
+
+/// Copies `source` into `target` with [`copy_file`], the holes of a file not being
+/// reported on Windows.
+#[cfg(not(unix))]
+pub fn copy_file_sparse<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+    limiter: Option<&mut RateLimiter>,
+) -> Result<u64, SyncError> {
+    copy_file(source, target, limiter)
+}
+
+/// Updates `target` to the contents and permissions of `source` by comparing them
+/// block by block and only writing the blocks that differ, returning the number of
+/// bytes written.
+///
+/// Suits large files where only a small region changed, such as VM images or
+/// mailboxes, sparing the writes of the unchanged blocks at the cost of reading both
+/// files.
+pub fn patch_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+    mut limiter: Option<&mut RateLimiter>,
+) -> Result<u64, SyncError> {
+    let (source, target) = (source.as_ref(), target.as_ref());
+    let mut source_file = open_source(source)?;
+    let metadata = source_file.metadata().with_path(source)?;
+    let mut target_file = File::options()
+        .read(true)
+        .write(true)
+        .open(target)
+        .with_path(target)?;
+
+    let mut source_block = vec![0; DELTA_BLOCK_SIZE];
+    let mut target_block = vec![0; DELTA_BLOCK_SIZE];
+    let mut written = 0;
+    let mut offset = 0;
+    loop {
+        let read = read_block(&mut source_file, &mut source_block).with_path(source)?;
+        // Checked after the read, which may have hung past the cancellation.
+        if cancelled() {
+            return Err(SyncError::Interrupted);
+        }
+        if read == 0 {
+            break;
+        }
+        let target_read = read_block(&mut target_file, &mut target_block).with_path(target)?;
+        if source_block[..read] != target_block[..target_read] {
+            if let Some(limiter) = &mut limiter {
+                limiter.acquire(read as u64);
+            }
+            target_file
+                .seek(SeekFrom::Start(offset))
+                .with_path(target)?;
+            target_file
+                .write_all(&source_block[..read])
+                .with_path(target)?;
+            written += read as u64;
+        }
+        offset += read as u64;
+    }
+    target_file.set_len(metadata.len()).with_path(target)?;
+    target_file
+        .set_permissions(metadata.permissions())
+        .with_path(target)?;
+
+    Ok(written)
+}
+
+/// Reads from `file` until `buffer` is full or the end of the file, returning the
+/// number of bytes read.
+fn read_block(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match file.read(&mut buffer[read..])? {
+            0 => break,
+            count => read += count,
+        }
+    }
+    Ok(read)
+}
+
+/// Copies up to `length` bytes from the current position of the source file to the
+/// current position of the target file.
+///
+/// On Linux the data is copied in the kernel with `copy_file_range`, without going
+/// through userspace, falling back to reads and writes where it isn't supported. Data
+/// hashed along the way with `hasher` is always read and written in userspace.
+fn copy_data(
+    (source_file, source): (&mut File, &Path),
+    (target_file, target): (&mut File, &Path),
+    length: u64,
+    limiter: &mut Option<&mut RateLimiter>,
+    mut hasher: Option<&mut Sha256>,
+) -> Result<u64, SyncError> {
+    #[cfg(target_os = "linux")]
+    let (copied, length) = if hasher.is_some() {
+        (0, length)
+    } else {
+        let (copied, complete) =
+            kernel_copy(source_file, target_file, length, limiter).with_path(target)?;
+        if complete {
+            return Ok(copied);
+        }
+        (copied, length - copied)
+    };
+    #[cfg(not(target_os = "linux"))]
+    let copied = 0;
+
+    let mut source_reader = source_file.take(length);
+    // Copies through a buffer rather than with `std::io::copy`, which would use
+    // `copy_file_range` again.
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut user_copied = 0;
+    loop {
+        let read = source_reader.read(&mut buffer).with_path(source)?;
+        // Checked after the read, which may have hung past the cancellation.
+        if cancelled() {
+            return Err(SyncError::Interrupted);
+        }
+        if read == 0 {
+            break;
+        }
+        if let Some(limiter) = limiter {
+            limiter.acquire(read as u64);
+        }
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buffer[..read]);
+        }
+        target_file.write_all(&buffer[..read]).with_path(target)?;
+        user_copied += read as u64;
+    }
+    Ok(copied + user_copied)
+}
+
+/// Copies up to `length` bytes between the current positions of the files with
+/// `copy_file_range`, returning the number of bytes copied and whether the copy is
+/// complete, i.e. the `length` bytes or up to the end of the source file were copied.
+///
+/// The copy is incomplete, for the rest to be copied in userspace, when the kernel
+/// can't copy between the files, e.g. across filesystems on kernels older than 5.3 or
+/// from pseudo filesystems such as `/proc`, or when it's cancelled, the userspace copy
+/// then failing right away.
+#[cfg(target_os = "linux")]
+fn kernel_copy(
+    source_file: &File,
+    target_file: &File,
+    length: u64,
+    limiter: &mut Option<&mut RateLimiter>,
+) -> std::io::Result<(u64, bool)> {
+    const EPERM: i32 = 1;
+    const EXDEV: i32 = 18;
+    const ENOSYS: i32 = 38;
+    const EOPNOTSUPP: i32 = 95;
+
+    if !KERNEL_COPY.load(Ordering::Relaxed) {
+        return Ok((0, false));
+    }
+    let cancellable = CANCELLATION.with_borrow(Option::is_some);
+    let mut copied = 0;
+    while copied < length {
+        if cancelled() {
+            return Ok((copied, false));
+        }
+        let chunk_size = match limiter {
+            Some(_) => BUFFER_SIZE as u64,
+            None if cancellable => CANCELLABLE_COPY_SIZE,
+            None => KERNEL_COPY_SIZE,
+        }
+        .min(length - copied);
+        if let Some(limiter) = limiter {
+            limiter.acquire(chunk_size);
+        }
+        // SAFETY: both file descriptors stay open while their files are alive, and null
+        // offsets make the call use and update the positions of the files.
+        let result = unsafe {
+            copy_file_range(
+                source_file.as_raw_fd(),
+                std::ptr::null_mut(),
+                target_file.as_raw_fd(),
+                std::ptr::null_mut(),
+                chunk_size as usize,
+                0,
+            )
+        };
+        match result {
+            // Pseudo files report no data to the kernel copy despite having some.
+            0 if copied == 0 => return Ok((0, false)),
+            0 => return Ok((copied, true)),
+            count if count > 0 => copied += count as u64,
+            _ => {
+                let error = std::io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(ENOSYS) => {
+                        KERNEL_COPY.store(false, Ordering::Relaxed);
+                        return Ok((copied, false));
+                    }
+                    Some(EPERM | EXDEV | EINVAL | EOPNOTSUPP) => return Ok((copied, false)),
+                    _ if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                    _ => return Err(error),
+                }
+            }
+        }
+    }
+    Ok((copied, true))
+}
+
+/// Creates at `target` a special file of the same type, permissions and device
+/// number as `source`, such as a FIFO, a socket or a device node.
+///
+/// Creating device nodes usually requires root privileges.
+#[cfg(unix)]
+pub fn create_special<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+) -> Result<(), SyncError> {
+    let (source, target) = (source.as_ref(), target.as_ref());
+    let metadata = source.metadata().with_path(source)?;
+    let target_path = CString::new(target.as_os_str().as_bytes())
+        .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))
+        .with_path(target)?;
+    // SAFETY: `target_path` is a valid NUL-terminated string for the whole call.
+    if unsafe { mknod(target_path.as_ptr(), metadata.mode(), metadata.rdev()) } != 0 {
+        return Err(std::io::Error::last_os_error()).with_path(target);
+    }
+    std::fs::set_permissions(target, metadata.permissions()).with_path(target)
+}
+
+/// Fails, special files not existing on Windows.
+#[cfg(not(unix))]
+pub fn create_special<P: AsRef<Path>, Q: AsRef<Path>>(
+    _source: P,
+    target: Q,
+) -> Result<(), SyncError> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported)).with_path(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    #[cfg(unix)]
+    fn it_recreates_fifos() {
+        use std::os::unix::fs::FileTypeExt;
+        let directory = testing::test_dir("it_recreates_fifos");
+        let status = std::process::Command::new("mkfifo")
+            .arg(directory.join("source"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        create_special(directory.join("source"), directory.join("target")).unwrap();
+        let metadata = directory.join("target").metadata().unwrap();
+        assert!(metadata.file_type().is_fifo());
+        assert_eq!(
+            metadata.mode(),
+            directory.join("source").metadata().unwrap().mode()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_preserves_holes_of_sparse_files() {
+        let directory = testing::test_dir("it_preserves_holes_of_sparse_files");
+        let mut source_file = File::create(directory.join("source")).unwrap();
+        source_file.set_len(16 * 1024 * 1024).unwrap();
+        source_file.seek(SeekFrom::Start(8 * 1024 * 1024)).unwrap();
+        source_file.write_all(b"data").unwrap();
+        drop(source_file);
+
+        copy_file_sparse(directory.join("source"), directory.join("target"), None).unwrap();
+        let target_metadata = directory.join("target").metadata().unwrap();
+        assert_eq!(target_metadata.len(), 16 * 1024 * 1024);
+        assert!(target_metadata.blocks() * 512 < 1024 * 1024);
+        assert_eq!(
+            std::fs::read(directory.join("target")).unwrap(),
+            std::fs::read(directory.join("source")).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_falls_back_to_userspace_copies_of_pseudo_files() {
+        let directory = testing::test_dir("it_falls_back_to_userspace_copies_of_pseudo_files");
+        let data: Vec<u8> = (0..3 * BUFFER_SIZE + 7).map(|i| i as u8).collect();
+        std::fs::write(directory.join("source"), &data).unwrap();
+
+        let mut limiter = RateLimiter::new(u64::MAX);
+        let copied = copy_file(
+            directory.join("source"),
+            directory.join("target"),
+            Some(&mut limiter),
+        )
+        .unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(std::fs::read(directory.join("target")).unwrap(), data);
+
+        // Reports a size of 0 and no data to `copy_file_range`.
+        let copied = copy_file("/proc/self/status", directory.join("status"), None).unwrap();
+        assert!(copied > 0);
+        assert!(
+            std::fs::read_to_string(directory.join("status"))
+                .unwrap()
+                .contains("Pid:")
+        );
+    }
+
+    #[test]
+    fn it_hashes_the_data_copied() {
+        let directory = testing::test_dir("it_hashes_the_data_copied");
+        let data: Vec<u8> = (0..2 * BUFFER_SIZE + 3).map(|i| (i % 251) as u8).collect();
+        std::fs::write(directory.join("source"), &data).unwrap();
+
+        let (copied, hash) =
+            copy_file_hashed(directory.join("source"), directory.join("target"), None).unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(std::fs::read(directory.join("target")).unwrap(), data);
+        assert_eq!(
+            hash,
+            crate::hash::hash_file(directory.join("source")).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_stops_cancelled_copies() {
+        let directory = testing::test_dir("it_stops_cancelled_copies");
+        std::fs::write(directory.join("source"), vec![7; 2 * BUFFER_SIZE]).unwrap();
+        let (source, target) = (directory.join("source"), directory.join("target"));
+
+        let token = CancellationToken::new();
+        let copied = with_cancellation(Some(token.clone()), || copy_file(&source, &target, None));
+        assert_eq!(copied.unwrap(), 2 * BUFFER_SIZE as u64);
+        token.cancel();
+        let copied = with_cancellation(Some(token), || copy_file(&source, &target, None));
+        assert!(matches!(copied, Err(SyncError::Interrupted)));
+        assert!(copy_file(&source, &target, None).is_ok());
+    }
+
+    #[test]
+    fn it_only_writes_the_changed_blocks() {
+        let directory = testing::test_dir("it_only_writes_the_changed_blocks");
+        let mut content = vec![7; 4 * DELTA_BLOCK_SIZE];
+        std::fs::write(directory.join("target"), &content).unwrap();
+        content[DELTA_BLOCK_SIZE + 10] = 8;
+        content.extend_from_slice(b"tail");
+        std::fs::write(directory.join("source"), &content).unwrap();
+
+        let written = patch_file(directory.join("source"), directory.join("target"), None).unwrap();
+        assert_eq!(written, DELTA_BLOCK_SIZE as u64 + 4);
+        assert_eq!(std::fs::read(directory.join("target")).unwrap(), content);
+
+        content.truncate(DELTA_BLOCK_SIZE);
+        std::fs::write(directory.join("source"), &content).unwrap();
+        let written = patch_file(directory.join("source"), directory.join("target"), None).unwrap();
+        assert_eq!(written, 0);
+        assert_eq!(std::fs::read(directory.join("target")).unwrap(), content);
+    }
+
+    #[test]
+    fn it_parses_reflink_modes_as_displayed() {
+        for reflink in [Reflink::Auto, Reflink::Always, Reflink::Never] {
+            assert_eq!(reflink.to_string().parse(), Ok(reflink));
+        }
+        assert!("sometimes".parse::<Reflink>().is_err());
+    }
+
+    #[test]
+    fn it_does_not_wait_within_the_bucket_capacity() {
+        let mut limiter = RateLimiter::new(1024 * 1024);
+        let now = Instant::now();
+        limiter.acquire(512 * 1024);
+        assert!(now.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn it_waits_when_the_bucket_is_empty() {
+        let mut limiter = RateLimiter::new(1000);
+        let now = Instant::now();
+        limiter.acquire(1000);
+        limiter.acquire(200);
+        assert!(now.elapsed() >= Duration::from_millis(150));
+    }
+}
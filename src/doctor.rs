@@ -0,0 +1,463 @@
+//! **doctor** contains the diagnostics of the environment of a destination, probing
+//! the capabilities of its file system and its free space so that problems show up
+//! before a big run rather than during it.
+
+use crate::{
+    error::{IoResultExt, SyncError},
+    platform,
+    sync::Replicator,
+};
+use std::{
+    fmt,
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Name of the directory the probe files are written to, removed once done.
+pub const DOCTOR_DIR_NAME: &str = ".acsync_doctor";
+
+/// Outcome of a [`Check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// The check found something to act on before a run.
+    Warning,
+    /// The check can't be made on this platform or destination.
+    Skipped,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warning => "warn",
+            CheckStatus::Skipped => "skip",
+        })
+    }
+}
+
+/// Result of one diagnostic of a [`Doctor`] run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    /// What was found, followed by what to do about it for warnings.
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Result of a [`Doctor`] run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    pub fn warning_count(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Warning)
+            .count()
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", " Doctor ")?;
+        for check in &self.checks {
+            writeln!(
+                f,
+                "[{:<4}] {}: {}",
+                check.status.to_string(),
+                check.name,
+                check.detail
+            )?;
+        }
+        writeln!(f, "Warnings: {}", self.warning_count())?;
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+/// Diagnoses the environment of a destination directory: whether it is writable, the
+/// type of its file system, whether it keeps symbolic links, extended attributes and
+/// the holes of sparse files, the granularity of its modification dates and its free
+/// space, compared with the size a run from an origin would copy.
+///
+/// A destination that doesn't exist yet is diagnosed through its nearest existing
+/// ancestor.
+#[derive(Debug)]
+pub struct Doctor {
+    destination: PathBuf,
+    origin: Option<PathBuf>,
+}
+
+impl Doctor {
+    pub fn new<P: AsRef<Path>>(destination: P) -> Self {
+        Doctor {
+            destination: destination.as_ref().to_path_buf(),
+            origin: None,
+        }
+    }
+
+    /// Estimates the size a replicate run from `origin` would copy, to compare it with
+    /// the free space of the destination.
+    pub fn origin<P: AsRef<Path>>(mut self, origin: Option<P>) -> Self {
+        self.origin = origin.map(|origin| origin.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn run(&self) -> Result<DoctorReport, SyncError> {
+        let mut report = DoctorReport::default();
+        let directory = self
+            .destination
+            .ancestors()
+            .find(|ancestor| ancestor.is_dir())
+            .unwrap_or(Path::new("."));
+        let probe = directory.join(DOCTOR_DIR_NAME);
+        let writable = std::fs::create_dir(&probe).and_then(|_| {
+            std::fs::write(probe.join("file"), "acsync")?;
+            Ok(())
+        });
+        match writable {
+            Ok(()) => report.checks.push(Check::new(
+                "Destination",
+                CheckStatus::Ok,
+                format!("{} is writable", directory.display()),
+            )),
+            Err(error) => {
+                let _ = std::fs::remove_dir_all(&probe);
+                report.checks.push(Check::new(
+                    "Destination",
+                    CheckStatus::Warning,
+                    format!(
+                        "can't write into {}: {error}, fix its permissions or mount it read-write",
+                        directory.display()
+                    ),
+                ));
+                return Ok(report);
+            }
+        }
+
+        report.checks.push(check_file_system(directory));
+        let checks = [
+            check_symlinks(&probe),
+            check_xattrs(&probe),
+            check_sparse_files(&probe),
+            check_timestamps(&probe),
+        ];
+        std::fs::remove_dir_all(&probe).with_path(&probe)?;
+        report.checks.extend(checks);
+        report.checks.push(self.check_space(directory)?);
+        Ok(report)
+    }
+
+    fn check_space(&self, directory: &Path) -> Result<Check, SyncError> {
+        let available = match platform::available_space(directory) {
+            Ok(available) => available,
+            Err(error) => {
+                return Ok(Check::new(
+                    "Disk space",
+                    CheckStatus::Skipped,
+                    format!("can't tell the free space: {error}"),
+                ));
+            }
+        };
+        let Some(origin) = &self.origin else {
+            return Ok(Check::new(
+                "Disk space",
+                CheckStatus::Ok,
+                format!("{} available", format_size(available)),
+            ));
+        };
+        let stats = match Replicator::new(origin, &self.destination)
+            .dryrun(true)
+            .run()
+        {
+            Ok(stats) => stats,
+            Err(
+                error @ (SyncError::ProtectedDestination { .. }
+                | SyncError::ForeignDestination { .. }),
+            ) => {
+                return Ok(Check::new(
+                    "Disk space",
+                    CheckStatus::Warning,
+                    format!("{error}, runs need --force to replicate into it"),
+                ));
+            }
+            Err(error) => return Err(error),
+        };
+        let needed = stats.total_file_copied_size;
+        let detail = format!(
+            "{} available, {} to copy from {}",
+            format_size(available),
+            format_size(needed),
+            origin.display()
+        );
+        Ok(if needed > available {
+            Check::new(
+                "Disk space",
+                CheckStatus::Warning,
+                format!(
+                    "{detail}, free {} more or use --compress or --store",
+                    format_size(needed - available)
+                ),
+            )
+        } else if needed > available - available / 10 {
+            Check::new(
+                "Disk space",
+                CheckStatus::Warning,
+                format!("{detail}, less than 10% would be left"),
+            )
+        } else {
+            Check::new("Disk space", CheckStatus::Ok, detail)
+        })
+    }
+}
+
+fn check_file_system(directory: &Path) -> Check {
+    let name = match platform::file_system_type(directory) {
+        Ok(name) => name,
+        Err(error) => {
+            return Check::new(
+                "File system",
+                CheckStatus::Skipped,
+                format!("can't tell the type: {error}"),
+            );
+        }
+    };
+    match name.as_str() {
+        "vfat" => Check::new(
+            "File system",
+            CheckStatus::Warning,
+            "vfat, files larger than 4 GiB can't be stored and permissions aren't kept, \
+             prefer exFAT or a native file system",
+        ),
+        "nfs" | "cifs" | "smb2" | "fuse" => Check::new(
+            "File system",
+            CheckStatus::Warning,
+            format!(
+                "{name}, a network or FUSE file system whose dates may drift, \
+                 consider --modify_window=1 or --size_only"
+            ),
+        ),
+        _ => Check::new("File system", CheckStatus::Ok, name),
+    }
+}
+
+fn check_symlinks(probe: &Path) -> Check {
+    match platform::symlink(Path::new("file"), &probe.join("link")) {
+        Ok(()) => Check::new("Symbolic links", CheckStatus::Ok, "supported"),
+        Err(error) => Check::new(
+            "Symbolic links",
+            CheckStatus::Warning,
+            format!("not supported ({error}), symbolic links of the origin will fail to copy"),
+        ),
+    }
+}
+
+fn check_xattrs(probe: &Path) -> Check {
+    let file = probe.join("file");
+    match platform::set_xattr(&file, "user.acsync_doctor", b"1") {
+        Ok(()) => {
+            let _ = platform::remove_xattr(&file, "user.acsync_doctor");
+            Check::new("Extended attributes", CheckStatus::Ok, "supported")
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::Unsupported => Check::new(
+            "Extended attributes",
+            CheckStatus::Skipped,
+            "can't be probed on this platform",
+        ),
+        Err(error) => Check::new(
+            "Extended attributes",
+            CheckStatus::Warning,
+            format!("not supported ({error}), extended attributes can't be kept"),
+        ),
+    }
+}
+
+fn check_sparse_files(probe: &Path) -> Check {
+    #[cfg(unix)]
+    {
+        use std::{
+            io::{Seek, SeekFrom, Write},
+            os::unix::fs::MetadataExt,
+        };
+
+        const SIZE: u64 = 16 * 1024 * 1024;
+        let path = probe.join("sparse");
+        let metadata = File::create(&path).and_then(|mut file| {
+            file.set_len(SIZE)?;
+            file.seek(SeekFrom::Start(SIZE / 2))?;
+            file.write_all(b"acsync")?;
+            file.sync_all()?;
+            file.metadata()
+        });
+        match metadata {
+            Ok(metadata) if metadata.blocks() * 512 < SIZE / 2 => {
+                Check::new("Sparse files", CheckStatus::Ok, "holes are kept")
+            }
+            Ok(_) => Check::new(
+                "Sparse files",
+                CheckStatus::Warning,
+                "holes are filled, disk images take their full size even with --sparse",
+            ),
+            Err(error) => Check::new(
+                "Sparse files",
+                CheckStatus::Warning,
+                format!("can't create a sparse file: {error}"),
+            ),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = probe;
+        Check::new(
+            "Sparse files",
+            CheckStatus::Skipped,
+            "can't be probed on this platform",
+        )
+    }
+}
+
+fn check_timestamps(probe: &Path) -> Check {
+    let written = Duration::new(1_700_000_001, 123_456_789);
+    let path = probe.join("file");
+    let read = File::options()
+        .write(true)
+        .open(&path)
+        .and_then(|file| file.set_modified(UNIX_EPOCH + written))
+        .and_then(|_| path.metadata()?.modified())
+        .map(|modified| modified.duration_since(UNIX_EPOCH).unwrap_or_default());
+    let read = match read {
+        Ok(read) => read,
+        Err(error) => {
+            return Check::new(
+                "Timestamps",
+                CheckStatus::Warning,
+                format!("can't set modification dates: {error}"),
+            );
+        }
+    };
+    match timestamp_granularity(written, read) {
+        Some(granularity) if granularity >= Duration::from_secs(1) => Check::new(
+            "Timestamps",
+            CheckStatus::Warning,
+            format!(
+                "{granularity:?} granularity, use --modify_window={}",
+                granularity.as_secs()
+            ),
+        ),
+        Some(granularity) => Check::new(
+            "Timestamps",
+            CheckStatus::Ok,
+            format!("{granularity:?} granularity"),
+        ),
+        None => Check::new(
+            "Timestamps",
+            CheckStatus::Warning,
+            format!(
+                "modification dates aren't kept ({read:?} read back for {written:?}), use --size_only"
+            ),
+        ),
+    }
+}
+
+/// Returns the granularity of the modification dates of a file system, from a date
+/// `written` to a file and the date `read` back, or `None` when they differ by more
+/// than rounding.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::doctor::timestamp_granularity;
+/// # use std::time::Duration;
+/// #
+/// let written = Duration::new(1_700_000_001, 123_456_789);
+/// assert_eq!(
+///     timestamp_granularity(written, Duration::new(1_700_000_001, 123_456_700)),
+///     Some(Duration::from_nanos(100))
+/// );
+/// assert_eq!(
+///     timestamp_granularity(written, Duration::from_secs(1_700_000_000)),
+///     Some(Duration::from_secs(2))
+/// );
+/// assert_eq!(timestamp_granularity(written, Duration::from_secs(42)), None);
+/// ```
+pub fn timestamp_granularity(written: Duration, read: Duration) -> Option<Duration> {
+    [
+        Duration::from_nanos(1),
+        Duration::from_nanos(100),
+        Duration::from_micros(1),
+        Duration::from_millis(1),
+        Duration::from_millis(10),
+        Duration::from_secs(1),
+        Duration::from_secs(2),
+    ]
+    .into_iter()
+    .find(|granularity| {
+        let units = written.as_nanos() / granularity.as_nanos();
+        let floor = Duration::from_nanos((units * granularity.as_nanos()) as u64);
+        read == floor || read == floor + *granularity
+    })
+}
+
+/// Formats a size in the largest unit keeping it above 1, e.g. `1.5 GiB`.
+fn format_size(size: u64) -> String {
+    let mut value = size as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if value < 1024.0 {
+            return format!("{value:.1} {unit}");
+        }
+        value /= 1024.0;
+    }
+    format!("{value:.1} TiB")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_diagnoses_a_writable_destination_and_removes_its_probes() {
+        let root = testing::test_dir("it_diagnoses_a_writable_destination_and_removes_its_probes");
+        let origin = root.join("origin");
+        std::fs::create_dir_all(&origin).unwrap();
+        std::fs::write(origin.join("notes.txt"), "notes").unwrap();
+
+        let report = Doctor::new(root.join("destination"))
+            .origin(Some(&origin))
+            .run()
+            .unwrap();
+        let names: Vec<&str> = report.checks.iter().map(|check| check.name).collect();
+        assert_eq!(
+            names,
+            [
+                "Destination",
+                "File system",
+                "Symbolic links",
+                "Extended attributes",
+                "Sparse files",
+                "Timestamps",
+                "Disk space"
+            ]
+        );
+        assert_eq!(report.checks[0].status, CheckStatus::Ok);
+        #[cfg(unix)]
+        assert_eq!(report.checks[2].status, CheckStatus::Ok);
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        assert!(report.checks[6].detail.contains("5.0 B to copy"));
+        assert!(!root.join(DOCTOR_DIR_NAME).exists());
+        assert!(!root.join("destination").exists());
+    }
+}
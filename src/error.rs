@@ -0,0 +1,270 @@
+//! **error** contains the error type returned by the synchronization engine.
+
+use crate::{cli_helper, names::TargetFs, sync::PROTECT_FILE_NAME};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Errors that can happen while synchronizing files.
+#[derive(Debug)]
+pub enum SyncError {
+    /// An I/O operation failed on the given path.
+    Io { path: PathBuf, source: io::Error },
+    /// A required argument was not informed.
+    MissingArgument(String),
+    /// An argument was informed with an invalid value.
+    InvalidArgument { name: String, reason: String },
+    /// The config file at the given path is not valid.
+    InvalidConfig {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    /// Walking the directory tree failed at the given path.
+    WalkError { path: PathBuf, source: io::Error },
+    /// The current user is not allowed to access the given path.
+    PermissionDenied { path: PathBuf },
+    /// The content of a file doesn't match its expected checksum.
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    /// Some files failed while the run kept going.
+    FilesFailed(u64),
+    /// An external hook command failed to start or exited with a non-zero status.
+    HookFailed { command: String, reason: String },
+    /// The destination diverges from the origin in the given number of paths.
+    VerifyMismatch(u64),
+    /// The run was interrupted before completion.
+    Interrupted,
+    /// Another run, of the process `pid` started at `since`, holds the lock file at the
+    /// given path.
+    Locked {
+        path: PathBuf,
+        pid: u32,
+        since: SystemTime,
+    },
+    /// The files to write, of `needed` bytes, don't fit in the `available` bytes of the
+    /// file system holding the given path.
+    InsufficientSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+    /// A run would delete `count` of the `total` files replicated by the previous run,
+    /// over the deletion limits.
+    TooManyDeletions { count: u64, total: u64 },
+    /// The destination is marked as protected from runs.
+    ProtectedDestination { path: PathBuf },
+    /// The destination holds the files replicated from another origin.
+    ForeignDestination { path: PathBuf, origin: PathBuf },
+    /// The source file at the given path only differs by case from the `other` one, so
+    /// they can't both be replicated into a case-insensitive destination.
+    CaseCollision { path: PathBuf, other: PathBuf },
+    /// The source file at the given path can't be named as is on the file system of the
+    /// destination.
+    InvalidName {
+        path: PathBuf,
+        file_system: TargetFs,
+        reason: String,
+    },
+    /// The copy of the file at the given path took longer than `timeout`.
+    TimedOut { path: PathBuf, timeout: Duration },
+    /// The source file at the given path kept changing while being copied.
+    SourceChanged { path: PathBuf },
+    /// The directory at the given path couldn't be read while walking the source, its
+    /// files being left out of the run.
+    Unreadable { path: PathBuf, source: io::Error },
+}
+
+impl SyncError {
+    /// Returns the process exit code reporting this error:
+    ///
+    /// - `1`: an argument or the config file is invalid;
+    /// - `2`: some files or a hook failed, the destination is locked or full, or the run
+    ///   aborted on a failure;
+    /// - `3`: the destination doesn't match the origin;
+    /// - `4`: the run was interrupted.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SyncError::MissingArgument(_)
+            | SyncError::InvalidArgument { .. }
+            | SyncError::InvalidConfig { .. } => 1,
+            SyncError::Io { .. }
+            | SyncError::WalkError { .. }
+            | SyncError::PermissionDenied { .. }
+            | SyncError::FilesFailed(_)
+            | SyncError::HookFailed { .. }
+            | SyncError::Locked { .. }
+            | SyncError::InsufficientSpace { .. }
+            | SyncError::TooManyDeletions { .. }
+            | SyncError::ProtectedDestination { .. }
+            | SyncError::ForeignDestination { .. }
+            | SyncError::CaseCollision { .. }
+            | SyncError::InvalidName { .. }
+            | SyncError::TimedOut { .. }
+            | SyncError::SourceChanged { .. }
+            | SyncError::Unreadable { .. } => 2,
+            SyncError::ChecksumMismatch { .. } | SyncError::VerifyMismatch(_) => 3,
+            SyncError::Interrupted => 4,
+        }
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            SyncError::MissingArgument(name) => write!(f, "Argument {name} must be informed!"),
+            SyncError::InvalidArgument { name, reason } => {
+                write!(f, "Invalid value for {name}: {reason}")
+            }
+            SyncError::InvalidConfig { path, line, reason } => {
+                write!(f, "{}:{line}: {reason}", path.display())
+            }
+            SyncError::WalkError { path, source } => {
+                write!(f, "Failed to walk {}: {source}", path.display())
+            }
+            SyncError::PermissionDenied { path } => {
+                write!(f, "Permission denied on {}", path.display())
+            }
+            SyncError::ChecksumMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Checksum mismatch on {}: expected {expected}, found {actual}",
+                path.display()
+            ),
+            SyncError::FilesFailed(count) => write!(f, "{count} files failed"),
+            SyncError::HookFailed { command, reason } => {
+                write!(f, "Hook {command:?} failed: {reason}")
+            }
+            SyncError::VerifyMismatch(count) => {
+                write!(f, "Destination diverges from origin in {count} paths")
+            }
+            SyncError::Interrupted => write!(f, "Interrupted"),
+            SyncError::Locked { path, pid, since } => write!(
+                f,
+                "{} is held by process {pid} since {}",
+                path.display(),
+                cli_helper::format_time(*since)
+            ),
+            SyncError::InsufficientSpace {
+                path,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Not enough space in {}: {} KBs to write but {} KBs available",
+                path.display(),
+                (needed / 1024) as f64,
+                (available / 1024) as f64
+            ),
+            SyncError::TooManyDeletions { count, total } => write!(
+                f,
+                "Refusing to delete {count} of the {total} files replicated before, over the deletion limit"
+            ),
+            SyncError::ProtectedDestination { path } => write!(
+                f,
+                "{} is protected by a {} file",
+                path.display(),
+                PROTECT_FILE_NAME
+            ),
+            SyncError::ForeignDestination { path, origin } => write!(
+                f,
+                "{} holds the files replicated from another origin, {}",
+                path.display(),
+                origin.display()
+            ),
+            SyncError::CaseCollision { path, other } => write!(
+                f,
+                "{} only differs by case from {}, which a case-insensitive destination can't hold apart",
+                path.display(),
+                other.display()
+            ),
+            SyncError::InvalidName {
+                path,
+                file_system,
+                reason,
+            } => write!(
+                f,
+                "{} can't be named as is on a {file_system} destination: {reason}",
+                path.display()
+            ),
+            SyncError::TimedOut { path, timeout } => {
+                write!(f, "Gave up on copying {} after {timeout:?}", path.display())
+            }
+            SyncError::SourceChanged { path } => {
+                write!(f, "{} changed while being copied", path.display())
+            }
+            SyncError::Unreadable { path, source } => {
+                write!(f, "Can't read {}, skipping it: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SyncError::Io { source, .. }
+            | SyncError::WalkError { source, .. }
+            | SyncError::Unreadable { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Extension to attach the path an I/O operation was working on to its error.
+pub trait IoResultExt<T> {
+    /// Maps the error into [`SyncError::PermissionDenied`] or [`SyncError::Io`] for `path`.
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T, SyncError>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T, SyncError> {
+        self.map_err(|source| {
+            let path = path.as_ref().to_path_buf();
+            if source.kind() == io::ErrorKind::PermissionDenied {
+                SyncError::PermissionDenied { path }
+            } else {
+                SyncError::Io { path, source }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_attaches_the_path_to_io_errors() {
+        let result: io::Result<()> = Err(io::Error::from(io::ErrorKind::NotFound));
+        let error = result.with_path("foo/bar.txt").unwrap_err();
+        assert!(matches!(&error, SyncError::Io { path, .. } if path == Path::new("foo/bar.txt")));
+        assert!(error.to_string().starts_with("foo/bar.txt: "));
+    }
+
+    #[test]
+    fn it_distinguishes_exit_codes() {
+        assert_eq!(SyncError::MissingArgument("origin".into()).exit_code(), 1);
+        assert_eq!(SyncError::FilesFailed(2).exit_code(), 2);
+        assert_eq!(SyncError::VerifyMismatch(1).exit_code(), 3);
+        assert_eq!(SyncError::Interrupted.exit_code(), 4);
+    }
+
+    #[test]
+    fn it_maps_permission_errors_to_permission_denied() {
+        let result: io::Result<()> = Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(
+            result.with_path("foo"),
+            Err(SyncError::PermissionDenied { .. })
+        ));
+    }
+}
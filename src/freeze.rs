@@ -0,0 +1,64 @@
+//! **freeze** contains the read-only snapshots an origin is read from, so that the files
+//! modified during a run are copied as they were when it started rather than torn.
+
+use crate::{error::SyncError, warn};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Name of the snapshot created at the root of an origin during a run.
+pub const SNAPSHOT_DIR_NAME: &str = ".acsync_snapshot";
+
+/// Read-only Btrfs snapshot of an origin, deleted when dropped.
+#[derive(Debug)]
+pub struct FrozenSource {
+    path: PathBuf,
+}
+
+impl FrozenSource {
+    /// Takes a read-only snapshot of the `source` subvolume into its
+    /// [`SNAPSHOT_DIR_NAME`] directory, with the `btrfs` command.
+    ///
+    /// Fails when `source` isn't the root of a Btrfs subvolume or the snapshot of a
+    /// previous run was left behind.
+    pub fn create<P: AsRef<Path>>(source: P) -> Result<Self, SyncError> {
+        let source = source.as_ref();
+        let path = source.join(SNAPSHOT_DIR_NAME);
+        btrfs(&["subvolume", "snapshot", "-r"], &[source, &path]).map_err(|error| {
+            SyncError::Io {
+                path: source.to_path_buf(),
+                source: error,
+            }
+        })?;
+        Ok(FrozenSource { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FrozenSource {
+    fn drop(&mut self) {
+        if let Err(error) = btrfs(&["subvolume", "delete"], &[&self.path]) {
+            warn!("Can't delete the snapshot {}: {error}", self.path.display());
+        }
+    }
+}
+
+/// Runs the `btrfs` command with `arguments` and `paths`, failing with its error
+/// output.
+fn btrfs(arguments: &[&str], paths: &[&Path]) -> io::Result<()> {
+    let output = Command::new("btrfs").args(arguments).args(paths).output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "btrfs {} failed: {}",
+            arguments.join(" "),
+            message.trim()
+        )));
+    }
+    Ok(())
+}
@@ -1,26 +1,200 @@
+use crate::platform;
 use std::{
-    collections::VecDeque,
-    fs::ReadDir,
+    collections::{HashMap, VecDeque},
+    fs::{FileType, Metadata, ReadDir},
     io::Result,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
-#[derive(Debug)]
-enum InnerEntryPath {
-    Path(PathBuf),
-    DeferredPath(PathBuf),
+/// Entry yielded by [`FileSearcher`], carrying the metadata read while walking the
+/// tree so that callers don't need to stat its path again.
+///
+/// Like [`Path::metadata`], the metadata follows symbolic links.
+#[derive(Debug, Clone)]
+pub struct SearchEntry {
+    path: PathBuf,
+    depth: usize,
+    metadata: Metadata,
+}
+
+impl SearchEntry {
+    pub(crate) fn new(path: PathBuf, depth: usize) -> Result<Self> {
+        let metadata = platform::short_path(&path).metadata()?;
+        Ok(SearchEntry {
+            path,
+            depth,
+            metadata,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    /// Number of levels below the start path, which is at depth 0.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.metadata.file_type()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.metadata.is_dir()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.metadata.is_file()
+    }
+}
+
+/// Order in which the entries of each directory are yielded by [`FileSearcher`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Keep the order returned by the operating system.
+    #[default]
+    None,
+    /// Sort entries by file name.
+    Name,
+    /// Sort entries by modification date, oldest first.
+    Mtime,
+    /// Sort entries by size, smallest first.
+    Size,
+}
+
+impl SortOrder {
+    fn sort(&self, entries: &mut [SearchEntry]) {
+        match self {
+            SortOrder::None => {}
+            SortOrder::Name => entries.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name())),
+            SortOrder::Mtime => entries.sort_by(|a, b| {
+                (a.metadata.modified().ok(), a.path.file_name())
+                    .cmp(&(b.metadata.modified().ok(), b.path.file_name()))
+            }),
+            SortOrder::Size => entries.sort_by(|a, b| {
+                (a.metadata.len(), a.path.file_name()).cmp(&(b.metadata.len(), b.path.file_name()))
+            }),
+        }
+    }
+}
+
+/// Kinds of entries yielded by [`FileSearcher`], all of them by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileTypes {
+    /// Files, including the special files yielded with [`FileSearcher::specials`].
+    pub files: bool,
+    /// Directories, which are still descended into when not yielded.
+    pub dirs: bool,
+    /// Symbolic links, yielded with the metadata of their targets. When disabled, they
+    /// are neither yielded nor followed.
+    pub symlinks: bool,
+}
+
+impl Default for FileTypes {
+    fn default() -> Self {
+        FileTypes {
+            files: true,
+            dirs: true,
+            symlinks: true,
+        }
+    }
+}
+
+impl FileTypes {
+    fn matches(&self, entry: &SearchEntry) -> bool {
+        if entry.is_dir() {
+            self.dirs
+        } else {
+            self.files
+        }
+    }
+}
+
+/// What [`FileSearcher`] does with the directories it can't read, e.g. for lack of
+/// permission.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Yields their errors and goes on with the walk.
+    #[default]
+    Yield,
+    /// Silently goes on with the walk.
+    Skip,
+    /// Goes on with the walk, recording their paths and errors, see
+    /// [`SearchIterator::skipped`].
+    Collect,
+    /// Yields the first error and ends the walk.
+    Abort,
+}
+
+/// Order in which [`FileSearcher`] walks the directory tree.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TraversalOrder {
+    /// Depth-first, yielding each directory before its entries.
+    #[default]
+    PreOrder,
+    /// Depth-first, yielding each directory after its entries, useful to remove
+    /// directories bottom-up.
+    PostOrder,
+    /// Breadth-first, yielding all entries of a level before descending to the next one.
+    BreadthFirst,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 struct FileSearcherOptions {
-    overall: bool,
+    traversal_order: TraversalOrder,
+    min_depth: usize,
     max_depth: usize,
+    hidden: bool,
+    specials: bool,
+    file_types: FileTypes,
+    same_file_system: bool,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+    sort_order: SortOrder,
+    on_error: ErrorPolicy,
     includes: Vec<String>,
     excludes: Vec<String>,
+    excludes_file: Option<String>,
     extensions: Vec<String>,
 }
 
-#[derive(Debug, Default)]
+impl FileSearcherOptions {
+    fn has_metadata_filters(&self) -> bool {
+        self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer_than.is_some()
+            || self.older_than.is_some()
+    }
+
+    fn matches_metadata(&self, metadata: &Metadata) -> bool {
+        let modified = metadata.modified().ok();
+        self.min_size
+            .is_none_or(|min_size| metadata.len() >= min_size)
+            && self
+                .max_size
+                .is_none_or(|max_size| metadata.len() <= max_size)
+            && self
+                .newer_than
+                .is_none_or(|newer_than| modified.is_some_and(|modified| modified > newer_than))
+            && self
+                .older_than
+                .is_none_or(|older_than| modified.is_some_and(|modified| modified < older_than))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct FileSearcher {
     start_path: Option<PathBuf>,
     options: FileSearcherOptions,
@@ -34,6 +208,7 @@ impl FileSearcher {
                 start_path: Some(start_path),
                 options: FileSearcherOptions {
                     max_depth: usize::MAX,
+                    hidden: true,
                     ..FileSearcherOptions::default()
                 },
             }
@@ -41,6 +216,7 @@ impl FileSearcher {
             FileSearcher {
                 options: FileSearcherOptions {
                     max_depth: usize::MAX,
+                    hidden: true,
                     ..FileSearcherOptions::default()
                 },
                 ..FileSearcher::default()
@@ -48,17 +224,229 @@ impl FileSearcher {
         }
     }
 
+    /// Walks the tree as configured so far, leaving the searcher to be iterated again,
+    /// e.g. once per run of a periodic job. Each walk reads the tree anew.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::fs::FileSearcher;
+    /// #
+    /// let mut searcher = FileSearcher::new("src");
+    /// searcher.set_max_depth(0);
+    /// assert_eq!(searcher.iter().count(), 1);
+    /// searcher.set_max_depth(1);
+    /// assert!(searcher.iter().count() > 1);
+    /// ```
+    pub fn iter(&self) -> IntoIter {
+        self.clone().into_iter()
+    }
+
+    /// Shorthand to walk the tree in [`TraversalOrder::PostOrder`] when `flag` is true.
     pub fn overall(mut self, flag: bool) -> Self {
-        self.options.overall = flag;
+        self.set_overall(flag);
+        self
+    }
+
+    pub fn traversal_order(mut self, traversal_order: TraversalOrder) -> Self {
+        self.set_traversal_order(traversal_order);
+        self
+    }
+
+    /// Only yields entries at least `min_depth` levels below the start path, which
+    /// is at depth 0. Shallower directories are still traversed.
+    pub fn min_depth(mut self, min_depth: usize) -> Self {
+        self.set_min_depth(min_depth);
         self
     }
 
     pub fn max_depth(mut self, max_depth: usize) -> Self {
-        self.options.max_depth = max_depth;
+        self.set_max_depth(max_depth);
+        self
+    }
+
+    /// Whether dot-files and dot-directories are yielded, enabled by default. Hidden
+    /// directories are not descended into when disabled.
+    pub fn hidden(mut self, flag: bool) -> Self {
+        self.set_hidden(flag);
+        self
+    }
+
+    /// Whether special files such as FIFOs, sockets and device nodes are yielded,
+    /// disabled by default.
+    pub fn specials(mut self, flag: bool) -> Self {
+        self.set_specials(flag);
+        self
+    }
+
+    /// Only yields the kinds of entries of `file_types`, e.g. files and no directories,
+    /// rather than leaving it to the caller to filter them afterwards.
+    pub fn file_types(mut self, file_types: FileTypes) -> Self {
+        self.set_file_types(file_types);
+        self
+    }
+
+    /// Whether directories on other file systems than the start path, such as `/proc`
+    /// or network mounts, are not descended into. They are still yielded, so that
+    /// their mount points can be recreated. Disabled by default, and without effect on
+    /// Windows.
+    pub fn same_file_system(mut self, flag: bool) -> Self {
+        self.set_same_file_system(flag);
+        self
+    }
+
+    /// Skips files smaller than `min_size` bytes.
+    pub fn min_size(mut self, min_size: Option<u64>) -> Self {
+        self.set_min_size(min_size);
+        self
+    }
+
+    /// Skips files larger than `max_size` bytes.
+    pub fn max_size(mut self, max_size: Option<u64>) -> Self {
+        self.set_max_size(max_size);
+        self
+    }
+
+    /// Skips files not modified after `newer_than`.
+    pub fn newer_than(mut self, newer_than: Option<SystemTime>) -> Self {
+        self.set_newer_than(newer_than);
+        self
+    }
+
+    /// Skips files not modified before `older_than`.
+    pub fn older_than(mut self, older_than: Option<SystemTime>) -> Self {
+        self.set_older_than(older_than);
+        self
+    }
+
+    pub fn sort_by(mut self, sort_order: SortOrder) -> Self {
+        self.set_sort_by(sort_order);
+        self
+    }
+
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.set_on_error(policy);
         self
     }
 
     pub fn includes<P: AsRef<Path>>(mut self, includes: &[P]) -> Self {
+        self.set_includes(includes);
+        self
+    }
+
+    pub fn excludes<P: AsRef<Path>>(mut self, excludes: &[P]) -> Self {
+        self.set_excludes(excludes);
+        self
+    }
+
+    /// Excludes the entries matching the patterns listed one per line in the
+    /// `file_name` file of each directory walked, such as `.acsync_excludes`. Like
+    /// nested `.gitignore` files, the patterns of a directory only apply to its
+    /// subtree, matched against the paths relative to it.
+    pub fn excludes_file(mut self, file_name: Option<&str>) -> Self {
+        self.set_excludes_file(file_name);
+        self
+    }
+
+    pub fn extensions(mut self, extensions: Option<impl AsRef<str>>) -> Self {
+        self.set_extensions(extensions);
+        self
+    }
+}
+
+/// Setters configuring a searcher in place, e.g. one kept around and iterated several
+/// times with [`FileSearcher::iter`].
+impl FileSearcher {
+    /// Sets [`FileSearcher::overall`] in place.
+    pub fn set_overall(&mut self, flag: bool) -> &mut Self {
+        self.options.traversal_order = if flag {
+            TraversalOrder::PostOrder
+        } else {
+            TraversalOrder::PreOrder
+        };
+        self
+    }
+
+    /// Sets [`FileSearcher::traversal_order`] in place.
+    pub fn set_traversal_order(&mut self, traversal_order: TraversalOrder) -> &mut Self {
+        self.options.traversal_order = traversal_order;
+        self
+    }
+
+    /// Sets [`FileSearcher::min_depth`] in place.
+    pub fn set_min_depth(&mut self, min_depth: usize) -> &mut Self {
+        self.options.min_depth = min_depth;
+        self
+    }
+
+    /// Sets [`FileSearcher::max_depth`] in place.
+    pub fn set_max_depth(&mut self, max_depth: usize) -> &mut Self {
+        self.options.max_depth = max_depth;
+        self
+    }
+
+    /// Sets [`FileSearcher::hidden`] in place.
+    pub fn set_hidden(&mut self, flag: bool) -> &mut Self {
+        self.options.hidden = flag;
+        self
+    }
+
+    /// Sets [`FileSearcher::specials`] in place.
+    pub fn set_specials(&mut self, flag: bool) -> &mut Self {
+        self.options.specials = flag;
+        self
+    }
+
+    /// Sets [`FileSearcher::file_types`] in place.
+    pub fn set_file_types(&mut self, file_types: FileTypes) -> &mut Self {
+        self.options.file_types = file_types;
+        self
+    }
+
+    /// Sets [`FileSearcher::same_file_system`] in place.
+    pub fn set_same_file_system(&mut self, flag: bool) -> &mut Self {
+        self.options.same_file_system = flag;
+        self
+    }
+
+    /// Sets [`FileSearcher::min_size`] in place.
+    pub fn set_min_size(&mut self, min_size: Option<u64>) -> &mut Self {
+        self.options.min_size = min_size;
+        self
+    }
+
+    /// Sets [`FileSearcher::max_size`] in place.
+    pub fn set_max_size(&mut self, max_size: Option<u64>) -> &mut Self {
+        self.options.max_size = max_size;
+        self
+    }
+
+    /// Sets [`FileSearcher::newer_than`] in place.
+    pub fn set_newer_than(&mut self, newer_than: Option<SystemTime>) -> &mut Self {
+        self.options.newer_than = newer_than;
+        self
+    }
+
+    /// Sets [`FileSearcher::older_than`] in place.
+    pub fn set_older_than(&mut self, older_than: Option<SystemTime>) -> &mut Self {
+        self.options.older_than = older_than;
+        self
+    }
+
+    /// Sets [`FileSearcher::sort_by`] in place.
+    pub fn set_sort_by(&mut self, sort_order: SortOrder) -> &mut Self {
+        self.options.sort_order = sort_order;
+        self
+    }
+
+    /// Sets [`FileSearcher::on_error`] in place.
+    pub fn set_on_error(&mut self, policy: ErrorPolicy) -> &mut Self {
+        self.options.on_error = policy;
+        self
+    }
+
+    /// Sets [`FileSearcher::includes`] in place.
+    pub fn set_includes<P: AsRef<Path>>(&mut self, includes: &[P]) -> &mut Self {
         self.options.includes = includes
             .iter()
             .map(|item| item.as_ref().to_path_buf().to_string_lossy().to_string())
@@ -66,7 +454,8 @@ impl FileSearcher {
         self
     }
 
-    pub fn excludes<P: AsRef<Path>>(mut self, excludes: &[P]) -> Self {
+    /// Sets [`FileSearcher::excludes`] in place.
+    pub fn set_excludes<P: AsRef<Path>>(&mut self, excludes: &[P]) -> &mut Self {
         self.options.excludes = excludes
             .iter()
             .map(|item| item.as_ref().to_path_buf().to_string_lossy().to_string())
@@ -74,7 +463,14 @@ impl FileSearcher {
         self
     }
 
-    pub fn extensions(mut self, extensions: Option<impl AsRef<str>>) -> Self {
+    /// Sets [`FileSearcher::excludes_file`] in place.
+    pub fn set_excludes_file(&mut self, file_name: Option<&str>) -> &mut Self {
+        self.options.excludes_file = file_name.map(String::from);
+        self
+    }
+
+    /// Sets [`FileSearcher::extensions`] in place.
+    pub fn set_extensions(&mut self, extensions: Option<impl AsRef<str>>) -> &mut Self {
         self.options.extensions = extensions
             .map(|value| {
                 value
@@ -89,93 +485,336 @@ impl FileSearcher {
 }
 
 impl IntoIterator for FileSearcher {
-    type Item = Result<PathBuf>;
+    type Item = Result<SearchEntry>;
 
     type IntoIter = IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
+        let start_entry = self
+            .start_path
+            .and_then(|path| SearchEntry::new(path, 0).ok());
+        let root_device = start_entry
+            .as_ref()
+            .filter(|_| self.options.same_file_system)
+            .and_then(|entry| platform::device(&entry.metadata));
         IntoIter {
+            root_device,
+            scoped_excludes: HashMap::new(),
             options: self.options,
-            offset_depth: self
-                .start_path
-                .as_ref()
-                .map(|path| path.components().count())
-                .unwrap_or(0),
-            pending_paths: match self.start_path {
-                Some(path) => VecDeque::from([InnerEntryPath::Path(path)]),
-                None => VecDeque::new(),
-            },
-            current_read_directory: None,
+            start_entry,
+            current_directory: None,
+            frames: vec![],
+            pending_directories: VecDeque::new(),
+            skipped: vec![],
         }
     }
 }
 
+impl IntoIterator for &FileSearcher {
+    type Item = Result<SearchEntry>;
+
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Entries of a directory left to yield.
+#[derive(Debug)]
+enum Listing {
+    /// Read from the open directory, whose path is kept along, one at a time in the order
+    /// of the operating system.
+    Open(ReadDir, PathBuf),
+    /// Paths read in advance, sorted by name unless too many directories were open,
+    /// their metadata being read when yielded.
+    Paths(std::vec::IntoIter<PathBuf>),
+    /// Entries read in advance and sorted by their metadata.
+    Entries(std::vec::IntoIter<SearchEntry>),
+}
+
+/// Directory being walked, one per level below the start path.
+#[derive(Debug)]
+struct Frame {
+    listing: Listing,
+    /// Depth of the entries of the directory.
+    depth: usize,
+    /// The directory itself, yielded after its entries in [`TraversalOrder::PostOrder`].
+    deferred: Option<SearchEntry>,
+}
+
+/// Iterator of the entries of a [`FileSearcher`].
+///
+/// Its memory grows with the depth of the tree rather than its size: depth-first walks
+/// keep one frame per level, holding the open handle of the directory when the
+/// entries aren't sorted, up to [`MAX_OPEN_DIRECTORIES`] of them, or the names of its
+/// remaining entries otherwise, plus their metadata when sorted by date or size. Breadth-
+/// first walks keep the directories of the next level instead.
 #[derive(Debug)]
 pub struct IntoIter {
     options: FileSearcherOptions,
-    pending_paths: VecDeque<InnerEntryPath>,
-    current_read_directory: Option<ReadDir>,
-    offset_depth: usize,
+    /// Device of the start path when restricted to its file system.
+    root_device: Option<u64>,
+    /// Patterns of the excludes files found so far, by directory.
+    scoped_excludes: HashMap<PathBuf, Vec<String>>,
+    start_entry: Option<SearchEntry>,
+    /// Directory just yielded, descended into on the next call unless skipped.
+    current_directory: Option<(PathBuf, usize)>,
+    frames: Vec<Frame>,
+    /// Directories left to descend into in [`TraversalOrder::BreadthFirst`].
+    pending_directories: VecDeque<(PathBuf, usize)>,
+    /// Paths that couldn't be read under [`ErrorPolicy::Collect`], with their errors.
+    skipped: Vec<(PathBuf, std::io::Error)>,
 }
 
+/// Entry of a walk, or the error reading the directory at a path.
+type WalkResult = std::result::Result<SearchEntry, (PathBuf, std::io::Error)>;
+
+/// Count of directory handles a depth-first walk keeps open at most, deeper
+/// directories being read in advance so that deep trees don't run out of file
+/// descriptors.
+pub const MAX_OPEN_DIRECTORIES: usize = 32;
+
 impl IntoIter {
-    fn inner_next(&mut self) -> Option<Result<PathBuf>> {
-        while !self.pending_paths.is_empty() || self.current_read_directory.is_some() {
-            if let Some(read_dir) = &mut self.current_read_directory {
-                for entry_result in read_dir {
+    /// Returns true if `entry` is on another file system than the start path, when
+    /// restricted to it.
+    fn crosses_file_system(&self, entry: &SearchEntry) -> bool {
+        self.root_device
+            .is_some_and(|root_device| platform::device(&entry.metadata) != Some(root_device))
+    }
+
+    /// Loads the patterns of the excludes file of `directory`, if any.
+    fn load_excludes_file(&mut self, directory: &Path) {
+        let Some(file_name) = &self.options.excludes_file else {
+            return;
+        };
+        if let Ok(content) =
+            std::fs::read_to_string(&*platform::short_path(&directory.join(file_name)))
+        {
+            let patterns: Vec<String> = content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect();
+            if !patterns.is_empty() {
+                self.scoped_excludes
+                    .insert(directory.to_path_buf(), patterns);
+            }
+        }
+    }
+
+    /// Returns true if `path` matches the patterns of the excludes file of one of its
+    /// ancestors.
+    fn matches_scoped_excludes(&self, path: &Path) -> bool {
+        path.ancestors().skip(1).any(|directory| {
+            self.scoped_excludes.get(directory).is_some_and(|patterns| {
+                let relative_path = path.strip_prefix(directory).unwrap_or(path);
+                let relative_path = relative_path.to_string_lossy();
+                patterns
+                    .iter()
+                    .any(|pattern| relative_path.contains(&pattern[..]))
+            })
+        })
+    }
+
+    /// Returns the entry of `path` if it is yielded: not hidden unless enabled, not a
+    /// broken symbolic link, which has no metadata, not a symbolic link unless enabled,
+    /// and not a special file unless enabled.
+    fn child_entry(
+        options: &FileSearcherOptions,
+        path: PathBuf,
+        depth: usize,
+    ) -> Option<SearchEntry> {
+        if !options.hidden
+            && path
+                .file_name()
+                .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+        {
+            return None;
+        }
+        if !options.file_types.symlinks
+            && platform::short_path(&path)
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.is_symlink())
+        {
+            return None;
+        }
+        let entry = SearchEntry::new(path, depth).ok()?;
+        (entry.is_file() || entry.is_dir() || options.specials).then_some(entry)
+    }
+
+    /// Pushes the frame of the `directory` whose entries are at `depth`, returning the
+    /// error that interrupted the reading of its entries, if any, after the frame of
+    /// the entries read so far.
+    fn open_directory(
+        &mut self,
+        directory: &Path,
+        depth: usize,
+        deferred: Option<SearchEntry>,
+    ) -> Result<()> {
+        self.load_excludes_file(directory);
+        // Entries are named after `directory`, not the path it was read from.
+        let mut read_dir = platform::short_path(directory).read_dir()?;
+        let open_count = self
+            .frames
+            .iter()
+            .filter(|frame| matches!(frame.listing, Listing::Open(..)))
+            .count();
+        let mut read_error = None;
+        let listing = match self.options.sort_order {
+            SortOrder::None if open_count < MAX_OPEN_DIRECTORIES => {
+                Listing::Open(read_dir, directory.to_path_buf())
+            }
+            SortOrder::None | SortOrder::Name => {
+                let mut paths = vec![];
+                for entry_result in &mut read_dir {
                     match entry_result {
-                        Ok(entry) => {
-                            let path = entry.path();
-                            let current_depth = path.components().count() - self.offset_depth;
-                            if (path.is_file() || path.is_dir())
-                                && current_depth <= self.options.max_depth
-                            {
-                                self.pending_paths.push_front(InnerEntryPath::Path(path));
-                            }
+                        Ok(entry) => paths.push(directory.join(entry.file_name())),
+                        Err(error) => {
+                            read_error = Some(error);
+                            break;
                         }
-                        Err(error) => return Some(Err(error)),
                     }
                 }
-                self.current_read_directory = None;
-            } else if let Some(entry_path) = self.pending_paths.pop_front() {
-                match entry_path {
-                    InnerEntryPath::DeferredPath(pending_path) => return Some(Ok(pending_path)),
-                    InnerEntryPath::Path(pending_path) => {
-                        if pending_path.is_dir() {
-                            match pending_path.read_dir() {
-                                Ok(read_dir) => {
-                                    self.current_read_directory = Some(read_dir);
-                                }
-                                Err(error) => return Some(Err(error)),
-                            }
-                            if self.options.overall {
-                                self.pending_paths
-                                    .push_front(InnerEntryPath::DeferredPath(pending_path));
-                            } else {
-                                return Some(Ok(pending_path));
-                            }
-                        } else {
-                            return Some(Ok(pending_path));
+                if self.options.sort_order == SortOrder::Name {
+                    paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+                }
+                Listing::Paths(paths.into_iter())
+            }
+            SortOrder::Mtime | SortOrder::Size => {
+                let mut entries = vec![];
+                for entry_result in &mut read_dir {
+                    match entry_result {
+                        Ok(entry) => {
+                            let path = directory.join(entry.file_name());
+                            entries.extend(Self::child_entry(&self.options, path, depth))
+                        }
+                        Err(error) => {
+                            read_error = Some(error);
+                            break;
                         }
                     }
                 }
-            } else {
-                return None;
+                self.options.sort_order.sort(&mut entries);
+                Listing::Entries(entries.into_iter())
+            }
+        };
+        self.frames.push(Frame {
+            listing,
+            depth,
+            deferred,
+        });
+        read_error.map_or(Ok(()), Err)
+    }
+
+    /// Returns the next entry of the innermost directory being walked, or `None` once
+    /// all of them were yielded.
+    fn next_child(&mut self) -> Option<WalkResult> {
+        let frame = self.frames.last_mut()?;
+        let depth = frame.depth;
+        loop {
+            let entry = match &mut frame.listing {
+                Listing::Open(read_dir, directory) => match read_dir.next()? {
+                    Ok(entry) => {
+                        let path = directory.join(entry.file_name());
+                        Self::child_entry(&self.options, path, depth)
+                    }
+                    Err(error) => return Some(Err((directory.clone(), error))),
+                },
+                Listing::Paths(paths) => Self::child_entry(&self.options, paths.next()?, depth),
+                Listing::Entries(entries) => Some(entries.next()?),
+            };
+            if let Some(entry) = entry {
+                return Some(Ok(entry));
+            }
+        }
+    }
+
+    /// Returns `entry`, walking it first when it is a directory to walk in
+    /// [`TraversalOrder::PostOrder`].
+    fn visit(&mut self, entry: SearchEntry) -> Option<WalkResult> {
+        let walked = entry.is_dir()
+            && entry.depth < self.options.max_depth
+            && !self.crosses_file_system(&entry);
+        if !walked {
+            return Some(Ok(entry));
+        }
+        if self.options.traversal_order == TraversalOrder::PostOrder {
+            let (path, depth) = (entry.path.clone(), entry.depth + 1);
+            return self
+                .open_directory(&path, depth, Some(entry))
+                .err()
+                .map(|error| Err((path, error)));
+        }
+        self.current_directory = Some((entry.path.clone(), entry.depth + 1));
+        Some(Ok(entry))
+    }
+
+    fn inner_next(&mut self) -> Option<WalkResult> {
+        if let Some(entry) = self.start_entry.take()
+            && let Some(result) = self.visit(entry)
+        {
+            return Some(result);
+        }
+        loop {
+            if let Some((directory, depth)) = self.current_directory.take() {
+                if self.options.traversal_order == TraversalOrder::BreadthFirst {
+                    self.pending_directories.push_back((directory, depth));
+                } else if let Err(error) = self.open_directory(&directory, depth, None) {
+                    return Some(Err((directory, error)));
+                }
+            }
+            if self.frames.is_empty() {
+                let (directory, depth) = self.pending_directories.pop_front()?;
+                if let Err(error) = self.open_directory(&directory, depth, None) {
+                    return Some(Err((directory, error)));
+                }
+                continue;
+            }
+            match self.next_child() {
+                Some(Ok(entry)) => {
+                    if let Some(result) = self.visit(entry) {
+                        return Some(result);
+                    }
+                }
+                Some(Err(error)) => return Some(Err(error)),
+                None => {
+                    if let Some(frame) = self.frames.pop()
+                        && let Some(directory) = frame.deferred
+                    {
+                        return Some(Ok(directory));
+                    }
+                }
             }
         }
-        None
     }
 }
 
 impl Iterator for IntoIter {
-    type Item = Result<PathBuf>;
+    type Item = Result<SearchEntry>;
 
     fn next(&mut self) -> Option<Self::Item> {
         while let Some(result) = self.inner_next() {
-            let path = match result {
-                Ok(path) => path,
-                Err(error) => return Some(Err(error)),
+            let entry = match result {
+                Ok(entry) => entry,
+                Err((path, error)) => match self.options.on_error {
+                    ErrorPolicy::Yield => return Some(Err(error)),
+                    ErrorPolicy::Skip => continue,
+                    ErrorPolicy::Collect => {
+                        self.skipped.push((path, error));
+                        continue;
+                    }
+                    ErrorPolicy::Abort => {
+                        self.start_entry = None;
+                        self.current_directory = None;
+                        self.frames.clear();
+                        self.pending_directories.clear();
+                        return Some(Err(error));
+                    }
+                },
             };
+            let path = &entry.path;
 
             let to_excludes = if self.options.excludes.is_empty() {
                 false
@@ -184,14 +823,18 @@ impl Iterator for IntoIter {
                     .excludes
                     .iter()
                     .any(|item| path.to_string_lossy().contains(&item[..]))
-            };
+            } || self.matches_scoped_excludes(path);
             if to_excludes {
-                if path.is_dir() {
+                if entry.is_dir() {
                     self.skip_current_directory();
                 }
                 continue;
             }
 
+            if entry.depth < self.options.min_depth || !self.options.file_types.matches(&entry) {
+                continue;
+            }
+
             let to_includes = if self.options.includes.is_empty() {
                 true
             } else {
@@ -218,69 +861,598 @@ impl Iterator for IntoIter {
                 continue;
             }
 
-            return Some(Ok(path));
+            if self.options.has_metadata_filters()
+                && entry.is_file()
+                && !self.options.matches_metadata(&entry.metadata)
+            {
+                continue;
+            }
+
+            return Some(Ok(entry));
         }
         None
     }
 }
 
-impl IntoIter {
-    pub fn filter_path<P: FnMut(&PathBuf) -> bool>(self, predicate: P) -> FilterPath<Self, P> {
-        FilterPath {
+/// Iterator of the entries of a [`FileSearcher`], possibly filtered or pruned, whose walk
+/// can be steered as it goes.
+pub trait SearchIterator: Iterator<Item = Result<SearchEntry>> {
+    /// Doesn't descend into the directory just yielded, without effect in
+    /// [`TraversalOrder::PostOrder`] where its entries were already yielded.
+    fn skip_current_directory(&mut self);
+
+    /// Paths that couldn't be read so far under [`ErrorPolicy::Collect`], with their
+    /// errors.
+    fn skipped(&self) -> &[(PathBuf, std::io::Error)];
+
+    /// Takes the paths of [`SearchIterator::skipped`], e.g. to report them as the walk
+    /// goes.
+    fn take_skipped(&mut self) -> Vec<(PathBuf, std::io::Error)>;
+
+    /// Only yields the entries matching `predicate`, still descending into the
+    /// directories it rejects, see [`SearchIterator::prune`] to skip them. The predicate
+    /// is given the depth and metadata read during the walk along with the path, so it
+    /// doesn't need to stat the entry again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::fs::{FileSearcher, SearchIterator};
+    /// #
+    /// let top_level_files = FileSearcher::new("src")
+    ///     .into_iter()
+    ///     .filter_entry(|entry| entry.depth() == 1 && entry.is_file());
+    /// assert!(top_level_files.count() > 0);
+    /// ```
+    fn filter_entry<P: FnMut(&SearchEntry) -> bool>(self, predicate: P) -> FilterEntry<Self, P>
+    where
+        Self: Sized,
+    {
+        FilterEntry {
             inner: self,
             predicate,
         }
     }
 
-    pub fn skip_current_directory(&mut self) {
-        self.current_read_directory = None;
+    /// Only yields the entries whose path matches `predicate`, as
+    /// [`SearchIterator::filter_entry`] does with the whole entry.
+    fn filter_path<P: FnMut(&PathBuf) -> bool>(
+        self,
+        mut predicate: P,
+    ) -> FilterEntry<Self, impl FnMut(&SearchEntry) -> bool>
+    where
+        Self: Sized,
+    {
+        self.filter_entry(move |entry| predicate(&entry.path))
+    }
+
+    /// Doesn't descend into the directories matching `predicate`, still yielding the
+    /// directories themselves, like the `-prune` of `find`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::fs::{FileSearcher, SearchIterator};
+    /// #
+    /// let sources = FileSearcher::new(".")
+    ///     .into_iter()
+    ///     .prune(|entry| entry.path().ends_with("target") || entry.path().ends_with(".git"))
+    ///     .filter_path(|path| path.extension().is_some_and(|extension| extension == "rs"));
+    /// assert!(sources.count() > 0);
+    /// ```
+    fn prune<P: FnMut(&SearchEntry) -> bool>(self, predicate: P) -> Prune<Self, P>
+    where
+        Self: Sized,
+    {
+        Prune {
+            inner: self,
+            predicate,
+        }
+    }
+}
+
+impl SearchIterator for IntoIter {
+    fn skip_current_directory(&mut self) {
+        self.current_directory = None;
+    }
+
+    fn skipped(&self) -> &[(PathBuf, std::io::Error)] {
+        &self.skipped
+    }
+
+    fn take_skipped(&mut self) -> Vec<(PathBuf, std::io::Error)> {
+        std::mem::take(&mut self.skipped)
     }
 }
 
+/// Iterator of [`SearchIterator::filter_entry`] and [`SearchIterator::filter_path`].
 #[derive(Debug)]
-pub struct FilterPath<I, P> {
+pub struct FilterEntry<I, P> {
     inner: I,
     predicate: P,
 }
 
-impl<P> Iterator for FilterPath<IntoIter, P>
+impl<I, P> Iterator for FilterEntry<I, P>
 where
-    P: FnMut(&PathBuf) -> bool,
+    I: SearchIterator,
+    P: FnMut(&SearchEntry) -> bool,
 {
-    type Item = Result<PathBuf>;
+    type Item = Result<SearchEntry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(result) = self.inner.next() {
-            let path = match result {
-                Ok(path) => path,
+        for result in self.inner.by_ref() {
+            let entry = match result {
+                Ok(entry) => entry,
                 Err(error) => return Some(Err(error)),
             };
-
-            if !(self.predicate)(&path) {
-                if path.is_dir() {
-                    self.inner.skip_current_directory();
-                }
-                continue;
+            if (self.predicate)(&entry) {
+                return Some(Ok(entry));
             }
-
-            return Some(Ok(path));
         }
         None
     }
 }
 
-impl<P> FilterPath<IntoIter, P>
+impl<I, P> SearchIterator for FilterEntry<I, P>
 where
-    P: FnMut(&PathBuf) -> bool,
+    I: SearchIterator,
+    P: FnMut(&SearchEntry) -> bool,
 {
-    pub fn filter_path(self, predicate: P) -> FilterPath<Self, P> {
-        FilterPath {
-            inner: self,
-            predicate,
+    fn skip_current_directory(&mut self) {
+        self.inner.skip_current_directory();
+    }
+
+    fn skipped(&self) -> &[(PathBuf, std::io::Error)] {
+        self.inner.skipped()
+    }
+
+    fn take_skipped(&mut self) -> Vec<(PathBuf, std::io::Error)> {
+        self.inner.take_skipped()
+    }
+}
+
+/// Iterator of [`SearchIterator::prune`].
+#[derive(Debug)]
+pub struct Prune<I, P> {
+    inner: I,
+    predicate: P,
+}
+
+impl<I, P> Iterator for Prune<I, P>
+where
+    I: SearchIterator,
+    P: FnMut(&SearchEntry) -> bool,
+{
+    type Item = Result<SearchEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.inner.next()?;
+        if let Ok(entry) = &result
+            && entry.is_dir()
+            && (self.predicate)(entry)
+        {
+            self.inner.skip_current_directory();
+        }
+        Some(result)
+    }
+}
+
+impl<I, P> SearchIterator for Prune<I, P>
+where
+    I: SearchIterator,
+    P: FnMut(&SearchEntry) -> bool,
+{
+    fn skip_current_directory(&mut self) {
+        self.inner.skip_current_directory();
+    }
+
+    fn skipped(&self) -> &[(PathBuf, std::io::Error)] {
+        self.inner.skipped()
+    }
+
+    fn take_skipped(&mut self) -> Vec<(PathBuf, std::io::Error)> {
+        self.inner.take_skipped()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn create_tree(name: &str, files: &[(&str, usize)]) -> PathBuf {
+        let root = testing::test_dir(name);
+        for (file, size) in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, vec![b'x'; *size]).unwrap();
         }
+        root
+    }
+
+    fn relative_paths(
+        root: &Path,
+        searcher: impl IntoIterator<Item = Result<SearchEntry>>,
+    ) -> Vec<String> {
+        searcher
+            .into_iter()
+            .map(|result| {
+                result
+                    .unwrap()
+                    .path()
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_sorts_entries_by_name() {
+        let root = create_tree(
+            "it_sorts_entries_by_name",
+            &[("c.txt", 1), ("a/y.txt", 1), ("a/x.txt", 1), ("b.txt", 1)],
+        );
+        assert_eq!(
+            relative_paths(&root, FileSearcher::new(&root).sort_by(SortOrder::Name)),
+            vec!["", "a", "a/x.txt", "a/y.txt", "b.txt", "c.txt"]
+        );
+    }
+
+    #[test]
+    fn it_iterates_a_searcher_several_times() {
+        let root = create_tree(
+            "it_iterates_a_searcher_several_times",
+            &[("a/x.txt", 1), ("b.txt", 1)],
+        );
+        let mut searcher = FileSearcher::new(&root).sort_by(SortOrder::Name);
+        assert_eq!(
+            relative_paths(&root, searcher.iter()),
+            vec!["", "a", "a/x.txt", "b.txt"]
+        );
+        searcher.set_min_depth(1).set_max_depth(1);
+        let copy = searcher.clone();
+        assert_eq!(relative_paths(&root, &searcher), vec!["a", "b.txt"]);
+        assert_eq!(relative_paths(&root, &searcher), vec!["a", "b.txt"]);
+        assert_eq!(relative_paths(&root, copy), vec!["a", "b.txt"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_yields_only_the_given_file_types() {
+        let root = create_tree(
+            "it_yields_only_the_given_file_types",
+            &[("a/x.txt", 1), ("b.txt", 1)],
+        );
+        std::os::unix::fs::symlink(root.join("a"), root.join("c")).unwrap();
+        let searcher = |files, dirs, symlinks| {
+            FileSearcher::new(&root)
+                .sort_by(SortOrder::Name)
+                .file_types(FileTypes {
+                    files,
+                    dirs,
+                    symlinks,
+                })
+        };
+        assert_eq!(
+            relative_paths(&root, searcher(true, false, true)),
+            vec!["a/x.txt", "b.txt", "c/x.txt"]
+        );
+        assert_eq!(
+            relative_paths(&root, searcher(false, true, true)),
+            vec!["", "a", "c"]
+        );
+        assert_eq!(
+            relative_paths(&root, searcher(true, true, false)),
+            vec!["", "a", "a/x.txt", "b.txt"]
+        );
     }
 
-    pub fn skip_current_directory(&mut self) {
-        self.inner.current_read_directory = None;
+    #[test]
+    fn it_prunes_directories_apart_from_filtering_entries() {
+        let root = create_tree(
+            "it_prunes_directories_apart_from_filtering_entries",
+            &[("a/x.txt", 1), ("a/b/y.txt", 1), ("c/z.txt", 1)],
+        );
+        let searcher = || {
+            FileSearcher::new(&root)
+                .sort_by(SortOrder::Name)
+                .into_iter()
+        };
+        assert_eq!(
+            relative_paths(&root, searcher().filter_path(|path| !path.ends_with("a"))),
+            vec!["", "a/b", "a/b/y.txt", "a/x.txt", "c", "c/z.txt"]
+        );
+        assert_eq!(
+            relative_paths(&root, searcher().prune(|entry| entry.path().ends_with("a"))),
+            vec!["", "a", "c", "c/z.txt"]
+        );
+        assert_eq!(
+            relative_paths(
+                &root,
+                searcher()
+                    .prune(|entry| entry.path().ends_with("b"))
+                    .filter_entry(|entry| entry.depth() == 2 && entry.is_file())
+            ),
+            vec!["a/x.txt", "c/z.txt"]
+        );
+        assert_eq!(
+            relative_paths(
+                &root,
+                searcher().filter_path(|path| path.ends_with("y.txt"))
+            ),
+            vec!["a/b/y.txt"]
+        );
+    }
+
+    #[test]
+    fn it_handles_unreadable_directories_by_policy() {
+        let name = "it_handles_unreadable_directories_by_policy";
+        // Removes the directory `a` once yielded, for the walk to fail reading it.
+        let walk = |policy| {
+            let root = create_tree(name, &[("a/x.txt", 1), ("c.txt", 1)]);
+            let mut walk = FileSearcher::new(&root)
+                .sort_by(SortOrder::Name)
+                .on_error(policy)
+                .into_iter();
+            assert!(walk.nth(1).unwrap().unwrap().path().ends_with("a"));
+            std::fs::remove_dir_all(root.join("a")).unwrap();
+            let rest: Vec<_> = walk.by_ref().map(|result| result.is_ok()).collect();
+            (rest, walk.take_skipped(), root)
+        };
+
+        assert_eq!(walk(ErrorPolicy::Yield).0, [false, true]);
+        assert_eq!(walk(ErrorPolicy::Skip).0, [true]);
+        let (rest, skipped, root) = walk(ErrorPolicy::Collect);
+        assert_eq!(rest, [true]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, root.join("a"));
+        assert_eq!(walk(ErrorPolicy::Abort).0, [false]);
+    }
+
+    #[test]
+    fn it_walks_the_tree_in_the_given_traversal_order() {
+        let root = create_tree(
+            "it_walks_the_tree_in_the_given_traversal_order",
+            &[("a/x.txt", 1), ("a/b/y.txt", 1), ("c.txt", 1)],
+        );
+        let searcher = |traversal_order| {
+            FileSearcher::new(&root)
+                .sort_by(SortOrder::Name)
+                .traversal_order(traversal_order)
+        };
+        assert_eq!(
+            relative_paths(&root, searcher(TraversalOrder::PreOrder)),
+            vec!["", "a", "a/b", "a/b/y.txt", "a/x.txt", "c.txt"]
+        );
+        assert_eq!(
+            relative_paths(&root, searcher(TraversalOrder::PostOrder)),
+            vec!["a/b/y.txt", "a/b", "a/x.txt", "a", "c.txt", ""]
+        );
+        assert_eq!(
+            relative_paths(&root, searcher(TraversalOrder::BreadthFirst)),
+            vec!["", "a", "c.txt", "a/b", "a/x.txt", "a/b/y.txt"]
+        );
+    }
+
+    #[test]
+    fn it_yields_entries_between_min_and_max_depth() {
+        let root = create_tree(
+            "it_yields_entries_between_min_and_max_depth",
+            &[("a/b/c/d.txt", 1), ("e.txt", 1)],
+        );
+        assert_eq!(
+            relative_paths(
+                &root,
+                FileSearcher::new(&root)
+                    .sort_by(SortOrder::Name)
+                    .min_depth(1)
+                    .max_depth(2)
+            ),
+            vec!["a", "a/b", "e.txt"]
+        );
+    }
+
+    #[test]
+    fn it_counts_depth_from_the_start_path_as_given() {
+        let root = create_tree(
+            "it_counts_depth_from_the_start_path_as_given",
+            &[("a/b.txt", 1)],
+        );
+        let start_path = root.join("a").join("..").join(".");
+        assert_eq!(
+            FileSearcher::new(&start_path)
+                .sort_by(SortOrder::Name)
+                .min_depth(2)
+                .into_iter()
+                .map(|result| result.unwrap().into_path())
+                .collect::<Vec<_>>(),
+            vec![start_path.join("a").join("b.txt")]
+        );
+    }
+
+    #[test]
+    fn it_prunes_hidden_entries() {
+        let root = create_tree(
+            "it_prunes_hidden_entries",
+            &[
+                (".git/config", 1),
+                (".env", 1),
+                ("a/.b.txt", 1),
+                ("a/c.txt", 1),
+            ],
+        );
+        assert_eq!(
+            relative_paths(
+                &root,
+                FileSearcher::new(&root)
+                    .sort_by(SortOrder::Name)
+                    .hidden(false)
+            ),
+            vec!["", "a", "a/c.txt"]
+        );
+        assert_eq!(
+            relative_paths(&root, FileSearcher::new(&root).sort_by(SortOrder::Name)).len(),
+            7
+        );
+    }
+
+    #[test]
+    fn it_skips_files_outside_the_size_range() {
+        let root = create_tree(
+            "it_skips_files_outside_the_size_range",
+            &[("a/small.txt", 1), ("a/medium.txt", 10), ("large.txt", 100)],
+        );
+        assert_eq!(
+            relative_paths(
+                &root,
+                FileSearcher::new(&root)
+                    .sort_by(SortOrder::Name)
+                    .min_size(Some(5))
+                    .max_size(Some(50))
+            ),
+            vec!["", "a", "a/medium.txt"]
+        );
+    }
+
+    #[test]
+    fn it_skips_files_outside_the_modification_range() {
+        let root = create_tree(
+            "it_skips_files_outside_the_modification_range",
+            &[("old.txt", 1), ("new.txt", 1)],
+        );
+        let now = SystemTime::now();
+        let day = std::time::Duration::from_secs(86400);
+        std::fs::File::options()
+            .write(true)
+            .open(root.join("old.txt"))
+            .unwrap()
+            .set_modified(now - 10 * day)
+            .unwrap();
+        assert_eq!(
+            relative_paths(
+                &root,
+                FileSearcher::new(&root)
+                    .sort_by(SortOrder::Name)
+                    .newer_than(Some(now - day))
+            ),
+            vec!["", "new.txt"]
+        );
+        assert_eq!(
+            relative_paths(
+                &root,
+                FileSearcher::new(&root)
+                    .sort_by(SortOrder::Name)
+                    .older_than(Some(now - day))
+            ),
+            vec!["", "old.txt"]
+        );
+    }
+
+    #[test]
+    fn it_stays_on_the_file_system_of_the_start_path() {
+        let (Ok(root), Ok(proc)) = (Path::new("/").metadata(), Path::new("/proc").metadata())
+        else {
+            return;
+        };
+        if platform::device(&root) == platform::device(&proc) {
+            return;
+        }
+        let paths: Vec<PathBuf> = FileSearcher::new("/")
+            .max_depth(2)
+            .same_file_system(true)
+            .into_iter()
+            .filter_map(|result| result.ok().map(SearchEntry::into_path))
+            .collect();
+        assert!(paths.contains(&PathBuf::from("/proc")));
+        assert!(
+            !paths
+                .iter()
+                .any(|path| path.parent() == Some(Path::new("/proc")))
+        );
+    }
+
+    #[test]
+    fn it_yields_entries_with_their_depth_and_metadata() {
+        let root = create_tree(
+            "it_yields_entries_with_their_depth_and_metadata",
+            &[("a/b.txt", 3)],
+        );
+        let entries: Vec<SearchEntry> = FileSearcher::new(&root)
+            .sort_by(SortOrder::Name)
+            .into_iter()
+            .map(|result| result.unwrap())
+            .collect();
+        let summary: Vec<(usize, bool, u64)> = entries
+            .iter()
+            .map(|entry| (entry.depth(), entry.is_dir(), entry.metadata().len()))
+            .filter(|(_, is_dir, _)| !is_dir)
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert!(entries[1].file_type().is_dir());
+        assert_eq!(summary, vec![(2, false, 3)]);
+    }
+
+    #[test]
+    fn it_scopes_excludes_files_to_their_subtree() {
+        let root = create_tree(
+            "it_scopes_excludes_files_to_their_subtree",
+            &[
+                (".acsync_excludes", 0),
+                ("a/.acsync_excludes", 0),
+                ("a/build/x.o", 1),
+                ("a/keep.txt", 1),
+                ("b/build/y.o", 1),
+                ("b/skip.log", 1),
+            ],
+        );
+        std::fs::write(root.join(".acsync_excludes"), "skip\n").unwrap();
+        std::fs::write(root.join("a/.acsync_excludes"), "build\n\n").unwrap();
+        assert_eq!(
+            relative_paths(
+                &root,
+                FileSearcher::new(&root)
+                    .sort_by(SortOrder::Name)
+                    .hidden(false)
+                    .excludes_file(Some(".acsync_excludes"))
+            ),
+            vec!["", "a", "a/keep.txt", "b", "b/build", "b/build/y.o"]
+        );
+    }
+
+    #[test]
+    fn it_sorts_entries_by_size() {
+        let root = create_tree(
+            "it_sorts_entries_by_size",
+            &[("a.txt", 30), ("b.txt", 10), ("c.txt", 20)],
+        );
+        assert_eq!(
+            relative_paths(&root, FileSearcher::new(&root).sort_by(SortOrder::Size)),
+            vec!["", "b.txt", "c.txt", "a.txt"]
+        );
+    }
+
+    #[test]
+    fn it_walks_trees_deeper_than_the_open_directories() {
+        let depth = MAX_OPEN_DIRECTORIES * 2;
+        let deepest = (0..depth).map(|_| "d").collect::<Vec<_>>().join("/");
+        let root = create_tree(
+            "it_walks_trees_deeper_than_the_open_directories",
+            &[(&format!("{deepest}/x.txt"), 1), ("d/y.txt", 1)],
+        );
+        for traversal_order in [TraversalOrder::PreOrder, TraversalOrder::PostOrder] {
+            let paths = relative_paths(
+                &root,
+                FileSearcher::new(&root).traversal_order(traversal_order),
+            );
+            assert_eq!(paths.len(), depth + 3);
+            let position = |path: &str| paths.iter().position(|item| item == path).unwrap();
+            let x_position = position(&format!("{deepest}/x.txt"));
+            match traversal_order {
+                TraversalOrder::PostOrder => assert!(x_position < position(&deepest)),
+                _ => assert!(x_position > position(&deepest)),
+            }
+        }
     }
 }
@@ -0,0 +1,86 @@
+//! **hook** contains the external commands run before and after a replicate run and
+//! on each of its actions, e.g. to mount a destination or send a notification.
+
+use crate::{
+    error::SyncError,
+    sync::{PlannedAction, SyncStats},
+};
+use std::process::Command;
+
+/// Runs `command` with `sh -c`, or `cmd /C` on Windows, adding the `env` variables to its environment.
+///
+/// Fails with [`SyncError::HookFailed`] when the command can't be started or exits
+/// with a non-zero status.
+pub fn run_hook(command: &str, env: &[(&str, String)]) -> Result<(), SyncError> {
+    let failed = |reason: String| SyncError::HookFailed {
+        command: command.to_string(),
+        reason,
+    };
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let status = Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .status()
+        .map_err(|error| failed(error.to_string()))?;
+    if !status.success() {
+        return Err(failed(status.to_string()));
+    }
+    Ok(())
+}
+
+/// Returns the variables describing an action to the per-file hook:
+/// `ACSYNC_ACTION`, `ACSYNC_FLAGS` and `ACSYNC_PATH`.
+pub fn action_env(action: &PlannedAction) -> Vec<(&'static str, String)> {
+    vec![
+        ("ACSYNC_ACTION", action.kind.to_string()),
+        ("ACSYNC_FLAGS", action.kind.flags().to_string()),
+        ("ACSYNC_PATH", action.path.to_string_lossy().into_owned()),
+    ]
+}
+
+/// Returns the variables describing the outcome of a run to the post hook:
+/// `ACSYNC_EXIT_CODE`, then the file counts of the stats when the run didn't fail.
+pub fn result_env(result: Result<&SyncStats, &SyncError>) -> Vec<(&'static str, String)> {
+    let stats = match result {
+        Ok(stats) => stats,
+        Err(error) => return vec![("ACSYNC_EXIT_CODE", error.exit_code().to_string())],
+    };
+    let exit_code = match stats.file_failed_count {
+        _ if stats.interrupted => SyncError::Interrupted.exit_code(),
+        0 => 0,
+        count => SyncError::FilesFailed(count).exit_code(),
+    };
+    vec![
+        ("ACSYNC_EXIT_CODE", exit_code.to_string()),
+        ("ACSYNC_FILE_COUNT", stats.file_count.to_string()),
+        ("ACSYNC_COPIED_COUNT", stats.file_copied_count.to_string()),
+        (
+            "ACSYNC_OVERRIDDEN_COUNT",
+            stats.file_overrided_count.to_string(),
+        ),
+        ("ACSYNC_MOVED_COUNT", stats.file_moved_count.to_string()),
+        ("ACSYNC_DELETED_COUNT", stats.file_deleted_count.to_string()),
+        ("ACSYNC_FAILED_COUNT", stats.file_failed_count.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn it_runs_hooks_with_their_environment() {
+        let env = [("ACSYNC_PATH", "notes.txt".to_string())];
+        assert!(run_hook("test \"$ACSYNC_PATH\" = notes.txt", &env).is_ok());
+        let Err(SyncError::HookFailed { reason, .. }) = run_hook("exit 3", &env) else {
+            panic!("expected the hook to fail");
+        };
+        assert!(reason.contains('3'));
+    }
+}
@@ -0,0 +1,617 @@
+//! **http** contains the HTTP/1.1 client of the object storage transport of
+//! [`RemoteReplicator`](crate::remote::RemoteReplicator), along with the few bits of
+//! percent-encoding and XML its requests and answers need.
+//!
+//! It only speaks plain HTTP: there's no TLS without a dependency, so HTTPS endpoints
+//! are reached through a local TLS proxy such as stunnel.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    net::TcpStream,
+};
+
+/// Longest status line or header line of a response.
+const MAX_LINE_SIZE: u64 = 64 * 1024;
+
+/// Location of an HTTP endpoint, written `http://HOST[:PORT][/PATH]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    /// Host and port of the server, as connected to.
+    pub address: String,
+    /// Value of the `Host` header, without the default port.
+    pub host: String,
+    /// Path of the endpoint, without a trailing `/`.
+    pub path: String,
+}
+
+impl HttpUrl {
+    /// Parses an `http://` URL, failing with the reason on `https://` URLs and any
+    /// other value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::http::HttpUrl;
+    /// #
+    /// let url = HttpUrl::parse("http://minio.local:9000/storage/").unwrap();
+    /// assert_eq!(url.address, "minio.local:9000");
+    /// assert_eq!(url.host, "minio.local:9000");
+    /// assert_eq!(url.path, "/storage");
+    /// assert_eq!(HttpUrl::parse("http://nas.local").unwrap().address, "nas.local:80");
+    /// assert!(HttpUrl::parse("https://s3.amazonaws.com").is_err());
+    /// ```
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value.starts_with("https://") {
+            return Err(
+                "uses TLS, which acsync doesn't speak, go through a local TLS proxy such as stunnel with an http:// URL"
+                    .to_string(),
+            );
+        }
+        let rest = value
+            .strip_prefix("http://")
+            .ok_or_else(|| "is not an http:// URL".to_string())?;
+        HttpUrl::from_parts(rest, 80).ok_or_else(|| "is not a valid URL".to_string())
+    }
+
+    /// Returns the URL of the `HOST[:PORT][/PATH]` part of a URL, connecting to the
+    /// `default_port` when there's none.
+    pub(crate) fn from_parts(value: &str, default_port: u16) -> Option<Self> {
+        let (host, path) = match value.find('/') {
+            Some(index) => value.split_at(index),
+            None => (value, ""),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let has_port = host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        let address = if has_port {
+            host.to_string()
+        } else {
+            format!("{host}:{default_port}")
+        };
+        Some(HttpUrl {
+            address,
+            host: host.to_string(),
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+/// Body of a request, read again when it's sent a second time.
+pub(crate) enum Body<'a> {
+    Bytes(&'a [u8]),
+    /// The first bytes of a file, from its start.
+    File(&'a mut File, u64),
+}
+
+impl Body<'_> {
+    fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File(_, length) => *length,
+        }
+    }
+
+    fn write_to<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Body::Bytes(bytes) => writer.write_all(bytes),
+            Body::File(file, length) => {
+                file.seek(SeekFrom::Start(0))?;
+                let copied = io::copy(&mut file.take(*length), writer)?;
+                if copied < *length {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "file shorter than the body",
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Status, headers and body of a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub reason: String,
+    /// Headers with lowercase names.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Returns the value of the header named `name`, given in lowercase.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Returns the response if it's successful, its error otherwise.
+    pub fn success(self) -> io::Result<Self> {
+        match self.is_success() {
+            true => Ok(self),
+            false => Err(self.error()),
+        }
+    }
+
+    /// Returns the error of an unsuccessful response, with the message of its XML body,
+    /// as S3 answers them, if any.
+    pub fn error(&self) -> io::Error {
+        let kind = match self.status {
+            404 => ErrorKind::NotFound,
+            401 | 403 => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Other,
+        };
+        let body = String::from_utf8_lossy(&self.body);
+        let message = match xml_element(&body, "Message") {
+            Some(message) => format!(
+                "HTTP {} {}: {}",
+                self.status,
+                self.reason,
+                xml_text(message)
+            ),
+            None => format!("HTTP {} {}", self.status, self.reason),
+        };
+        io::Error::new(kind, message)
+    }
+}
+
+/// Connection to an HTTP server, kept alive between requests.
+pub(crate) struct HttpClient {
+    address: String,
+    connection: Option<(BufReader<TcpStream>, TcpStream)>,
+}
+
+impl HttpClient {
+    /// Returns a client of the server at the `address`, connecting on the first
+    /// request.
+    pub fn new(address: &str) -> Self {
+        HttpClient {
+            address: address.to_string(),
+            connection: None,
+        }
+    }
+
+    /// Sends the `method` request of the `target`, with the `headers`, which must
+    /// include `Host`, and the `body`, returning the response once read whole.
+    ///
+    /// A request failing on a connection kept alive, which the server may have closed
+    /// meanwhile, is sent again on a new one.
+    pub fn send(
+        &mut self,
+        method: &str,
+        target: &str,
+        headers: &[(String, String)],
+        mut body: Body<'_>,
+    ) -> io::Result<Response> {
+        let reused = self.connection.is_some();
+        match self.try_send(method, target, headers, &mut body) {
+            Err(error)
+                if reused
+                    && matches!(
+                        error.kind(),
+                        ErrorKind::BrokenPipe
+                            | ErrorKind::ConnectionReset
+                            | ErrorKind::ConnectionAborted
+                            | ErrorKind::UnexpectedEof
+                    ) =>
+            {
+                self.try_send(method, target, headers, &mut body)
+            }
+            result => result,
+        }
+    }
+
+    fn try_send(
+        &mut self,
+        method: &str,
+        target: &str,
+        headers: &[(String, String)],
+        body: &mut Body<'_>,
+    ) -> io::Result<Response> {
+        let (reader, stream) = match &mut self.connection {
+            Some(connection) => connection,
+            None => {
+                let stream = TcpStream::connect(&self.address)?;
+                let reader = BufReader::new(stream.try_clone()?);
+                self.connection.insert((reader, stream))
+            }
+        };
+        let result = (|| {
+            let mut request = format!("{method} {target} HTTP/1.1\r\n");
+            for (name, value) in headers {
+                request += &format!("{name}: {value}\r\n");
+            }
+            let length = body.len();
+            if length > 0 || !matches!(method, "GET" | "HEAD" | "DELETE") {
+                request += &format!("Content-Length: {length}\r\n");
+            }
+            request += "\r\n";
+            let mut writer = BufWriter::new(&*stream);
+            writer.write_all(request.as_bytes())?;
+            body.write_to(&mut writer)?;
+            writer.flush()?;
+            drop(writer);
+            read_response(reader, method == "HEAD")
+        })();
+        match result {
+            Ok((response, true)) => Ok(response),
+            Ok((response, false)) => {
+                self.connection = None;
+                Ok(response)
+            }
+            Err(error) => {
+                self.connection = None;
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Reads a response, skipping the interim ones, along with whether the connection
+/// can be kept alive.
+fn read_response<R: BufRead>(reader: &mut R, head: bool) -> io::Result<(Response, bool)> {
+    let invalid = || io::Error::new(ErrorKind::InvalidData, "malformed HTTP response");
+    loop {
+        let status_line = read_line(reader)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default().to_string();
+        let status: u16 = parts
+            .next()
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(invalid)?;
+        let reason = parts.next().unwrap_or_default().to_string();
+        let mut headers = vec![];
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(invalid)?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+        if (100..200).contains(&status) {
+            continue;
+        }
+
+        let mut response = Response {
+            status,
+            reason,
+            headers,
+            body: vec![],
+        };
+        let mut keep_alive = version != "HTTP/1.0"
+            && !response
+                .header("connection")
+                .is_some_and(|connection| connection.eq_ignore_ascii_case("close"));
+        if head || status == 204 || status == 304 {
+            return Ok((response, keep_alive));
+        }
+        let chunked = response
+            .header("transfer-encoding")
+            .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+        if chunked {
+            response.body = read_chunked(reader)?;
+        } else if let Some(length) = response.header("content-length") {
+            let length: u64 = length.parse().map_err(|_| invalid())?;
+            reader.take(length).read_to_end(&mut response.body)?;
+            if (response.body.len() as u64) < length {
+                return Err(io::Error::from(ErrorKind::UnexpectedEof));
+            }
+        } else {
+            reader.read_to_end(&mut response.body)?;
+            keep_alive = false;
+        }
+        return Ok((response, keep_alive));
+    }
+}
+
+/// Reads a body sent with the chunked transfer encoding.
+fn read_chunked<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = vec![];
+    loop {
+        let line = read_line(reader)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "malformed chunk size"))?;
+        if size == 0 {
+            // Skips the trailer.
+            while !read_line(reader)?.is_empty() {}
+            return Ok(body);
+        }
+        let start = body.len();
+        reader.take(size).read_to_end(&mut body)?;
+        if ((body.len() - start) as u64) < size {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        read_line(reader)?;
+    }
+}
+
+/// Reads a line, without its line break, failing at the end of the stream.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = vec![];
+    reader.take(MAX_LINE_SIZE).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Err(io::Error::from(ErrorKind::UnexpectedEof));
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Returns the `Authorization` header value of the HTTP Basic authentication of
+/// `user` with `password`.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::http;
+/// #
+/// assert_eq!(http::basic_authorization("Aladdin", "open sesame"), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+/// ```
+pub fn basic_authorization(user: &str, password: &str) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let credentials = format!("{user}:{password}");
+    let mut encoded = String::from("Basic ");
+    for group in credentials.as_bytes().chunks(3) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (index, byte)| {
+            bits | (*byte as u32) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            match index <= group.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 63) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+/// Returns the `value` with every byte percent-encoded but the unreserved characters
+/// of RFC 3986 and those of `keep`.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::http;
+/// #
+/// assert_eq!(http::percent_encode("photos/été 2024.jpg", "/"), "photos/%C3%A9t%C3%A9%202024.jpg");
+/// assert_eq!(http::percent_encode("a/b", ""), "a%2Fb");
+/// ```
+pub fn percent_encode(value: &str, keep: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric()
+            || b"-_.~".contains(&byte)
+            || keep.as_bytes().contains(&byte)
+        {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+/// Returns the `value` with its percent-encoded bytes decoded, replacing the invalid
+/// UTF-8 sequences.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::http;
+/// #
+/// assert_eq!(http::percent_decode("photos/%C3%A9t%C3%A9%202024.jpg"), "photos/été 2024.jpg");
+/// assert_eq!(http::percent_decode("100%"), "100%");
+/// ```
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Returns the content of every element named `name`, whatever its namespace prefix,
+/// empty for the self-closing ones. Elements of the same name can't be nested.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::http;
+/// #
+/// let xml = "<d:response><d:href>/a</d:href><d:collection/></d:response><href>/b</href>";
+/// assert_eq!(http::xml_elements(xml, "href"), vec!["/a", "/b"]);
+/// assert_eq!(http::xml_elements(xml, "collection"), vec![""]);
+/// assert!(http::xml_elements(xml, "getcontentlength").is_empty());
+/// ```
+pub fn xml_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut elements = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with(['/', '?', '!']) {
+            continue;
+        }
+        let qualified_name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let local_name = qualified_name.rsplit(':').next().unwrap_or_default();
+        if local_name != name {
+            continue;
+        }
+        if tag.ends_with('/') {
+            elements.push("");
+            continue;
+        }
+        let Some(close) = rest.find(&format!("</{qualified_name}>")) else {
+            break;
+        };
+        elements.push(&rest[..close]);
+        rest = &rest[close + qualified_name.len() + 3..];
+    }
+    elements
+}
+
+/// Returns the content of the first element named `name`, see [`xml_elements`].
+pub fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    xml_elements(xml, name).into_iter().next()
+}
+
+/// Returns the text of XML content, with its entities and character references
+/// decoded.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::http;
+/// #
+/// assert_eq!(http::xml_text("Tom &amp; Jerry&#39;s &lt;notes&gt;"), "Tom & Jerry's <notes>");
+/// ```
+pub fn xml_text(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        text += &rest[..start];
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(char) => {
+                text.push(char);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text + rest
+}
+
+/// Returns the `value` escaped to be written as XML content.
+pub(crate) fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn it_reads_chunked_responses_and_keeps_connections_alive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            for answer in [
+                "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;x=y\r\n world\r\n0\r\n\r\n",
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 47\r\n\r\n<Error><Message>No such key</Message></Error>\n\n",
+            ] {
+                while !read_line(&mut reader).unwrap().is_empty() {}
+                writer.write_all(answer.as_bytes()).unwrap();
+            }
+        });
+
+        let mut client = HttpClient::new(&address);
+        let headers = [("Host".to_string(), address.clone())];
+        let response = client
+            .send("GET", "/greeting", &headers, Body::Bytes(&[]))
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"hello world");
+        let error = client
+            .send("GET", "/missing", &headers, Body::Bytes(&[]))
+            .unwrap()
+            .success()
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(error.to_string(), "HTTP 404 Not Found: No such key");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn it_sends_requests_again_on_a_closed_connection() {
+        // Answers a single request per connection, closed without telling.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                while !read_line(&mut reader).unwrap().is_empty() {}
+                let mut body = [0; 5];
+                reader.read_exact(&mut body).unwrap();
+                let mut writer = stream;
+                writer
+                    .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 5\r\n\r\n")
+                    .unwrap();
+                writer.write_all(&body).unwrap();
+            }
+        });
+
+        let mut client = HttpClient::new(&address);
+        let headers = [("Host".to_string(), address.clone())];
+        for _ in 0..2 {
+            let response = client
+                .send("PUT", "/notes.txt", &headers, Body::Bytes(b"notes"))
+                .unwrap();
+            assert_eq!(response.status, 201);
+            assert_eq!(response.body, b"notes");
+        }
+        server.join().unwrap();
+    }
+}
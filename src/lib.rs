@@ -1,2 +1,42 @@
+pub mod archive;
+pub mod bench;
+pub mod cache;
+pub mod cancel;
+pub mod clean;
 pub mod cli_helper;
+pub mod config;
+pub mod copy;
+pub mod doctor;
+pub mod error;
+pub mod freeze;
 pub mod fs;
+pub mod hash;
+pub mod hook;
+pub mod http;
+pub mod lock;
+pub mod log;
+pub mod manifest;
+pub mod names;
+pub mod notify;
+pub mod observer;
+pub mod owner;
+pub mod pipeline;
+pub mod platform;
+pub mod progress;
+pub mod prompt;
+pub mod prune;
+pub mod remote;
+pub mod report;
+pub mod review;
+pub mod s3;
+pub mod signal;
+pub mod state;
+pub mod stats;
+pub mod store;
+pub mod sync;
+#[cfg(test)]
+mod testing;
+pub mod undo;
+pub mod unicode;
+pub mod verify;
+pub mod webdav;
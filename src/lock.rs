@@ -0,0 +1,290 @@
+//! **lock** contains the lock file keeping two runs from replicating into the same
+//! destination at the same time.
+
+use crate::{
+    error::{IoResultExt, SyncError},
+    warn,
+};
+use std::{
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the lock file created at the root of a destination during a run.
+pub const LOCK_FILE_NAME: &str = ".acsync_lock";
+
+/// Interval between two attempts to take a lock held by another run.
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Age past which the file reserving the removal of a stale lock is assumed to be left
+/// by a run that crashed while removing it.
+const BREAKER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Counter keeping the temporary lock files of the threads of this process apart.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(unix)]
+const ESRCH: i32 = 3;
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn kill(pid: std::ffi::c_int, signal: std::ffi::c_int) -> std::ffi::c_int;
+}
+
+#[cfg(windows)]
+const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+#[cfg(windows)]
+const STILL_ACTIVE: u32 = 259;
+
+#[cfg(windows)]
+unsafe extern "system" {
+    fn OpenProcess(access: u32, inherit_handle: i32, pid: u32) -> *mut std::ffi::c_void;
+    fn GetExitCodeProcess(process: *mut std::ffi::c_void, exit_code: *mut u32) -> i32;
+    fn CloseHandle(handle: *mut std::ffi::c_void) -> i32;
+}
+
+/// Lock of a destination directory, released when dropped.
+#[derive(Debug)]
+pub struct DestinationLock {
+    path: PathBuf,
+    /// Content written in the lock file, so that dropping the lock only removes its own.
+    content: String,
+}
+
+impl DestinationLock {
+    /// Takes the lock of the `directory` by creating its lock file with the process ID
+    /// and the start time of the run.
+    ///
+    /// The lock file is written aside and linked into place, so that other runs never
+    /// see it empty. A lock left by a process that no longer runs is removed, while one
+    /// whose content can't be read as a process ID is considered held. A lock held by
+    /// another run is waited for up to `wait`, failing with [`SyncError::Locked`]
+    /// afterwards.
+    pub fn acquire<P: AsRef<Path>>(directory: P, wait: Duration) -> Result<Self, SyncError> {
+        let path = directory.as_ref().join(LOCK_FILE_NAME);
+        let started = Instant::now();
+        loop {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let content = format!("{}\t{timestamp}\n", std::process::id());
+            if create_lock(&path, &content)? {
+                return Ok(DestinationLock { path, content });
+            }
+
+            let Some(content) = read_lock(&path)? else {
+                continue;
+            };
+            let owner = parse_lock(&content);
+            if let Some((pid, _)) = owner
+                && !is_running(pid)
+            {
+                warn!("Removing stale lock {} of process {pid}", path.display());
+                if remove_stale_lock(&path, &content)? {
+                    continue;
+                }
+            }
+            if started.elapsed() >= wait {
+                let (pid, timestamp) = owner.unwrap_or_default();
+                return Err(SyncError::Locked {
+                    path,
+                    pid,
+                    since: UNIX_EPOCH + Duration::from_secs(timestamp),
+                });
+            }
+            std::thread::sleep(RETRY_INTERVAL);
+        }
+    }
+}
+
+impl Drop for DestinationLock {
+    fn drop(&mut self) {
+        if read_lock(&self.path).is_ok_and(|content| content.as_ref() == Some(&self.content)) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Creates the lock file at `path` with its whole `content` at once, returning false if
+/// it already exists.
+///
+/// The content is written to a temporary file hard linked to `path`, which fails if it
+/// exists. File systems without hard links fall back to creating the lock file before
+/// writing it, leaving a short window during which it's seen empty.
+fn create_lock(path: &Path, content: &str) -> Result<bool, SyncError> {
+    let temp_path = path.with_file_name(format!(
+        "{LOCK_FILE_NAME}.{}.{}",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&temp_path, content).with_path(&temp_path)?;
+    let linked = std::fs::hard_link(&temp_path, path);
+    let _ = std::fs::remove_file(&temp_path);
+    match linked {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == ErrorKind::AlreadyExists => Ok(false),
+        Err(_) => match std::fs::File::create_new(path) {
+            Ok(mut file) => {
+                file.write_all(content.as_bytes()).with_path(path)?;
+                Ok(true)
+            }
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(error) => Err(error).with_path(path),
+        },
+    }
+}
+
+/// Removes the lock file at `path` left by a process that no longer runs, provided it
+/// still has the `stale` content, returning false if another run is removing it.
+///
+/// Runs finding the same stale lock take turns through a file created next to it, so
+/// that none removes the lock another one took after removing the stale one.
+fn remove_stale_lock(path: &Path, stale: &str) -> Result<bool, SyncError> {
+    let breaker = path.with_file_name(format!("{LOCK_FILE_NAME}.breaking"));
+    match std::fs::File::create_new(&breaker) {
+        Ok(_) => {}
+        Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+            let abandoned = std::fs::metadata(&breaker)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    modified
+                        .elapsed()
+                        .is_ok_and(|elapsed| elapsed >= BREAKER_TIMEOUT)
+                });
+            if abandoned {
+                let _ = std::fs::remove_file(&breaker);
+            }
+            return Ok(false);
+        }
+        Err(error) => return Err(error).with_path(&breaker),
+    }
+    let removed = match read_lock(path) {
+        Ok(Some(content)) if content == stale => match std::fs::remove_file(path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error).with_path(path),
+            _ => Ok(true),
+        },
+        Ok(_) => Ok(true),
+        Err(error) => Err(error),
+    };
+    let _ = std::fs::remove_file(&breaker);
+    removed
+}
+
+/// Returns the content of the lock file, or `None` if it was removed in the meantime.
+fn read_lock(path: &Path) -> Result<Option<String>, SyncError> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error).with_path(path),
+    }
+}
+
+/// Returns the process ID and start timestamp written in the lock file `content`, or
+/// `None` if it doesn't start with a process ID.
+fn parse_lock(content: &str) -> Option<(u32, u64)> {
+    let mut fields = content.trim().split('\t');
+    let pid = fields.next()?.parse().ok()?;
+    let timestamp = fields
+        .next()
+        .and_then(|timestamp| timestamp.parse().ok())
+        .unwrap_or(0);
+    Some((pid, timestamp))
+}
+
+/// Returns true if a process with the `pid` exists, even one of another user.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = std::ffi::c_int::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: the null signal only checks whether the process exists.
+    pid > 0
+        && (unsafe { kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() != Some(ESRCH))
+}
+
+/// Returns true if a process with the `pid` is still running, assuming it is when it
+/// can't be queried, e.g. one of another user.
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    if pid == 0 {
+        return false;
+    }
+    // SAFETY: the handle is checked before use and closed right after.
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return std::io::Error::last_os_error().kind() == ErrorKind::PermissionDenied;
+        }
+        let mut exit_code = 0;
+        let queried = GetExitCodeProcess(process, &mut exit_code) != 0;
+        CloseHandle(process);
+        !queried || exit_code == STILL_ACTIVE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_refuses_a_held_lock_and_removes_stale_ones() {
+        let directory = testing::test_dir("it_refuses_a_held_lock_and_removes_stale_ones");
+
+        let lock = DestinationLock::acquire(&directory, Duration::ZERO).unwrap();
+        let Err(SyncError::Locked { pid, .. }) =
+            DestinationLock::acquire(&directory, Duration::ZERO)
+        else {
+            panic!("expected the lock to be held");
+        };
+        assert_eq!(pid, std::process::id());
+        drop(lock);
+        assert!(!directory.join(LOCK_FILE_NAME).exists());
+
+        std::fs::write(directory.join(LOCK_FILE_NAME), "0\t0\n").unwrap();
+        assert!(DestinationLock::acquire(&directory, Duration::ZERO).is_ok());
+
+        std::fs::write(directory.join(LOCK_FILE_NAME), "").unwrap();
+        assert!(matches!(
+            DestinationLock::acquire(&directory, Duration::ZERO),
+            Err(SyncError::Locked { pid: 0, .. })
+        ));
+        assert!(directory.join(LOCK_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn it_gives_a_contended_lock_to_a_single_run() {
+        let directory = testing::test_dir("it_gives_a_contended_lock_to_a_single_run");
+
+        for stale in [false, true] {
+            if stale {
+                std::fs::write(directory.join(LOCK_FILE_NAME), "0\t0\n").unwrap();
+            }
+            let barrier = std::sync::Barrier::new(8);
+            let acquired = std::thread::scope(|scope| {
+                let threads: Vec<_> = (0..8)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            barrier.wait();
+                            let lock = DestinationLock::acquire(&directory, Duration::ZERO);
+                            // Holds the lock until every thread tried to take it.
+                            barrier.wait();
+                            lock.is_ok()
+                        })
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().unwrap())
+                    .filter(|&acquired| acquired)
+                    .count()
+            });
+            assert_eq!(acquired, 1);
+            assert!(!directory.join(LOCK_FILE_NAME).exists());
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! **log** contains the logging facility used to report what a run is doing, printing
+//! records to the terminal and optionally appending them, timestamped, to a log file.
+//!
+//! Records are written with the [`error!`](crate::error!), [`warn!`](crate::warn!),
+//! [`info!`](crate::info!), [`debug!`](crate::debug!) and [`trace!`](crate::trace!)
+//! macros, and only the ones at or above the configured [`Level`] are kept.
+//!
+//! Under systemd, records can instead be prefixed by their syslog priority, e.g.
+//! `<4>` for a warning, which the journal strips and keeps as the record priority.
+
+use crate::{
+    cli_helper,
+    error::{IoResultExt, SyncError},
+};
+use std::{
+    fmt,
+    fs::File,
+    io::Write,
+    path::Path,
+    str::FromStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+    },
+    time::SystemTime,
+};
+
+/// Severity of a log record, from the most to the least important.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!(
+                "unknown log level {value:?}, expected one of error, warn, info, debug or trace"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Level {
+    /// Returns the syslog priority of the level, from 3 for errors to 7 for debug and
+    /// trace records.
+    pub fn priority(&self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static JOURNAL: AtomicBool = AtomicBool::new(false);
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Keeps the records at or above `level`, [`Level::Info`] by default.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns true if records of `level` are kept.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Prefixes the printed records by their syslog priority instead of their level, for
+/// the journal. Usually enabled when `JOURNAL_STREAM` is set, systemd setting it when
+/// the output of a service goes to the journal.
+pub fn set_journal(flag: bool) {
+    JOURNAL.store(flag, Ordering::Relaxed);
+}
+
+/// Appends the kept records to the file at `path`, besides printing them.
+pub fn set_file<P: AsRef<Path>>(path: P) -> Result<(), SyncError> {
+    let path = path.as_ref();
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_path(path)?;
+    *LOG_FILE.lock().unwrap_or_else(|error| error.into_inner()) = Some(file);
+    Ok(())
+}
+
+/// Writes a record of `level`, used by the logging macros.
+///
+/// Errors and warnings are printed to the standard error prefixed by their level, the
+/// other records to the standard output as they are. In journal mode, they are all
+/// printed to the standard error prefixed by their priority.
+pub fn log(level: Level, arguments: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    match level {
+        _ if JOURNAL.load(Ordering::Relaxed) => eprintln!("<{}>{arguments}", level.priority()),
+        Level::Error | Level::Warn => eprintln!("{level}: {arguments}"),
+        _ => println!("{arguments}"),
+    }
+    if let Some(file) = LOG_FILE
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_mut()
+    {
+        let _ = writeln!(
+            file,
+            "{} {level:<5} {arguments}",
+            cli_helper::format_time(SystemTime::now())
+        );
+    }
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log::log($crate::log::Level::Error, format_args!($($arg)+)) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log::log($crate::log::Level::Warn, format_args!($($arg)+)) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log::log($crate::log::Level::Info, format_args!($($arg)+)) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log::log($crate::log::Level::Debug, format_args!($($arg)+)) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log::log($crate::log::Level::Trace, format_args!($($arg)+)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_levels_as_displayed() {
+        for level in [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ] {
+            assert_eq!(level.to_string().parse(), Ok(level));
+        }
+        assert!("verbose".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn it_orders_levels_by_verbosity() {
+        assert!(Level::Error < Level::Warn);
+        assert!(Level::Debug < Level::Trace);
+    }
+}
@@ -1,255 +1,1545 @@
-use acsync::fs::FileSearcher;
+use acsync::bench::Bench;
+use acsync::clean::Cleaner;
+use acsync::config::{Config, Value};
+use acsync::doctor::Doctor;
+use acsync::error::IoResultExt;
+use acsync::error::SyncError;
+use acsync::fs::{FileSearcher, SortOrder};
+use acsync::hook;
+use acsync::log::{self, Level};
+use acsync::manifest::Manifest;
+use acsync::notify;
+use acsync::owner::IdMap;
+use acsync::prompt::AutoPrompter;
+use acsync::prune::Pruner;
+use acsync::remote::{self, RemoteReplicator, Server};
+use acsync::report::{History, RunReport};
+use acsync::review::review;
+use acsync::s3::{S3Config, S3Url};
+use acsync::signal;
+use acsync::stats::Analyzer;
+use acsync::store::ContentStore;
+use acsync::sync::read_patterns;
+use acsync::sync::{ChangeDetection, ConflictPolicy, ExistingPolicy, Replicator, SyncStats};
+use acsync::undo::Undoer;
+use acsync::verify::Verifier;
+use acsync::webdav::{self, DavConfig};
 use acsync::{
-    cli_helper::{self, Arg, ArgsParser},
-    create_args_parser,
+    cli_helper::{self, Arg, ArgsParser, Req},
+    create_args_parser, debug, error, info, warn,
 };
-use std::os::unix::fs::MetadataExt;
+use std::hash::{BuildHasher, RandomState};
+use std::net::TcpListener;
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
 create_args_parser! {
     @attr #[derive(Debug)]
     /// This is another convenient file synchronizer
     enum Command {
         /// Copy files from a origin to a destination directory
+        @alias(rep, cp)
+        @examples(
+            "acsync replicate ~/Documents /media/backup/Documents",
+            "acsync replicate ~/Documents /media/backup/Documents --dryrun --delete",
+            "acsync replicate /media/backup/Documents ~/Documents --back",
+        )
         Replicate {
             /// Directory with original files
-            origin: Arg<String>,
+            @env ACSYNC_ORIGIN
+            @validate(cli_helper::location)
+            origin: Req<String>,
             /// Destination directory to where files will be replicated
-            destination: Arg<String>,
-            /// Question to user if desire override dated files
+            @env ACSYNC_DESTINATION
+            @validate(cli_helper::location)
+            destination: Req<String>,
+            /// Question to user if desire override dated files (same as --on_conflict=prompt)
             override_question: Option<bool>,
+            /// How to resolve conflicting files: prompt, newer, older, larger, skip (default) or rename
+            on_conflict: Option<String>,
+            /// Tell conflicting files by their size only, for file systems with unreliable timestamps
+            size_only: Option<bool>,
+            /// Always tell files present in both directories as conflicting, whatever their size and date
+            ignore_times: Option<bool>,
+            /// Consider modification dates within DURATION (e.g. 2s for FAT) of each other as equal
+            modify_window: Option<String>,
+            /// Only update the files already in the destination, never creating new ones
+            existing: Option<bool>,
+            /// Only create the files missing in the destination, never touching existing ones
+            ignore_existing: Option<bool>,
+            /// How to handle origin files whose paths only differ by case: auto (default, error on case-insensitive destinations), error, rename or ignore
+            case_collisions: Option<String>,
+            /// File system whose name restrictions the destination paths are checked against: auto (default, detected), fat, ntfs or posix
+            target_fs: Option<String>,
+            /// How to handle origin paths invalid on the destination file system: error (default), skip or transcode (recorded in .acsync_names)
+            invalid_names: Option<String>,
+            /// Unicode normalization form of the destination names, also used to compare them with the origin ones: nfc, nfd or off (default)
+            normalize: Option<String>,
+            /// Answer yes to every question, such as overriding a file under --on_conflict=prompt
+            yes: Option<bool>,
+            /// Answer no to every question
+            no: Option<bool>,
             /// Restore back from destination directory to original director
             back: Option<bool>,
             /// Run command without sideeffect
             dryrun: Option<bool>,
+            /// Review the planned actions and apply only the accepted ones
+            interactive: Option<bool>,
+            /// Print one line per action with its change flags, e.g. ">st. docs/notes.txt"
+            itemize: Option<bool>,
+            /// Draw a progress line on the standard error
+            progress: Option<bool>,
+            /// Scan the origin first to show the percentage done and an ETA (implies --progress)
+            prescan: Option<bool>,
+            /// Limit copy throughput to RATE bytes per second (e.g. 10M, 500K)
+            @env ACSYNC_BWLIMIT
+            bwlimit: Option<String>,
+            /// Keep going when a file fails, reporting the failures at the end
+            ignore_errors: Option<bool>,
+            /// Write files directly to the destination instead of a temporary file renamed on success
+            inplace: Option<bool>,
+            /// Copy overridden files whole instead of only writing their changed blocks
+            whole_file: Option<bool>,
+            /// Clone files on copy-on-write filesystems: auto (default), always or never
+            reflink: Option<String>,
+            /// Keep the holes of sparse files, such as disk images, in the destination
+            sparse: Option<bool>,
+            /// Include dot-files and dot-directories, enabled by default (--no_hidden skips them)
+            hidden: Option<bool>,
+            /// Recreate FIFOs and sockets in the destination
+            specials: Option<bool>,
+            /// Recreate block and character devices in the destination (usually requires root)
+            devices: Option<bool>,
+            /// Don't descend into directories on other file systems, such as /proc or network mounts
+            one_file_system: Option<bool>,
+            /// Fix the permissions, ownership and symlink targets drifted in the destination for unchanged files
+            perms: Option<bool>,
+            /// Translate the origin user IDs before fixing the destination ones with --perms, as comma-separated FROM:TO pairs of IDs or names (e.g. 1000:1001,www-data:nginx,*:nobody)
+            usermap: Option<String>,
+            /// Translate the origin group IDs before fixing the destination ones with --perms, as comma-separated FROM:TO pairs of IDs or names
+            groupmap: Option<String>,
+            /// Preserve the POSIX ACLs (Linux) or extended ACLs (macOS) of the files and directories, fixing drifted ones with --perms
+            acls: Option<bool>,
+            /// Only repair the permissions, ownership, modification times and symlink targets of the files already in the destination, copying and deleting nothing
+            metadata_only: Option<bool>,
+            /// Hash every file again instead of reusing the checksums cached in the destination
+            no_cache: Option<bool>,
+            /// Skip files smaller than SIZE (e.g. 1K)
+            min_size: Option<String>,
+            /// Skip files larger than SIZE (e.g. 2G)
+            max_size: Option<String>,
+            /// Comma-separated patterns to exclude, in addition to those of .acsync_excludes (repeatable)
+            excludes: Vec<String>,
+            /// Only replicate the relative paths listed in FILE, one per line, or in the standard input with -
+            files_from: Option<String>,
+            /// Only consider files modified after an age (e.g. 7d) or UTC date (e.g. 2024-01-01)
+            newer_than: Option<String>,
+            /// Only consider files modified before an age (e.g. 30d) or UTC date (e.g. 2024-01-01)
+            older_than: Option<String>,
+            /// Hard-link files unchanged in this previous snapshot directory instead of copying them
+            link_dest: Option<String>,
+            /// Keep the previous version of overridden files as file~
+            backup: Option<bool>,
+            /// Keep the previous version of overridden files in DIR, preserving their relative paths
+            backup_dir: Option<String>,
+            /// Delete the destination files replicated by a previous run and removed from the origin
+            delete: Option<bool>,
+            /// Move deleted files to a timestamped directory of the trash directory instead (implies --delete)
+            delete_to_trash: Option<bool>,
+            /// Abort before replicating anything if --delete would delete more than COUNT files
+            max_delete: Option<String>,
+            /// Abort before replicating anything if --delete would delete more than PERCENT % of the files replicated before
+            max_delete_percent: Option<String>,
+            /// Trash directory of --delete_to_trash (default .acsync_trash in the destination)
+            trash_dir: Option<String>,
+            /// Read back every copied file and compare it against the data hashed while copying it
+            verify: Option<bool>,
+            /// Read back PERCENT % (e.g. 5%) of the copied files, picked at random, and compare them against the origin
+            verify_sample: Option<String>,
+            /// Retry the copies failing with a transient I/O error or a checksum mismatch up to COUNT times
+            retries: Option<String>,
+            /// Wait DURATION (e.g. 2s, the default) before the first retry of --retries, twice as long before every other one
+            retry_delay: Option<String>,
+            /// Give up on the copy of a file taking longer than DURATION (e.g. 60s), counting it as failed and going on with the next ones
+            timeout: Option<String>,
+            /// Copy the files changed while being copied again up to COUNT times, then count them as failed instead of keeping a torn copy
+            recopies: Option<String>,
+            /// Read the files from a read-only snapshot of the origin, which must be a Btrfs subvolume
+            snapshot: Option<bool>,
+            /// Order the files are copied in, so that an interrupted run already copied those that matter most: walk (default), smallest-first, largest-first or newest-first
+            order: Option<String>,
+            /// Store files compressed in the destination with zstd or gzip
+            compress: Option<String>,
+            /// Store files deduplicated in a content-addressed store, with one snapshot per run
+            store: Option<bool>,
+            /// Hash the upcoming files of --store on COUNT reader and COUNT hasher threads while storing, 0 to hash them one by one (default the CPU count)
+            hash_threads: Option<String>,
+            /// Wait up to DURATION (e.g. 30s) for another run to release the destination lock instead of failing
+            wait_lock: Option<String>,
+            /// Check that the files to copy fit in the free space of the destination before copying them, at the cost of a dry run walking both directories first
+            check_space: Option<bool>,
+            /// Replicate with a warning when a safety check fails, e.g. a protected destination or one lacking the free space for the files to copy
+            force: Option<bool>,
+            /// Keep running, replicating again every DURATION (e.g. 15m) plus up to a tenth of it
+            every: Option<String>,
+            /// Shell command run before the replication, which is aborted if it fails
+            pre_cmd: Option<String>,
+            /// Shell command run after the replication, even when it failed
+            post_cmd: Option<String>,
+            /// Shell command run on each action, with ACSYNC_ACTION, ACSYNC_FLAGS and ACSYNC_PATH
+            file_cmd: Option<String>,
+            /// Pre-shared token of the server of an acsync:// origin or destination
+            @env ACSYNC_TOKEN
+            token: Option<String>,
+            /// Compress the transfers with an acsync:// URL with zstd at level N (1-19), except already compressed files
+            compress_level: Option<String>,
+            /// Use the values of the NAME profile of the config file for the options not informed
+            @env ACSYNC_PROFILE
+            profile: Option<String>,
+            /// Config file with the profiles (default ~/.config/acsync/config.toml)
+            @env ACSYNC_CONFIG
+            config: Option<String>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Report differences between a destination directory and its origin without copying
+        @examples(
+            "acsync verify ~/Documents /media/backup/Documents",
+        )
+        Verify {
+            /// Directory with original files
+            @validate(cli_helper::existing_directory)
+            origin: Req<String>,
+            /// Destination directory to be verified
+            @validate(cli_helper::existing_directory)
+            destination: Req<String>,
+            /// Include dot-files and dot-directories, enabled by default (--no_hidden skips them)
+            hidden: Option<bool>,
+            /// Hash every file again instead of reusing the checksums cached in the destination
+            no_cache: Option<bool>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Materialize a snapshot of a content-addressed store into a directory
+        @examples(
+            "acsync restore /media/backup/Documents ~/Documents --snapshot=1700000000",
+        )
+        Restore {
+            /// Store written by replicate --store
+            @validate(cli_helper::existing_directory)
+            store: Req<String>,
+            /// Directory where the files of the snapshot will be restored
+            destination: Req<String>,
+            /// Name of the snapshot to restore (default the newest)
+            snapshot: Option<String>,
+            /// Run command without sideeffect
+            dryrun: Option<bool>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Remove the old snapshots of a store or of a directory of dated snapshot directories
+        @examples(
+            "acsync prune /media/backup --keep_daily=7 --keep_weekly=4 --keep_monthly=6",
+        )
+        Prune {
+            /// Store written by replicate --store, or directory of snapshot directories named after their date
+            @validate(cli_helper::existing_directory)
+            destination: Req<String>,
+            /// Keep the N most recent snapshots (default 1)
+            keep_last: Option<String>,
+            /// Keep the most recent snapshot of each of the last N days
+            keep_daily: Option<String>,
+            /// Keep the most recent snapshot of each of the last N weeks
+            keep_weekly: Option<String>,
+            /// Keep the most recent snapshot of each of the last N months
+            keep_monthly: Option<String>,
+            /// Run command without sideeffect
+            dryrun: Option<bool>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Serve a directory to replicate runs of other machines, as acsync://HOST:PORT/PATH
+        @examples(
+            "acsync serve --root /srv/backup --listen 0.0.0.0:8733 --allow_cleartext",
+        )
+        Serve {
+            /// Directory served
+            @validate(cli_helper::existing_directory)
+            root: Option<String>,
+            /// Address and port to listen on (default 127.0.0.1:8733)
+            listen: Option<String>,
+            /// Listen on an address other than a loopback one, although the traffic isn't encrypted
+            allow_cleartext: Option<bool>,
+            /// Refuse the requests modifying the served directory
+            read_only: Option<bool>,
+            /// Pre-shared token the clients must authenticate with
+            @env ACSYNC_TOKEN
+            token: Option<String>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Remove temporary files of interrupted runs and stale state from a destination directory
+        @examples(
+            "acsync clean /media/backup/Documents --prune_empty_dirs --dryrun",
+        )
+        Clean {
+            /// Destination directory to be cleaned
+            @validate(cli_helper::existing_directory)
+            destination: Req<String>,
+            /// Run command without sideeffect
+            dryrun: Option<bool>,
+            /// Also remove the directories left empty
+            prune_empty_dirs: Option<bool>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Summarize the files of a directory without syncing, honoring its include/exclude lists
+        @examples(
+            "acsync stats ~/Documents --top=5",
+        )
+        Stats {
+            /// Directory to be analyzed
+            @validate(cli_helper::existing_directory)
+            origin: Req<String>,
+            /// Include dot-files and dot-directories, enabled by default (--no_hidden skips them)
+            hidden: Option<bool>,
+            /// Number of largest files and deepest paths to report (default 10)
+            top: Option<String>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Check the file system and free space of a destination before a big run
+        @examples(
+            "acsync doctor /media/backup --origin ~/Documents",
+        )
+        Doctor {
+            /// Destination directory to be checked, or the directory it will be created in
+            destination: Req<String>,
+            /// Origin directory whose files to copy are compared with the free space
+            @validate(cli_helper::existing_directory)
+            origin: Option<String>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// List the past replicate runs of a destination from their reports, or show one of them
+        @examples(
+            "acsync history /media/backup",
+            "acsync history /media/backup --show=1760520000",
+        )
+        History {
+            /// Destination directory whose runs to list
+            @validate(cli_helper::existing_directory)
+            destination: Req<String>,
+            /// Show the details of the run ID instead
+            show: Option<String>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Reverse the last replicate run of a destination from its report, restoring backups and trashed files
+        @examples(
+            "acsync undo /media/backup --dryrun",
+        )
+        Undo {
+            /// Destination directory whose last run to reverse
+            @validate(cli_helper::existing_directory)
+            destination: Req<String>,
+            /// Count what would be reversed without changing anything
+            dryrun: Option<bool>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Create the list of the files of a directory with their sizes, hashes and permissions, or verify a directory against it
+        @examples(
+            "acsync manifest create /media/archive > archive.manifest",
+            "acsync manifest verify /media/archive archive.manifest",
+        )
+        Manifest {
+            /// Action: create (printed to the standard output) or verify
+            action: Req<String>,
+            /// Directory to be listed or verified
+            @validate(cli_helper::existing_directory)
+            directory: Req<String>,
+            /// Manifest file to verify the directory against
+            manifest: Arg<String>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Time the copy, comparison and hashing strategies from a directory into a destination, to pick flags
+        @examples(
+            "acsync bench ~/Documents /media/backup --runs=3",
+        )
+        Bench {
+            /// Directory to be copied
+            @validate(cli_helper::existing_directory)
+            origin: Req<String>,
+            /// Directory on the storage to measure, where a temporary acsync_bench directory is written
+            destination: Req<String>,
+            /// Run each strategy N times and report the fastest run (default 1)
+            runs: Option<String>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Print the paths of a directory selected by the filters, to debug include/exclude lists
+        @examples(
+            "acsync list ~/Documents --extensions=rs,toml --max_depth=3",
+        )
+        List {
+            /// Directory to be listed
+            @validate(cli_helper::existing_directory)
+            origin: Req<String>,
+            /// Comma-separated patterns to include, in addition to those of .acsync_includes (repeatable)
+            includes: Vec<String>,
+            /// Comma-separated patterns to exclude, in addition to those of .acsync_excludes (repeatable)
+            excludes: Vec<String>,
+            /// Comma-separated extensions of the files to list (e.g. rs,toml)
+            extensions: Option<String>,
+            /// Only list paths at least N levels below the directory
+            min_depth: Option<String>,
+            /// Only list paths at most N levels below the directory
+            max_depth: Option<String>,
+            /// Include dot-files and dot-directories, enabled by default (--no_hidden skips them)
+            hidden: Option<bool>,
+            /// Don't descend into directories on other file systems, such as /proc or network mounts
+            one_file_system: Option<bool>,
+            /// Skip files smaller than SIZE (e.g. 1K)
+            min_size: Option<String>,
+            /// Skip files larger than SIZE (e.g. 2G)
+            max_size: Option<String>,
+            /// Log level: error, warn, info (default), debug or trace
+            @env ACSYNC_LOG_LEVEL
+            log_level: Option<String>,
+            /// Append timestamped log records to PATH
+            @env ACSYNC_LOG_FILE
+            log_file: Option<String>,
+        },
+        /// Print the completion script of a shell, e.g. `source <(acsync completions bash)`
+        @examples(
+            "source <(acsync completions bash)",
+        )
+        Completions {
+            /// Shell to be completed: bash, zsh or fish
+            shell: Req<String>,
+        },
+        @default
+        @examples("acsync --profile=work")
+        Entry {
+            /// Replicate with the values of the NAME profile of the config file
+            @env ACSYNC_PROFILE
+            profile: Option<String>,
+            /// Config file with the profiles (default ~/.config/acsync/config.toml)
+            @env ACSYNC_CONFIG
+            config: Option<String>,
         },
-        @default Entry {},
     }
 }
 
-fn replicate<P: AsRef<std::path::Path>>(
-    source: P,
-    target: P,
-    override_question: bool,
-    dryrun: bool,
-    debug: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let source = source.as_ref().to_path_buf();
-    let target = target.as_ref().to_path_buf();
-
-    let includes: Vec<String> =
-        if let Ok(includes) = std::fs::read_to_string(source.join(".acsync_includes")) {
-            println!("Found file .acsync_includes, loading...");
-            includes
-                .split_terminator('\n')
-                .map(|item| item.to_string())
-                .collect()
-        } else {
-            vec![]
-        };
-    let excludes: Vec<String> =
-        if let Ok(excludes) = std::fs::read_to_string(source.join(".acsync_excludes")) {
-            println!("Found file .acsync_excludes, loading...");
-            excludes
-                .split_terminator('\n')
-                .map(|item| item.to_string())
-                .collect()
-        } else {
-            vec![]
-        };
+/// Parses the value of the `name` option, if informed.
+fn parse_value<T, E: ToString>(
+    name: &str,
+    value: &Option<String>,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> Result<Option<T>, SyncError> {
+    value
+        .as_deref()
+        .map(|value| {
+            parse(value).map_err(|reason| SyncError::InvalidArgument {
+                name: name.to_string(),
+                reason: reason.to_string(),
+            })
+        })
+        .transpose()
+}
 
-    let paths_iter = FileSearcher::new(&source)
-        .includes(&includes)
-        .excludes(&excludes)
-        .into_iter()
-        .filter_map(|result| result.ok());
+/// Splits the comma-separated items of every value of a repeatable option.
+fn split_list(values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
 
-    let mut file_copied_count = 0;
-    let mut total_file_copied_size = 0;
-    let mut file_dated_count = 0;
-    let mut total_file_dated_size = 0;
-    let mut file_overrided_count = 0;
-    let mut total_file_overrided_size = 0;
-    let mut directory_created_count = 0;
-    let mut file_count = 0;
-    let mut total_file_size = 0;
+/// Parses a human readable size such as `500K` or `10M`.
+fn parse_size(value: &str) -> Result<u64, String> {
+    cli_helper::parse_size(value).ok_or(format!("invalid size {value:?}"))
+}
 
-    if source.is_dir() && !target.exists() {
-        if debug {
-            println!("Creating target directory {} ...", target.display());
-        }
-        if !dryrun {
-            let source_metadata = source.metadata()?;
+/// Parses a duration such as `30s` or `5m`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    cli_helper::parse_duration(value).ok_or(format!("invalid duration {value:?}"))
+}
+
+/// Parses an age such as `7d` or an UTC date such as `2024-01-01`.
+fn parse_time(value: &str) -> Result<SystemTime, String> {
+    cli_helper::parse_time(value).ok_or(format!("invalid age or date {value:?}"))
+}
+
+/// Reads the relative paths listed one per line in the `path` file, or in the standard
+/// input when `-`, skipping the empty lines.
+fn read_file_list(path: &str) -> Result<Vec<PathBuf>, SyncError> {
+    let content = if path == "-" {
+        std::io::read_to_string(std::io::stdin()).with_path(path)?
+    } else {
+        std::fs::read_to_string(path).with_path(path)?
+    };
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Returns a random duration up to `max`, so that scheduled runs of several machines
+/// don't hit a shared destination at the same time.
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().hash_one(SystemTime::now());
+    max.mul_f64((random % 1000) as f64 / 1000.0)
+}
+
+/// Sets the log level, `debug` unless informed by `--log_level`, and the log file.
+fn setup_log(
+    debug: &Option<bool>,
+    log_level: &Option<String>,
+    log_file: &Option<String>,
+) -> Result<(), SyncError> {
+    match parse_value("log_level", log_level, str::parse)? {
+        Some(log_level) => log::set_level(log_level),
+        None if debug.unwrap_or_default() => log::set_level(Level::Debug),
+        None => {}
+    }
+    if let Some(log_file) = log_file {
+        log::set_file(log_file)?;
+    }
+    log::set_journal(std::env::var_os("JOURNAL_STREAM").is_some());
+    Ok(())
+}
 
-            std::fs::DirBuilder::new().create(&target)?;
+/// Returns the value of the `name` option, falling back to its environment variable.
+fn option_or_env(name: &str, args: &[String]) -> Option<String> {
+    cli_helper::get_option_value(name, args)
+        .0
+        .map(String::from)
+        .or_else(|| {
+            Command::env_options()
+                .into_iter()
+                .find(|(option_name, ..)| *option_name == name)
+                .and_then(|(.., env_name)| std::env::var(env_name).ok())
+        })
+}
 
-            std::fs::set_permissions(&target, source_metadata.permissions())?;
+/// Completes the command-line `args` with the values of the profile informed by
+/// `--profile`, running the replicate command when no command is given.
+fn profile_args(mut args: Vec<String>) -> Result<Vec<String>, SyncError> {
+    let Some(profile_name) = option_or_env("profile", &args) else {
+        return Ok(args);
+    };
+    let config_path = match option_or_env("config", &args) {
+        Some(config_path) => config_path.into(),
+        None => Config::default_path().ok_or(SyncError::MissingArgument("config".to_string()))?,
+    };
+    let config = Config::load(&config_path)?;
+    let mut profile =
+        config
+            .profile(&profile_name)
+            .cloned()
+            .ok_or_else(|| SyncError::InvalidArgument {
+                name: "profile".to_string(),
+                reason: format!("{profile_name:?} not found in {}", config_path.display()),
+            })?;
+    // Environment variables take precedence over the profile values.
+    for (name, env_name) in Command::env_options() {
+        if let Ok(value) = std::env::var(env_name) {
+            profile.insert(name, Value::String(value));
         }
-        directory_created_count += 1;
     }
+    let value_options = Command::value_options();
+    if cli_helper::argument_indexes(&args, &value_options).is_empty() {
+        args.insert(0, "replicate".to_string());
+    }
+    Ok(profile.merge_args(&args, &value_options))
+}
 
-    for source_path in paths_iter {
-        let relative_path = source_path.strip_prefix(&source)?;
-        let target_path = PathBuf::from(&target).join(relative_path);
-        let source_size = source_path.metadata()?.size();
-
-        let mut check_parent_directory = target_path.as_path();
-        while let Some(parent) = check_parent_directory.parent()
-            && !parent.exists()
-        {
-            check_parent_directory = parent;
-            let check_relative_path_directory = parent.strip_prefix(&target)?;
-            let check_source_path_directory =
-                PathBuf::from(&source).join(check_relative_path_directory);
-            if check_source_path_directory.is_dir() {
-                if debug {
-                    println!("Creating directory {} ...", parent.display());
-                }
-                if !dryrun {
-                    let source_metadata = check_source_path_directory.metadata()?;
-
-                    std::fs::DirBuilder::new().create(&parent)?;
-
-                    std::fs::set_permissions(&parent, source_metadata.permissions())?;
-                }
-                directory_created_count += 1;
-            }
-        }
-
-        if target_path.exists() && target_path.is_file() && source_path.is_file() {
-            let source_modified_date = source_path.metadata()?.modified()?;
-            let target_modified_date = target_path.metadata()?.modified()?;
-            let target_size = target_path.metadata()?.size();
-            if source_modified_date > target_modified_date && source_size != target_size {
-                file_dated_count += 1;
-                total_file_dated_size += target_size;
-                if debug {
-                    println!(
-                        "File {} is dated in {:?} ({} KBs != {} KBs)",
-                        target_path.display(),
-                        source_modified_date.duration_since(target_modified_date)?,
-                        (source_size / 1024) as f64,
-                        (target_size / 1024) as f64
-                    );
-                }
-                if override_question {
-                    if !debug {
-                        println!(
-                            "File {} is dated in {:?} ({} KBs != {} KBs)",
-                            target_path.display(),
-                            source_modified_date.duration_since(target_modified_date)?,
-                            (source_size / 1024) as f64,
-                            (target_size / 1024) as f64
-                        );
-                    }
-                    println!("Do you want to override the file content? (Y/N) ");
-
-                    let mut input = String::new();
-                    std::io::stdin().read_line(&mut input)?;
-                    if input.starts_with("y") || input.starts_with("Y") {
-                        if debug {
-                            println!(
-                                "Copying file {} ({} KBs)...",
-                                relative_path.display(),
-                                (source_size / 1024) as f64
-                            );
-                        }
-                        if !dryrun {
-                            std::fs::copy(&source_path, &target_path)?;
-                        }
-                        file_overrided_count += 1;
-                        total_file_overrided_size += source_size;
-                    }
-                }
-            }
-        } else if source_path.is_file() {
-            if debug {
-                println!(
-                    "Copying file {} ({} KBs)...",
-                    relative_path.display(),
-                    (source_size / 1024) as f64
-                );
+/// Runs the `replicator`, after the review of its actions when `interactive`, printing
+/// the actions when `itemize` and running `file_cmd` on each of them.
+///
+/// Returns `None` when there was nothing to replicate or nothing was accepted.
+fn replicate(
+    replicator: Replicator,
+    destination: &str,
+    interactive: bool,
+    itemize: bool,
+    file_cmd: Option<&str>,
+    hook_env: &[(&str, String)],
+) -> Result<Option<SyncStats>, SyncError> {
+    let replicator = if interactive {
+        let actions = replicator.plan()?;
+        if actions.is_empty() {
+            info!("Nothing to replicate");
+            return Ok(None);
+        }
+        let approved =
+            review(&actions, std::io::stdin().lock(), std::io::stdout()).with_path(destination)?;
+        let Some(approved) = approved else {
+            info!("Nothing applied");
+            return Ok(None);
+        };
+        replicator.approved(Some(approved))
+    } else {
+        replicator
+    };
+    signal::install();
+    if !itemize && file_cmd.is_none() {
+        return replicator.run().map(Some);
+    }
+    replicator
+        .run_itemized(|action| {
+            if itemize {
+                println!("{action}");
             }
-            if !dryrun {
-                std::fs::copy(&source_path, &target_path)?;
+            if let Some(file_cmd) = file_cmd {
+                let env = [hook_env, &hook::action_env(action)].concat();
+                if let Err(error) = hook::run_hook(file_cmd, &env) {
+                    warn!("{error}");
+                }
             }
-            file_copied_count += 1;
-            total_file_copied_size += source_size;
-        }
-        if source_path.is_file() {
-            file_count += 1;
-            total_file_size += source_size;
+        })
+        .map(Some)
+}
+
+/// Returns the command-line `args` with the values of the options holding secrets
+/// masked, e.g. to record them in a report.
+fn redact_arguments(args: &[String]) -> Vec<String> {
+    let mut redacted = vec![];
+    let mut secret_follows = false;
+    for arg in args {
+        if secret_follows {
+            redacted.push("***".to_string());
+            secret_follows = false;
+        } else if arg == "--token" {
+            redacted.push(arg.clone());
+            secret_follows = true;
+        } else if arg.starts_with("--token=") {
+            redacted.push("--token=***".to_string());
+        } else {
+            redacted.push(arg.clone());
         }
     }
-
-    println!("{:#^80}", " Stats ");
-    println!(
-        "Copied files: {file_copied_count} ({} KBs)",
-        (total_file_copied_size / 1024) as f64
-    );
-    println!(
-        "Dated files: {file_dated_count} ({} KBs)",
-        (total_file_dated_size / 1024) as f64
-    );
-    println!(
-        "Overrided files: {file_overrided_count} ({} KBs)",
-        (total_file_overrided_size / 1024) as f64
-    );
-    println!("Directory created: {directory_created_count}");
-    println!(
-        "Files found: {file_count} ({} KBs)",
-        (total_file_size / 1024) as f64
-    );
-    println!("{:#^80}\n", "");
-
-    Ok(())
+    redacted
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let now = Instant::now();
-
-    let command = Command::parse();
-
-    let result = match &command {
+/// Runs the `command` parsed from the command-line `args`.
+fn run(command: &Command, args: &[String]) -> Result<(), SyncError> {
+    match command {
         Command::Replicate {
             origin,
             destination,
             override_question,
+            on_conflict,
+            size_only,
+            ignore_times,
+            modify_window,
+            existing,
+            ignore_existing,
+            case_collisions,
+            target_fs,
+            invalid_names,
+            normalize,
+            yes,
+            no,
             back,
             dryrun,
+            interactive,
+            itemize,
+            progress,
+            prescan,
+            bwlimit,
+            ignore_errors,
+            inplace,
+            whole_file,
+            reflink,
+            sparse,
+            hidden,
+            specials,
+            devices,
+            one_file_system,
+            perms,
+            usermap,
+            groupmap,
+            acls,
+            metadata_only,
+            no_cache,
+            min_size,
+            max_size,
+            excludes,
+            files_from,
+            newer_than,
+            older_than,
+            link_dest,
+            backup,
+            backup_dir,
+            delete,
+            delete_to_trash,
+            max_delete,
+            max_delete_percent,
+            trash_dir,
+            verify,
+            verify_sample,
+            retries,
+            retry_delay,
+            timeout,
+            recopies,
+            snapshot,
+            order,
+            compress,
+            store,
+            hash_threads,
+            wait_lock,
+            check_space,
+            force,
+            every,
+            pre_cmd,
+            post_cmd,
+            file_cmd,
+            token,
+            compress_level,
+            log_level,
+            log_file,
             debug,
+            ..
         } => {
-            let override_question = override_question.unwrap_or_default();
+            setup_log(debug, log_level, log_file)?;
+            let on_conflict = match parse_value("on_conflict", on_conflict, str::parse)? {
+                Some(on_conflict) => on_conflict,
+                None if override_question.unwrap_or_default() => ConflictPolicy::Prompt,
+                None => ConflictPolicy::Skip,
+            };
+            let change_detection = match (
+                size_only.unwrap_or_default(),
+                ignore_times.unwrap_or_default(),
+            ) {
+                (true, true) => {
+                    return Err(SyncError::InvalidArgument {
+                        name: "size_only".to_string(),
+                        reason: "can't be combined with --ignore_times".to_string(),
+                    });
+                }
+                (true, false) => ChangeDetection::SizeOnly,
+                (false, true) => ChangeDetection::IgnoreTimes,
+                (false, false) => ChangeDetection::SizeAndTime,
+            };
+            let existing_policy = match (
+                existing.unwrap_or_default(),
+                ignore_existing.unwrap_or_default(),
+            ) {
+                (true, true) => {
+                    return Err(SyncError::InvalidArgument {
+                        name: "existing".to_string(),
+                        reason: "can't be combined with --ignore_existing".to_string(),
+                    });
+                }
+                (true, false) => ExistingPolicy::Existing,
+                (false, true) => ExistingPolicy::IgnoreExisting,
+                (false, false) => ExistingPolicy::All,
+            };
+            let answer = match (yes.unwrap_or_default(), no.unwrap_or_default()) {
+                (true, true) => {
+                    return Err(SyncError::InvalidArgument {
+                        name: "yes".to_string(),
+                        reason: "can't be combined with --no".to_string(),
+                    });
+                }
+                (true, false) => Some(true),
+                (false, true) => Some(false),
+                (false, false) => None,
+            };
             let back = back.unwrap_or_default();
             let dryrun = dryrun.unwrap_or_default();
-            let debug = debug.unwrap_or_default();
+            let bwlimit = parse_value("bwlimit", bwlimit, parse_size)?;
+            let min_size = parse_value("min_size", min_size, parse_size)?;
+            let max_size = parse_value("max_size", max_size, parse_size)?;
+            let newer_than = parse_value("newer_than", newer_than, parse_time)?;
+            let older_than = parse_value("older_than", older_than, parse_time)?;
+            let compress = parse_value("compress", compress, str::parse)?;
+            let reflink = parse_value("reflink", reflink, str::parse)?;
+            let case_collisions = parse_value("case_collisions", case_collisions, str::parse)?;
+            let target_fs = parse_value("target_fs", target_fs, str::parse)?;
+            let invalid_names = parse_value("invalid_names", invalid_names, str::parse)?;
+            let normalize = parse_value("normalize", normalize, str::parse)?;
+            let usermap = parse_value("usermap", usermap, IdMap::users)?;
+            let groupmap = parse_value("groupmap", groupmap, IdMap::groups)?;
+            let wait_lock = parse_value("wait_lock", wait_lock, parse_duration)?;
+            let modify_window = parse_value("modify_window", modify_window, parse_duration)?;
+            let every = parse_value("every", every, parse_duration)?;
+            let compress_level = parse_value("compress_level", compress_level, str::parse::<u32>)?;
+            let hash_threads = parse_value("hash_threads", hash_threads, str::parse::<usize>)?;
+            if hash_threads.is_some() && !store.unwrap_or_default() {
+                return Err(SyncError::InvalidArgument {
+                    name: "hash_threads".to_string(),
+                    reason: "only applies to --store".to_string(),
+                });
+            }
+            let max_delete = parse_value("max_delete", max_delete, str::parse::<u64>)?;
+            let max_delete_percent =
+                parse_value("max_delete_percent", max_delete_percent, |value| {
+                    value
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|percent| *percent <= 100)
+                        .ok_or("expected a percentage from 0 to 100")
+                })?;
+            let retries = parse_value("retries", retries, str::parse::<u32>)?;
+            let retry_delay = parse_value("retry_delay", retry_delay, parse_duration)?;
+            let timeout = parse_value("timeout", timeout, parse_duration)?;
+            let recopies = parse_value("recopies", recopies, str::parse::<u32>)?;
+            let order = parse_value("order", order, str::parse)?;
+            if retry_delay.is_some() && retries.is_none() {
+                return Err(SyncError::InvalidArgument {
+                    name: "retry_delay".to_string(),
+                    reason: "only applies to --retries".to_string(),
+                });
+            }
+            let verify_sample = parse_value("verify_sample", verify_sample, |value| {
+                let value = value.strip_suffix('%').unwrap_or(value);
+                value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|percent| *percent <= 100)
+                    .ok_or("expected a percentage from 0 to 100")
+            })?;
+            if let Some(name) = [
+                ("max_delete", max_delete.is_some()),
+                ("max_delete_percent", max_delete_percent.is_some()),
+            ]
+            .into_iter()
+            .find_map(|(name, informed)| informed.then_some(name))
+                && !delete.unwrap_or_default()
+                && !delete_to_trash.unwrap_or_default()
+            {
+                return Err(SyncError::InvalidArgument {
+                    name: name.to_string(),
+                    reason: "only applies to --delete or --delete_to_trash".to_string(),
+                });
+            }
+            if compress_level
+                .is_some_and(|level| !(1..=remote::MAX_COMPRESS_LEVEL).contains(&level))
+            {
+                return Err(SyncError::InvalidArgument {
+                    name: "compress_level".to_string(),
+                    reason: format!("expected a level from 1 to {}", remote::MAX_COMPRESS_LEVEL),
+                });
+            }
+            if every.is_some() && interactive.unwrap_or_default() {
+                return Err(SyncError::InvalidArgument {
+                    name: "every".to_string(),
+                    reason: "can't be combined with --interactive".to_string(),
+                });
+            }
+            let files_from = files_from.as_deref().map(read_file_list).transpose()?;
 
             if back {
-                println!("Syncing back...");
+                info!("Syncing back...");
             }
             if dryrun {
-                println!("Dry run mode...");
+                info!("Dry run mode...");
             }
 
-            let origin = origin.as_ref().ok_or("Origin argument must be informed!")?;
+            let origin = origin
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("origin".to_string()))?;
             let destination = destination
                 .as_ref()
-                .ok_or("Destination argument must be informed!")?;
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
 
-            if back {
-                replicate(destination, origin, override_question, dryrun, debug)
+            let remote_replicator = if [origin, destination]
+                .iter()
+                .any(|location| remote::is_remote(location))
+            {
+                let unsupported = [
+                    ("interactive", interactive.unwrap_or_default()),
+                    ("itemize", itemize.unwrap_or_default()),
+                    ("progress", progress.unwrap_or_default()),
+                    ("prescan", prescan.unwrap_or_default()),
+                    ("existing", existing.unwrap_or_default()),
+                    ("ignore_existing", ignore_existing.unwrap_or_default()),
+                    ("case_collisions", case_collisions.is_some()),
+                    ("target_fs", target_fs.is_some()),
+                    ("invalid_names", invalid_names.is_some()),
+                    ("normalize", normalize.is_some()),
+                    ("inplace", inplace.unwrap_or_default()),
+                    ("whole_file", whole_file.unwrap_or_default()),
+                    ("reflink", reflink.is_some()),
+                    ("sparse", sparse.unwrap_or_default()),
+                    ("specials", specials.unwrap_or_default()),
+                    ("devices", devices.unwrap_or_default()),
+                    ("one_file_system", one_file_system.unwrap_or_default()),
+                    ("perms", perms.unwrap_or_default()),
+                    ("usermap", usermap.is_some()),
+                    ("groupmap", groupmap.is_some()),
+                    ("acls", acls.unwrap_or_default()),
+                    ("metadata_only", metadata_only.unwrap_or_default()),
+                    ("min_size", min_size.is_some()),
+                    ("max_size", max_size.is_some()),
+                    ("excludes", !excludes.is_empty()),
+                    ("files_from", files_from.is_some()),
+                    ("newer_than", newer_than.is_some()),
+                    ("older_than", older_than.is_some()),
+                    ("link_dest", link_dest.is_some()),
+                    ("backup", backup.unwrap_or_default()),
+                    ("backup_dir", backup_dir.is_some()),
+                    ("delete_to_trash", delete_to_trash.unwrap_or_default()),
+                    ("max_delete", max_delete.is_some()),
+                    ("max_delete_percent", max_delete_percent.is_some()),
+                    ("trash_dir", trash_dir.is_some()),
+                    ("verify", verify.unwrap_or_default()),
+                    ("verify_sample", verify_sample.is_some()),
+                    ("retries", retries.is_some()),
+                    ("retry_delay", retry_delay.is_some()),
+                    ("timeout", timeout.is_some()),
+                    ("recopies", recopies.is_some()),
+                    ("snapshot", snapshot.unwrap_or_default()),
+                    ("order", order.is_some()),
+                    ("compress", compress.is_some()),
+                    ("store", store.unwrap_or_default()),
+                    ("check_space", check_space.unwrap_or_default()),
+                    ("file_cmd", file_cmd.is_some()),
+                ];
+                if let Some((name, _)) = unsupported.into_iter().find(|(_, informed)| *informed) {
+                    return Err(SyncError::InvalidArgument {
+                        name: name.to_string(),
+                        reason: "isn't supported with acsync://, s3:// and dav:// URLs".to_string(),
+                    });
+                }
+                let s3_config = if [origin, destination]
+                    .iter()
+                    .any(|location| S3Url::parse(location).is_some())
+                {
+                    Some(S3Config::from_env()?)
+                } else {
+                    None
+                };
+                let dav_config = if [origin, destination]
+                    .iter()
+                    .any(|location| webdav::parse_url(location).is_some())
+                {
+                    DavConfig::from_env()?
+                } else {
+                    None
+                };
+                let remote_replicator = if back {
+                    RemoteReplicator::new(destination, origin)
+                } else {
+                    RemoteReplicator::new(origin, destination)
+                };
+                Some(
+                    remote_replicator
+                        .on_conflict(on_conflict)
+                        .change_detection(change_detection)
+                        .modify_window(modify_window.unwrap_or_default())
+                        .dryrun(dryrun)
+                        .hidden(hidden.unwrap_or(true))
+                        .delete(delete.unwrap_or_default())
+                        .bwlimit(bwlimit)
+                        .token(token.clone())
+                        .compress_level(compress_level)
+                        .s3_config(s3_config)
+                        .dav_config(dav_config),
+                )
+            } else if compress_level.is_some() {
+                return Err(SyncError::InvalidArgument {
+                    name: "compress_level".to_string(),
+                    reason:
+                        "only applies to acsync:// URLs, use --compress to store files compressed"
+                            .to_string(),
+                });
             } else {
-                replicate(origin, destination, override_question, dryrun, debug)
+                None
+            };
+            let replicator = if back {
+                Replicator::new(destination, origin)
+            } else {
+                Replicator::new(origin, destination)
+            };
+            let replicator = replicator
+                .on_conflict(on_conflict)
+                .change_detection(change_detection)
+                .modify_window(modify_window.unwrap_or_default())
+                .existing_policy(existing_policy)
+                .case_collisions(case_collisions.unwrap_or_default())
+                .target_fs(target_fs.unwrap_or_default())
+                .invalid_names(invalid_names.unwrap_or_default())
+                .normalize(normalize.unwrap_or_default())
+                .dryrun(dryrun)
+                .ignore_errors(ignore_errors.unwrap_or_default())
+                .inplace(inplace.unwrap_or_default())
+                .whole_file(whole_file.unwrap_or_default())
+                .reflink(reflink.unwrap_or_default())
+                .sparse(sparse.unwrap_or_default())
+                .hidden(hidden.unwrap_or(true))
+                .specials(specials.unwrap_or_default())
+                .devices(devices.unwrap_or_default())
+                .one_file_system(one_file_system.unwrap_or_default())
+                .perms(perms.unwrap_or_default())
+                .usermap(usermap)
+                .groupmap(groupmap)
+                .acls(acls.unwrap_or_default())
+                .metadata_only(metadata_only.unwrap_or_default())
+                .ignore_cache(no_cache.unwrap_or_default())
+                .min_size(min_size)
+                .max_size(max_size)
+                .excludes(split_list(excludes))
+                .files_from(files_from)
+                .newer_than(newer_than)
+                .older_than(older_than)
+                .bwlimit(bwlimit)
+                .link_dest(link_dest.as_ref())
+                .backup(backup.unwrap_or_default())
+                .backup_dir(backup_dir.as_ref())
+                .delete(delete.unwrap_or_default())
+                .delete_to_trash(delete_to_trash.unwrap_or_default())
+                .max_delete(max_delete)
+                .max_delete_percent(max_delete_percent)
+                .trash_dir(trash_dir.as_ref())
+                .verify(verify.unwrap_or_default())
+                .verify_sample(verify_sample)
+                .retries(retries.unwrap_or_default())
+                .retry_delay(retry_delay)
+                .timeout(timeout)
+                .recopies(recopies)
+                .snapshot(snapshot.unwrap_or_default())
+                .order(order.unwrap_or_default())
+                .compress(compress)
+                .store(store.unwrap_or_default())
+                .hash_threads(hash_threads)
+                .progress(progress.unwrap_or_default())
+                .prescan(prescan.unwrap_or_default())
+                .wait_lock(wait_lock.unwrap_or_default())
+                .check_space(check_space.unwrap_or_default())
+                .force(force.unwrap_or_default())
+                .report(true)
+                .arguments(redact_arguments(args));
+            let replicator = match answer {
+                Some(answer) => replicator.prompter(AutoPrompter(answer)),
+                None => replicator,
+            };
+            let hook_env = [
+                ("ACSYNC_ORIGIN", origin.clone()),
+                ("ACSYNC_DESTINATION", destination.clone()),
+            ];
+            let replicate_once = || -> Result<(), SyncError> {
+                if let Some(pre_cmd) = pre_cmd {
+                    debug!("Running pre command {pre_cmd:?} ...");
+                    hook::run_hook(pre_cmd, &hook_env)?;
+                }
+                let result = match &remote_replicator {
+                    Some(remote_replicator) => {
+                        signal::install();
+                        remote_replicator.run().map(Some)
+                    }
+                    None => replicate(
+                        replicator.clone(),
+                        destination,
+                        interactive.unwrap_or_default(),
+                        itemize.unwrap_or_default(),
+                        file_cmd.as_deref(),
+                        &hook_env,
+                    ),
+                };
+                if let Some(post_cmd) = post_cmd {
+                    debug!("Running post command {post_cmd:?} ...");
+                    let result_env = match &result {
+                        Ok(Some(stats)) => hook::result_env(Ok(stats)),
+                        Ok(None) => hook::result_env(Ok(&SyncStats::default())),
+                        Err(error) => hook::result_env(Err(error)),
+                    };
+                    let env = [hook_env.as_slice(), &result_env].concat();
+                    if let Err(error) = hook::run_hook(post_cmd, &env) {
+                        match result {
+                            Err(_) => error!("{error}"),
+                            Ok(_) => return Err(error),
+                        }
+                    }
+                }
+                let Some(stats) = result? else {
+                    return Ok(());
+                };
+                println!("{stats}");
+
+                if stats.interrupted {
+                    return Err(SyncError::Interrupted);
+                }
+
+                if stats.file_failed_count > 0 {
+                    return Err(SyncError::FilesFailed(stats.file_failed_count));
+                }
+
+                Ok(())
+            };
+            let Some(every) = every else {
+                return replicate_once();
+            };
+            signal::install();
+            signal::install_reload();
+            notify::notify("READY=1");
+            notify::spawn_watchdog();
+            loop {
+                let started = Instant::now();
+                notify::notify("STATUS=Replicating");
+                match replicate_once() {
+                    Ok(()) => info!("Run succeeded in {:?}", started.elapsed()),
+                    Err(SyncError::Interrupted) => break,
+                    Err(error) => error!("Run failed with code {}: {error}", error.exit_code()),
+                }
+                let delay = every + jitter(every / 10);
+                let delay = Duration::from_secs(delay.as_secs());
+                info!("Next run in {delay:?}");
+                notify::notify(&format!("STATUS=Next run in {delay:?}"));
+                if !signal::sleep(delay) {
+                    break;
+                }
+            }
+            // The configuration is read again by `main`, starting over.
+            if signal::reload_requested() && !signal::interrupted() {
+                notify::notify_reloading();
+                info!("Reloading the configuration ...");
+                return Ok(());
+            }
+            notify::notify("STOPPING=1");
+            info!("Stopped");
+        }
+        Command::Verify {
+            origin,
+            destination,
+            hidden,
+            no_cache,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let origin = origin
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("origin".to_string()))?;
+            let destination = destination
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
+
+            let report = Verifier::new(origin, destination)
+                .hidden(hidden.unwrap_or(true))
+                .ignore_cache(no_cache.unwrap_or_default())
+                .run()?;
+            println!("{report}");
+
+            if !report.is_consistent() {
+                return Err(SyncError::VerifyMismatch(report.divergences.len() as u64));
+            }
+        }
+        Command::Restore {
+            store,
+            destination,
+            snapshot,
+            dryrun,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let dryrun = dryrun.unwrap_or_default();
+            if dryrun {
+                info!("Dry run mode...");
             }
+
+            let store = store
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("store".to_string()))?;
+            let destination = destination
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
+
+            let stats =
+                ContentStore::new(store).restore(snapshot.as_deref(), destination, dryrun)?;
+            println!("{stats}");
         }
-        Command::Entry { .. } => {
-            command.print_help();
-            Ok(())
+        Command::Prune {
+            destination,
+            keep_last,
+            keep_daily,
+            keep_weekly,
+            keep_monthly,
+            dryrun,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let dryrun = dryrun.unwrap_or_default();
+            if dryrun {
+                info!("Dry run mode...");
+            }
+            let keep_last = parse_value("keep_last", keep_last, str::parse::<usize>)?;
+            let keep_daily = parse_value("keep_daily", keep_daily, str::parse::<usize>)?;
+            let keep_weekly = parse_value("keep_weekly", keep_weekly, str::parse::<usize>)?;
+            let keep_monthly = parse_value("keep_monthly", keep_monthly, str::parse::<usize>)?;
+            // Pruning without any rule would remove every snapshot but the last one.
+            if [keep_last, keep_daily, keep_weekly, keep_monthly]
+                .iter()
+                .all(Option::is_none)
+            {
+                return Err(SyncError::MissingArgument(
+                    "keep_last, keep_daily, keep_weekly or keep_monthly".to_string(),
+                ));
+            }
+
+            let destination = destination
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
+
+            let report = Pruner::new(destination)
+                .dryrun(dryrun)
+                .keep_last(keep_last.unwrap_or(1))
+                .keep_daily(keep_daily.unwrap_or_default())
+                .keep_weekly(keep_weekly.unwrap_or_default())
+                .keep_monthly(keep_monthly.unwrap_or_default())
+                .run()?;
+            println!("{report}");
+        }
+        Command::Serve {
+            root,
+            listen,
+            allow_cleartext,
+            read_only,
+            token,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let root = root
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("root".to_string()))?;
+            let listen = listen
+                .clone()
+                .unwrap_or_else(|| format!("127.0.0.1:{}", remote::DEFAULT_PORT));
+            let listener = TcpListener::bind(&listen).with_path(&listen)?;
+            let address = listener.local_addr().with_path(&listen)?;
+            // The traffic, tokens included, goes in cleartext as there is no TLS yet.
+            if !address.ip().is_loopback() && !allow_cleartext.unwrap_or_default() {
+                return Err(SyncError::InvalidArgument {
+                    name: "listen".to_string(),
+                    reason: format!(
+                        "{address} isn't a loopback address and the traffic isn't encrypted, pass --allow_cleartext to listen on it anyway"
+                    ),
+                });
+            }
+            if token.is_none() {
+                warn!("Serving without --token, any client reaching {listen} has access");
+            }
+            Server::new(root)
+                .read_only(read_only.unwrap_or_default())
+                .token(token.clone())
+                .serve(listener)?;
+        }
+        Command::Clean {
+            destination,
+            dryrun,
+            prune_empty_dirs,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let dryrun = dryrun.unwrap_or_default();
+            if dryrun {
+                info!("Dry run mode...");
+            }
+
+            let destination = destination
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
+
+            let report = Cleaner::new(destination)
+                .dryrun(dryrun)
+                .prune_empty_dirs(prune_empty_dirs.unwrap_or_default())
+                .run()?;
+            println!("{report}");
+        }
+        Command::Stats {
+            origin,
+            hidden,
+            top,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let top = parse_value("top", top, str::parse::<usize>)?;
+
+            let origin = origin
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("origin".to_string()))?;
+
+            let mut analyzer = Analyzer::new(origin).hidden(hidden.unwrap_or(true));
+            if let Some(top) = top {
+                analyzer = analyzer.top(top);
+            }
+            println!("{}", analyzer.run()?);
+        }
+        Command::Doctor {
+            destination,
+            origin,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+
+            let destination = destination
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
+
+            let report = Doctor::new(destination).origin(origin.as_ref()).run()?;
+            println!("{report}");
+        }
+        Command::History {
+            destination,
+            show,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let show = parse_value("show", show, str::parse::<u64>)?;
+
+            let destination = destination
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
+
+            let Some(id) = show else {
+                println!("{}", History::load(destination)?);
+                return Ok(());
+            };
+            if !RunReport::list(destination)?.contains(&id) {
+                return Err(SyncError::InvalidArgument {
+                    name: "show".to_string(),
+                    reason: format!("no report {id} in {destination}"),
+                });
+            }
+            println!("{}", RunReport::load(destination, id)?);
+        }
+        Command::Undo {
+            destination,
+            dryrun,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+
+            let destination = destination
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
+
+            let stats = Undoer::new(destination)
+                .dryrun(dryrun.unwrap_or_default())
+                .run()?;
+            println!("{stats}");
+        }
+        Command::Manifest {
+            action,
+            directory,
+            manifest,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+
+            let directory = directory
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("directory".to_string()))?;
+
+            match (action.as_deref(), manifest) {
+                (Some("create"), None) => {
+                    print!("{}", Manifest::create(directory)?);
+                }
+                (Some("create"), Some(_)) => {
+                    return Err(SyncError::InvalidArgument {
+                        name: "manifest".to_string(),
+                        reason: "create prints the manifest to the standard output".to_string(),
+                    });
+                }
+                (Some("verify"), Some(manifest)) => {
+                    let report = Manifest::load(manifest)?.verify(directory)?;
+                    println!("{report}");
+                    if !report.is_consistent() {
+                        return Err(SyncError::VerifyMismatch(report.divergences.len() as u64));
+                    }
+                }
+                (Some("verify"), None) => {
+                    return Err(SyncError::MissingArgument("manifest".to_string()));
+                }
+                (Some(action), _) => {
+                    return Err(SyncError::InvalidArgument {
+                        name: "action".to_string(),
+                        reason: format!("unknown action {action:?}, expected create or verify"),
+                    });
+                }
+                (None, _) => return Err(SyncError::MissingArgument("action".to_string())),
+            }
+        }
+        Command::Bench {
+            origin,
+            destination,
+            runs,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let runs = parse_value("runs", runs, str::parse::<usize>)?;
+
+            let origin = origin
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("origin".to_string()))?;
+            let destination = destination
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("destination".to_string()))?;
+
+            let report = Bench::new(origin, destination)
+                .runs(runs.unwrap_or(1))
+                .run()?;
+            println!("{report}");
+        }
+        Command::List {
+            origin,
+            includes,
+            excludes,
+            extensions,
+            min_depth,
+            max_depth,
+            hidden,
+            one_file_system,
+            min_size,
+            max_size,
+            log_level,
+            log_file,
+            debug,
+        } => {
+            setup_log(debug, log_level, log_file)?;
+            let min_depth = parse_value("min_depth", min_depth, str::parse::<usize>)?;
+            let max_depth = parse_value("max_depth", max_depth, str::parse::<usize>)?;
+            let min_size = parse_value("min_size", min_size, parse_size)?;
+            let max_size = parse_value("max_size", max_size, parse_size)?;
+
+            let origin = origin
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("origin".to_string()))?;
+
+            let mut include_patterns = read_patterns(origin, ".acsync_includes");
+            include_patterns.extend(split_list(includes));
+            let exclude_patterns = split_list(excludes);
+
+            let paths_iter = FileSearcher::new(origin)
+                .sort_by(SortOrder::Name)
+                .min_depth(min_depth.unwrap_or_default())
+                .max_depth(max_depth.unwrap_or(usize::MAX))
+                .hidden(hidden.unwrap_or(true))
+                .same_file_system(one_file_system.unwrap_or_default())
+                .min_size(min_size)
+                .max_size(max_size)
+                .includes(&include_patterns)
+                .excludes(&exclude_patterns)
+                .excludes_file(Some(".acsync_excludes"))
+                .extensions(extensions.as_ref());
+            for result in paths_iter {
+                let entry = result.map_err(|source| SyncError::WalkError {
+                    path: origin.into(),
+                    source,
+                })?;
+                println!("{}", entry.path().display());
+            }
+        }
+        Command::Completions { shell, .. } => {
+            let shell = parse_value("shell", shell, str::parse)?
+                .ok_or(SyncError::MissingArgument("shell".to_string()))?;
+            print!("{}", Command::completions(shell));
+        }
+        Command::Entry { .. } => command.print_help(),
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let now = Instant::now();
+
+    // A recurring run stops on SIGHUP to be started over with the profile read again.
+    let result = loop {
+        let result = profile_args(std::env::args().skip(1).collect()).and_then(|args| {
+            match Command::try_parse_slice(&args) {
+                Ok(command) => run(&command, &args),
+                Err(error) => error.exit(),
+            }
+        });
+        if result.is_err() || !signal::take_reload() {
+            break result;
         }
     };
 
-    println!("Elapsed execution time: {:?}", now.elapsed());
+    // On stderr, so that the output of list, manifest create or completions can be
+    // piped or redirected as is.
+    eprintln!("Elapsed execution time: {:?}", now.elapsed());
 
-    result
+    if let Err(error) = result {
+        error!("{error}");
+        std::process::exit(error.exit_code());
+    }
 }
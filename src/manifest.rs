@@ -0,0 +1,238 @@
+//! **manifest** contains the portable list of the files of a directory with their sizes,
+//! hashes and permissions, so that a copy moved to offline media can be verified long
+//! after the original tree is gone.
+
+use crate::{
+    archive::{escape, unescape},
+    debug,
+    error::{IoResultExt, SyncError},
+    fs::{FileSearcher, SortOrder},
+    hash, info, platform,
+    sync::is_metadata_path,
+    verify::{Divergence, VerifyReport},
+};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// First line of a manifest, naming its format and version.
+pub const MANIFEST_HEADER: &str = "acsync manifest 1";
+
+/// A file listed in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Path relative to the listed directory, with `/` separators on every platform.
+    pub path: PathBuf,
+    pub size: u64,
+    /// SHA-256 hash of the content, in hexadecimal.
+    pub hash: String,
+    pub mode: u32,
+}
+
+/// List of the files of a directory, written as text with one tab-separated
+/// `path, size, hash, mode` line per file after the [`MANIFEST_HEADER`] line.
+///
+/// Only files are listed, the directories are implied by their paths. The acsync
+/// metadata files, trash and reports are left out.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Lists and hashes the files of the `directory`, sorted by path.
+    pub fn create<P: AsRef<Path>>(directory: P) -> Result<Self, SyncError> {
+        let directory = directory.as_ref();
+        let mut manifest = Manifest::default();
+        for (path, relative_path) in files(directory)? {
+            debug!("Hashing {} ...", path.display());
+            let metadata = path.metadata().with_path(&path)?;
+            manifest.entries.push(ManifestEntry {
+                path: relative_path,
+                size: metadata.len(),
+                hash: hash::hash_file(&path)?,
+                mode: platform::mode(&metadata),
+            });
+        }
+        Ok(manifest)
+    }
+
+    /// Reads the manifest file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SyncError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).with_path(path)?;
+        let invalid = |reason: String| SyncError::Io {
+            path: path.to_path_buf(),
+            source: std::io::Error::new(std::io::ErrorKind::InvalidData, reason),
+        };
+        let mut lines = content.lines();
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(invalid("not an acsync manifest".to_string()));
+        }
+        let mut manifest = Manifest::default();
+        for (number, line) in lines.enumerate() {
+            let entry = parse_entry(line)
+                .ok_or_else(|| invalid(format!("malformed entry on line {}", number + 2)))?;
+            manifest.entries.push(entry);
+        }
+        Ok(manifest)
+    }
+
+    /// Compares the files of the `directory` with the listed ones, by size, hash and
+    /// permissions.
+    pub fn verify<P: AsRef<Path>>(&self, directory: P) -> Result<VerifyReport, SyncError> {
+        let directory = directory.as_ref();
+        let mut report = VerifyReport::default();
+        let mut divergence = |divergence: Divergence| {
+            info!("{divergence}");
+            report.divergences.push(divergence);
+        };
+        for entry in &self.entries {
+            let path = directory.join(&entry.path);
+            debug!("Checking {} ...", path.display());
+            let relative = entry.path.clone();
+            let Ok(metadata) = path.metadata() else {
+                divergence(Divergence::Missing(relative));
+                continue;
+            };
+            if !metadata.is_file() {
+                divergence(Divergence::TypeMismatch(relative));
+            } else if metadata.len() != entry.size {
+                divergence(Divergence::SizeMismatch {
+                    path: relative,
+                    origin_size: entry.size,
+                    destination_size: metadata.len(),
+                });
+            } else if hash::hash_file(&path)? != entry.hash {
+                divergence(Divergence::HashMismatch(relative));
+            } else if platform::mode(&metadata) != entry.mode {
+                divergence(Divergence::PermissionMismatch {
+                    path: relative,
+                    origin_mode: entry.mode,
+                    destination_mode: platform::mode(&metadata),
+                });
+            }
+        }
+
+        let listed: BTreeSet<&Path> = self
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_path())
+            .collect();
+        for (_, relative_path) in files(directory)? {
+            if !listed.contains(relative_path.as_path()) {
+                divergence(Divergence::Extra(relative_path));
+            }
+        }
+        report.file_checked_count = self.entries.len() as u64;
+        Ok(report)
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{MANIFEST_HEADER}")?;
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{:04o}",
+                escape(&entry.path.to_string_lossy()),
+                entry.size,
+                entry.hash,
+                entry.mode
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Option<ManifestEntry> {
+    let mut fields = line.split('\t');
+    let entry = ManifestEntry {
+        path: PathBuf::from(unescape(fields.next()?)),
+        size: fields.next()?.parse().ok()?,
+        hash: fields.next()?.to_string(),
+        mode: u32::from_str_radix(fields.next()?, 8).ok()?,
+    };
+    fields.next().is_none().then_some(entry)
+}
+
+/// Returns the paths of the files of the `directory` along with their relative paths
+/// joined by `/`, sorted by name.
+fn files(directory: &Path) -> Result<Vec<(PathBuf, PathBuf)>, SyncError> {
+    let mut files = Vec::new();
+    for result in FileSearcher::new(directory)
+        .sort_by(SortOrder::Name)
+        .hidden(true)
+    {
+        let entry = result.map_err(|source| SyncError::WalkError {
+            path: directory.to_path_buf(),
+            source,
+        })?;
+        let path = entry.path();
+        let relative_path = path.strip_prefix(directory).unwrap_or(path);
+        if !entry.is_file() || is_metadata_path(relative_path) {
+            continue;
+        }
+        let portable_path: Vec<_> = relative_path
+            .iter()
+            .map(|component| component.to_string_lossy())
+            .collect();
+        files.push((path.to_path_buf(), PathBuf::from(portable_path.join("/"))));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_creates_loads_and_verifies_manifests() {
+        let root = testing::test_dir("it_creates_loads_and_verifies_manifests");
+        let directory = root.join("archive");
+        std::fs::create_dir_all(directory.join("docs")).unwrap();
+        std::fs::write(directory.join("docs/notes.txt"), "notes").unwrap();
+        std::fs::write(directory.join("photo\t1.jpg"), vec![7; 1000]).unwrap();
+        std::fs::write(directory.join("plan.txt"), "plan").unwrap();
+
+        let manifest = Manifest::create(&directory).unwrap();
+        let paths: Vec<&Path> = manifest
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_path())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("docs/notes.txt"),
+                Path::new("photo\t1.jpg"),
+                Path::new("plan.txt")
+            ]
+        );
+        let manifest_path = root.join("archive.manifest");
+        std::fs::write(&manifest_path, manifest.to_string()).unwrap();
+        let manifest = Manifest::load(&manifest_path).unwrap();
+        assert_eq!(manifest, Manifest::create(&directory).unwrap());
+        assert!(manifest.verify(&directory).unwrap().is_consistent());
+
+        std::fs::write(directory.join("docs/notes.txt"), "NOTES").unwrap();
+        std::fs::remove_file(directory.join("plan.txt")).unwrap();
+        std::fs::write(directory.join("extra.txt"), "extra").unwrap();
+        let report = manifest.verify(&directory).unwrap();
+        assert_eq!(
+            report.divergences,
+            [
+                Divergence::HashMismatch(PathBuf::from("docs/notes.txt")),
+                Divergence::Missing(PathBuf::from("plan.txt")),
+                Divergence::Extra(PathBuf::from("extra.txt")),
+            ]
+        );
+
+        std::fs::write(&manifest_path, "path\t1\n").unwrap();
+        assert!(Manifest::load(&manifest_path).is_err());
+    }
+}
@@ -0,0 +1,543 @@
+//! **names** contains the file name restrictions of the destination file systems, and
+//! the mapping of the names transcoded to fit them back to the original ones.
+
+use crate::{
+    archive::{escape, unescape},
+    error::{IoResultExt, SyncError},
+    hash::Sha256,
+    platform, unicode,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Name of the file kept at the root of a destination mapping the transcoded paths to
+/// the original ones.
+pub const NAMES_FILE_NAME: &str = ".acsync_names";
+
+/// Characters Windows doesn't allow in names, along with the control characters.
+const ILLEGAL_CHARACTERS: &str = "<>:\"\\|?*";
+
+/// Device names Windows doesn't allow as names, whatever their case and extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Longest extension kept by the names shortened by [`TargetFs::transcode`].
+const MAX_EXTENSION_LENGTH: usize = 16;
+
+/// File system of a destination, restricting the names and the length of its paths.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TargetFs {
+    /// Detected from the type of the file system of the destination, see
+    /// [`TargetFs::detect`].
+    #[default]
+    Auto,
+    /// FAT32 and exFAT, without the characters `<>:"\|?*`, the control characters nor
+    /// the device names of Windows (`CON`, `NUL`, `COM1`...), with names of up to 255
+    /// UTF-16 units not ending with a dot or a space and paths of up to 260 units.
+    Fat,
+    /// NTFS and the other file systems of Windows, with the names of FAT and paths of up
+    /// to 32767 UTF-16 units.
+    Ntfs,
+    /// Unix file systems, with names of up to 255 bytes and paths of any length.
+    Posix,
+}
+
+impl FromStr for TargetFs {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "auto" => Ok(TargetFs::Auto),
+            "fat" => Ok(TargetFs::Fat),
+            "ntfs" => Ok(TargetFs::Ntfs),
+            "posix" => Ok(TargetFs::Posix),
+            _ => Err(format!(
+                "unknown file system {value:?}, expected one of auto, fat, ntfs or posix"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TargetFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TargetFs::Auto => "auto",
+            TargetFs::Fat => "fat",
+            TargetFs::Ntfs => "ntfs",
+            TargetFs::Posix => "posix",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl TargetFs {
+    /// Returns the file system holding the `directory`, or its closest existing
+    /// ancestor: [`TargetFs::Fat`] for vfat and exFAT, [`TargetFs::Ntfs`] for NTFS and
+    /// on Windows, [`TargetFs::Posix`] otherwise.
+    pub fn detect(directory: &Path) -> TargetFs {
+        let directory = directory
+            .ancestors()
+            .find(|ancestor| ancestor.is_dir())
+            .unwrap_or(Path::new("."));
+        match platform::file_system_type(directory).as_deref() {
+            Ok("vfat" | "exfat") => TargetFs::Fat,
+            Ok("ntfs") => TargetFs::Ntfs,
+            _ if cfg!(windows) => TargetFs::Ntfs,
+            _ => TargetFs::Posix,
+        }
+    }
+
+    /// Returns why `relative_path` can't be used as is on the file system, if it can't.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::names::TargetFs;
+    /// # use std::path::Path;
+    /// #
+    /// assert_eq!(TargetFs::Posix.check(Path::new("notes: draft.txt")), None);
+    /// assert!(TargetFs::Fat.check(Path::new("notes: draft.txt")).is_some());
+    /// assert!(TargetFs::Ntfs.check(Path::new("docs/con.txt")).is_some());
+    /// ```
+    pub fn check(&self, relative_path: &Path) -> Option<String> {
+        // `PATH_MAX` only limits the system calls, see `platform::short_path`.
+        let max_path_length = match self {
+            TargetFs::Fat => Some(260),
+            TargetFs::Ntfs => Some(32767),
+            TargetFs::Auto | TargetFs::Posix => None,
+        };
+        if let Some(max_path_length) = max_path_length
+            && self.length(&relative_path.to_string_lossy()) > max_path_length
+        {
+            return Some(format!(
+                "the path is longer than {max_path_length} {}",
+                self.unit()
+            ));
+        }
+        relative_path
+            .iter()
+            .find_map(|name| self.check_name(&name.to_string_lossy()))
+    }
+
+    fn check_name(&self, name: &str) -> Option<String> {
+        if self.length(name) > MAX_NAME_LENGTH {
+            return Some(format!(
+                "{name:?} is longer than {MAX_NAME_LENGTH} {}",
+                self.unit()
+            ));
+        }
+        if !self.is_windows() {
+            return None;
+        }
+        if let Some(char) = name.chars().find(|char| is_illegal(*char)) {
+            return Some(format!("{name:?} contains {char:?}"));
+        }
+        if is_reserved(name) {
+            return Some(format!("{name:?} is a reserved device name"));
+        }
+        if name.ends_with(['.', ' ']) {
+            return Some(format!("{name:?} ends with a dot or a space"));
+        }
+        None
+    }
+
+    /// Returns `relative_path` with its names that can't be used as is on the file
+    /// system transcoded, to be recorded in a [`NameMap`].
+    ///
+    /// Illegal characters, the first character of device names and the trailing dots
+    /// and spaces are percent-encoded, e.g. `:` as `%3A`, and names too long are
+    /// shortened, keeping their extension, with a hash of the original name. Paths too
+    /// long are still too long.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::names::TargetFs;
+    /// # use std::path::{Path, PathBuf};
+    /// #
+    /// assert_eq!(
+    ///     TargetFs::Fat.transcode(Path::new("docs?/notes: draft.txt")),
+    ///     PathBuf::from("docs%3F/notes%3A draft.txt")
+    /// );
+    /// assert_eq!(TargetFs::Ntfs.transcode(Path::new("aux.c")), PathBuf::from("%61ux.c"));
+    /// ```
+    pub fn transcode(&self, relative_path: &Path) -> PathBuf {
+        relative_path
+            .iter()
+            .map(|name| {
+                let name = name.to_string_lossy();
+                match self.check_name(&name) {
+                    Some(_) => self.transcode_name(&name),
+                    None => name.into_owned(),
+                }
+            })
+            .collect()
+    }
+
+    fn transcode_name(&self, name: &str) -> String {
+        let mut transcoded = String::new();
+        if self.is_windows() {
+            let chars: Vec<char> = name.chars().collect();
+            let trailing = chars
+                .iter()
+                .rev()
+                .take_while(|char| matches!(char, '.' | ' '))
+                .count();
+            for (position, char) in chars.iter().enumerate() {
+                if is_illegal(*char)
+                    || position == 0 && is_reserved(name)
+                    || position >= chars.len() - trailing
+                {
+                    // Only ASCII characters are encoded.
+                    transcoded += &format!("%{:02X}", *char as u32);
+                } else {
+                    transcoded.push(*char);
+                }
+            }
+        } else {
+            transcoded += name;
+        }
+        if self.length(&transcoded) <= MAX_NAME_LENGTH {
+            return transcoded;
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(name.as_bytes());
+        let hash = hasher.finalize_hex();
+        let (mut stem, extension) = match transcoded.rsplit_once('.') {
+            Some((stem, extension))
+                if !stem.is_empty() && extension.len() <= MAX_EXTENSION_LENGTH =>
+            {
+                (stem.to_string(), format!(".{extension}"))
+            }
+            _ => (transcoded.clone(), String::new()),
+        };
+        let suffix = format!("~{}{extension}", &hash[..8]);
+        while self.length(&stem) + self.length(&suffix) > MAX_NAME_LENGTH {
+            stem.pop();
+        }
+        stem + &suffix
+    }
+
+    fn is_windows(&self) -> bool {
+        matches!(self, TargetFs::Fat | TargetFs::Ntfs)
+    }
+
+    fn unit(&self) -> &'static str {
+        match self.is_windows() {
+            true => "UTF-16 units",
+            false => "bytes",
+        }
+    }
+
+    /// Returns the length of `value` in the units of the file system.
+    fn length(&self, value: &str) -> usize {
+        match self.is_windows() {
+            true => value.encode_utf16().count(),
+            false => value.len(),
+        }
+    }
+}
+
+fn is_illegal(char: char) -> bool {
+    ILLEGAL_CHARACTERS.contains(char) || char.is_ascii_control()
+}
+
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// What to do with the source paths that can't be used as is on the destination file
+/// system.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidNames {
+    /// Fail on them, or count them as failed with
+    /// [`Replicator::ignore_errors`](crate::sync::Replicator::ignore_errors).
+    #[default]
+    Error,
+    /// Leave them out with a warning.
+    Skip,
+    /// Replicate them under the names of [`TargetFs::transcode`], recorded in the
+    /// [`NameMap`] of the destination.
+    Transcode,
+}
+
+impl FromStr for InvalidNames {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "error" => Ok(InvalidNames::Error),
+            "skip" => Ok(InvalidNames::Skip),
+            "transcode" => Ok(InvalidNames::Transcode),
+            _ => Err(format!(
+                "unknown invalid name policy {value:?}, expected one of error, skip or transcode"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for InvalidNames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            InvalidNames::Error => "error",
+            InvalidNames::Skip => "skip",
+            InvalidNames::Transcode => "transcode",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Unicode normalization form of the names written in a destination.
+///
+/// APFS and HFS+ hand the names out decomposed (NFD) while Linux and Windows programs
+/// mostly write them composed (NFC), so the same name can be spelled by different
+/// characters on each side.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Names are written and compared as they are.
+    #[default]
+    Off,
+    /// Names are written composed and compared by their composed form.
+    Nfc,
+    /// Names are written decomposed and compared by their decomposed form.
+    Nfd,
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "off" => Ok(Normalization::Off),
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            _ => Err(format!(
+                "unknown normalization {value:?}, expected one of nfc, nfd or off"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Normalization::Off => "off",
+            Normalization::Nfc => "nfc",
+            Normalization::Nfd => "nfd",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Normalization {
+    /// Returns the `name` in this normalization form.
+    ///
+    /// Names which aren't valid UTF-8 are returned as they are.
+    pub fn normalize_name(&self, name: &OsStr) -> OsString {
+        match (self, name.to_str()) {
+            (Normalization::Nfc, Some(name)) => unicode::nfc(name).into(),
+            (Normalization::Nfd, Some(name)) => unicode::nfd(name).into(),
+            _ => name.to_os_string(),
+        }
+    }
+
+    /// Returns the `relative_path` with each of its names in this normalization form.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::names::Normalization;
+    /// # use std::path::Path;
+    /// #
+    /// let path = Path::new("cafe\u{301}/menu.txt");
+    /// assert_eq!(Normalization::Nfc.normalize(path), Path::new("caf\u{e9}/menu.txt"));
+    /// assert_eq!(Normalization::Off.normalize(path), path);
+    /// ```
+    pub fn normalize(&self, relative_path: &Path) -> PathBuf {
+        relative_path
+            .iter()
+            .map(|name| self.normalize_name(name))
+            .collect()
+    }
+}
+
+/// Finds the files of a directory by the normalized form of their names, keeping the
+/// listings read to do so.
+#[derive(Debug, Default)]
+pub struct NormalizedNames {
+    normalization: Normalization,
+    listings: HashMap<PathBuf, HashMap<OsString, OsString>>,
+}
+
+impl NormalizedNames {
+    pub fn new(normalization: Normalization) -> Self {
+        NormalizedNames {
+            normalization,
+            listings: HashMap::new(),
+        }
+    }
+
+    /// Returns the relative path of the file of the `directory` named as `relative_path`
+    /// once both are normalized, or `None` if there is none.
+    ///
+    /// Paths existing as they are are returned without listing any directory.
+    pub fn find(&mut self, directory: &Path, relative_path: &Path) -> Option<PathBuf> {
+        let mut found_path = PathBuf::new();
+        for name in relative_path {
+            let parent = directory.join(&found_path);
+            if parent.join(name).symlink_metadata().is_ok() {
+                found_path.push(name);
+                continue;
+            }
+            let normalization = self.normalization;
+            let listing = self.listings.entry(parent).or_insert_with_key(|parent| {
+                let entries = std::fs::read_dir(parent).into_iter().flatten().flatten();
+                entries
+                    .map(|entry| {
+                        (
+                            normalization.normalize_name(&entry.file_name()),
+                            entry.file_name(),
+                        )
+                    })
+                    .collect()
+            });
+            found_path.push(listing.get(&normalization.normalize_name(name))?);
+        }
+        Some(found_path)
+    }
+}
+
+/// Mapping of the relative paths transcoded in a destination to the original ones, so
+/// that the files can be replicated back or verified under their original names.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NameMap {
+    originals: BTreeMap<PathBuf, PathBuf>,
+    transcoded: HashMap<PathBuf, PathBuf>,
+}
+
+impl NameMap {
+    pub fn new() -> Self {
+        NameMap::default()
+    }
+
+    /// Loads the mapping of the `directory`.
+    ///
+    /// Returns an empty mapping if the directory has no mapping file.
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Self, SyncError> {
+        let names_path = directory.as_ref().join(NAMES_FILE_NAME);
+        let mut names = NameMap::new();
+        if !names_path.is_file() {
+            return Ok(names);
+        }
+        let content = std::fs::read_to_string(&names_path).with_path(&names_path)?;
+        for line in content.lines() {
+            let (transcoded, original) = line.split_once('\t').ok_or_else(|| SyncError::Io {
+                path: names_path.clone(),
+                source: std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed entry"),
+            })?;
+            names.insert(unescape(transcoded), unescape(original));
+        }
+        Ok(names)
+    }
+
+    /// Writes the mapping file into the `directory`, removing it once the mapping is
+    /// empty.
+    pub fn save<P: AsRef<Path>>(&self, directory: P) -> Result<(), SyncError> {
+        let names_path = directory.as_ref().join(NAMES_FILE_NAME);
+        if self.originals.is_empty() {
+            if names_path.exists() {
+                std::fs::remove_file(&names_path).with_path(&names_path)?;
+            }
+            return Ok(());
+        }
+        let mut content = String::new();
+        for (transcoded, original) in &self.originals {
+            content += &format!(
+                "{}\t{}\n",
+                escape(&transcoded.to_string_lossy()),
+                escape(&original.to_string_lossy())
+            );
+        }
+        std::fs::write(&names_path, content).with_path(&names_path)
+    }
+
+    pub fn insert<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, transcoded: P, original: Q) {
+        let (transcoded, original) = (transcoded.as_ref(), original.as_ref());
+        self.originals
+            .insert(transcoded.to_path_buf(), original.to_path_buf());
+        self.transcoded
+            .insert(original.to_path_buf(), transcoded.to_path_buf());
+    }
+
+    pub fn remove<P: AsRef<Path>>(&mut self, transcoded: P) {
+        if let Some(original) = self.originals.remove(transcoded.as_ref()) {
+            self.transcoded.remove(&original);
+        }
+    }
+
+    /// Returns the original relative path of the `transcoded` one, if it was transcoded.
+    pub fn original<P: AsRef<Path>>(&self, transcoded: P) -> Option<&Path> {
+        self.originals
+            .get(transcoded.as_ref())
+            .map(PathBuf::as_path)
+    }
+
+    /// Returns the relative path the `original` one was transcoded to, if it was.
+    pub fn transcoded<P: AsRef<Path>>(&self, original: P) -> Option<&Path> {
+        self.transcoded.get(original.as_ref()).map(PathBuf::as_path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.originals.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_and_transcodes_names_for_each_file_system() {
+        let path = Path::new("Q1: plans?/CON.txt");
+        assert_eq!(TargetFs::Posix.check(path), None);
+        assert_eq!(
+            TargetFs::Fat.check(path),
+            Some("\"Q1: plans?\" contains ':'".to_string())
+        );
+        let transcoded = TargetFs::Fat.transcode(path);
+        assert_eq!(transcoded, PathBuf::from("Q1%3A plans%3F/%43ON.txt"));
+        assert_eq!(TargetFs::Fat.check(&transcoded), None);
+        assert_eq!(
+            TargetFs::Ntfs.transcode(Path::new("draft. ")),
+            PathBuf::from("draft%2E%20")
+        );
+
+        let long_name = format!("{}.txt", "é".repeat(200));
+        assert!(TargetFs::Ntfs.check(Path::new(&long_name)).is_none());
+        assert!(TargetFs::Posix.check(Path::new(&long_name)).is_some());
+        let shortened = TargetFs::Posix.transcode(Path::new(&long_name));
+        let shortened = shortened.to_str().unwrap();
+        assert!(shortened.len() <= MAX_NAME_LENGTH && shortened.ends_with(".txt"));
+        assert_ne!(
+            TargetFs::Posix.transcode(Path::new(&format!("{}.txt", "é".repeat(201)))),
+            PathBuf::from(shortened)
+        );
+
+        let deep_path = "docs/".repeat(60);
+        assert!(TargetFs::Fat.check(Path::new(&deep_path)).is_some());
+        assert!(TargetFs::Ntfs.check(Path::new(&deep_path)).is_none());
+    }
+}
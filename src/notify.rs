@@ -0,0 +1,136 @@
+//! **notify** contains the notifications sent to systemd when running as a service,
+//! following the `sd_notify` protocol, so that a `Type=notify` unit knows when the
+//! process is ready, reloading or stopping and can watch it with `WatchdogSec=`.
+//!
+//! Every function does nothing when the process wasn't started by systemd, i.e. when
+//! `NOTIFY_SOCKET` isn't set.
+
+use crate::debug;
+use std::{sync::Once, time::Duration};
+
+/// Sends the `state` assignments, e.g. `READY=1` or `STATUS=Waiting`, one per line, to
+/// the socket of `NOTIFY_SOCKET`.
+///
+/// Failures are only logged, a service being able to run without its manager
+/// listening.
+pub fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(error) = send(&socket, state) {
+        debug!("Can't notify {state:?} to {}: {error}", socket.display());
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let sender = UnixDatagram::unbound()?;
+    match socket.as_bytes() {
+        // Sockets of the abstract namespace are only available on Linux.
+        #[cfg(target_os = "linux")]
+        [b'@', name @ ..] => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            sender.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        }
+        _ => {
+            sender.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &std::ffi::OsStr, _state: &str) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Tells systemd that the configuration is being reloaded, along with the time of the
+/// request that `Type=notify-reload` units require.
+pub fn notify_reloading() {
+    match monotonic_usec() {
+        Some(usec) => notify(&format!("RELOADING=1\nMONOTONIC_USEC={usec}")),
+        None => notify("RELOADING=1"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn monotonic_usec() -> Option<u64> {
+    const CLOCK_MONOTONIC: std::ffi::c_int = 1;
+
+    #[repr(C)]
+    struct Timespec {
+        seconds: std::ffi::c_long,
+        nanoseconds: std::ffi::c_long,
+    }
+
+    unsafe extern "C" {
+        fn clock_gettime(clock: std::ffi::c_int, time: *mut Timespec) -> std::ffi::c_int;
+    }
+
+    let mut time = Timespec {
+        seconds: 0,
+        nanoseconds: 0,
+    };
+    // SAFETY: the timespec is a valid pointer for the call.
+    if unsafe { clock_gettime(CLOCK_MONOTONIC, &mut time) } != 0 {
+        return None;
+    }
+    Some(time.seconds as u64 * 1_000_000 + time.nanoseconds as u64 / 1000)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn monotonic_usec() -> Option<u64> {
+    None
+}
+
+/// Returns the interval systemd expects `WATCHDOG=1` pings within, if the watchdog of
+/// the unit is enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID")
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
+
+/// Pings the watchdog of the unit from a background thread at half its interval, for
+/// as long as the process runs. Later calls do nothing, e.g. after a reload.
+pub fn spawn_watchdog() {
+    static SPAWNED: Once = Once::new();
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    SPAWNED.call_once(|| {
+        debug!("Pinging the systemd watchdog every {:?}", interval / 2);
+        std::thread::spawn(move || {
+            loop {
+                notify("WATCHDOG=1");
+                std::thread::sleep(interval / 2);
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn it_sends_the_state_to_the_notify_socket() {
+        use std::os::unix::net::UnixDatagram;
+
+        let directory = crate::testing::test_dir("it_sends_the_state_to_the_notify_socket");
+        let socket = directory.join("notify.sock");
+        let receiver = UnixDatagram::bind(&socket).unwrap();
+
+        send(socket.as_os_str(), "READY=1\nSTATUS=Waiting").unwrap();
+        let mut buffer = [0; 64];
+        let length = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"READY=1\nSTATUS=Waiting");
+    }
+}
@@ -0,0 +1,52 @@
+//! **observer** contains the events a replicate run reports as it goes, behind a trait
+//! so that graphical or terminal user interfaces can be built on top of it.
+
+use crate::{error::SyncError, sync::SyncStats};
+use std::path::Path;
+
+/// What happened during a run, as reported to a [`SyncObserver`].
+#[derive(Debug)]
+pub enum SyncEvent<'a> {
+    /// The walk of the `source` directory started.
+    ScanStarted { source: &'a Path },
+    /// The file at the relative `path` was copied into the target, or overrode the one
+    /// there, with `bytes` bytes.
+    FileCopied { path: &'a Path, bytes: u64 },
+    /// Replicating the relative `path`, when known, failed. The run goes on with the
+    /// next files under [`crate::sync::Replicator::ignore_errors`], and stops otherwise.
+    Error {
+        path: Option<&'a Path>,
+        error: &'a SyncError,
+    },
+    /// The run ended, having gone through the files or stopped on an error, with the
+    /// final `stats`.
+    Finished { stats: &'a SyncStats },
+}
+
+/// Follows the events of a run, see [`crate::sync::Replicator::run_with_observer`].
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::observer::{SyncEvent, SyncObserver};
+/// #
+/// #[derive(Default)]
+/// struct CopiedBytes(u64);
+///
+/// impl SyncObserver for CopiedBytes {
+///     fn on_event(&mut self, event: &SyncEvent<'_>) {
+///         if let SyncEvent::FileCopied { bytes, .. } = event {
+///             self.0 += bytes;
+///         }
+///     }
+/// }
+/// ```
+pub trait SyncObserver {
+    fn on_event(&mut self, event: &SyncEvent<'_>);
+}
+
+impl<O: SyncObserver + ?Sized> SyncObserver for &mut O {
+    fn on_event(&mut self, event: &SyncEvent<'_>) {
+        (**self).on_event(event);
+    }
+}
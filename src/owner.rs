@@ -0,0 +1,101 @@
+//! **owner** contains the translation of the user and group IDs owning the source files
+//! into those of the destination host, whose numeric IDs rarely match.
+
+use crate::platform;
+use std::fmt;
+
+/// Translation table of user or group IDs, given as comma-separated `FROM:TO` pairs
+/// such as `1000:1001,www-data:nginx`.
+///
+/// Each side is a numeric ID or a name looked up in `/etc/passwd` or `/etc/group`, and
+/// `*` as `FROM` matches any ID. The first pair matching an ID applies, the IDs no pair
+/// matches are kept.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::owner::IdMap;
+/// #
+/// let map = IdMap::users("1000:1001,0:2000,*:65534").unwrap();
+/// assert_eq!(map.map(1000), 1001);
+/// assert_eq!(map.map(0), 2000);
+/// assert_eq!(map.map(33), 65534);
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IdMap {
+    /// Pairs of IDs, `None` matching any ID.
+    pairs: Vec<(Option<u32>, u32)>,
+}
+
+impl IdMap {
+    /// Parses a table of user IDs or names.
+    pub fn users(value: &str) -> Result<Self, String> {
+        IdMap::parse(value, "user", platform::user_id)
+    }
+
+    /// Parses a table of group IDs or names.
+    pub fn groups(value: &str) -> Result<Self, String> {
+        IdMap::parse(value, "group", platform::group_id)
+    }
+
+    fn parse(value: &str, kind: &str, lookup: fn(&str) -> Option<u32>) -> Result<Self, String> {
+        let id = |name: &str| {
+            name.parse()
+                .ok()
+                .or_else(|| lookup(name))
+                .ok_or_else(|| format!("unknown {kind} {name:?}"))
+        };
+        let mut pairs = vec![];
+        for pair in value.split(',').map(str::trim) {
+            let Some((from, to)) = pair.split_once(':') else {
+                return Err(format!("{pair:?} is not a FROM:TO pair"));
+            };
+            let from = match from {
+                "*" => None,
+                from => Some(id(from)?),
+            };
+            pairs.push((from, id(to)?));
+        }
+        Ok(IdMap { pairs })
+    }
+
+    /// Returns the ID `id` translates to.
+    pub fn map(&self, id: u32) -> u32 {
+        self.pairs
+            .iter()
+            .find(|(from, _)| from.is_none_or(|from| from == id))
+            .map_or(id, |(_, to)| *to)
+    }
+}
+
+impl fmt::Display for IdMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs: Vec<String> = self
+            .pairs
+            .iter()
+            .map(|(from, to)| match from {
+                Some(from) => format!("{from}:{to}"),
+                None => format!("*:{to}"),
+            })
+            .collect();
+        write!(f, "{}", pairs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_and_applies_id_maps() {
+        let map = IdMap::users("1000:1001, root:33,*:65534").unwrap();
+        assert_eq!(map.to_string(), "1000:1001,0:33,*:65534");
+        assert_eq!((map.map(1000), map.map(0), map.map(7)), (1001, 33, 65534));
+        let map = IdMap::groups("0:1000").unwrap();
+        assert_eq!((map.map(0), map.map(5)), (1000, 5));
+
+        assert!(IdMap::users("1000").is_err());
+        assert!(IdMap::users("no_such_user_acsync:0").is_err());
+        assert!(IdMap::groups("1000:no_such_group_acsync").is_err());
+    }
+}
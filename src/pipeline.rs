@@ -0,0 +1,230 @@
+//! **pipeline** contains the hashing pipeline of store runs, where reader threads read
+//! the upcoming files and feed their chunks to hasher threads while the current file is
+//! copied, so that the disk and the CPU are busy at the same time.
+
+use crate::{
+    error::{IoResultExt, SyncError},
+    hash::Sha256,
+};
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, SyncSender},
+    },
+    thread::JoinHandle,
+};
+
+/// Size of the chunks read by the reader threads.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Count of chunks read ahead of the hasher of a file, bounding the memory used to
+/// about `CHUNK_BUFFER * CHUNK_SIZE` per hasher thread.
+const CHUNK_BUFFER: usize = 4;
+
+/// Count of files a run may submit ahead of the one it copies.
+pub const LOOKAHEAD: usize = 64;
+
+/// File to hash, along with the channel its digest is sent to.
+type Job = (PathBuf, SyncSender<Result<String, SyncError>>);
+
+/// Chunks of a file being read, handed from a reader thread to a hasher thread.
+type Stream = (
+    PathBuf,
+    Receiver<io::Result<Vec<u8>>>,
+    SyncSender<Result<String, SyncError>>,
+);
+
+/// Digest of a file submitted to a [`HashPipeline`], not necessarily computed yet.
+#[derive(Debug)]
+pub struct PendingHash {
+    path: PathBuf,
+    result: Receiver<Result<String, SyncError>>,
+}
+
+impl PendingHash {
+    /// Blocks until the SHA-256 digest of the file is computed.
+    pub fn wait(self) -> Result<String, SyncError> {
+        self.result.recv().unwrap_or_else(|_| {
+            Err(SyncError::Io {
+                path: self.path,
+                source: io::Error::other("hashing thread stopped"),
+            })
+        })
+    }
+}
+
+/// Bounded pipeline hashing files on background threads, with as many reader threads as
+/// hasher threads.
+///
+/// Every queue between the stages is bounded, so readers wait for busy hashers instead
+/// of loading whole files into memory. Dropping the pipeline waits for its threads,
+/// after the files already submitted are hashed.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::pipeline::HashPipeline;
+/// #
+/// let path = std::env::temp_dir().join("acsync_pipeline_example.txt");
+/// std::fs::write(&path, "abc").unwrap();
+///
+/// let pipeline = HashPipeline::new(2);
+/// let pending = pipeline.submit(&path);
+/// assert_eq!(
+///     pending.wait().unwrap(),
+///     "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+/// );
+/// ```
+#[derive(Debug)]
+pub struct HashPipeline {
+    jobs: Option<SyncSender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl HashPipeline {
+    /// Starts `threads` reader threads and `threads` hasher threads, at least one of
+    /// each.
+    pub fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+        let (jobs, job_receiver) = mpsc::sync_channel::<Job>(LOOKAHEAD);
+        let (streams, stream_receiver) = mpsc::sync_channel::<Stream>(threads);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let stream_receiver = Arc::new(Mutex::new(stream_receiver));
+
+        let mut handles = vec![];
+        for _ in 0..threads {
+            let job_receiver = Arc::clone(&job_receiver);
+            let streams = streams.clone();
+            handles.push(std::thread::spawn(move || {
+                while let Some((path, result)) = next(&job_receiver) {
+                    let (chunks, chunk_receiver) = mpsc::sync_channel(CHUNK_BUFFER);
+                    if streams
+                        .send((path.clone(), chunk_receiver, result))
+                        .is_err()
+                    {
+                        break;
+                    }
+                    read_chunks(&path, &chunks);
+                }
+            }));
+        }
+        drop(streams);
+        for _ in 0..threads {
+            let stream_receiver = Arc::clone(&stream_receiver);
+            handles.push(std::thread::spawn(move || {
+                while let Some((path, chunks, result)) = next(&stream_receiver) {
+                    let _ = result.send(hash_chunks(&path, &chunks));
+                }
+            }));
+        }
+        HashPipeline {
+            jobs: Some(jobs),
+            threads: handles,
+        }
+    }
+
+    /// Queues the `path` file for hashing, blocking while [`LOOKAHEAD`] files are
+    /// already queued.
+    pub fn submit<P: AsRef<Path>>(&self, path: P) -> PendingHash {
+        let path = path.as_ref().to_path_buf();
+        let (result, result_receiver) = mpsc::sync_channel(1);
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send((path.clone(), result));
+        }
+        PendingHash {
+            path,
+            result: result_receiver,
+        }
+    }
+}
+
+impl Drop for HashPipeline {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns the next item of a receiver shared between threads, or `None` once its
+/// sender is dropped.
+fn next<T>(receiver: &Mutex<Receiver<T>>) -> Option<T> {
+    receiver
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .recv()
+        .ok()
+}
+
+/// Sends the content of the `path` file in chunks, ending with an empty chunk, or with
+/// the first read error.
+fn read_chunks(path: &Path, chunks: &SyncSender<io::Result<Vec<u8>>>) {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) => {
+            let _ = chunks.send(Err(error));
+            return;
+        }
+    };
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = match file.read(&mut chunk) {
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => {
+                let _ = chunks.send(Err(error));
+                return;
+            }
+        };
+        chunk.truncate(read);
+        if chunks.send(Ok(chunk)).is_err() || read == 0 {
+            return;
+        }
+    }
+}
+
+/// Returns the SHA-256 digest of the chunks of the `path` file.
+fn hash_chunks(path: &Path, chunks: &Receiver<io::Result<Vec<u8>>>) -> Result<String, SyncError> {
+    let mut hasher = Sha256::new();
+    loop {
+        let chunk = chunks
+            .recv()
+            .unwrap_or_else(|_| Err(io::ErrorKind::UnexpectedEof.into()))
+            .with_path(path)?;
+        if chunk.is_empty() {
+            return Ok(hasher.finalize_hex());
+        }
+        hasher.update(&chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash;
+    use crate::testing;
+
+    #[test]
+    fn it_hashes_the_submitted_files_in_the_background() {
+        let directory = testing::test_dir("it_hashes_the_submitted_files_in_the_background");
+        let paths: Vec<PathBuf> = (0..20)
+            .map(|i| {
+                let path = directory.join(format!("{i}.bin"));
+                std::fs::write(&path, vec![i as u8; i * CHUNK_SIZE / 3]).unwrap();
+                path
+            })
+            .collect();
+
+        let pipeline = HashPipeline::new(3);
+        let pending: Vec<PendingHash> = paths.iter().map(|path| pipeline.submit(path)).collect();
+        let missing = pipeline.submit(directory.join("missing.bin"));
+        for (path, pending) in paths.iter().zip(pending) {
+            assert_eq!(pending.wait().unwrap(), hash::hash_file(path).unwrap());
+        }
+        assert!(missing.wait().is_err());
+    }
+}
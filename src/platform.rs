@@ -0,0 +1,731 @@
+//! **platform** contains the access to the file metadata and file system features that
+//! differ between Unix and Windows, so that the rest of the crate doesn't depend on
+//! either one.
+
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::{
+    collections::HashSet,
+    fs::{FileType, Metadata},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Returns the permission bits of a file, e.g. `0o644`.
+///
+/// Windows only has a read-only attribute, reported as `0o444`, or `0o666` otherwise.
+pub fn mode(metadata: &Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        metadata.mode() & 0o7777
+    }
+    #[cfg(not(unix))]
+    {
+        if metadata.permissions().readonly() {
+            0o444
+        } else {
+            0o666
+        }
+    }
+}
+
+/// Sets the permission bits of `path`, e.g. `0o644`.
+///
+/// Windows only has a read-only attribute, set when no write bit is.
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+    }
+    #[cfg(not(unix))]
+    {
+        let mut permissions = path.metadata()?.permissions();
+        permissions.set_readonly(mode & 0o222 == 0);
+        std::fs::set_permissions(path, permissions)
+    }
+}
+
+/// Returns the ID of the device holding a file, or `None` where it is not available.
+pub fn device(metadata: &Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        Some(metadata.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Returns the inode number of a file, or `0` where it is not available.
+pub fn inode(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        metadata.ino()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0
+    }
+}
+
+/// Returns the user and group IDs owning a file, or `None` on Windows.
+pub fn owner(metadata: &Metadata) -> Option<(u32, u32)> {
+    #[cfg(unix)]
+    {
+        Some((metadata.uid(), metadata.gid()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Changes the user and group IDs owning `path`.
+pub fn set_owner(path: &Path, (uid, gid): (u32, u32)) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::chown(path, Some(uid), Some(gid))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, uid, gid);
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Returns true for block and character devices, which never exist on Windows.
+pub fn is_device(file_type: &FileType) -> bool {
+    #[cfg(unix)]
+    {
+        file_type.is_block_device() || file_type.is_char_device()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = file_type;
+        false
+    }
+}
+
+/// Creates at `link` a symlink pointing to `original`.
+///
+/// Windows distinguishes symlinks to files from symlinks to directories, so the kind
+/// is chosen from what `original` points to, defaulting to a file symlink when it
+/// doesn't exist.
+pub fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(original, link)
+    }
+    #[cfg(windows)]
+    {
+        let resolved = match link.parent() {
+            Some(parent) => parent.join(original),
+            None => original.to_path_buf(),
+        };
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(original, link)
+        } else {
+            std::os::windows::fs::symlink_file(original, link)
+        }
+    }
+}
+
+/// Returns true when `path` is a symlink, or a junction on Windows.
+pub fn is_link(path: &Path) -> bool {
+    path.symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Sets the modification time of `path`, a file or a directory.
+pub fn set_modified(path: &Path, modified: SystemTime) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // `FILE_FLAG_BACKUP_SEMANTICS`, without which directories can't be opened.
+        options.write(true).custom_flags(0x0200_0000);
+    }
+    #[cfg(not(windows))]
+    options.read(true);
+    options.open(path)?.set_modified(modified)
+}
+
+/// Returns the path to use as the root of a walk or a copy.
+///
+/// On Windows, the path is made absolute with the `\\?\` prefix, which lifts the
+/// legacy limit of 260 characters for every path joined to it. Other platforms have no
+/// such limit and get the path unchanged.
+pub fn long_path(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    if let Ok(absolute) = std::path::absolute(path) {
+        let value = absolute.to_string_lossy();
+        if value.starts_with(r"\\?\") {
+            return absolute;
+        }
+        return match value.strip_prefix(r"\\") {
+            Some(unc) => PathBuf::from(format!(r"\\?\UNC\{unc}")),
+            None => PathBuf::from(format!(r"\\?\{value}")),
+        };
+    }
+    path.to_path_buf()
+}
+
+/// Longest path the system calls of Linux accept, `PATH_MAX` without the nul.
+#[cfg(target_os = "linux")]
+const MAX_PATH_LENGTH: usize = 4095;
+
+/// Path reaching a file whose path may be too long for the system calls, see
+/// [`short_path`].
+///
+/// It is only valid as long as it lives, since it may go through a directory it keeps
+/// open.
+#[derive(Debug)]
+pub struct ShortPath {
+    path: PathBuf,
+    #[cfg(target_os = "linux")]
+    _directory: Option<std::fs::File>,
+}
+
+impl std::ops::Deref for ShortPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for ShortPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Returns a path to `path` short enough for the system calls, to walk and copy trees
+/// deeper than `PATH_MAX`.
+///
+/// On Linux, the directories of a path too long are opened one chunk of `PATH_MAX` at a
+/// time, each relative to the previous one, and the rest of the path is reached through
+/// the last one as `/proc/self/fd/N/rest`. The path is returned unchanged when it is
+/// short enough, on other platforms, or when a directory can't be opened, in which
+/// case using it fails as it would have. Windows paths are lifted by [`long_path`]
+/// instead.
+pub fn short_path(path: &Path) -> ShortPath {
+    #[cfg(target_os = "linux")]
+    if path.as_os_str().len() > MAX_PATH_LENGTH
+        && let Some(short_path) = open_short_path(path)
+    {
+        return short_path;
+    }
+    ShortPath {
+        path: path.to_path_buf(),
+        #[cfg(target_os = "linux")]
+        _directory: None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_short_path(path: &Path) -> Option<ShortPath> {
+    use std::os::fd::AsRawFd;
+
+    let base = |directory: &Option<std::fs::File>| match directory {
+        Some(directory) => PathBuf::from(format!("/proc/self/fd/{}", directory.as_raw_fd())),
+        None => PathBuf::new(),
+    };
+    let mut directory = None;
+    let mut rest = PathBuf::new();
+    for name in path {
+        if base(&directory).join(&rest).join(name).as_os_str().len() > MAX_PATH_LENGTH {
+            directory = Some(std::fs::File::open(base(&directory).join(&rest)).ok()?);
+            rest = PathBuf::new();
+        }
+        rest.push(name);
+    }
+    Some(ShortPath {
+        path: base(&directory).join(rest),
+        _directory: directory,
+    })
+}
+
+/// Returns the space available to unprivileged users on the file system holding
+/// `path`, in bytes.
+///
+/// Fails with [`io::ErrorKind::Unsupported`] on other systems than Linux and macOS.
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        let path = ffi::c_path(path)?;
+        let mut stat = ffi::StatVfs::default();
+        // SAFETY: the path is nul-terminated and the structure is large enough for the
+        // one filled by the call.
+        if unsafe { ffi::statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ffi::widen(stat.f_bavail) * ffi::widen(stat.f_frsize))
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = path;
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Returns the name of the type of the file system holding `path`, e.g. `ext4` or
+/// `vfat`, or its magic number in hexadecimal when it isn't a known one.
+///
+/// Fails with [`io::ErrorKind::Unsupported`] on other systems than Linux.
+pub fn file_system_type(path: &Path) -> io::Result<String> {
+    #[cfg(target_os = "linux")]
+    {
+        let path = ffi::c_path(path)?;
+        let mut stat = ffi::StatFs::default();
+        // SAFETY: as for `statvfs`.
+        if unsafe { ffi::statfs(path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = match stat.f_type as u32 {
+            0xEF53 => "ext4",
+            0x9123683E => "btrfs",
+            0x58465342 => "xfs",
+            0x2FC12FC1 => "zfs",
+            0xF2F52010 => "f2fs",
+            0x01021994 => "tmpfs",
+            0x794C7630 => "overlayfs",
+            0x4D44 => "vfat",
+            0x2011BAB0 => "exfat",
+            0x5346544E | 0x7366746E => "ntfs",
+            0x6969 => "nfs",
+            0xFF534D42 => "cifs",
+            0xFE534D42 => "smb2",
+            0x65735546 => "fuse",
+            magic => return Ok(format!("{magic:#x}")),
+        };
+        Ok(name.to_string())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Sets the `name` extended attribute of `path`, e.g. `user.comment`, to `value`.
+///
+/// Fails with [`io::ErrorKind::Unsupported`] on other systems than Linux.
+pub fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let (path, name) = (ffi::c_path(path)?, ffi::c_name(name)?);
+        // SAFETY: the path and the name are nul-terminated and the value is valid for
+        // its length.
+        let result = unsafe {
+            ffi::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (path, name, value);
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Removes the `name` extended attribute of `path`.
+///
+/// Fails with [`io::ErrorKind::Unsupported`] on other systems than Linux.
+pub fn remove_xattr(path: &Path, name: &str) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let (path, name) = (ffi::c_path(path)?, ffi::c_name(name)?);
+        // SAFETY: the path and the name are nul-terminated.
+        if unsafe { ffi::removexattr(path.as_ptr(), name.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (path, name);
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Returns true when both relative paths name the same file, comparing them without
+/// regard to case on Windows, whose file systems are case-insensitive.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::platform;
+/// # use std::path::Path;
+/// #
+/// assert!(platform::same_path(Path::new("docs/a.txt"), Path::new("docs/a.txt")));
+/// assert!(!platform::same_path(Path::new("docs/a.txt"), Path::new("docs/b.txt")));
+/// ```
+pub fn same_path(path: &Path, other: &Path) -> bool {
+    if cfg!(windows) {
+        path.to_string_lossy().to_lowercase() == other.to_string_lossy().to_lowercase()
+    } else {
+        path == other
+    }
+}
+
+/// Extended attributes holding the POSIX ACLs of Linux, those of directories having a
+/// default one inherited by the files created in them.
+#[cfg(target_os = "linux")]
+const ACL_XATTRS: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+/// Access control list of a file beyond its permission bits, kept in the format of the
+/// platform to be compared and applied as is.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Acl {
+    /// Binary POSIX ACLs by extended attribute on Linux, the text of the extended ACL on
+    /// macOS.
+    entries: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl Acl {
+    /// Returns true if the file has no ACL beyond its permission bits.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Returns the ACL of `path`, following symbolic links.
+///
+/// File systems without ACLs give an empty one. Fails with
+/// [`io::ErrorKind::Unsupported`] on other systems than Linux and macOS.
+pub fn acl(path: &Path) -> io::Result<Acl> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut acl = Acl::default();
+        for name in ACL_XATTRS {
+            match get_xattr(path, name) {
+                Ok(value) => acl.entries.push((name, value)),
+                Err(error)
+                    if matches!(error.raw_os_error(), Some(ffi::ENODATA | ffi::EOPNOTSUPP)) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(acl)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let path = ffi::c_path(path)?;
+        // SAFETY: the path is nul-terminated, the ACL and its text are freed once read.
+        unsafe {
+            let acl = ffi::acl_get_file(path.as_ptr(), ffi::ACL_TYPE_EXTENDED);
+            if acl.is_null() {
+                let error = io::Error::last_os_error();
+                return match error.raw_os_error() {
+                    Some(ffi::ENOENT | ffi::ENOTSUP) => Ok(Acl::default()),
+                    _ => Err(error),
+                };
+            }
+            let text = ffi::acl_to_text(acl, std::ptr::null_mut());
+            ffi::acl_free(acl);
+            if text.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let value = std::ffi::CStr::from_ptr(text).to_bytes().to_vec();
+            ffi::acl_free(text.cast());
+            Ok(Acl {
+                entries: vec![("extended", value)],
+            })
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = path;
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Replaces the ACL of `path` with `acl`, removing it when `acl` is empty.
+///
+/// Fails with [`io::ErrorKind::Unsupported`] on other systems than Linux and macOS.
+pub fn set_acl(path: &Path, acl: &Acl) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        for name in ACL_XATTRS {
+            match acl
+                .entries
+                .iter()
+                .find(|(entry_name, _)| *entry_name == name)
+            {
+                Some((_, value)) => set_xattr(path, name, value)?,
+                None => match remove_xattr(path, name) {
+                    Err(error)
+                        if !matches!(
+                            error.raw_os_error(),
+                            Some(ffi::ENODATA | ffi::EOPNOTSUPP)
+                        ) =>
+                    {
+                        return Err(error);
+                    }
+                    _ => {}
+                },
+            }
+        }
+        Ok(())
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let path = ffi::c_path(path)?;
+        let text = match acl.entries.first() {
+            Some((_, value)) => {
+                Some(std::ffi::CString::new(value.clone()).map_err(io::Error::other)?)
+            }
+            None => None,
+        };
+        // SAFETY: the path and the text are nul-terminated, the ACL is freed once set.
+        unsafe {
+            let acl = match &text {
+                Some(text) => ffi::acl_from_text(text.as_ptr()),
+                None => ffi::acl_init(0),
+            };
+            if acl.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let result = ffi::acl_set_file(path.as_ptr(), ffi::ACL_TYPE_EXTENDED, acl);
+            let error = io::Error::last_os_error();
+            ffi::acl_free(acl);
+            if result != 0 {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = (path, acl);
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Returns the value of the extended attribute `name` of `path`.
+#[cfg(target_os = "linux")]
+fn get_xattr(path: &Path, name: &str) -> io::Result<Vec<u8>> {
+    let (path, name) = (ffi::c_path(path)?, ffi::c_name(name)?);
+    let mut value: Vec<u8> = vec![];
+    loop {
+        // SAFETY: the path and the name are nul-terminated and the value is valid for
+        // its length, asking for the size of the value when empty.
+        let size = unsafe {
+            ffi::getxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        let Ok(size) = usize::try_from(size) else {
+            let error = io::Error::last_os_error();
+            // The value grew since its size was asked for.
+            if error.raw_os_error() == Some(ffi::ERANGE) {
+                value.clear();
+                continue;
+            }
+            return Err(error);
+        };
+        if value.is_empty() && size > 0 {
+            value.resize(size, 0);
+            continue;
+        }
+        value.truncate(size);
+        return Ok(value);
+    }
+}
+
+/// Returns true when the file system of the `directory` doesn't tell names apart by
+/// case, as exFAT and the defaults of macOS and Windows.
+///
+/// Looks up one of its entries with the case of its letters swapped, falling back to the
+/// platform default when the directory has no entry with letters.
+pub fn case_insensitive(directory: &Path) -> io::Result<bool> {
+    let names = std::fs::read_dir(directory)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<io::Result<HashSet<String>>>()?;
+    for name in &names {
+        let swapped: String = name
+            .chars()
+            .map(|char| match char.is_lowercase() {
+                true => char.to_uppercase().collect::<String>(),
+                false => char.to_lowercase().collect(),
+            })
+            .collect();
+        // Both names listed are two files of a case-sensitive file system.
+        if swapped != *name && !names.contains(&swapped) {
+            return Ok(directory.join(swapped).symlink_metadata().is_ok());
+        }
+    }
+    Ok(cfg!(any(windows, target_os = "macos")))
+}
+
+/// Returns the ID of the user named `name` in `/etc/passwd`, or `None` if it has no such
+/// user.
+///
+/// Users only known to a directory service, such as those of macOS, aren't found.
+pub fn user_id(name: &str) -> Option<u32> {
+    account_id(Path::new("/etc/passwd"), name)
+}
+
+/// Returns the ID of the group named `name` in `/etc/group`, or `None` if it has no such
+/// group.
+pub fn group_id(name: &str) -> Option<u32> {
+    account_id(Path::new("/etc/group"), name)
+}
+
+/// Returns the ID, the third field, of the line of `name` in a `name:password:id:...`
+/// account database.
+fn account_id(database: &Path, name: &str) -> Option<u32> {
+    let content = std::fs::read_to_string(database).ok()?;
+    content.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        (fields.next()? == name).then_some(())?;
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+/// Declarations of the C library functions without a counterpart in the standard
+/// library.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod ffi {
+    use std::{
+        ffi::{CString, c_char, c_int, c_ulong},
+        io,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+    };
+
+    /// Type of the block counts of `statvfs`.
+    #[cfg(target_os = "linux")]
+    type BlockCount = c_ulong;
+    #[cfg(target_os = "macos")]
+    type BlockCount = std::ffi::c_uint;
+
+    /// `struct statvfs`, with room to spare after the fields read.
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct StatVfs {
+        pub f_bsize: c_ulong,
+        pub f_frsize: c_ulong,
+        pub f_blocks: BlockCount,
+        pub f_bfree: BlockCount,
+        pub f_bavail: BlockCount,
+        spare: [u64; 16],
+    }
+
+    /// `struct statfs`, of which only the leading type is read.
+    #[cfg(target_os = "linux")]
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct StatFs {
+        pub f_type: std::ffi::c_long,
+        spare: [u64; 31],
+    }
+
+    unsafe extern "C" {
+        pub fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+        #[cfg(target_os = "linux")]
+        pub fn statfs(path: *const c_char, buf: *mut StatFs) -> c_int;
+        #[cfg(target_os = "linux")]
+        pub fn setxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *const std::ffi::c_void,
+            size: usize,
+            flags: c_int,
+        ) -> c_int;
+        #[cfg(target_os = "linux")]
+        pub fn removexattr(path: *const c_char, name: *const c_char) -> c_int;
+        #[cfg(target_os = "linux")]
+        pub fn getxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *mut std::ffi::c_void,
+            size: usize,
+        ) -> isize;
+        #[cfg(target_os = "macos")]
+        pub fn acl_get_file(path: *const c_char, kind: c_int) -> Acl;
+        #[cfg(target_os = "macos")]
+        pub fn acl_set_file(path: *const c_char, kind: c_int, acl: Acl) -> c_int;
+        #[cfg(target_os = "macos")]
+        pub fn acl_to_text(acl: Acl, length: *mut isize) -> *mut c_char;
+        #[cfg(target_os = "macos")]
+        pub fn acl_from_text(text: *const c_char) -> Acl;
+        #[cfg(target_os = "macos")]
+        pub fn acl_init(count: c_int) -> Acl;
+        #[cfg(target_os = "macos")]
+        pub fn acl_free(object: *mut std::ffi::c_void) -> c_int;
+    }
+
+    #[cfg(target_os = "linux")]
+    pub const ENODATA: c_int = 61;
+    #[cfg(target_os = "linux")]
+    pub const ERANGE: c_int = 34;
+    #[cfg(target_os = "linux")]
+    pub const EOPNOTSUPP: c_int = 95;
+
+    /// `acl_t`, an opaque pointer.
+    #[cfg(target_os = "macos")]
+    pub type Acl = *mut std::ffi::c_void;
+    #[cfg(target_os = "macos")]
+    pub const ACL_TYPE_EXTENDED: c_int = 0x100;
+    #[cfg(target_os = "macos")]
+    pub const ENOENT: c_int = 2;
+    #[cfg(target_os = "macos")]
+    pub const ENOTSUP: c_int = 45;
+
+    /// Converts a field whose width depends on the target, such as a `c_ulong`.
+    pub fn widen<T: Into<u64>>(value: T) -> u64 {
+        value.into()
+    }
+
+    pub fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn c_name(name: &str) -> io::Result<CString> {
+        CString::new(name).map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_reads_the_metadata_of_the_platform() {
+        let directory = testing::test_dir("it_reads_the_metadata_of_the_platform");
+        let file_path = directory.join("file.txt");
+        std::fs::write(&file_path, "abc").unwrap();
+        let mut permissions = file_path.metadata().unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&file_path, permissions).unwrap();
+
+        let metadata = file_path.metadata().unwrap();
+        assert_eq!(mode(&metadata) & 0o222, 0);
+        assert!(!is_device(&metadata.file_type()));
+
+        symlink(Path::new("file.txt"), &directory.join("link.txt")).unwrap();
+        assert!(is_link(&directory.join("link.txt")));
+        assert!(!is_link(&file_path));
+    }
+}
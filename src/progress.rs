@@ -0,0 +1,202 @@
+//! **progress** contains the progress line drawn on the standard error while a
+//! replicate run goes through the files of its source directory.
+
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// Minimum interval between two redraws of the progress line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Time constant of the moving average of the throughput: the throughput measured that
+/// long ago weighs about a third as much as the latest one.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Progress of a run through its files, against the totals of a pre-scan if any.
+#[derive(Debug)]
+pub struct Progress {
+    total_file_count: Option<u64>,
+    total_file_size: Option<u64>,
+    file_count: u64,
+    file_size: u64,
+    /// Moving average of the throughput in bytes per second, once sampled.
+    throughput: Option<f64>,
+    /// Instant of the last throughput sample, with the size gone through by then.
+    sampled: (Instant, u64),
+    drawn: Option<Instant>,
+}
+
+impl Progress {
+    /// Creates the progress of a run going through `totals`, the file count and size
+    /// found by a pre-scan, or an open-ended progress if there was none.
+    pub fn new(totals: Option<(u64, u64)>) -> Self {
+        Progress {
+            total_file_count: totals.map(|(file_count, _)| file_count),
+            total_file_size: totals.map(|(_, file_size)| file_size),
+            file_count: 0,
+            file_size: 0,
+            throughput: None,
+            sampled: (Instant::now(), 0),
+            drawn: None,
+        }
+    }
+
+    /// Accounts a file of `size` bytes gone through, redrawing the progress line if it
+    /// was not drawn recently.
+    pub fn advance(&mut self, size: u64) {
+        self.file_count += 1;
+        self.file_size += size;
+        if self
+            .drawn
+            .is_none_or(|drawn| drawn.elapsed() >= REDRAW_INTERVAL)
+        {
+            self.draw();
+        }
+    }
+
+    /// Draws the final progress line and moves to the next line.
+    pub fn finish(&mut self) {
+        self.draw();
+        eprintln!();
+    }
+
+    fn draw(&mut self) {
+        self.sample(Instant::now());
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r{}\x1b[K", self.line());
+        let _ = stderr.flush();
+        self.drawn = Some(Instant::now());
+    }
+
+    /// Folds the throughput since the previous sample into its moving average, weighing
+    /// it by the time it was measured over.
+    fn sample(&mut self, now: Instant) {
+        let (sampled, sampled_size) = self.sampled;
+        let elapsed = now.saturating_duration_since(sampled).as_secs_f64();
+        if elapsed == 0.0 {
+            return;
+        }
+        let throughput = self.file_size.saturating_sub(sampled_size) as f64 / elapsed;
+        let weight = 1.0 - (-elapsed / THROUGHPUT_WINDOW.as_secs_f64()).exp();
+        self.throughput = Some(match self.throughput {
+            Some(average) => average + weight * (throughput - average),
+            None => throughput,
+        });
+        self.sampled = (now, self.file_size);
+    }
+
+    /// Returns the progress line, e.g. `12.3 GB of 48.0 GB (25%), 120 of 480 files, 85.2
+    /// MB/s, ETA 3m 12s` with pre-scan totals, or `12.3 GB, 120 files, 85.2 MB/s` without.
+    pub fn line(&self) -> String {
+        let throughput = self
+            .throughput
+            .map(|throughput| format!(", {}/s", format_size(throughput as u64)))
+            .unwrap_or_default();
+        let (Some(total_file_count), Some(total_file_size)) =
+            (self.total_file_count, self.total_file_size)
+        else {
+            return format!(
+                "{}, {} files{throughput}",
+                format_size(self.file_size),
+                self.file_count
+            );
+        };
+        let percentage = match total_file_size {
+            0 => 100,
+            _ => self.file_size.min(total_file_size) * 100 / total_file_size,
+        };
+        let mut line = format!(
+            "{} of {} ({percentage}%), {} of {total_file_count} files{throughput}",
+            format_size(self.file_size),
+            format_size(total_file_size),
+            self.file_count
+        );
+        if let Some(remaining) = self.remaining_time() {
+            line += &format!(", ETA {}", format_duration(remaining));
+        }
+        line
+    }
+
+    /// Estimates the time left from the moving average of the throughput, adapting to
+    /// runs going through large files after small ones or slowing down.
+    fn remaining_time(&self) -> Option<Duration> {
+        let remaining_size = self.total_file_size?.saturating_sub(self.file_size);
+        let throughput = self.throughput.filter(|throughput| *throughput > 0.0)?;
+        Duration::try_from_secs_f64(remaining_size as f64 / throughput).ok()
+    }
+}
+
+/// Returns a human readable size with one decimal, e.g. `12.3 GB`.
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Returns a short human readable duration, e.g. `1h 05m`, `3m 12s` or `45s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_describes_the_progress_against_the_totals() {
+        let mut progress = Progress::new(Some((4, 4 * 1024 * 1024)));
+        progress.file_count = 1;
+        progress.file_size = 1024 * 1024;
+        assert_eq!(progress.line(), "1.0 MB of 4.0 MB (25%), 1 of 4 files");
+        let started = progress.sampled.0;
+        progress.sample(started + Duration::from_secs(1));
+        assert_eq!(
+            progress.line(),
+            "1.0 MB of 4.0 MB (25%), 1 of 4 files, 1.0 MB/s, ETA 3s"
+        );
+
+        let mut progress = Progress::new(None);
+        progress.file_count = 3;
+        progress.file_size = 1536;
+        assert_eq!(progress.line(), "1.5 KB, 3 files");
+
+        assert_eq!(format_duration(Duration::from_secs(192)), "3m 12s");
+        assert_eq!(format_duration(Duration::from_secs(3900)), "1h 05m");
+    }
+
+    #[test]
+    fn it_averages_the_throughput_over_time() {
+        let mut progress = Progress::new(None);
+        let started = progress.sampled.0;
+        progress.file_size = 10 * 1024 * 1024;
+        progress.sample(started + Duration::from_secs(10));
+        assert_eq!(progress.throughput, Some(1024.0 * 1024.0));
+
+        // A burst of a second barely moves the average, a steady rate takes it over.
+        progress.file_size += 11 * 1024 * 1024;
+        progress.sample(started + Duration::from_secs(11));
+        let throughput = progress.throughput.unwrap() / (1024.0 * 1024.0);
+        assert!((1.9..2.0).contains(&throughput));
+        for second in 12..60 {
+            progress.file_size += 4 * 1024 * 1024;
+            progress.sample(started + Duration::from_secs(second));
+        }
+        let throughput = progress.throughput.unwrap() / (1024.0 * 1024.0);
+        assert!((3.9..4.0).contains(&throughput));
+    }
+}
@@ -0,0 +1,95 @@
+//! **prompt** contains the questions asked to the user during a run, such as whether
+//! to override a dated file, behind a trait so that they can be answered without a
+//! terminal.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, Write},
+    sync::Mutex,
+};
+
+/// Asks yes or no questions to the user.
+pub trait Prompter: fmt::Debug + Send + Sync {
+    /// Asks the `question`, returning true if the user answered yes.
+    fn confirm(&self, question: &str) -> io::Result<bool>;
+}
+
+/// Asks the questions on the standard output, reading the answers from the standard
+/// input. Answers starting with `y` or `Y` are yes, any other is no.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn confirm(&self, question: &str) -> io::Result<bool> {
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{question} (Y/N) ")?;
+        stdout.flush()?;
+        let mut input = String::new();
+        io::stdin().lock().read_line(&mut input)?;
+        Ok(input.starts_with('y') || input.starts_with('Y'))
+    }
+}
+
+/// Answers every question with the same answer, e.g. for `--yes` or `--no`.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoPrompter(pub bool);
+
+impl Prompter for AutoPrompter {
+    fn confirm(&self, _question: &str) -> io::Result<bool> {
+        Ok(self.0)
+    }
+}
+
+/// Answers the questions with a list of answers given in advance, recording the
+/// questions asked, for tests.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::prompt::{MockPrompter, Prompter};
+/// #
+/// let prompter = MockPrompter::new([true, false]);
+/// assert!(prompter.confirm("First?").unwrap());
+/// assert!(!prompter.confirm("Second?").unwrap());
+/// assert!(prompter.confirm("Third?").is_err());
+/// assert_eq!(prompter.questions(), ["First?", "Second?", "Third?"]);
+/// ```
+#[derive(Debug, Default)]
+pub struct MockPrompter {
+    answers: Mutex<VecDeque<bool>>,
+    questions: Mutex<Vec<String>>,
+}
+
+impl MockPrompter {
+    pub fn new<I: IntoIterator<Item = bool>>(answers: I) -> Self {
+        MockPrompter {
+            answers: Mutex::new(answers.into_iter().collect()),
+            questions: Mutex::default(),
+        }
+    }
+
+    /// Returns the questions asked so far.
+    pub fn questions(&self) -> Vec<String> {
+        self.questions
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .clone()
+    }
+}
+
+impl Prompter for MockPrompter {
+    /// Returns the next answer, failing with [`io::ErrorKind::UnexpectedEof`] once they
+    /// were all used.
+    fn confirm(&self, question: &str) -> io::Result<bool> {
+        self.questions
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .push(question.to_string());
+        self.answers
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .pop_front()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }
+}
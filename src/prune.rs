@@ -0,0 +1,304 @@
+//! **prune** contains the removal of the old snapshots of a destination, either the
+//! date-named snapshot directories of `--link_dest` backups or the snapshots of a
+//! content-addressed store, following retention rules.
+
+use crate::{
+    cli_helper,
+    error::{IoResultExt, SyncError},
+    info,
+    lock::DestinationLock,
+    store::{ContentStore, SNAPSHOTS_DIR_NAME},
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Result of a [`Pruner`] run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PruneReport {
+    pub snapshot_kept_count: u64,
+    pub snapshot_removed_count: u64,
+    pub object_removed_count: u64,
+    pub total_object_removed_size: u64,
+}
+
+impl fmt::Display for PruneReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", " Prune ")?;
+        writeln!(f, "Snapshots kept: {}", self.snapshot_kept_count)?;
+        writeln!(f, "Snapshots removed: {}", self.snapshot_removed_count)?;
+        if self.object_removed_count > 0 {
+            writeln!(
+                f,
+                "Unreferenced objects removed: {} ({} KBs)",
+                self.object_removed_count,
+                (self.total_object_removed_size / 1024) as f64
+            )?;
+        }
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+#[derive(Debug)]
+struct PrunerOptions {
+    dryrun: bool,
+    keep_last: usize,
+    keep_daily: usize,
+    keep_weekly: usize,
+    keep_monthly: usize,
+}
+
+impl Default for PrunerOptions {
+    fn default() -> Self {
+        PrunerOptions {
+            dryrun: false,
+            keep_last: 1,
+            keep_daily: 0,
+            keep_weekly: 0,
+            keep_monthly: 0,
+        }
+    }
+}
+
+/// Returns the name of the day, week or month a time falls in.
+type Period = fn(SystemTime) -> String;
+
+/// Snapshot of a destination, with the date it was taken at.
+#[derive(Debug)]
+enum Snapshot {
+    Directory(PathBuf, SystemTime),
+    Manifest(String, SystemTime),
+}
+
+impl Snapshot {
+    fn time(&self) -> SystemTime {
+        match self {
+            Snapshot::Directory(_, time) | Snapshot::Manifest(_, time) => *time,
+        }
+    }
+}
+
+/// Removes the snapshots of a destination not selected by any retention rule.
+///
+/// The destination is either a content-addressed store written by
+/// [`Replicator::store`](crate::sync::Replicator::store), whose unreferenced objects
+/// are removed along with its snapshots, or a directory of snapshot directories named
+/// after their date, e.g. `2024-06-02`. Other directories are never removed.
+#[derive(Debug)]
+pub struct Pruner {
+    destination: PathBuf,
+    options: PrunerOptions,
+}
+
+impl Pruner {
+    pub fn new<P: AsRef<Path>>(destination: P) -> Self {
+        Pruner {
+            destination: destination.as_ref().to_path_buf(),
+            options: PrunerOptions::default(),
+        }
+    }
+
+    pub fn dryrun(mut self, flag: bool) -> Self {
+        self.options.dryrun = flag;
+        self
+    }
+
+    /// Keeps the `count` most recent snapshots, 1 by default so that the last one is
+    /// always kept.
+    pub fn keep_last(mut self, count: usize) -> Self {
+        self.options.keep_last = count;
+        self
+    }
+
+    /// Keeps the most recent snapshot of each of the last `count` days with snapshots.
+    pub fn keep_daily(mut self, count: usize) -> Self {
+        self.options.keep_daily = count;
+        self
+    }
+
+    /// Keeps the most recent snapshot of each of the last `count` weeks with snapshots,
+    /// weeks starting on Monday.
+    pub fn keep_weekly(mut self, count: usize) -> Self {
+        self.options.keep_weekly = count;
+        self
+    }
+
+    /// Keeps the most recent snapshot of each of the last `count` months with
+    /// snapshots.
+    pub fn keep_monthly(mut self, count: usize) -> Self {
+        self.options.keep_monthly = count;
+        self
+    }
+
+    pub fn run(&self) -> Result<PruneReport, SyncError> {
+        let destination = &self.destination;
+        let dryrun = self.options.dryrun;
+        // Keeps a concurrent store run from writing objects collected right away.
+        let _lock = if dryrun {
+            None
+        } else {
+            Some(DestinationLock::acquire(destination, Duration::ZERO)?)
+        };
+        let store = ContentStore::new(destination);
+        let is_store = destination.join(SNAPSHOTS_DIR_NAME).is_dir();
+        let mut snapshots = if is_store {
+            store
+                .snapshots()?
+                .into_iter()
+                .filter_map(|name| {
+                    let timestamp = name.parse().ok()?;
+                    Some(Snapshot::Manifest(
+                        name,
+                        UNIX_EPOCH + Duration::from_secs(timestamp),
+                    ))
+                })
+                .collect()
+        } else {
+            self.snapshot_directories()?
+        };
+        snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.time()));
+
+        let mut report = PruneReport::default();
+        let kept = self.retained(&snapshots);
+        for (snapshot, kept) in snapshots.iter().zip(kept) {
+            if kept {
+                report.snapshot_kept_count += 1;
+                continue;
+            }
+            match snapshot {
+                Snapshot::Directory(path, _) => {
+                    info!("Removing snapshot directory {} ...", path.display());
+                    if !dryrun {
+                        std::fs::remove_dir_all(path).with_path(path)?;
+                    }
+                }
+                Snapshot::Manifest(name, _) => {
+                    info!("Removing snapshot {name} ...");
+                    if !dryrun {
+                        store.remove_snapshot(name)?;
+                    }
+                }
+            }
+            report.snapshot_removed_count += 1;
+        }
+        // The objects of the snapshots a dry run would remove are still referenced.
+        if is_store && !dryrun {
+            let (count, size) = store.collect_garbage(false)?;
+            report.object_removed_count = count;
+            report.total_object_removed_size = size;
+        }
+        Ok(report)
+    }
+
+    /// Returns the subdirectories of the destination named after a date.
+    fn snapshot_directories(&self) -> Result<Vec<Snapshot>, SyncError> {
+        let destination = &self.destination;
+        let mut snapshots = vec![];
+        for entry in std::fs::read_dir(destination).with_path(destination)? {
+            let entry = entry.with_path(destination)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Ages such as `7d` are valid times too, but not snapshot names.
+            if entry.path().is_dir()
+                && cli_helper::parse_duration(&name).is_none()
+                && let Some(time) = cli_helper::parse_time(&name)
+            {
+                snapshots.push(Snapshot::Directory(entry.path(), time));
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Returns whether each of the `snapshots`, sorted from the newest, is kept by a
+    /// retention rule.
+    fn retained(&self, snapshots: &[Snapshot]) -> Vec<bool> {
+        let PrunerOptions {
+            keep_last,
+            keep_daily,
+            keep_weekly,
+            keep_monthly,
+            ..
+        } = self.options;
+        let mut kept: Vec<bool> = (0..snapshots.len()).map(|i| i < keep_last).collect();
+        let rules: [(usize, Period); 3] = [
+            (keep_daily, |time| {
+                cli_helper::format_time(time)[..10].to_string()
+            }),
+            (keep_weekly, |time| {
+                let days = time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+                    / 86400;
+                // 1970-01-01 was a Thursday.
+                ((days + 3) / 7).to_string()
+            }),
+            (keep_monthly, |time| {
+                cli_helper::format_time(time)[..7].to_string()
+            }),
+        ];
+        for (count, period) in rules {
+            let mut last_period = None;
+            let mut period_count = 0;
+            for (position, snapshot) in snapshots.iter().enumerate() {
+                if period_count == count {
+                    break;
+                }
+                let period = period(snapshot.time());
+                if last_period.as_ref() != Some(&period) {
+                    kept[position] = true;
+                    period_count += 1;
+                    last_period = Some(period);
+                }
+            }
+        }
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_keeps_the_snapshots_selected_by_the_retention_rules() {
+        let root = testing::test_dir("it_keeps_the_snapshots_selected_by_the_retention_rules");
+        for name in [
+            "2024-05-15",
+            "2024-05-31",
+            "2024-06-01",
+            "2024-06-02T08:00:00",
+            "2024-06-02T20:00:00",
+            "2024-06-03",
+            "not-a-snapshot",
+        ] {
+            std::fs::create_dir_all(root.join(name)).unwrap();
+        }
+
+        let report = Pruner::new(&root)
+            .keep_daily(2)
+            .keep_monthly(2)
+            .run()
+            .unwrap();
+        assert_eq!(
+            (report.snapshot_kept_count, report.snapshot_removed_count),
+            (3, 3)
+        );
+        let mut names: Vec<String> = std::fs::read_dir(&root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "2024-05-31",
+                "2024-06-02T20:00:00",
+                "2024-06-03",
+                "not-a-snapshot"
+            ]
+        );
+    }
+}
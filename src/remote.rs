@@ -0,0 +1,1252 @@
+//! **remote** contains the `acsync://` protocol, serving a directory to the other
+//! machines of a network with [`Server`] and replicating from or to it with
+//! [`RemoteReplicator`].
+//!
+//! Every message is a big-endian `u32` length followed by a header of tab-separated
+//! fields, then a big-endian `u64` length followed by the data, if any. The requests
+//! are `LIST`, `STAT`, `READ`, `WRITE`, `COMMIT`, `MKDIR` and `DELETE`, answered by
+//! `OK` and their result fields, or by `ERR`, an error kind and a message. Paths are
+//! relative to the served directory, with `/` separators.
+//!
+//! A connection starts with `AUTH` when the server requires a token, and with
+//! `COMPRESS` to agree on a zstd level, after which the chunks of `READ` and `WRITE`
+//! can be compressed, flagged by a trailing `z` field.
+//!
+//! [`RemoteReplicator`] also replicates from or to the buckets of S3-compatible
+//! storages, given as `s3://` URLs, see [`crate::s3`], and the collections of WebDAV
+//! shares, given as `dav://` URLs, see [`crate::webdav`].
+
+use crate::{
+    archive::{self, Compression, escape, unescape},
+    copy::{self, RateLimiter},
+    debug,
+    error::{IoResultExt, SyncError},
+    fs::FileSearcher,
+    info,
+    lock::DestinationLock,
+    s3::{Bucket, S3Config, S3Url},
+    signal,
+    sync::{self, ChangeDetection, ConflictPolicy, SyncStats},
+    warn,
+    webdav::{self, DavConfig, Share},
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Port of `acsync serve` when none is informed.
+pub const DEFAULT_PORT: u16 = 8733;
+
+/// Largest chunk of file content read or written by a request.
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Highest zstd level accepted by a server.
+pub const MAX_COMPRESS_LEVEL: u32 = 19;
+
+/// Extensions of the file formats already compressed, whose chunks are transferred as
+/// they are.
+pub const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "avi", "br", "bz2", "docx", "flac", "gif", "gz", "heic", "jpeg", "jpg", "lz4", "mkv",
+    "mov", "mp3", "mp4", "ogg", "pdf", "png", "rar", "tgz", "webm", "webp", "xlsx", "xz", "zip",
+    "zst",
+];
+
+/// Returns true if the `path` has the extension of an already compressed format.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::remote;
+/// #
+/// assert!(remote::is_compressed("photos/IMG_0001.JPG"));
+/// assert!(!remote::is_compressed("docs/notes.txt"));
+/// ```
+pub fn is_compressed<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| COMPRESSED_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Location of a directory served by `acsync serve`, written
+/// `acsync://HOST[:PORT]/PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteUrl {
+    /// Host and port of the server.
+    pub address: String,
+    /// Directory relative to the served one.
+    pub path: PathBuf,
+}
+
+impl RemoteUrl {
+    /// Parses an `acsync://` URL, returning `None` for any other value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::remote::RemoteUrl;
+    /// # use std::path::PathBuf;
+    /// #
+    /// let url = RemoteUrl::parse("acsync://nas.local/photos/2024").unwrap();
+    /// assert_eq!(url.address, "nas.local:8733");
+    /// assert_eq!(url.path, PathBuf::from("photos/2024"));
+    /// assert_eq!(RemoteUrl::parse("acsync://[::1]:9000").unwrap().address, "[::1]:9000");
+    /// assert!(RemoteUrl::parse("/media/backup").is_none());
+    /// ```
+    pub fn parse(value: &str) -> Option<Self> {
+        let rest = value.strip_prefix("acsync://")?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if authority.is_empty() {
+            return None;
+        }
+        let has_port = authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        let address = if has_port {
+            authority.to_string()
+        } else {
+            format!("{authority}:{DEFAULT_PORT}")
+        };
+        Some(RemoteUrl {
+            address,
+            path: PathBuf::from(path.trim_matches('/')),
+        })
+    }
+}
+
+impl fmt::Display for RemoteUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "acsync://{}/{}", self.address, wire_path(&self.path))
+    }
+}
+
+/// Type, size and modification date of a file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EntryInfo {
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Writes a message made of the `header` and the `data`.
+fn write_message<W: Write>(writer: &mut W, header: &str, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(header.len() as u32).to_be_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(&(data.len() as u64).to_be_bytes())?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+/// Reads a message, failing with [`ErrorKind::InvalidData`] when its header or data is
+/// larger than allowed.
+fn read_message<R: Read>(reader: &mut R, max_data_size: u64) -> io::Result<(String, Vec<u8>)> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_HEADER_SIZE {
+        return Err(io::Error::new(ErrorKind::InvalidData, "header too large"));
+    }
+    let mut header = vec![0; length];
+    reader.read_exact(&mut header)?;
+    let header =
+        String::from_utf8(header).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))?;
+    let mut length = [0; 8];
+    reader.read_exact(&mut length)?;
+    let length = u64::from_be_bytes(length);
+    if length > max_data_size {
+        return Err(io::Error::new(ErrorKind::InvalidData, "data too large"));
+    }
+    let mut data = vec![0; length as usize];
+    reader.read_exact(&mut data)?;
+    Ok((header, data))
+}
+
+/// Returns the `path` with `/` separators, as sent over the wire.
+pub(crate) fn wire_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub(crate) fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+pub(crate) fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+fn error_kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::NotFound => "not_found",
+        ErrorKind::PermissionDenied => "permission_denied",
+        ErrorKind::InvalidInput => "invalid_input",
+        _ => "other",
+    }
+}
+
+fn error_kind(name: &str) -> ErrorKind {
+    match name {
+        "not_found" => ErrorKind::NotFound,
+        "permission_denied" => ErrorKind::PermissionDenied,
+        "invalid_input" => ErrorKind::InvalidInput,
+        _ => ErrorKind::Other,
+    }
+}
+
+/// Returns the files and directories under `directory`, keyed by their path relative
+/// to it, or nothing if it doesn't exist.
+fn list_tree(directory: &Path) -> io::Result<Vec<(PathBuf, EntryInfo)>> {
+    if !directory.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for result in FileSearcher::new(directory).hidden(true) {
+        let entry = result?;
+        let Ok(relative_path) = entry.path().strip_prefix(directory) else {
+            continue;
+        };
+        if relative_path.as_os_str().is_empty()
+            || relative_path.to_string_lossy().ends_with(".acsync_tmp")
+            || !entry.is_file() && !entry.is_dir()
+        {
+            continue;
+        }
+        let metadata = entry.metadata();
+        entries.push((
+            relative_path.to_path_buf(),
+            EntryInfo {
+                is_dir: entry.is_dir(),
+                size: if entry.is_dir() { 0 } else { metadata.len() },
+                modified: metadata.modified()?,
+            },
+        ));
+    }
+    Ok(entries)
+}
+
+/// Reads up to [`CHUNK_SIZE`] bytes of the `path` file from `offset`.
+fn read_chunk(path: &Path, offset: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    file.take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Writes the `chunk` at `offset` of the temporary file of `path`, which is created
+/// anew, along with its missing parents, for the first chunk.
+fn write_chunk(path: &Path, offset: u64, chunk: &[u8]) -> io::Result<()> {
+    let write_path = copy::temporary_path(path);
+    let mut file = if offset == 0 {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        File::create(&write_path)?
+    } else {
+        let mut file = File::options().write(true).open(&write_path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file
+    };
+    file.write_all(chunk)
+}
+
+/// Moves the temporary file written by [`write_chunk`] to `path`, with the `modified`
+/// date.
+fn commit_file(path: &Path, modified: SystemTime) -> io::Result<()> {
+    let write_path = copy::temporary_path(path);
+    File::options()
+        .write(true)
+        .open(&write_path)?
+        .set_modified(modified)?;
+    std::fs::rename(&write_path, path)
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Serves a directory to [`RemoteReplicator`] clients, one thread per connection.
+///
+/// Requests are limited to the paths inside the directory, which itself can't be
+/// written to nor deleted, and a connection is closed on a wrong token. The traffic,
+/// token included, isn't encrypted: there's no TLS without a dependency, so beyond a
+/// trusted network it should go through a tunnel such as SSH port forwarding, stunnel
+/// or a VPN.
+#[derive(Debug, Clone)]
+pub struct Server {
+    root: PathBuf,
+    read_only: bool,
+    token: Option<String>,
+}
+
+impl Server {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Server {
+            root: root.as_ref().to_path_buf(),
+            read_only: false,
+            token: None,
+        }
+    }
+
+    /// Refuses the requests modifying the served directory, so that it can only be
+    /// an origin.
+    pub fn read_only(mut self, flag: bool) -> Self {
+        self.read_only = flag;
+        self
+    }
+
+    /// Requires the clients to authenticate with the pre-shared `token` before any
+    /// request.
+    pub fn token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Answers the connections accepted by the `listener` until the process stops.
+    pub fn serve(&self, listener: TcpListener) -> Result<(), SyncError> {
+        let address = listener.local_addr().with_path(&self.root)?;
+        info!("Serving {} on {address} ...", self.root.display());
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    warn!("Failed to accept a connection: {error}");
+                    continue;
+                }
+            };
+            let server = self.clone();
+            std::thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map(|peer| peer.to_string())
+                    .unwrap_or_default();
+                debug!("Connection from {peer}");
+                if let Err(error) = server.serve_connection(stream) {
+                    warn!("Connection from {peer} failed: {error}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn serve_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        let mut session = Session {
+            authenticated: self.token.is_none(),
+            compress_level: 0,
+        };
+        loop {
+            let (header, data) = match read_message(&mut reader, CHUNK_SIZE as u64) {
+                Ok(message) => message,
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(error) => return Err(error),
+            };
+            match self.handle(&header, &data, &mut session) {
+                Ok((fields, data)) => write_message(&mut writer, &format!("OK{fields}"), &data)?,
+                Err(error) => {
+                    debug!("Request {header:?} failed: {error}");
+                    let answer = format!(
+                        "ERR\t{}\t{}",
+                        error_kind_name(error.kind()),
+                        escape(&error.to_string())
+                    );
+                    write_message(&mut writer, &answer, &[])?;
+                    // Closed on a wrong token, so that tokens can't be guessed in a loop.
+                    if header.split('\t').next() == Some("AUTH") {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the request of the `header`, returning the fields and the data of its
+    /// answer. Only `AUTH` is accepted until the `session` is authenticated.
+    fn handle(
+        &self,
+        header: &str,
+        data: &[u8],
+        session: &mut Session,
+    ) -> io::Result<(String, Vec<u8>)> {
+        let fields: Vec<&str> = header.split('\t').collect();
+        let field = |index: usize| {
+            fields
+                .get(index)
+                .copied()
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "malformed request"))
+        };
+        let number = |index: usize| {
+            field(index)?
+                .parse::<u64>()
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "malformed request"))
+        };
+        let denied = |reason: &str| io::Error::new(ErrorKind::PermissionDenied, reason);
+        let request = fields[0];
+        if request == "AUTH" {
+            let token = unescape(field(1)?);
+            if let Some(expected) = &self.token
+                && !same_token(expected, &token)
+            {
+                return Err(denied("invalid token"));
+            }
+            session.authenticated = true;
+            return Ok(Default::default());
+        }
+        if !session.authenticated {
+            return Err(denied("authentication required"));
+        }
+        if self.read_only && matches!(request, "WRITE" | "COMMIT" | "MKDIR" | "DELETE") {
+            return Err(denied("the served directory is read-only"));
+        }
+        if request == "COMPRESS" {
+            session.compress_level = if Compression::Zstd.is_available() {
+                (number(1)? as u32).min(MAX_COMPRESS_LEVEL)
+            } else {
+                0
+            };
+            return Ok((format!("\t{}", session.compress_level), vec![]));
+        }
+        let compressed = fields.last() == Some(&"z") && session.compress_level > 0;
+        let path = self.resolve(field(1)?)?;
+        if matches!(request, "WRITE" | "COMMIT" | "MKDIR" | "DELETE") && path == self.root {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the served directory itself can't be changed",
+            ));
+        }
+        match request {
+            "LIST" => {
+                let mut listing = String::new();
+                for (relative_path, info) in list_tree(&path)? {
+                    listing += &format!(
+                        "{}\t{}\t{}\t{}\n",
+                        if info.is_dir { 'd' } else { 'f' },
+                        info.size,
+                        to_nanos(info.modified),
+                        escape(&wire_path(&relative_path))
+                    );
+                }
+                Ok((String::new(), listing.into_bytes()))
+            }
+            "STAT" => match path.metadata() {
+                Ok(metadata) => Ok((
+                    format!(
+                        "\t{}\t{}\t{}",
+                        if metadata.is_dir() { 'd' } else { 'f' },
+                        metadata.len(),
+                        to_nanos(metadata.modified()?)
+                    ),
+                    vec![],
+                )),
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    Ok(("\t-".to_string(), vec![]))
+                }
+                Err(error) => Err(error),
+            },
+            "READ" => {
+                let chunk = read_chunk(&path, number(2)?)?;
+                if compressed {
+                    let compressed_chunk = archive::compress_bytes(
+                        &chunk,
+                        Compression::Zstd,
+                        Some(session.compress_level),
+                    )?;
+                    if compressed_chunk.len() < chunk.len() {
+                        return Ok(("\tz".to_string(), compressed_chunk));
+                    }
+                }
+                Ok((String::new(), chunk))
+            }
+            "WRITE" => {
+                let chunk = if compressed {
+                    decompress_chunk(data)?
+                } else {
+                    data.to_vec()
+                };
+                write_chunk(&path, number(2)?, &chunk).map(|_| Default::default())
+            }
+            "COMMIT" => commit_file(&path, from_nanos(number(2)?)).map(|_| Default::default()),
+            "MKDIR" => std::fs::create_dir_all(&path).map(|_| Default::default()),
+            "DELETE" => remove_path(&path).map(|_| Default::default()),
+            request => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown request {request:?}"),
+            )),
+        }
+    }
+
+    /// Returns the path of the served directory for the escaped `relative_path`,
+    /// refusing any path outside of it.
+    fn resolve(&self, relative_path: &str) -> io::Result<PathBuf> {
+        let relative_path = PathBuf::from(unescape(relative_path));
+        if !relative_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "not a relative path inside the served directory",
+            ));
+        }
+        Ok(self.root.join(relative_path))
+    }
+}
+
+/// State of a connection to a [`Server`].
+struct Session {
+    authenticated: bool,
+    /// Level of zstd agreed on with `COMPRESS`, 0 when not compressing.
+    compress_level: u32,
+}
+
+/// Decompresses a chunk, refusing more than [`CHUNK_SIZE`] bytes.
+fn decompress_chunk(data: &[u8]) -> io::Result<Vec<u8>> {
+    archive::decompress_bytes_limited(data, Compression::Zstd, CHUNK_SIZE)
+}
+
+/// Compares the tokens in a time independent of their content.
+fn same_token(token: &str, other: &str) -> bool {
+    token.len() == other.len()
+        && token
+            .bytes()
+            .zip(other.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Connection to a server, sending one request at a time.
+struct Connection {
+    url: RemoteUrl,
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    /// Level of zstd agreed on with the server, 0 when not compressing.
+    compress_level: u32,
+}
+
+impl Connection {
+    /// Connects to the server of the `url`, authenticating with the `token` if any,
+    /// and agreeing on the `compress_level` if any and zstd is available on both ends.
+    fn open(
+        url: &RemoteUrl,
+        token: Option<&str>,
+        compress_level: Option<u32>,
+    ) -> Result<Self, SyncError> {
+        let name = url.to_string();
+        let stream = TcpStream::connect(&url.address).with_path(&name)?;
+        let mut connection = Connection {
+            url: url.clone(),
+            reader: BufReader::new(stream.try_clone().with_path(&name)?),
+            writer: BufWriter::new(stream),
+            compress_level: 0,
+        };
+        if let Some(token) = token {
+            connection.exchange(&name, &format!("AUTH\t{}", escape(token)), &[])?;
+        }
+        if let Some(level) = compress_level {
+            if !Compression::Zstd.is_available() {
+                warn!("Transferring uncompressed, zstd is not available");
+            } else {
+                let (fields, _) = connection.exchange(&name, &format!("COMPRESS\t{level}"), &[])?;
+                connection.compress_level = fields
+                    .first()
+                    .and_then(|level| level.parse().ok())
+                    .unwrap_or_default();
+                if connection.compress_level == 0 {
+                    warn!("Transferring uncompressed, zstd is not available on {name}");
+                }
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Returns true if the chunks of the `path` file are worth compressing.
+    fn compresses(&self, path: &Path) -> bool {
+        self.compress_level > 0 && !is_compressed(path)
+    }
+
+    /// Sends the `request` on `path`, relative to the URL directory, with the
+    /// `arguments` and `data`, returning the fields and data of the answer.
+    fn request(
+        &mut self,
+        request: &str,
+        path: &Path,
+        arguments: &[String],
+        data: &[u8],
+    ) -> Result<(Vec<String>, Vec<u8>), SyncError> {
+        let full_path = self.url.path.join(path);
+        let name = format!("acsync://{}/{}", self.url.address, wire_path(&full_path));
+        let mut header = format!("{request}\t{}", escape(&wire_path(&full_path)));
+        for argument in arguments {
+            header += &format!("\t{argument}");
+        }
+        self.exchange(&name, &header, data)
+    }
+
+    /// Sends the `header` and `data`, returning the fields and data of the answer,
+    /// with the errors reported on `name`.
+    fn exchange(
+        &mut self,
+        name: &str,
+        header: &str,
+        data: &[u8],
+    ) -> Result<(Vec<String>, Vec<u8>), SyncError> {
+        write_message(&mut self.writer, header, data).with_path(name)?;
+        let (header, data) = read_message(&mut self.reader, u64::MAX).with_path(name)?;
+        let mut fields: Vec<String> = header.split('\t').map(String::from).collect();
+        match fields.first().map(String::as_str) {
+            Some("OK") => Ok((fields.split_off(1), data)),
+            Some("ERR") => {
+                let kind = error_kind(fields.get(1).map_or("", String::as_str));
+                let message = unescape(fields.get(2).map_or("", String::as_str));
+                Err(io::Error::new(kind, message)).with_path(name)
+            }
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "malformed answer")).with_path(name),
+        }
+    }
+}
+
+/// Returns the URL of the `location` if it is an `acsync://` one.
+fn remote_url(location: &Path) -> Option<RemoteUrl> {
+    location.to_str().and_then(RemoteUrl::parse)
+}
+
+/// Returns true if the `location` is replicated by a [`RemoteReplicator`] rather than
+/// being a local path, i.e. if it is an `acsync://`, an `s3://` or a `dav://` URL.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::remote;
+/// #
+/// assert!(remote::is_remote("acsync://nas.local/photos"));
+/// assert!(remote::is_remote("s3://backup/photos"));
+/// assert!(remote::is_remote("dav://cloud.local/remote.php/dav/files/ana"));
+/// assert!(!remote::is_remote("/media/backup"));
+/// ```
+pub fn is_remote(location: &str) -> bool {
+    RemoteUrl::parse(location).is_some()
+        || S3Url::parse(location).is_some()
+        || webdav::parse_url(location).is_some()
+}
+
+/// Origin or destination of a [`RemoteReplicator`] run.
+enum Side {
+    Local(PathBuf),
+    Remote(Box<Connection>),
+    S3(Box<Bucket>),
+    Dav(Box<Share>),
+}
+
+impl Side {
+    fn open(location: &Path, options: &RemoteReplicatorOptions) -> Result<Self, SyncError> {
+        if let Some(url) = remote_url(location) {
+            return Ok(Side::Remote(Box::new(Connection::open(
+                &url,
+                options.token.as_deref(),
+                options.compress_level,
+            )?)));
+        }
+        if let Some(url) = location.to_str().and_then(S3Url::parse) {
+            let config = options
+                .s3_config
+                .as_ref()
+                .ok_or(SyncError::MissingArgument("AWS_ENDPOINT_URL".to_string()))?;
+            return Ok(Side::S3(Box::new(Bucket::open(&url, config)?)));
+        }
+        if let Some(url) = location.to_str().and_then(webdav::parse_url) {
+            return Ok(Side::Dav(Box::new(Share::open(
+                &url,
+                options.dav_config.as_ref(),
+            ))));
+        }
+        Ok(Side::Local(location.to_path_buf()))
+    }
+
+    fn list(&mut self) -> Result<BTreeMap<PathBuf, EntryInfo>, SyncError> {
+        match self {
+            Side::Local(root) => Ok(list_tree(root).with_path(&*root)?.into_iter().collect()),
+            Side::Remote(connection) => {
+                let (_, data) = connection.request("LIST", Path::new(""), &[], &[])?;
+                let listing = String::from_utf8_lossy(&data);
+                let mut entries = BTreeMap::new();
+                for line in listing.lines() {
+                    let mut fields = line.splitn(4, '\t');
+                    let entry = (|| {
+                        let is_dir = fields.next()? == "d";
+                        let size = fields.next()?.parse().ok()?;
+                        let modified = from_nanos(fields.next()?.parse().ok()?);
+                        let path = PathBuf::from(unescape(fields.next()?));
+                        Some((
+                            path,
+                            EntryInfo {
+                                is_dir,
+                                size,
+                                modified,
+                            },
+                        ))
+                    })()
+                    .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "malformed listing"))
+                    .with_path(connection.url.to_string())?;
+                    entries.insert(entry.0, entry.1);
+                }
+                Ok(entries)
+            }
+            Side::S3(bucket) => bucket.list(),
+            Side::Dav(share) => share.list(),
+        }
+    }
+
+    fn read_chunk(&mut self, path: &Path, offset: u64) -> Result<Vec<u8>, SyncError> {
+        match self {
+            Side::Local(root) => {
+                let path = root.join(path);
+                read_chunk(&path, offset).with_path(&path)
+            }
+            Side::Remote(connection) => {
+                let mut arguments = vec![offset.to_string()];
+                if connection.compresses(path) {
+                    arguments.push("z".to_string());
+                }
+                let (fields, data) = connection.request("READ", path, &arguments, &[])?;
+                if fields.last().is_some_and(|field| field == "z") {
+                    return decompress_chunk(&data).with_path(path);
+                }
+                Ok(data)
+            }
+            Side::S3(bucket) => bucket.read_chunk(path, offset),
+            Side::Dav(share) => share.read_chunk(path, offset),
+        }
+    }
+
+    fn write_chunk(&mut self, path: &Path, offset: u64, chunk: &[u8]) -> Result<(), SyncError> {
+        match self {
+            Side::Local(root) => {
+                let path = root.join(path);
+                write_chunk(&path, offset, chunk).with_path(&path)
+            }
+            Side::Remote(connection) => {
+                let mut arguments = vec![offset.to_string()];
+                let mut data = None;
+                if connection.compresses(path) {
+                    let compressed_chunk = archive::compress_bytes(
+                        chunk,
+                        Compression::Zstd,
+                        Some(connection.compress_level),
+                    )
+                    .with_path(path)?;
+                    if compressed_chunk.len() < chunk.len() {
+                        arguments.push("z".to_string());
+                        data = Some(compressed_chunk);
+                    }
+                }
+                connection
+                    .request("WRITE", path, &arguments, data.as_deref().unwrap_or(chunk))
+                    .map(|_| ())
+            }
+            Side::S3(bucket) => bucket.write_chunk(path, offset, chunk),
+            Side::Dav(share) => share.write_chunk(path, offset, chunk),
+        }
+    }
+
+    fn commit(&mut self, path: &Path, modified: SystemTime) -> Result<(), SyncError> {
+        match self {
+            Side::Local(root) => {
+                let path = root.join(path);
+                commit_file(&path, modified).with_path(&path)
+            }
+            Side::Remote(connection) => connection
+                .request("COMMIT", path, &[to_nanos(modified).to_string()], &[])
+                .map(|_| ()),
+            Side::S3(bucket) => bucket.commit(path, modified),
+            Side::Dav(share) => share.commit(path, modified),
+        }
+    }
+
+    fn create_dir(&mut self, path: &Path) -> Result<(), SyncError> {
+        match self {
+            Side::Local(root) => {
+                let path = root.join(path);
+                std::fs::create_dir_all(&path).with_path(&path)
+            }
+            Side::Remote(connection) => connection.request("MKDIR", path, &[], &[]).map(|_| ()),
+            Side::S3(bucket) => bucket.create_dir(path),
+            Side::Dav(share) => share.create_dir(path),
+        }
+    }
+
+    fn remove(&mut self, path: &Path) -> Result<(), SyncError> {
+        match self {
+            Side::Local(root) => {
+                let path = root.join(path);
+                remove_path(&path).with_path(&path)
+            }
+            Side::Remote(connection) => connection.request("DELETE", path, &[], &[]).map(|_| ()),
+            Side::S3(bucket) => bucket.remove(path),
+            Side::Dav(share) => share.remove(path),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RemoteReplicatorOptions {
+    on_conflict: ConflictPolicy,
+    change_detection: ChangeDetection,
+    modify_window: Duration,
+    dryrun: bool,
+    hidden: bool,
+    delete: bool,
+    bwlimit: Option<u64>,
+    token: Option<String>,
+    compress_level: Option<u32>,
+    s3_config: Option<S3Config>,
+    dav_config: Option<DavConfig>,
+}
+
+/// Replicates files between a local directory and a directory served by `acsync
+/// serve`, given as an `acsync://` URL, a prefix of an S3 bucket, given as an `s3://`
+/// URL, or a collection of a WebDAV share, given as a `dav://` URL, in either
+/// direction.
+///
+/// It supports the main options of [`Replicator`](crate::sync::Replicator), with the
+/// same conflict detection, except for the conflict policies asking or renaming.
+#[derive(Debug)]
+pub struct RemoteReplicator {
+    origin: PathBuf,
+    destination: PathBuf,
+    options: RemoteReplicatorOptions,
+}
+
+impl RemoteReplicator {
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(origin: P, destination: Q) -> Self {
+        RemoteReplicator {
+            origin: origin.as_ref().to_path_buf(),
+            destination: destination.as_ref().to_path_buf(),
+            options: RemoteReplicatorOptions {
+                hidden: true,
+                ..RemoteReplicatorOptions::default()
+            },
+        }
+    }
+
+    /// Resolves the conflicts with `on_conflict`, which can't be
+    /// [`ConflictPolicy::Prompt`] or [`ConflictPolicy::Rename`].
+    pub fn on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.options.on_conflict = on_conflict;
+        self
+    }
+
+    pub fn change_detection(mut self, change_detection: ChangeDetection) -> Self {
+        self.options.change_detection = change_detection;
+        self
+    }
+
+    pub fn modify_window(mut self, window: Duration) -> Self {
+        self.options.modify_window = window;
+        self
+    }
+
+    pub fn dryrun(mut self, flag: bool) -> Self {
+        self.options.dryrun = flag;
+        self
+    }
+
+    /// Whether dot-files and dot-directories are replicated, enabled by default.
+    pub fn hidden(mut self, flag: bool) -> Self {
+        self.options.hidden = flag;
+        self
+    }
+
+    pub fn delete(mut self, flag: bool) -> Self {
+        self.options.delete = flag;
+        self
+    }
+
+    pub fn bwlimit(mut self, bytes_per_second: Option<u64>) -> Self {
+        self.options.bwlimit = bytes_per_second;
+        self
+    }
+
+    /// Authenticates to the server with the pre-shared `token`.
+    pub fn token(mut self, token: Option<String>) -> Self {
+        self.options.token = token;
+        self
+    }
+
+    /// Compresses the chunks transferred with zstd at `level`, from 1 to
+    /// [`MAX_COMPRESS_LEVEL`], except for the files of [`COMPRESSED_EXTENSIONS`].
+    pub fn compress_level(mut self, level: Option<u32>) -> Self {
+        self.options.compress_level = level;
+        self
+    }
+
+    /// Reaches the bucket of an `s3://` origin or destination with the endpoint and
+    /// credentials of `config`.
+    pub fn s3_config(mut self, config: Option<S3Config>) -> Self {
+        self.options.s3_config = config;
+        self
+    }
+
+    /// Authenticates to the share of a `dav://` origin or destination with the
+    /// credentials of `config`, anonymously if `None`.
+    pub fn dav_config(mut self, config: Option<DavConfig>) -> Self {
+        self.options.dav_config = config;
+        self
+    }
+
+    pub fn run(&self) -> Result<SyncStats, SyncError> {
+        let RemoteReplicatorOptions {
+            on_conflict,
+            change_detection,
+            modify_window,
+            dryrun,
+            delete,
+            ..
+        } = self.options;
+        if matches!(on_conflict, ConflictPolicy::Prompt | ConflictPolicy::Rename) {
+            return Err(SyncError::InvalidArgument {
+                name: "on_conflict".to_string(),
+                reason: "prompt and rename aren't supported with acsync://, s3:// and dav:// URLs"
+                    .to_string(),
+            });
+        }
+        let is_remote = |location: &Path| location.to_str().is_some_and(is_remote);
+        if is_remote(&self.origin) == is_remote(&self.destination) {
+            return Err(SyncError::InvalidArgument {
+                name: "destination".to_string(),
+                reason:
+                    "either the origin or the destination must be an acsync://, s3:// or dav:// URL"
+                        .to_string(),
+            });
+        }
+
+        let _lock = if is_remote(&self.destination) || dryrun {
+            None
+        } else {
+            std::fs::create_dir_all(&self.destination).with_path(&self.destination)?;
+            Some(DestinationLock::acquire(&self.destination, Duration::ZERO)?)
+        };
+        let mut origin = Side::open(&self.origin, &self.options)?;
+        let mut target = Side::open(&self.destination, &self.options)?;
+        let source_entries = self.visible(origin.list()?);
+        let target_entries = self.visible(target.list()?);
+        let mut limiter = self.options.bwlimit.map(RateLimiter::new);
+
+        let mut stats = SyncStats::default();
+        for (path, source) in &source_entries {
+            if signal::interrupted() {
+                stats.interrupted = true;
+                break;
+            }
+            if source.is_dir {
+                if !target_entries.contains_key(path) {
+                    debug!("Creating directory {} ...", path.display());
+                    if !dryrun {
+                        target.create_dir(path)?;
+                    }
+                    stats.directory_created_count += 1;
+                }
+                continue;
+            }
+            stats.file_count += 1;
+            stats.total_file_size += source.size;
+            let Some(existing) = target_entries.get(path) else {
+                debug!(
+                    "Copying file {} ({} KBs)...",
+                    path.display(),
+                    (source.size / 1024) as f64
+                );
+                if !dryrun {
+                    self.transfer(&mut origin, &mut target, path, source, limiter.as_mut())?;
+                }
+                stats.file_copied_count += 1;
+                stats.total_file_copied_size += source.size;
+                continue;
+            };
+            if existing.is_dir {
+                warn!(
+                    "Skipping file {}, a directory in the destination",
+                    path.display()
+                );
+                continue;
+            }
+            let is_conflict = match change_detection {
+                ChangeDetection::SizeAndTime => {
+                    !sync::same_time(source.modified, existing.modified, modify_window)
+                        && source.size != existing.size
+                }
+                ChangeDetection::SizeOnly => source.size != existing.size,
+                ChangeDetection::IgnoreTimes => true,
+            };
+            if !is_conflict {
+                continue;
+            }
+            stats.file_conflict_count += 1;
+            let source_is_newer = source.modified > existing.modified;
+            if source_is_newer {
+                stats.file_dated_count += 1;
+                stats.total_file_dated_size += existing.size;
+            }
+            let to_override = match on_conflict {
+                ConflictPolicy::Newer => source_is_newer,
+                ConflictPolicy::Older => !source_is_newer,
+                ConflictPolicy::Larger => source.size > existing.size,
+                _ => false,
+            };
+            if to_override {
+                debug!(
+                    "Copying file {} ({} KBs)...",
+                    path.display(),
+                    (source.size / 1024) as f64
+                );
+                if !dryrun {
+                    self.transfer(&mut origin, &mut target, path, source, limiter.as_mut())?;
+                }
+                stats.file_overrided_count += 1;
+                stats.total_file_overrided_size += source.size;
+            }
+        }
+
+        if delete && !stats.interrupted {
+            // Directories are removed with their content, which is still counted.
+            let mut deleted_directory: Option<&Path> = None;
+            for (path, info) in &target_entries {
+                if source_entries.contains_key(path) {
+                    continue;
+                }
+                if !deleted_directory.is_some_and(|directory| path.starts_with(directory)) {
+                    debug!("Deleting {} ...", path.display());
+                    if !dryrun {
+                        target.remove(path)?;
+                    }
+                    if info.is_dir {
+                        deleted_directory = Some(path);
+                    }
+                }
+                if !info.is_dir {
+                    stats.file_deleted_count += 1;
+                    stats.total_file_deleted_size += info.size;
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Drops the hidden entries, unless `hidden`, and the metadata paths of the root.
+    fn visible(&self, entries: BTreeMap<PathBuf, EntryInfo>) -> BTreeMap<PathBuf, EntryInfo> {
+        entries
+            .into_iter()
+            .filter(|(path, _)| {
+                !sync::is_metadata_path(path)
+                    && (self.options.hidden
+                        || !path.components().any(|component| {
+                            component.as_os_str().to_string_lossy().starts_with('.')
+                        }))
+            })
+            .collect()
+    }
+
+    /// Copies the `path` file chunk by chunk from the `origin` to the `target`.
+    fn transfer(
+        &self,
+        origin: &mut Side,
+        target: &mut Side,
+        path: &Path,
+        source: &EntryInfo,
+        mut limiter: Option<&mut RateLimiter>,
+    ) -> Result<(), SyncError> {
+        let mut offset = 0;
+        loop {
+            let chunk = origin.read_chunk(path, offset)?;
+            if let Some(limiter) = limiter.as_deref_mut() {
+                limiter.acquire(chunk.len() as u64);
+            }
+            target.write_chunk(path, offset, &chunk)?;
+            offset += chunk.len() as u64;
+            if chunk.len() < CHUNK_SIZE {
+                break;
+            }
+        }
+        target.commit(path, source.modified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_replicates_to_and_from_a_server() {
+        let root = testing::test_dir("it_replicates_to_and_from_a_server");
+        let (local, served, restored) = (
+            root.join("local"),
+            root.join("served"),
+            root.join("restored"),
+        );
+        std::fs::create_dir_all(local.join("docs")).unwrap();
+        std::fs::create_dir_all(served.join("backup/old")).unwrap();
+        std::fs::write(local.join("docs/notes.txt"), "notes").unwrap();
+        std::fs::write(local.join("large.bin"), vec![7; CHUNK_SIZE + 10]).unwrap();
+        std::fs::write(served.join("backup/old/extra.txt"), "extra").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(&served);
+        std::thread::spawn(move || server.serve(listener));
+        let url = format!("acsync://{address}/backup");
+
+        let stats = RemoteReplicator::new(&local, &url)
+            .delete(true)
+            .run()
+            .unwrap();
+        assert_eq!((stats.file_copied_count, stats.file_deleted_count), (2, 1));
+        assert_eq!(
+            std::fs::read(served.join("backup/large.bin"))
+                .unwrap()
+                .len(),
+            CHUNK_SIZE + 10
+        );
+        assert!(!served.join("backup/old").exists());
+
+        let stats = RemoteReplicator::new(&url, &restored).run().unwrap();
+        assert_eq!(stats.file_copied_count, 2);
+        assert_eq!(
+            std::fs::read_to_string(restored.join("docs/notes.txt")).unwrap(),
+            "notes"
+        );
+        assert_eq!(
+            std::fs::metadata(restored.join("docs/notes.txt"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            std::fs::metadata(local.join("docs/notes.txt"))
+                .unwrap()
+                .modified()
+                .unwrap()
+        );
+        let stats = RemoteReplicator::new(&url, &restored).run().unwrap();
+        assert_eq!(stats.file_copied_count, 0);
+
+        let error = RemoteReplicator::new(&local, format!("acsync://{address}/../escape"))
+            .run()
+            .unwrap_err();
+        assert!(error.to_string().contains("not a relative path"));
+    }
+
+    #[test]
+    fn it_compresses_the_chunks_once_agreed() {
+        if !Compression::Zstd.is_available() {
+            return;
+        }
+        let root = testing::test_dir("it_compresses_the_chunks_once_agreed");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url =
+            RemoteUrl::parse(&format!("acsync://{}", listener.local_addr().unwrap())).unwrap();
+        let server = Server::new(&root);
+        std::thread::spawn(move || server.serve(listener));
+
+        let connection = Connection::open(&url, None, Some(42)).unwrap();
+        assert_eq!(connection.compress_level, MAX_COMPRESS_LEVEL);
+        assert!(connection.compresses(Path::new("notes.txt")));
+        assert!(!connection.compresses(Path::new("photo.jpg")));
+        let mut side = Side::Remote(Box::new(connection));
+        let content = "compressible ".repeat(1000).into_bytes();
+        side.write_chunk(Path::new("notes.txt"), 0, &content)
+            .unwrap();
+        side.commit(Path::new("notes.txt"), UNIX_EPOCH).unwrap();
+        assert_eq!(std::fs::read(root.join("notes.txt")).unwrap(), content);
+        assert_eq!(side.read_chunk(Path::new("notes.txt"), 0).unwrap(), content);
+    }
+
+    #[test]
+    fn it_refuses_chunks_decompressing_past_the_chunk_size() {
+        if !Compression::Zstd.is_available() {
+            return;
+        }
+        let chunk = vec![0; CHUNK_SIZE];
+        let compressed = archive::compress_bytes(&chunk, Compression::Zstd, None).unwrap();
+        assert_eq!(decompress_chunk(&compressed).unwrap(), chunk);
+        let bomb = archive::compress_bytes(&vec![0; 64 * CHUNK_SIZE], Compression::Zstd, Some(19))
+            .unwrap();
+        let error = decompress_chunk(&bomb).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn it_requires_the_token_and_refuses_writes_when_read_only() {
+        let root = testing::test_dir("it_requires_the_token_and_refuses_writes_when_read_only");
+        let (local, served) = (root.join("local"), root.join("served"));
+        std::fs::create_dir_all(&local).unwrap();
+        std::fs::create_dir_all(&served).unwrap();
+        std::fs::write(local.join("notes.txt"), "notes").unwrap();
+        std::fs::write(served.join("shared.txt"), "shared").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("acsync://{}", listener.local_addr().unwrap());
+        let server = Server::new(&served)
+            .read_only(true)
+            .token(Some("s3cret".to_string()));
+        std::thread::spawn(move || server.serve(listener));
+
+        for token in [None, Some("wrong".to_string())] {
+            let result = RemoteReplicator::new(&url, local.join("copy"))
+                .token(token)
+                .run();
+            assert!(matches!(result, Err(SyncError::PermissionDenied { .. })));
+        }
+        let stats = RemoteReplicator::new(&url, local.join("copy"))
+            .token(Some("s3cret".to_string()))
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_copied_count, 1);
+        let result = RemoteReplicator::new(&local, &url)
+            .token(Some("s3cret".to_string()))
+            .run();
+        assert!(matches!(result, Err(SyncError::PermissionDenied { .. })));
+        assert!(!served.join("notes.txt").exists());
+    }
+
+    #[test]
+    fn it_closes_connections_on_a_wrong_token_and_protects_the_served_directory() {
+        let served = testing::test_dir(
+            "it_closes_connections_on_a_wrong_token_and_protects_the_served_directory",
+        );
+        std::fs::write(served.join("shared.txt"), "shared").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url =
+            RemoteUrl::parse(&format!("acsync://{}", listener.local_addr().unwrap())).unwrap();
+        let server = Server::new(&served).token(Some("s3cret".to_string()));
+        std::thread::spawn(move || server.serve(listener));
+
+        let mut stream = TcpStream::connect(&url.address).unwrap();
+        write_message(&mut stream, "AUTH\twrong", &[]).unwrap();
+        let (header, _) = read_message(&mut stream, 0).unwrap();
+        assert!(header.starts_with("ERR\t"));
+        let error = read_message(&mut stream, 0).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+
+        let mut connection = Connection::open(&url, Some("s3cret"), None).unwrap();
+        for request in ["DELETE", "MKDIR", "WRITE"] {
+            let result = connection.request(request, Path::new(""), &["0".to_string()], b"");
+            assert!(result.is_err());
+        }
+        assert!(served.join("shared.txt").exists());
+    }
+}
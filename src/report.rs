@@ -0,0 +1,521 @@
+//! **report** contains the reports written into a destination after each replicate run,
+//! so that the history of a backup can be audited without external logging, and its
+//! last run undone.
+
+use crate::{
+    archive::{escape, unescape},
+    cli_helper,
+    error::{IoResultExt, SyncError},
+    sync::SyncStats,
+    warn,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the directory of the destination holding what acsync keeps there besides
+/// its metadata files, such as the run reports.
+pub const ACSYNC_DIR_NAME: &str = ".acsync";
+
+/// Path, relative to the destination, of the directory the run reports are written to.
+pub const REPORTS_DIR_NAME: &str = ".acsync/reports";
+
+/// Declares the counters of [`SyncStats`] written into the reports, by name.
+macro_rules! counters {
+    ($($name:literal => $field:ident),* $(,)?) => {
+        fn counters(stats: &SyncStats) -> Vec<(&'static str, u64)> {
+            vec![$(($name, stats.$field)),*]
+        }
+
+        /// Returns false if `name` is not a counter.
+        fn set_counter(stats: &mut SyncStats, name: &str, value: u64) -> bool {
+            match name {
+                $($name => stats.$field = value,)*
+                _ => return false,
+            }
+            true
+        }
+    };
+}
+
+counters! {
+    "files" => file_count,
+    "bytes" => total_file_size,
+    "copied_files" => file_copied_count,
+    "copied_bytes" => total_file_copied_size,
+    "conflicting_files" => file_conflict_count,
+    "renamed_files" => file_renamed_count,
+    "dated_files" => file_dated_count,
+    "dated_bytes" => total_file_dated_size,
+    "overridden_files" => file_overrided_count,
+    "overridden_bytes" => total_file_overrided_size,
+    "delta_saved_bytes" => total_delta_saved_size,
+    "linked_files" => file_linked_count,
+    "linked_bytes" => total_file_linked_size,
+    "special_files" => file_special_count,
+    "metadata_fixed_files" => file_metadata_fixed_count,
+    "moved_files" => file_moved_count,
+    "moved_bytes" => total_file_moved_size,
+    "backed_up_files" => file_backed_up_count,
+    "deduplicated_files" => file_deduplicated_count,
+    "deleted_files" => file_deleted_count,
+    "deleted_bytes" => total_file_deleted_size,
+    "created_directories" => directory_created_count,
+    "transcoded_paths" => path_transcoded_count,
+    "skipped_paths" => path_skipped_count,
+    "sampled_files" => file_sampled_count,
+    "retried_files" => file_retried_count,
+    "failed_files" => file_failed_count,
+}
+
+/// How a reported run ended.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    #[default]
+    Succeeded,
+    /// The run aborted, or went through every file but some failed.
+    Failed,
+    /// The run was stopped by SIGINT or SIGTERM.
+    Interrupted,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Outcome::Succeeded => "succeeded",
+            Outcome::Failed => "failed",
+            Outcome::Interrupted => "interrupted",
+        })
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "succeeded" => Ok(Outcome::Succeeded),
+            "failed" => Ok(Outcome::Failed),
+            "interrupted" => Ok(Outcome::Interrupted),
+            _ => Err(format!("unknown outcome {value:?}")),
+        }
+    }
+}
+
+/// Change made to the destination by a run, recorded in its report so that it can be
+/// undone. Paths are relative to the destination, except the backup and trash paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A file or directory created by the run.
+    Created(PathBuf),
+    /// A file overridden by the run, whose previous version was moved to the backup
+    /// path if backups were enabled.
+    Overridden {
+        path: PathBuf,
+        backup: Option<PathBuf>,
+    },
+    /// A file moved by the run, e.g. after its source file was moved.
+    Moved { from: PathBuf, to: PathBuf },
+    /// A file deleted by the run, moved to the trash path if it was deleted to the
+    /// trash.
+    Deleted {
+        path: PathBuf,
+        trash: Option<PathBuf>,
+    },
+}
+
+impl Change {
+    /// Returns the report line of the change, without its line break.
+    fn to_line(&self) -> String {
+        let escape_path = |path: &Path| escape(&path.to_string_lossy());
+        match self {
+            Change::Created(path) => format!("created\t{}", escape_path(path)),
+            Change::Overridden { path, backup } => match backup {
+                Some(backup) => {
+                    format!("overridden\t{}\t{}", escape_path(path), escape_path(backup))
+                }
+                None => format!("overridden\t{}", escape_path(path)),
+            },
+            Change::Moved { from, to } => {
+                format!("moved\t{}\t{}", escape_path(from), escape_path(to))
+            }
+            Change::Deleted { path, trash } => match trash {
+                Some(trash) => format!("deleted\t{}\t{}", escape_path(path), escape_path(trash)),
+                None => format!("deleted\t{}", escape_path(path)),
+            },
+        }
+    }
+
+    /// Parses the `value` of a report line named `name`, returning `None` if the line
+    /// isn't a change.
+    fn from_line(name: &str, value: &str) -> Option<Result<Self, String>> {
+        let mut paths = value.split('\t').map(|path| PathBuf::from(unescape(path)));
+        let path = paths.next().unwrap_or_default();
+        let other = paths.next();
+        let change = match (name, other) {
+            ("created", None) => Change::Created(path),
+            ("overridden", backup) => Change::Overridden { path, backup },
+            ("moved", Some(to)) => Change::Moved { from: path, to },
+            ("deleted", trash) => Change::Deleted { path, trash },
+            ("created" | "moved", _) => return Some(Err(format!("malformed {name} change"))),
+            _ => return None,
+        };
+        Some(Ok(change))
+    }
+}
+
+/// Report of a replicate run, written into the [`REPORTS_DIR_NAME`] directory of its
+/// destination as `name<TAB>value` lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunReport {
+    /// Identifier of the report, the Unix time its run started at unless already taken
+    /// by another report. Set when the report is saved or loaded.
+    pub id: u64,
+    pub started: SystemTime,
+    pub duration: Duration,
+    pub origin: PathBuf,
+    pub destination: PathBuf,
+    /// Command line arguments of the run, if known.
+    pub arguments: Vec<String>,
+    pub outcome: Outcome,
+    /// Error aborting the run, if any.
+    pub failure: Option<String>,
+    /// Errors of the files that failed.
+    pub errors: Vec<String>,
+    pub stats: SyncStats,
+    /// Changes made to the destination, in the order they were made. Only recorded
+    /// for destinations that are neither compressed nor a store.
+    pub changes: Vec<Change>,
+    /// Time the run was undone at, if it was.
+    pub undone: Option<SystemTime>,
+}
+
+impl Default for RunReport {
+    fn default() -> Self {
+        RunReport {
+            id: 0,
+            started: UNIX_EPOCH,
+            duration: Duration::ZERO,
+            origin: PathBuf::new(),
+            destination: PathBuf::new(),
+            arguments: vec![],
+            outcome: Outcome::Succeeded,
+            failure: None,
+            errors: vec![],
+            stats: SyncStats::default(),
+            changes: vec![],
+            undone: None,
+        }
+    }
+}
+
+impl RunReport {
+    /// Writes the report into the [`REPORTS_DIR_NAME`] directory of the `directory`,
+    /// setting its identifier.
+    pub fn save<P: AsRef<Path>>(&mut self, directory: P) -> Result<(), SyncError> {
+        let reports_dir = directory.as_ref().join(REPORTS_DIR_NAME);
+        std::fs::create_dir_all(&reports_dir).with_path(&reports_dir)?;
+        let content = self.content();
+
+        let mut id = self
+            .started
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        loop {
+            let report_path = reports_dir.join(id.to_string());
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&report_path)
+            {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    file.write_all(content.as_bytes()).with_path(&report_path)?;
+                    self.id = id;
+                    return Ok(());
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => id += 1,
+                Err(error) => return Err(error).with_path(&report_path),
+            }
+        }
+    }
+
+    /// Writes the report over the saved report with the same identifier.
+    pub fn update<P: AsRef<Path>>(&self, directory: P) -> Result<(), SyncError> {
+        let report_path = directory
+            .as_ref()
+            .join(REPORTS_DIR_NAME)
+            .join(self.id.to_string());
+        std::fs::write(&report_path, self.content()).with_path(&report_path)
+    }
+
+    fn content(&self) -> String {
+        let mut content = format!("started\t{}\n", cli_helper::format_time(self.started));
+        content += &format!("duration\t{:.3}\n", self.duration.as_secs_f64());
+        content += &format!("origin\t{}\n", escape(&self.origin.to_string_lossy()));
+        content += &format!(
+            "destination\t{}\n",
+            escape(&self.destination.to_string_lossy())
+        );
+        for argument in &self.arguments {
+            content += &format!("argument\t{}\n", escape(argument));
+        }
+        content += &format!("outcome\t{}\n", self.outcome);
+        if let Some(failure) = &self.failure {
+            content += &format!("failure\t{}\n", escape(failure));
+        }
+        for (name, value) in counters(&self.stats) {
+            content += &format!("{name}\t{value}\n");
+        }
+        for error in &self.errors {
+            content += &format!("error\t{}\n", escape(error));
+        }
+        for change in &self.changes {
+            content += &change.to_line();
+            content.push('\n');
+        }
+        if let Some(undone) = self.undone {
+            content += &format!("undone\t{}\n", cli_helper::format_time(undone));
+        }
+        content
+    }
+
+    /// Loads the report `id` of the `directory`.
+    pub fn load<P: AsRef<Path>>(directory: P, id: u64) -> Result<Self, SyncError> {
+        let report_path = directory
+            .as_ref()
+            .join(REPORTS_DIR_NAME)
+            .join(id.to_string());
+        let content = std::fs::read_to_string(&report_path).with_path(&report_path)?;
+        let invalid_report = |reason: &str| SyncError::Io {
+            path: report_path.clone(),
+            source: std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string()),
+        };
+
+        let mut report = RunReport {
+            id,
+            ..RunReport::default()
+        };
+        for line in content.lines() {
+            let (name, value) = line
+                .split_once('\t')
+                .ok_or_else(|| invalid_report("malformed line"))?;
+            match name {
+                "started" => {
+                    report.started =
+                        parse_started(value).ok_or_else(|| invalid_report("malformed start"))?
+                }
+                "duration" => {
+                    report.duration = value
+                        .parse()
+                        .ok()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or_else(|| invalid_report("malformed duration"))?
+                }
+                "origin" => report.origin = PathBuf::from(unescape(value)),
+                "destination" => report.destination = PathBuf::from(unescape(value)),
+                "argument" => report.arguments.push(unescape(value)),
+                "outcome" => {
+                    report.outcome = value
+                        .parse()
+                        .map_err(|reason: String| invalid_report(&reason))?
+                }
+                "failure" => report.failure = Some(unescape(value)),
+                "error" => report.errors.push(unescape(value)),
+                "undone" => {
+                    report.undone = Some(
+                        parse_started(value)
+                            .ok_or_else(|| invalid_report("malformed undo time"))?,
+                    )
+                }
+                // Names unknown to this version are skipped.
+                _ => match Change::from_line(name, value) {
+                    Some(change) => report
+                        .changes
+                        .push(change.map_err(|reason| invalid_report(&reason))?),
+                    None => {
+                        if let Ok(value) = value.parse() {
+                            set_counter(&mut report.stats, name, value);
+                        }
+                    }
+                },
+            }
+        }
+        report.stats.interrupted = report.outcome == Outcome::Interrupted;
+        Ok(report)
+    }
+
+    /// Returns the identifiers of the reports of the `directory`, oldest first.
+    pub fn list<P: AsRef<Path>>(directory: P) -> Result<Vec<u64>, SyncError> {
+        let reports_dir = directory.as_ref().join(REPORTS_DIR_NAME);
+        if !reports_dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut ids: Vec<u64> = std::fs::read_dir(&reports_dir)
+            .with_path(&reports_dir)?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Returns the count of files the run copied, overrode, moved or deleted.
+    pub fn changed_file_count(&self) -> u64 {
+        self.stats.file_copied_count
+            + self.stats.file_overrided_count
+            + self.stats.file_moved_count
+            + self.stats.file_deleted_count
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", format!(" Run {} ", self.id))?;
+        writeln!(f, "Started: {}", cli_helper::format_time(self.started))?;
+        writeln!(f, "Duration: {:.3}s", self.duration.as_secs_f64())?;
+        writeln!(f, "Origin: {}", self.origin.display())?;
+        writeln!(f, "Destination: {}", self.destination.display())?;
+        writeln!(f, "Arguments: {}", self.arguments.join(" "))?;
+        writeln!(f, "Outcome: {}", self.outcome)?;
+        if let Some(undone) = self.undone {
+            writeln!(f, "Undone: {}", cli_helper::format_time(undone))?;
+        }
+        if let Some(failure) = &self.failure {
+            writeln!(f, "Failure: {failure}")?;
+        }
+        for error in &self.errors {
+            writeln!(f, "Error: {error}")?;
+        }
+        write!(f, "{}", self.stats)
+    }
+}
+
+/// Reports of the past runs of a destination, oldest first.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct History {
+    pub reports: Vec<RunReport>,
+}
+
+impl History {
+    /// Loads the reports of the `directory`, skipping with a warning those that can't
+    /// be read.
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Self, SyncError> {
+        let directory = directory.as_ref();
+        let mut history = History::default();
+        for id in RunReport::list(directory)? {
+            match RunReport::load(directory, id) {
+                Ok(report) => history.reports.push(report),
+                Err(error) => warn!("Skipping report {id}: {error}"),
+            }
+        }
+        Ok(history)
+    }
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", " History ")?;
+        writeln!(
+            f,
+            "{:<12}{:<26}{:>10}{:>13}{:>10}{:>9}",
+            "Id", "Started", "Duration", "Outcome", "Changed", "Errors"
+        )?;
+        for report in &self.reports {
+            let errors = report.errors.len() + usize::from(report.failure.is_some());
+            writeln!(
+                f,
+                "{:<12}{:<26}{:>10}{:>13}{:>10}{errors:>9}",
+                report.id,
+                cli_helper::format_time(report.started),
+                format!("{:.1}s", report.duration.as_secs_f64()),
+                match report.undone {
+                    Some(_) => "undone".to_string(),
+                    None => report.outcome.to_string(),
+                },
+                report.changed_file_count(),
+            )?;
+        }
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+/// Parses a start time written by [`cli_helper::format_time`].
+fn parse_started(value: &str) -> Option<SystemTime> {
+    let (time, millis) = value.strip_suffix('Z')?.split_once('.')?;
+    cli_helper::parse_time(time)?.checked_add(Duration::from_millis(millis.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_saves_and_loads_reports() {
+        let directory = testing::test_dir("it_saves_and_loads_reports");
+        let mut report = RunReport {
+            started: UNIX_EPOCH + Duration::from_millis(1704067200250),
+            duration: Duration::from_millis(1500),
+            origin: PathBuf::from("/home/user/Documents"),
+            destination: PathBuf::from("/media/backup/Documents"),
+            arguments: vec!["replicate".to_string(), "--delete".to_string()],
+            outcome: Outcome::Failed,
+            errors: vec!["Permission denied:\tsecret.txt".to_string()],
+            stats: SyncStats {
+                file_copied_count: 2,
+                file_failed_count: 1,
+                ..SyncStats::default()
+            },
+            changes: vec![
+                Change::Created(PathBuf::from("docs")),
+                Change::Overridden {
+                    path: PathBuf::from("docs/a\tb.txt"),
+                    backup: Some(PathBuf::from("/media/backup/docs/a\tb.txt~")),
+                },
+                Change::Moved {
+                    from: PathBuf::from("old.txt"),
+                    to: PathBuf::from("new.txt"),
+                },
+                Change::Deleted {
+                    path: PathBuf::from("gone.txt"),
+                    trash: None,
+                },
+            ],
+            ..RunReport::default()
+        };
+        report.save(&directory).unwrap();
+        let mut other = report.clone();
+        other.save(&directory).unwrap();
+        assert_eq!((report.id, other.id), (1704067200, 1704067201));
+
+        assert_eq!(
+            RunReport::list(&directory).unwrap(),
+            vec![1704067200, 1704067201]
+        );
+        assert_eq!(RunReport::load(&directory, report.id).unwrap(), report);
+    }
+
+    #[test]
+    fn it_lists_the_readable_reports_of_the_history() {
+        let directory = testing::test_dir("it_lists_the_readable_reports_of_the_history");
+        let mut report = RunReport {
+            stats: SyncStats {
+                file_copied_count: 2,
+                file_deleted_count: 1,
+                ..SyncStats::default()
+            },
+            ..RunReport::default()
+        };
+        report.save(&directory).unwrap();
+        std::fs::write(directory.join(REPORTS_DIR_NAME).join("1"), "malformed").unwrap();
+
+        let history = History::load(&directory).unwrap();
+        assert_eq!(history.reports, vec![report]);
+        assert_eq!(history.reports[0].changed_file_count(), 3);
+        assert!(history.to_string().contains("1970-01-01T00:00:00.000Z"));
+    }
+}
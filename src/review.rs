@@ -0,0 +1,144 @@
+//! **review** contains the interactive review of the actions planned by a replicate
+//! run, picking the ones to be applied.
+
+use crate::sync::PlannedAction;
+use std::{
+    collections::HashSet,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+/// Number of actions listed per page.
+const PAGE_SIZE: usize = 20;
+
+/// Lists `actions` page by page on `output` and reads the user commands from `input`,
+/// until the user applies or quits the review.
+///
+/// Every action starts rejected. The commands are:
+///
+/// - numbers or ranges such as `3 5-8`: toggle the listed actions;
+/// - `a` / `r`: accept / reject every action;
+/// - `n` / `p`: show the next / previous page;
+/// - `y`: apply the accepted actions;
+/// - `q`: quit without applying anything.
+///
+/// Returns the paths of the accepted actions, or `None` if the user quits.
+pub fn review<R: BufRead, W: Write>(
+    actions: &[PlannedAction],
+    mut input: R,
+    mut output: W,
+) -> io::Result<Option<HashSet<PathBuf>>> {
+    let page_count = actions.len().div_ceil(PAGE_SIZE).max(1);
+    let mut accepted = vec![false; actions.len()];
+    let mut page = 0;
+    loop {
+        writeln!(output)?;
+        for (index, action) in actions
+            .iter()
+            .enumerate()
+            .skip(page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+        {
+            writeln!(
+                output,
+                "[{}] {:>4} {:<8} {}",
+                if accepted[index] { "x" } else { " " },
+                index + 1,
+                action.kind,
+                action.path.display()
+            )?;
+        }
+        writeln!(
+            output,
+            "Page {}/{page_count}, {} of {} actions accepted",
+            page + 1,
+            accepted.iter().filter(|accepted| **accepted).count(),
+            actions.len()
+        )?;
+        write!(
+            output,
+            "Toggle (e.g. 3 5-8), a: accept all, r: reject all, n/p: next/previous page, \
+             y: apply, q: quit > "
+        )?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        match line.trim() {
+            "a" => accepted.fill(true),
+            "r" => accepted.fill(false),
+            "n" => page = (page + 1).min(page_count - 1),
+            "p" => page = page.saturating_sub(1),
+            "q" => return Ok(None),
+            "y" => {
+                return Ok(Some(
+                    actions
+                        .iter()
+                        .zip(accepted)
+                        .filter(|(_, accepted)| *accepted)
+                        .map(|(action, _)| action.path.clone())
+                        .collect(),
+                ));
+            }
+            selection => {
+                for token in selection.split_whitespace() {
+                    match parse_range(token, actions.len()) {
+                        Some((start, end)) => {
+                            for accepted in &mut accepted[start - 1..end] {
+                                *accepted = !*accepted;
+                            }
+                        }
+                        None => writeln!(output, "Invalid selection {token}")?,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses a 1-based action number or inclusive range of numbers up to `count`.
+fn parse_range(token: &str, count: usize) -> Option<(usize, usize)> {
+    let (start, end) = match token.split_once('-') {
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None => (token.parse().ok()?, token.parse().ok()?),
+    };
+    (1 <= start && start <= end && end <= count).then_some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::ActionKind;
+
+    #[test]
+    fn it_returns_the_accepted_paths() {
+        let actions: Vec<PlannedAction> = ["a.txt", "b.txt", "c.txt", "d.txt"]
+            .iter()
+            .map(|path| PlannedAction {
+                kind: ActionKind::Copy,
+                path: PathBuf::from(path),
+            })
+            .collect();
+        let mut output = vec![];
+
+        let approved = review(&actions, "a\n2-3 9\n3\ny\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            approved,
+            Some(HashSet::from([
+                PathBuf::from("a.txt"),
+                PathBuf::from("c.txt"),
+                PathBuf::from("d.txt")
+            ]))
+        );
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .contains("Invalid selection 9")
+        );
+
+        let approved = review(&actions, "a\nq\n".as_bytes(), io::sink()).unwrap();
+        assert_eq!(approved, None);
+    }
+}
@@ -0,0 +1,86 @@
+//! **signal** contains the handling of SIGINT and SIGTERM, turning them into a stop
+//! request that a replicate run checks between two files, and of SIGHUP, turning it
+//! into a request to reload the configuration of a recurring run.
+
+use std::{
+    ffi::c_int,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+const SIGHUP: c_int = 1;
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+
+unsafe extern "C" {
+    fn signal(signum: c_int, handler: usize) -> usize;
+    fn _exit(status: c_int) -> !;
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// Records the stop request, or exits right away when one was already recorded, e.g.
+/// on a second Ctrl-C.
+extern "C" fn handle(_signum: c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // Same code as `SyncError::Interrupted`.
+        unsafe { _exit(4) };
+    }
+}
+
+/// Installs the handler of SIGINT and SIGTERM, so that they stop the run gracefully
+/// instead of killing the process.
+pub fn install() {
+    let handler = handle as extern "C" fn(c_int) as usize;
+    unsafe {
+        signal(SIGINT, handler);
+        signal(SIGTERM, handler);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn handle_reload(_signum: c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
+}
+
+/// Installs the handler of SIGHUP, so that it requests a reload instead of killing the
+/// process. Does nothing on Windows, which has no SIGHUP.
+pub fn install_reload() {
+    #[cfg(unix)]
+    unsafe {
+        signal(SIGHUP, handle_reload as extern "C" fn(c_int) as usize);
+    }
+}
+
+/// Returns true once SIGINT or SIGTERM was received after [`install`].
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Returns true once SIGHUP was received after [`install_reload`], until the request
+/// is taken with [`take_reload`].
+pub fn reload_requested() -> bool {
+    RELOAD.load(Ordering::SeqCst)
+}
+
+/// Returns true if a reload was requested, clearing the request.
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+/// Sleeps for `duration`, waking up early when SIGINT or SIGTERM is received or a
+/// reload is requested.
+///
+/// Returns false if the sleep was interrupted.
+pub fn sleep(duration: Duration) -> bool {
+    let started = Instant::now();
+    while !interrupted() && !reload_requested() {
+        let Some(remaining) = duration.checked_sub(started.elapsed()) else {
+            return true;
+        };
+        std::thread::sleep(remaining.min(Duration::from_millis(200)));
+    }
+    false
+}
@@ -0,0 +1,122 @@
+//! **state** contains the manifest recording which files a replicate run left in the
+//! destination, so the next run can tell moved files apart from new ones, along with
+//! the origin they were replicated from.
+
+use crate::{
+    archive::{escape, unescape},
+    error::{IoResultExt, SyncError},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Name of the state manifest file kept at the root of a destination.
+pub const STATE_FILE_NAME: &str = ".acsync_state";
+
+/// Manifest of the files replicated into a destination, mapping their relative paths
+/// to their sizes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StateManifest {
+    origin: Option<PathBuf>,
+    entries: BTreeMap<PathBuf, u64>,
+}
+
+impl StateManifest {
+    pub fn new() -> Self {
+        StateManifest::default()
+    }
+
+    /// Loads the manifest of the `directory`.
+    ///
+    /// Returns an empty manifest if the directory has no manifest file.
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Self, SyncError> {
+        let state_path = directory.as_ref().join(STATE_FILE_NAME);
+        let mut state = StateManifest::new();
+        if !state_path.is_file() {
+            return Ok(state);
+        }
+        let content = std::fs::read_to_string(&state_path).with_path(&state_path)?;
+        let mut lines = content.lines().peekable();
+        // Manifests written before the origin was recorded start with an entry, whose
+        // size is never an absolute path.
+        if let Some(origin) = lines
+            .peek()
+            .and_then(|line| line.strip_prefix("origin\t"))
+            .map(|origin| PathBuf::from(unescape(origin)))
+            .filter(|origin| origin.is_absolute())
+        {
+            state.origin = Some(origin);
+            lines.next();
+        }
+        for line in lines {
+            let (path, size) = line
+                .rsplit_once('\t')
+                .and_then(|(path, size)| Some((path, size.parse().ok()?)))
+                .ok_or_else(|| SyncError::Io {
+                    path: state_path.clone(),
+                    source: std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed entry"),
+                })?;
+            state.entries.insert(PathBuf::from(unescape(path)), size);
+        }
+        Ok(state)
+    }
+
+    /// Writes the manifest file into the `directory`.
+    pub fn save<P: AsRef<Path>>(&self, directory: P) -> Result<(), SyncError> {
+        let state_path = directory.as_ref().join(STATE_FILE_NAME);
+        let mut content = String::new();
+        if let Some(origin) = &self.origin {
+            content += &format!("origin\t{}\n", escape(&origin.to_string_lossy()));
+        }
+        for (path, size) in &self.entries {
+            content += &format!("{}\t{size}\n", escape(&path.to_string_lossy()));
+        }
+        std::fs::write(&state_path, content).with_path(&state_path)
+    }
+
+    /// Returns the absolute path of the origin the files were replicated from, if
+    /// recorded.
+    pub fn origin(&self) -> Option<&Path> {
+        self.origin.as_deref()
+    }
+
+    pub fn set_origin<P: AsRef<Path>>(&mut self, origin: P) {
+        self.origin = Some(origin.as_ref().to_path_buf());
+    }
+
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P, size: u64) {
+        self.entries.insert(path.as_ref().to_path_buf(), size);
+    }
+
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) {
+        self.entries.remove(path.as_ref());
+    }
+
+    /// Returns an iterator over the recorded relative paths and sizes.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, u64)> {
+        self.entries
+            .iter()
+            .map(|(path, size)| (path.as_path(), *size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_saves_and_loads_the_manifest() {
+        let directory = testing::test_dir("it_saves_and_loads_the_manifest");
+        let mut state = StateManifest::new();
+        state.save(&directory).unwrap();
+        assert_eq!(StateManifest::load(&directory).unwrap(), state);
+
+        state.set_origin(std::env::temp_dir().join("origin\tdir"));
+        state.insert("docs/notes.txt", 42);
+        state.insert("a\tb.txt", 1);
+        state.save(&directory).unwrap();
+        assert_eq!(StateManifest::load(&directory).unwrap(), state);
+    }
+}
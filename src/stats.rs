@@ -0,0 +1,206 @@
+//! **stats** contains the analysis of a directory tree, summarizing what a replicate
+//! run from it would involve without copying anything.
+
+use crate::{
+    debug,
+    error::SyncError,
+    fs::{FileSearcher, SortOrder},
+    sync::read_patterns,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Result of an [`Analyzer`] run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TreeStats {
+    pub file_count: u64,
+    pub total_file_size: u64,
+    pub directory_count: u64,
+    /// The largest files and their sizes, largest first.
+    pub largest_files: Vec<(PathBuf, u64)>,
+    /// The deepest paths and their depths, deepest first.
+    pub deepest_paths: Vec<(PathBuf, usize)>,
+    /// Count and total size of the files of each extension, an empty one for files
+    /// without extension.
+    pub extensions: BTreeMap<String, (u64, u64)>,
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", " Tree Stats ")?;
+        writeln!(
+            f,
+            "Files found: {} ({} KBs)",
+            self.file_count,
+            (self.total_file_size / 1024) as f64
+        )?;
+        writeln!(f, "Directories found: {}", self.directory_count)?;
+        writeln!(f, "Largest files:")?;
+        for (path, size) in &self.largest_files {
+            writeln!(f, "\t{} ({} KBs)", path.display(), (size / 1024) as f64)?;
+        }
+        writeln!(f, "Deepest paths:")?;
+        for (path, depth) in &self.deepest_paths {
+            writeln!(f, "\t{} ({depth} levels)", path.display())?;
+        }
+        writeln!(f, "Extensions:")?;
+        let mut extensions: Vec<_> = self.extensions.iter().collect();
+        extensions.sort_by_key(|(extension, (_, size))| (Reverse(*size), *extension));
+        for (extension, (count, size)) in extensions {
+            let extension = if extension.is_empty() {
+                "(none)"
+            } else {
+                extension
+            };
+            writeln!(
+                f,
+                "\t{extension}: {count} files ({} KBs)",
+                (size / 1024) as f64
+            )?;
+        }
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+#[derive(Debug)]
+struct AnalyzerOptions {
+    hidden: bool,
+    top: usize,
+}
+
+/// Summarizes the files of a directory, honoring its `.acsync_includes` and
+/// `.acsync_excludes` patterns like a replicate run does.
+#[derive(Debug)]
+pub struct Analyzer {
+    directory: PathBuf,
+    options: AnalyzerOptions,
+}
+
+impl Analyzer {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        Analyzer {
+            directory: directory.as_ref().to_path_buf(),
+            options: AnalyzerOptions {
+                hidden: true,
+                top: 10,
+            },
+        }
+    }
+
+    /// Whether dot-files and dot-directories are analyzed, enabled by default.
+    pub fn hidden(mut self, flag: bool) -> Self {
+        self.options.hidden = flag;
+        self
+    }
+
+    /// Number of largest files and deepest paths reported, 10 by default.
+    pub fn top(mut self, top: usize) -> Self {
+        self.options.top = top;
+        self
+    }
+
+    pub fn run(&self) -> Result<TreeStats, SyncError> {
+        let directory = &self.directory;
+        let top = self.options.top;
+        let includes = read_patterns(directory, ".acsync_includes");
+        let mut stats = TreeStats::default();
+        let mut largest_files = BinaryHeap::new();
+        let mut deepest_paths = BinaryHeap::new();
+
+        let paths_iter = FileSearcher::new(directory)
+            .sort_by(SortOrder::Name)
+            .hidden(self.options.hidden)
+            .includes(&includes)
+            .excludes_file(Some(".acsync_excludes"));
+        for result in paths_iter {
+            let entry = result.map_err(|source| SyncError::WalkError {
+                path: directory.clone(),
+                source,
+            })?;
+            let path = entry.path();
+            let relative_path = path.strip_prefix(directory).unwrap_or(path).to_path_buf();
+            if relative_path.as_os_str().is_empty() {
+                continue;
+            }
+            debug!("Analyzing {} ...", relative_path.display());
+
+            deepest_paths.push(Reverse((entry.depth(), relative_path.clone())));
+            if deepest_paths.len() > top {
+                deepest_paths.pop();
+            }
+
+            let metadata = entry.metadata();
+            if metadata.is_dir() {
+                stats.directory_count += 1;
+                continue;
+            }
+            stats.file_count += 1;
+            stats.total_file_size += metadata.len();
+            let extension = relative_path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let (count, size) = stats.extensions.entry(extension).or_default();
+            *count += 1;
+            *size += metadata.len();
+            largest_files.push(Reverse((metadata.len(), relative_path)));
+            if largest_files.len() > top {
+                largest_files.pop();
+            }
+        }
+
+        stats.largest_files = largest_files
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((size, path))| (path, size))
+            .collect();
+        stats.deepest_paths = deepest_paths
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((depth, path))| (path, depth))
+            .collect();
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_summarizes_the_tree() {
+        let root = testing::test_dir("it_summarizes_the_tree");
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("small.txt"), [0; 10]).unwrap();
+        std::fs::write(root.join("a/big.TXT"), [0; 300]).unwrap();
+        std::fs::write(root.join("a/b/medium.bin"), [0; 200]).unwrap();
+        std::fs::write(root.join("a/b/Makefile"), [0; 1]).unwrap();
+
+        let stats = Analyzer::new(&root).top(2).run().unwrap();
+        assert_eq!(stats.file_count, 4);
+        assert_eq!(stats.total_file_size, 511);
+        assert_eq!(stats.directory_count, 2);
+        assert_eq!(
+            stats.largest_files,
+            vec![
+                (PathBuf::from("a/big.TXT"), 300),
+                (PathBuf::from("a/b/medium.bin"), 200)
+            ]
+        );
+        assert_eq!(
+            stats.deepest_paths,
+            vec![
+                (PathBuf::from("a/b/medium.bin"), 3),
+                (PathBuf::from("a/b/Makefile"), 3)
+            ]
+        );
+        assert_eq!(stats.extensions.get("txt"), Some(&(2, 310)));
+        assert_eq!(stats.extensions.get(""), Some(&(1, 1)));
+    }
+}
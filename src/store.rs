@@ -0,0 +1,332 @@
+//! **store** contains the content-addressed destination format, where every distinct
+//! file content is stored once under its SHA-256 digest and every run records a
+//! snapshot manifest of the paths pointing to them, so that repeated backups of
+//! mostly identical trees share their storage.
+
+use crate::{
+    archive::{escape, unescape},
+    copy::{self, RateLimiter},
+    debug,
+    error::{IoResultExt, SyncError},
+    fs::FileSearcher,
+    platform,
+    sync::SyncStats,
+};
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the directory of a store holding the file contents.
+pub const OBJECTS_DIR_NAME: &str = "objects";
+
+/// Name of the directory of a store holding the snapshot manifests.
+pub const SNAPSHOTS_DIR_NAME: &str = "snapshots";
+
+/// File of a snapshot, pointing to the object holding its content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub path: PathBuf,
+    pub hash: String,
+    pub size: u64,
+    pub mode: u32,
+    /// Modification date, in nanoseconds since the Unix epoch.
+    pub modified: u64,
+}
+
+/// Manifest of the files of a source directory at the time of a run, named after the
+/// timestamp of the run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl Snapshot {
+    /// Creates an empty snapshot named after the current timestamp.
+    pub fn now() -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Snapshot {
+            name: timestamp.to_string(),
+            entries: vec![],
+        }
+    }
+}
+
+/// Content-addressed store kept in a destination directory, with its objects under
+/// `objects/ab/cdef...` and its snapshot manifests under `snapshots/`.
+#[derive(Debug, Clone)]
+pub struct ContentStore {
+    root: PathBuf,
+}
+
+impl ContentStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        ContentStore {
+            root: platform::long_path(root.as_ref()),
+        }
+    }
+
+    /// Returns the path of the object holding the content with the `hash` digest.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use acsync::store::ContentStore;
+    /// # use std::path::PathBuf;
+    /// #
+    /// assert_eq!(
+    ///     ContentStore::new("backup").object_path("ba7816bf"),
+    ///     PathBuf::from("backup/objects/ba/7816bf")
+    /// );
+    /// ```
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        let (prefix, rest) = hash.split_at(hash.len().min(2));
+        self.root.join(OBJECTS_DIR_NAME).join(prefix).join(rest)
+    }
+
+    /// Returns true if the content with the `hash` digest is already stored.
+    pub fn contains(&self, hash: &str) -> bool {
+        self.object_path(hash).is_file()
+    }
+
+    /// Stores the content of the `path` file under its `hash` digest, writing it to a
+    /// temporary file renamed once complete.
+    pub fn insert<P: AsRef<Path>>(
+        &self,
+        path: P,
+        hash: &str,
+        limiter: Option<&mut RateLimiter>,
+    ) -> Result<(), SyncError> {
+        let object_path = self.object_path(hash);
+        if let Some(parent) = object_path.parent() {
+            std::fs::create_dir_all(parent).with_path(parent)?;
+        }
+        let write_path = copy::temporary_path(&object_path);
+        if let Err(error) = copy::copy_file(path, &write_path, limiter) {
+            let _ = std::fs::remove_file(&write_path);
+            return Err(error);
+        }
+        std::fs::rename(&write_path, &object_path).with_path(&object_path)
+    }
+
+    /// Returns the names of the snapshots of the store, from the oldest to the newest.
+    pub fn snapshots(&self) -> Result<Vec<String>, SyncError> {
+        let snapshots_dir = self.root.join(SNAPSHOTS_DIR_NAME);
+        if !snapshots_dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut names: Vec<String> = std::fs::read_dir(&snapshots_dir)
+            .with_path(&snapshots_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| !name.ends_with(".acsync_tmp"))
+            .collect();
+        names.sort_by_key(|name| (name.len(), name.clone()));
+        Ok(names)
+    }
+
+    /// Loads the `name` snapshot, or the newest one when `None`.
+    pub fn load_snapshot(&self, name: Option<&str>) -> Result<Snapshot, SyncError> {
+        let snapshots = self.snapshots()?;
+        let not_found = |reason: String| SyncError::InvalidArgument {
+            name: "snapshot".to_string(),
+            reason,
+        };
+        let name = match name {
+            Some(name) if snapshots.iter().any(|snapshot| snapshot == name) => name,
+            Some(name) => {
+                return Err(not_found(format!(
+                    "{name:?} not found in {}, expected one of {}",
+                    self.root.display(),
+                    snapshots.join(", ")
+                )));
+            }
+            None => snapshots.last().ok_or_else(|| {
+                not_found(format!("no snapshot found in {}", self.root.display()))
+            })?,
+        };
+
+        let snapshot_path = self.root.join(SNAPSHOTS_DIR_NAME).join(name);
+        let content = std::fs::read_to_string(&snapshot_path).with_path(&snapshot_path)?;
+        let mut snapshot = Snapshot {
+            name: name.to_string(),
+            entries: vec![],
+        };
+        for line in content.lines() {
+            let mut fields = line.splitn(5, '\t');
+            let entry = (|| {
+                Some(SnapshotEntry {
+                    hash: fields.next()?.to_string(),
+                    size: fields.next()?.parse().ok()?,
+                    mode: u32::from_str_radix(fields.next()?, 8).ok()?,
+                    modified: fields.next()?.parse().ok()?,
+                    path: PathBuf::from(unescape(fields.next()?)),
+                })
+            })()
+            .ok_or_else(|| SyncError::Io {
+                path: snapshot_path.clone(),
+                source: std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed entry"),
+            })?;
+            snapshot.entries.push(entry);
+        }
+        Ok(snapshot)
+    }
+
+    /// Writes the manifest of the `snapshot` into the store.
+    pub fn save_snapshot(&self, snapshot: &Snapshot) -> Result<(), SyncError> {
+        let snapshots_dir = self.root.join(SNAPSHOTS_DIR_NAME);
+        std::fs::create_dir_all(&snapshots_dir).with_path(&snapshots_dir)?;
+        let mut content = String::new();
+        for entry in &snapshot.entries {
+            content += &format!(
+                "{}\t{}\t{:o}\t{}\t{}\n",
+                entry.hash,
+                entry.size,
+                entry.mode,
+                entry.modified,
+                escape(&entry.path.to_string_lossy())
+            );
+        }
+        let snapshot_path = snapshots_dir.join(&snapshot.name);
+        let write_path = copy::temporary_path(&snapshot_path);
+        std::fs::write(&write_path, content).with_path(&write_path)?;
+        std::fs::rename(&write_path, &snapshot_path).with_path(&snapshot_path)
+    }
+
+    /// Removes the manifest of the `name` snapshot, leaving its objects to
+    /// [`ContentStore::collect_garbage`].
+    pub fn remove_snapshot(&self, name: &str) -> Result<(), SyncError> {
+        let snapshot_path = self.root.join(SNAPSHOTS_DIR_NAME).join(name);
+        std::fs::remove_file(&snapshot_path).with_path(&snapshot_path)
+    }
+
+    /// Removes the objects referenced by no snapshot, returning their count and total
+    /// size.
+    pub fn collect_garbage(&self, dryrun: bool) -> Result<(u64, u64), SyncError> {
+        let mut referenced = HashSet::new();
+        for name in self.snapshots()? {
+            let snapshot = self.load_snapshot(Some(&name))?;
+            referenced.extend(snapshot.entries.into_iter().map(|entry| entry.hash));
+        }
+        let objects_dir = self.root.join(OBJECTS_DIR_NAME);
+        let (mut count, mut size) = (0, 0);
+        for result in FileSearcher::new(&objects_dir).hidden(true) {
+            let entry = result.map_err(|source| SyncError::WalkError {
+                path: objects_dir.clone(),
+                source,
+            })?;
+            if !entry.is_file() || entry.depth() != 2 {
+                continue;
+            }
+            let path = entry.path();
+            let hash = path
+                .strip_prefix(&objects_dir)
+                .map(|relative_path| relative_path.to_string_lossy().replace(['/', '\\'], ""))
+                .unwrap_or_default();
+            if referenced.contains(&hash) {
+                continue;
+            }
+            debug!("Removing unreferenced object {hash} ...");
+            if !dryrun {
+                std::fs::remove_file(path).with_path(path)?;
+            }
+            count += 1;
+            size += entry.metadata().len();
+        }
+        Ok((count, size))
+    }
+
+    /// Materializes the files of the `name` snapshot, or of the newest one when `None`,
+    /// into the `target` directory, restoring their permissions and modification dates.
+    ///
+    /// Target files with the size and modification date of their snapshot entry are
+    /// left untouched, so an interrupted restore can be resumed.
+    pub fn restore<P: AsRef<Path>>(
+        &self,
+        name: Option<&str>,
+        target: P,
+        dryrun: bool,
+    ) -> Result<SyncStats, SyncError> {
+        let target = platform::long_path(target.as_ref());
+        let snapshot = self.load_snapshot(name)?;
+        debug!(
+            "Restoring snapshot {} into {} ...",
+            snapshot.name,
+            target.display()
+        );
+        let mut stats = SyncStats::default();
+        for entry in &snapshot.entries {
+            let target_path = target.join(&entry.path);
+            let modified = UNIX_EPOCH + Duration::from_nanos(entry.modified);
+            stats.file_count += 1;
+            stats.total_file_size += entry.size;
+            if let Ok(metadata) = target_path.metadata()
+                && metadata.is_file()
+                && metadata.len() == entry.size
+                && metadata.modified().ok() == Some(modified)
+            {
+                continue;
+            }
+            debug!("Restoring file {} ...", entry.path.display());
+            if !dryrun {
+                if let Some(parent) = target_path.parent()
+                    && !parent.exists()
+                {
+                    std::fs::create_dir_all(parent).with_path(parent)?;
+                    stats.directory_created_count += 1;
+                }
+                let write_path = copy::temporary_path(&target_path);
+                copy::copy_file(self.object_path(&entry.hash), &write_path, None)?;
+                platform::set_mode(&write_path, entry.mode).with_path(&write_path)?;
+                File::options()
+                    .write(true)
+                    .open(&write_path)
+                    .and_then(|file| file.set_modified(modified))
+                    .with_path(&write_path)?;
+                std::fs::rename(&write_path, &target_path).with_path(&target_path)?;
+            }
+            stats.file_copied_count += 1;
+            stats.total_file_copied_size += entry.size;
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn it_saves_and_loads_snapshots() {
+        let directory = testing::test_dir("it_saves_and_loads_snapshots");
+        let store = ContentStore::new(&directory);
+        assert!(store.load_snapshot(None).is_err());
+
+        let entry = SnapshotEntry {
+            path: PathBuf::from("docs/a\tb.txt"),
+            hash: "ba7816bf".to_string(),
+            size: 3,
+            mode: 0o644,
+            modified: 1_700_000_000_000_000_001,
+        };
+        for name in ["900", "1000"] {
+            let snapshot = Snapshot {
+                name: name.to_string(),
+                entries: vec![entry.clone()],
+            };
+            store.save_snapshot(&snapshot).unwrap();
+        }
+        assert_eq!(store.snapshots().unwrap(), ["900", "1000"]);
+        let snapshot = store.load_snapshot(None).unwrap();
+        assert_eq!(snapshot.name, "1000");
+        assert_eq!(snapshot.entries, [entry]);
+        assert!(store.load_snapshot(Some("42")).is_err());
+    }
+}
@@ -0,0 +1,4382 @@
+//! **sync** contains the types driving how files are synchronized between an origin
+//! and a destination directory.
+
+use crate::{
+    archive::{self, ArchiveIndex, Compression, INDEX_FILE_NAME},
+    cache::{self, CACHE_FILE_NAME, ChecksumCache},
+    cancel::CancellationToken,
+    copy::{self, RateLimiter, Reflink},
+    debug, error,
+    error::{IoResultExt, SyncError},
+    freeze::FrozenSource,
+    fs::{ErrorPolicy, FileSearcher, SearchEntry, SearchIterator, SortOrder},
+    hash, info,
+    lock::{DestinationLock, LOCK_FILE_NAME},
+    log::{self, Level},
+    names::{InvalidNames, NAMES_FILE_NAME, NameMap, Normalization, NormalizedNames, TargetFs},
+    observer::{SyncEvent, SyncObserver},
+    owner::IdMap,
+    pipeline::{HashPipeline, LOOKAHEAD, PendingHash},
+    platform,
+    progress::{self, Progress},
+    prompt::{Prompter, TerminalPrompter},
+    report::{ACSYNC_DIR_NAME, Change, Outcome, RunReport},
+    signal,
+    state::{STATE_FILE_NAME, StateManifest},
+    store::{ContentStore, Snapshot, SnapshotEntry},
+    warn,
+};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    fmt,
+    hash::{BuildHasher, RandomState},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc,
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "async")]
+mod asynchronous;
+#[cfg(feature = "async")]
+pub use asynchronous::{AsyncReplicator, Replication};
+
+/// Name of the directory of the destination where deleted files are moved to by
+/// [`Replicator::delete_to_trash`].
+pub const TRASH_DIR_NAME: &str = ".acsync_trash";
+
+/// Delay before the first retry of a failed copy with [`Replicator::retries`], doubled
+/// before every other one, unless set with [`Replicator::retry_delay`].
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Name of the file marking a destination that runs refuse to replicate into, unless
+/// forced with [`Replicator::force`].
+pub const PROTECT_FILE_NAME: &str = ".acsync_protect";
+
+/// Error code of a low-level I/O error, e.g. of a flaky network file system or drive.
+#[cfg(unix)]
+const EIO: i32 = 5;
+#[cfg(windows)]
+const EIO: i32 = 1117; // ERROR_IO_DEVICE
+
+/// Strategy used to resolve a conflict, i.e. a file present in both origin and
+/// destination with different sizes and modification dates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Ask the user whether the destination file should be overridden.
+    Prompt,
+    /// Keep the file with the most recent modification date.
+    Newer,
+    /// Keep the file with the oldest modification date.
+    Older,
+    /// Keep the largest file.
+    Larger,
+    /// Never override the destination file.
+    #[default]
+    Skip,
+    /// Keep both, moving the destination file aside to `file.conflict-<timestamp>`.
+    Rename,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "prompt" => Ok(ConflictPolicy::Prompt),
+            "newer" => Ok(ConflictPolicy::Newer),
+            "older" => Ok(ConflictPolicy::Older),
+            "larger" => Ok(ConflictPolicy::Larger),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "rename" => Ok(ConflictPolicy::Rename),
+            _ => Err(format!(
+                "unknown conflict policy {value:?}, expected one of prompt, newer, older, larger, skip or rename"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ConflictPolicy::Prompt => "prompt",
+            ConflictPolicy::Newer => "newer",
+            ConflictPolicy::Older => "older",
+            ConflictPolicy::Larger => "larger",
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Rename => "rename",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// What to do with the source files whose paths only differ by case, e.g. `Readme.md`
+/// and `README.md`, which a case-insensitive destination can't hold apart.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CaseCollisions {
+    /// Fail on collisions when the destination file system is case-insensitive, ignore
+    /// them otherwise.
+    #[default]
+    Auto,
+    /// Fail on collisions, whatever the destination file system, e.g. for a copy to be
+    /// moved to another platform later.
+    Error,
+    /// Replicate the colliding files after the first one as `file.case-<n>`.
+    Rename,
+    /// Replicate the colliding files as they are, the last one replacing the others on a
+    /// case-insensitive destination.
+    Ignore,
+}
+
+impl FromStr for CaseCollisions {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "auto" => Ok(CaseCollisions::Auto),
+            "error" => Ok(CaseCollisions::Error),
+            "rename" => Ok(CaseCollisions::Rename),
+            "ignore" => Ok(CaseCollisions::Ignore),
+            _ => Err(format!(
+                "unknown case collision policy {value:?}, expected one of auto, error, rename or ignore"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CaseCollisions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CaseCollisions::Auto => "auto",
+            CaseCollisions::Error => "error",
+            CaseCollisions::Rename => "rename",
+            CaseCollisions::Ignore => "ignore",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Order in which the source files are replicated, so that an interrupted run has
+/// already replicated the files that matter most.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::sync::TransferOrder;
+/// #
+/// let order: TransferOrder = "smallest-first".parse().unwrap();
+/// assert_eq!(order, TransferOrder::SmallestFirst);
+/// assert_eq!(order.to_string(), "smallest-first");
+/// assert_eq!("newest_first".parse(), Ok(TransferOrder::NewestFirst));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TransferOrder {
+    /// Replicate the files as the source is walked.
+    #[default]
+    Walk,
+    /// Replicate the smallest files first, the most files in the least time.
+    SmallestFirst,
+    /// Replicate the largest files first.
+    LargestFirst,
+    /// Replicate the most recently modified files first.
+    NewestFirst,
+}
+
+impl FromStr for TransferOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "walk" => Ok(TransferOrder::Walk),
+            // The underscored spellings are kept as aliases of the documented ones.
+            "smallest-first" | "smallest_first" => Ok(TransferOrder::SmallestFirst),
+            "largest-first" | "largest_first" => Ok(TransferOrder::LargestFirst),
+            "newest-first" | "newest_first" => Ok(TransferOrder::NewestFirst),
+            _ => Err(format!(
+                "unknown order {value:?}, expected one of walk, smallest-first, largest-first or newest-first"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TransferOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransferOrder::Walk => "walk",
+            TransferOrder::SmallestFirst => "smallest-first",
+            TransferOrder::LargestFirst => "largest-first",
+            TransferOrder::NewestFirst => "newest-first",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Which source files are replicated, depending on whether they already exist in the
+/// destination.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingPolicy {
+    /// Create the missing files and update the existing ones.
+    #[default]
+    All,
+    /// Only update the files already in the destination, never creating new ones.
+    Existing,
+    /// Only create the files missing in the destination, never touching existing ones.
+    IgnoreExisting,
+}
+
+/// How a file present in both origin and destination is told to be a conflict.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeDetection {
+    /// The sizes and the modification dates differ, the dates by more than
+    /// [`Replicator::modify_window`].
+    #[default]
+    SizeAndTime,
+    /// The sizes differ, whatever the modification dates, for file systems with
+    /// unreliable timestamps.
+    SizeOnly,
+    /// Always, leaving the decision to the [`ConflictPolicy`].
+    IgnoreTimes,
+}
+
+/// Kind of a [`PlannedAction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionKind {
+    /// Copy a file missing in the target.
+    Copy,
+    /// Override a conflicting target file.
+    Override,
+    /// Rename a conflicting target file and copy the source file.
+    Rename,
+    /// Move a target file to the path its source file was moved to.
+    Move,
+    /// Hard-link a file of the `link_dest` snapshot.
+    Link,
+    /// Recreate a special file.
+    Special,
+    /// Fix the permissions, ownership or symlink target of an unchanged target path.
+    Metadata,
+    /// Delete a target file removed from the source.
+    Delete,
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ActionKind::Copy => "copy",
+            ActionKind::Override => "override",
+            ActionKind::Rename => "rename",
+            ActionKind::Move => "move",
+            ActionKind::Link => "link",
+            ActionKind::Special => "special",
+            ActionKind::Metadata => "metadata",
+            ActionKind::Delete => "delete",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl ActionKind {
+    /// Returns the change flags of the action printed by `--itemize`: what happened to
+    /// the path (`>` copied, `c` created, `h` hard-linked, `m` moved, `*` deleted, `.`
+    /// left in place), then whether its size, time and metadata changed (`s`, `t`, `p`,
+    /// or `+` for a new path).
+    pub fn flags(&self) -> &'static str {
+        match self {
+            ActionKind::Copy | ActionKind::Rename => ">+++",
+            ActionKind::Override => ">st.",
+            ActionKind::Move => "m...",
+            ActionKind::Link => "h...",
+            ActionKind::Special => "c+++",
+            ActionKind::Delete => "*...",
+            ActionKind::Metadata => "...p",
+        }
+    }
+}
+
+/// Action a [`Replicator`] run would perform on a relative path, as returned by
+/// [`Replicator::plan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedAction {
+    pub kind: ActionKind,
+    pub path: PathBuf,
+}
+
+impl fmt::Display for PlannedAction {
+    /// Formats the action as an `--itemize` line, e.g. `>st. docs/notes.txt`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind.flags(), self.path.display())
+    }
+}
+
+/// Returns the path a conflicting destination file is moved to by [`ConflictPolicy::Rename`].
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::sync;
+/// # use std::path::PathBuf;
+/// #
+/// assert_eq!(
+///     sync::conflict_path("docs/notes.txt", 1700000000),
+///     PathBuf::from("docs/notes.txt.conflict-1700000000")
+/// );
+/// ```
+pub fn conflict_path<P: AsRef<Path>>(path: P, timestamp: u64) -> PathBuf {
+    let mut file_name = path.as_ref().as_os_str().to_os_string();
+    file_name.push(format!(".conflict-{timestamp}"));
+    PathBuf::from(file_name)
+}
+
+/// Returns the path the `count`-th source file colliding by case with others is
+/// replicated to under [`CaseCollisions::Rename`].
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::sync;
+/// # use std::path::PathBuf;
+/// #
+/// assert_eq!(
+///     sync::case_collision_path("docs/README.md", 2),
+///     PathBuf::from("docs/README.md.case-2")
+/// );
+/// ```
+pub fn case_collision_path<P: AsRef<Path>>(path: P, count: u64) -> PathBuf {
+    let mut file_name = path.as_ref().as_os_str().to_os_string();
+    file_name.push(format!(".case-{count}"));
+    PathBuf::from(file_name)
+}
+
+/// Returns the path an overridden destination file is kept at by [`Replicator::backup`].
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::sync;
+/// # use std::path::PathBuf;
+/// #
+/// assert_eq!(
+///     sync::backup_path("docs/notes.txt"),
+///     PathBuf::from("docs/notes.txt~")
+/// );
+/// ```
+pub fn backup_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut file_name = path.as_ref().as_os_str().to_os_string();
+    file_name.push("~");
+    PathBuf::from(file_name)
+}
+
+/// Returns true when both dates are within `window` of each other.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::sync;
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// #
+/// let date = UNIX_EPOCH + Duration::from_secs(1700000000);
+/// assert!(sync::same_time(date, date + Duration::from_secs(1), Duration::from_secs(2)));
+/// assert!(!sync::same_time(date + Duration::from_secs(3), date, Duration::from_secs(2)));
+/// ```
+pub fn same_time(date: SystemTime, other: SystemTime, window: Duration) -> bool {
+    let difference = date
+        .duration_since(other)
+        .or_else(|_| other.duration_since(date))
+        .unwrap_or_default();
+    difference <= window
+}
+
+/// Returns true for the files kept at the root of a destination to track its runs,
+/// which are neither replicated nor reported as extra.
+pub(crate) fn is_metadata_file(relative_path: &Path) -> bool {
+    [
+        STATE_FILE_NAME,
+        CACHE_FILE_NAME,
+        LOCK_FILE_NAME,
+        PROTECT_FILE_NAME,
+        NAMES_FILE_NAME,
+    ]
+    .iter()
+    .any(|name| platform::same_path(relative_path, Path::new(name)))
+}
+
+/// Returns true for the paths kept in a destination to track its runs, i.e. the
+/// [`is_metadata_file`] files and the trash and reports directories with their content.
+pub(crate) fn is_metadata_path(relative_path: &Path) -> bool {
+    is_metadata_file(relative_path)
+        || relative_path.starts_with(TRASH_DIR_NAME)
+        || relative_path.starts_with(ACSYNC_DIR_NAME)
+}
+
+/// Returns the patterns listed one per line in the `file_name` file of `directory`.
+///
+/// Returns an empty list if there is no such file.
+pub fn read_patterns<P: AsRef<Path>>(directory: P, file_name: &str) -> Vec<String> {
+    if let Ok(patterns) = std::fs::read_to_string(directory.as_ref().join(file_name)) {
+        info!("Found file {file_name}, loading...");
+        patterns
+            .split_terminator('\n')
+            .map(|item| item.to_string())
+            .collect()
+    } else {
+        vec![]
+    }
+}
+
+/// Counters of what happened during a [`Replicator`] run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncStats {
+    pub file_copied_count: u64,
+    pub total_file_copied_size: u64,
+    pub file_conflict_count: u64,
+    pub file_renamed_count: u64,
+    pub file_dated_count: u64,
+    pub total_file_dated_size: u64,
+    pub file_overrided_count: u64,
+    pub total_file_overrided_size: u64,
+    /// Bytes of the overridden files left unwritten because their blocks were unchanged.
+    pub total_delta_saved_size: u64,
+    pub file_linked_count: u64,
+    pub total_file_linked_size: u64,
+    pub file_special_count: u64,
+    pub file_metadata_fixed_count: u64,
+    pub file_moved_count: u64,
+    pub total_file_moved_size: u64,
+    pub file_backed_up_count: u64,
+    /// Files of a store run whose content was already stored.
+    pub file_deduplicated_count: u64,
+    pub file_deleted_count: u64,
+    pub total_file_deleted_size: u64,
+    pub directory_created_count: u64,
+    /// Paths replicated under the name transcoded for the target file system.
+    pub path_transcoded_count: u64,
+    /// Paths left out as they can't be named as is on the target file system.
+    pub path_skipped_count: u64,
+    /// Copied files read back and compared against their source by
+    /// [`Replicator::verify_sample`].
+    pub file_sampled_count: u64,
+    /// Files copied after failing at first, with [`Replicator::retries`].
+    pub file_retried_count: u64,
+    pub file_count: u64,
+    pub total_file_size: u64,
+    pub file_failed_count: u64,
+    /// Whether the run was a [`Replicator::dryrun`], which wrote nothing.
+    pub dryrun: bool,
+    /// Whether the run was stopped by SIGINT or SIGTERM before going through every file.
+    pub interrupted: bool,
+    /// Time the run took.
+    pub duration: Duration,
+}
+
+impl fmt::Display for SyncStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", " Stats ")?;
+        writeln!(
+            f,
+            "Copied files: {} ({} KBs)",
+            self.file_copied_count,
+            (self.total_file_copied_size / 1024) as f64
+        )?;
+        writeln!(f, "Conflicting files: {}", self.file_conflict_count)?;
+        writeln!(
+            f,
+            "Dated files: {} ({} KBs)",
+            self.file_dated_count,
+            (self.total_file_dated_size / 1024) as f64
+        )?;
+        writeln!(f, "Renamed conflicting files: {}", self.file_renamed_count)?;
+        writeln!(
+            f,
+            "Overrided files: {} ({} KBs)",
+            self.file_overrided_count,
+            (self.total_file_overrided_size / 1024) as f64
+        )?;
+        if self.total_delta_saved_size > 0 {
+            writeln!(
+                f,
+                "Unchanged blocks: {} KBs",
+                (self.total_delta_saved_size / 1024) as f64
+            )?;
+        }
+        writeln!(f, "Backed up files: {}", self.file_backed_up_count)?;
+        writeln!(
+            f,
+            "Linked files: {} ({} KBs)",
+            self.file_linked_count,
+            (self.total_file_linked_size / 1024) as f64
+        )?;
+        writeln!(
+            f,
+            "Moved files: {} ({} KBs)",
+            self.file_moved_count,
+            (self.total_file_moved_size / 1024) as f64
+        )?;
+        writeln!(f, "Special files: {}", self.file_special_count)?;
+        if self.file_deduplicated_count > 0 {
+            writeln!(f, "Deduplicated files: {}", self.file_deduplicated_count)?;
+        }
+        if self.path_transcoded_count > 0 {
+            writeln!(f, "Transcoded names: {}", self.path_transcoded_count)?;
+        }
+        if self.path_skipped_count > 0 {
+            writeln!(f, "Skipped invalid names: {}", self.path_skipped_count)?;
+        }
+        if self.file_metadata_fixed_count > 0 {
+            writeln!(f, "Metadata fixed: {}", self.file_metadata_fixed_count)?;
+        }
+        if self.file_sampled_count > 0 {
+            writeln!(f, "Verified samples: {}", self.file_sampled_count)?;
+        }
+        if self.file_retried_count > 0 {
+            writeln!(f, "Retried files: {}", self.file_retried_count)?;
+        }
+        writeln!(
+            f,
+            "Deleted files: {} ({} KBs)",
+            self.file_deleted_count,
+            (self.total_file_deleted_size / 1024) as f64
+        )?;
+        writeln!(f, "Directory created: {}", self.directory_created_count)?;
+        writeln!(
+            f,
+            "Files found: {} ({} KBs)",
+            self.file_count,
+            (self.total_file_size / 1024) as f64
+        )?;
+        if self.file_failed_count > 0 {
+            writeln!(f, "Failed files: {}", self.file_failed_count)?;
+        }
+        let written_size = self.total_file_copied_size + self.total_file_overrided_size;
+        if written_size > 0 && !self.duration.is_zero() && !self.dryrun {
+            writeln!(
+                f,
+                "Average throughput: {}/s",
+                progress::format_size((written_size as f64 / self.duration.as_secs_f64()) as u64)
+            )?;
+        }
+        if self.interrupted {
+            writeln!(f, "Interrupted before the end")?;
+        }
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct ReplicatorOptions {
+    on_conflict: ConflictPolicy,
+    existing_policy: ExistingPolicy,
+    change_detection: ChangeDetection,
+    case_collisions: CaseCollisions,
+    order: TransferOrder,
+    target_fs: TargetFs,
+    invalid_names: InvalidNames,
+    normalize: Normalization,
+    modify_window: Duration,
+    dryrun: bool,
+    ignore_errors: bool,
+    inplace: bool,
+    whole_file: bool,
+    reflink: Reflink,
+    sparse: bool,
+    hidden: bool,
+    specials: bool,
+    devices: bool,
+    one_file_system: bool,
+    perms: bool,
+    usermap: Option<IdMap>,
+    groupmap: Option<IdMap>,
+    acls: bool,
+    metadata_only: bool,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+    bwlimit: Option<u64>,
+    link_dest: Option<PathBuf>,
+    backup: bool,
+    backup_dir: Option<PathBuf>,
+    delete: bool,
+    delete_to_trash: bool,
+    max_delete: Option<u64>,
+    max_delete_percent: Option<u8>,
+    verify: bool,
+    verify_sample: Option<u8>,
+    retries: u32,
+    retry_delay: Option<Duration>,
+    timeout: Option<Duration>,
+    recopies: Option<u32>,
+    snapshot: bool,
+    cancellation: Option<CancellationToken>,
+    /// Source directory recorded as the origin when the source is read from a snapshot.
+    origin: Option<PathBuf>,
+    trash_dir: Option<PathBuf>,
+    approved: Option<HashSet<PathBuf>>,
+    files_from: Option<Vec<PathBuf>>,
+    prompter: Option<Arc<dyn Prompter>>,
+    excludes: Vec<String>,
+    compress: Option<Compression>,
+    store: bool,
+    hash_threads: Option<usize>,
+    progress: bool,
+    prescan: bool,
+    ignore_cache: bool,
+    wait_lock: Duration,
+    check_space: bool,
+    force: bool,
+    report: bool,
+    arguments: Vec<String>,
+}
+
+#[derive(Default)]
+struct ReplicateContext<'a> {
+    limiter: Option<RateLimiter>,
+    source_index: Option<ArchiveIndex>,
+    target_index: Option<ArchiveIndex>,
+    /// State manifest of the target, absent when either side is compressed.
+    state: Option<StateManifest>,
+    /// Files of the previous run no longer found in the source, grouped by size.
+    moved_candidates: HashMap<u64, Vec<PathBuf>>,
+    /// Checksum cache of the target, absent along with the state manifest unless the
+    /// target is a store.
+    cache: Option<ChecksumCache>,
+    /// Snapshot recorded when the target is a [`ContentStore`].
+    snapshot: Option<Snapshot>,
+    /// Count of the files of the state manifest, i.e. replicated by the previous run.
+    previous_file_count: u64,
+    /// Digests of the upcoming source files being computed by the [`HashPipeline`].
+    hashes: HashMap<PathBuf, PendingHash>,
+    /// Actions recorded instead of performed by [`Replicator::plan`].
+    plan: Option<Vec<PlannedAction>>,
+    /// Callback of [`Replicator::run_itemized`], called on every action.
+    on_action: Option<&'a mut dyn FnMut(&PlannedAction)>,
+    /// Observer of [`Replicator::run_with_observer`], told about every event.
+    observer: Option<&'a mut dyn SyncObserver>,
+    /// Errors of the files that failed under [`Replicator::ignore_errors`].
+    errors: Vec<String>,
+    /// Changes made to the target, recorded in its report along with the state manifest.
+    changes: Vec<Change>,
+    /// Target directories by their relative path with their source directory, to restore
+    /// their modification time, absent along with the state manifest.
+    directory_sources: HashMap<PathBuf, PathBuf>,
+    /// Relative paths of the target directories whose entries were changed by the run.
+    changed_directories: BTreeSet<PathBuf>,
+    /// Source files seen so far by their lowercase relative path, with the count of those
+    /// colliding with them, absent when collisions are ignored.
+    folded_paths: Option<HashMap<String, (PathBuf, u64)>>,
+    /// File system whose names the target paths are checked against, absent for a store.
+    target_fs: Option<TargetFs>,
+    /// Paths transcoded in the target, absent along with the state manifest.
+    target_names: Option<NameMap>,
+    /// Paths transcoded in the source, when replicating back from a transcoded target.
+    source_names: Option<NameMap>,
+    /// Target files by the normalized form of their names, absent when names aren't
+    /// normalized.
+    target_normalized: Option<NormalizedNames>,
+    /// Keys of the hashes picking the files verified by [`Replicator::verify_sample`],
+    /// random for every run.
+    sampler: RandomState,
+    stats: SyncStats,
+}
+
+/// Replicates the files of a source directory into a target directory.
+#[derive(Debug, Clone)]
+pub struct Replicator {
+    source: PathBuf,
+    target: PathBuf,
+    options: ReplicatorOptions,
+}
+
+impl Replicator {
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> Self {
+        Replicator {
+            source: platform::long_path(source.as_ref()),
+            target: platform::long_path(target.as_ref()),
+            options: ReplicatorOptions {
+                hidden: true,
+                ..ReplicatorOptions::default()
+            },
+        }
+    }
+
+    pub fn on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.options.on_conflict = on_conflict;
+        self
+    }
+
+    /// Restricts the run to the source files that already exist in the target, or to
+    /// those that don't.
+    pub fn existing_policy(mut self, existing_policy: ExistingPolicy) -> Self {
+        self.options.existing_policy = existing_policy;
+        self
+    }
+
+    /// What to do with the source files whose paths only differ by case,
+    /// [`CaseCollisions::Auto`] by default.
+    pub fn case_collisions(mut self, case_collisions: CaseCollisions) -> Self {
+        self.options.case_collisions = case_collisions;
+        self
+    }
+
+    /// Order in which the source files are replicated, [`TransferOrder::Walk`] by
+    /// default. Other orders list the whole source before replicating the first file,
+    /// directories first.
+    pub fn order(mut self, order: TransferOrder) -> Self {
+        self.options.order = order;
+        self
+    }
+
+    /// File system whose name restrictions the target paths are checked against,
+    /// detected from the target by default.
+    pub fn target_fs(mut self, target_fs: TargetFs) -> Self {
+        self.options.target_fs = target_fs;
+        self
+    }
+
+    /// What to do with the source paths that can't be named as is on the target file
+    /// system, [`InvalidNames::Error`] by default.
+    pub fn invalid_names(mut self, invalid_names: InvalidNames) -> Self {
+        self.options.invalid_names = invalid_names;
+        self
+    }
+
+    /// Unicode normalization form of the target names, [`Normalization::Off`] by
+    /// default.
+    ///
+    /// Files are also told apart by the normalized form of their names, so a target file
+    /// named in another form than its source is updated instead of duplicated, and isn't
+    /// deleted with [`Replicator::delete`].
+    pub fn normalize(mut self, normalize: Normalization) -> Self {
+        self.options.normalize = normalize;
+        self
+    }
+
+    /// How the files present in both source and target are told to conflict,
+    /// [`ChangeDetection::SizeAndTime`] by default.
+    pub fn change_detection(mut self, change_detection: ChangeDetection) -> Self {
+        self.options.change_detection = change_detection;
+        self
+    }
+
+    /// Considers modification dates within `modify_window` of each other as equal, e.g.
+    /// 2 seconds for FAT targets, which store dates with that granularity.
+    pub fn modify_window(mut self, modify_window: Duration) -> Self {
+        self.options.modify_window = modify_window;
+        self
+    }
+
+    pub fn dryrun(mut self, flag: bool) -> Self {
+        self.options.dryrun = flag;
+        self
+    }
+
+    /// Keeps replicating the remaining files when one of them fails, counting the
+    /// failure in [`SyncStats::file_failed_count`] instead of aborting the run.
+    pub fn ignore_errors(mut self, flag: bool) -> Self {
+        self.options.ignore_errors = flag;
+        self
+    }
+
+    /// Writes files directly to their target path instead of writing a temporary file
+    /// renamed on success, which leaves half-written files behind if interrupted.
+    pub fn inplace(mut self, flag: bool) -> Self {
+        self.options.inplace = flag;
+        self
+    }
+
+    /// Copies overridden files whole instead of only writing their changed blocks.
+    ///
+    /// Large files are otherwise patched with [`copy::patch_file`], in place with
+    /// [`Replicator::inplace`], or else into a clone of the target file, which requires
+    /// a copy-on-write filesystem.
+    pub fn whole_file(mut self, flag: bool) -> Self {
+        self.options.whole_file = flag;
+        self
+    }
+
+    /// Whether files are cloned instead of copied when the source and target are on
+    /// the same copy-on-write filesystem, [`Reflink::Auto`] by default.
+    pub fn reflink(mut self, reflink: Reflink) -> Self {
+        self.options.reflink = reflink;
+        self
+    }
+
+    /// Keeps the holes of sparse files, such as disk images, as holes in the target.
+    pub fn sparse(mut self, flag: bool) -> Self {
+        self.options.sparse = flag;
+        self
+    }
+
+    /// Whether dot-files and dot-directories are replicated, enabled by default.
+    pub fn hidden(mut self, flag: bool) -> Self {
+        self.options.hidden = flag;
+        self
+    }
+
+    /// Recreates FIFOs and sockets in the target directory.
+    pub fn specials(mut self, flag: bool) -> Self {
+        self.options.specials = flag;
+        self
+    }
+
+    /// Recreates block and character devices in the target directory, which usually
+    /// requires root privileges.
+    pub fn devices(mut self, flag: bool) -> Self {
+        self.options.devices = flag;
+        self
+    }
+
+    /// Doesn't descend into directories on other file systems than the source
+    /// directory, only recreating their mount points.
+    pub fn one_file_system(mut self, flag: bool) -> Self {
+        self.options.one_file_system = flag;
+        self
+    }
+
+    /// Compares the permissions, ownership and symlink targets of the paths already in
+    /// the target with their source, fixing the drifted ones even when their content is
+    /// unchanged.
+    pub fn perms(mut self, flag: bool) -> Self {
+        self.options.perms = flag;
+        self
+    }
+
+    /// Translates the user IDs of the source files before comparing and changing those
+    /// of the target ones with [`Replicator::perms`].
+    pub fn usermap(mut self, usermap: Option<IdMap>) -> Self {
+        self.options.usermap = usermap;
+        self
+    }
+
+    /// Translates the group IDs of the source files before comparing and changing those
+    /// of the target ones with [`Replicator::perms`].
+    pub fn groupmap(mut self, groupmap: Option<IdMap>) -> Self {
+        self.options.groupmap = groupmap;
+        self
+    }
+
+    /// Preserves the POSIX ACLs of Linux and the extended ACLs of macOS of the files and
+    /// directories replicated, fixing those drifted in the target with
+    /// [`Replicator::perms`].
+    pub fn acls(mut self, flag: bool) -> Self {
+        self.options.acls = flag;
+        self
+    }
+
+    /// Only repairs the permissions, ownership, modification times and symlink targets
+    /// of the paths already in the target, as with [`Replicator::perms`], without
+    /// creating, copying or deleting anything, e.g. after a copy made by another tool.
+    pub fn metadata_only(mut self, flag: bool) -> Self {
+        self.options.metadata_only = flag;
+        self
+    }
+
+    /// Skips files smaller than `min_size` bytes.
+    pub fn min_size(mut self, min_size: Option<u64>) -> Self {
+        self.options.min_size = min_size;
+        self
+    }
+
+    /// Skips files larger than `max_size` bytes.
+    pub fn max_size(mut self, max_size: Option<u64>) -> Self {
+        self.options.max_size = max_size;
+        self
+    }
+
+    /// Skips files not modified after `newer_than`.
+    pub fn newer_than(mut self, newer_than: Option<SystemTime>) -> Self {
+        self.options.newer_than = newer_than;
+        self
+    }
+
+    /// Skips files not modified before `older_than`.
+    pub fn older_than(mut self, older_than: Option<SystemTime>) -> Self {
+        self.options.older_than = older_than;
+        self
+    }
+
+    /// Excludes the files matching `excludes`, in addition to the patterns listed in
+    /// the `.acsync_excludes` files of the source directory and its subdirectories.
+    pub fn excludes(mut self, excludes: Vec<String>) -> Self {
+        self.options.excludes = excludes;
+        self
+    }
+
+    /// Limits the copy throughput to `bwlimit` bytes per second.
+    pub fn bwlimit(mut self, bwlimit: Option<u64>) -> Self {
+        self.options.bwlimit = bwlimit;
+        self
+    }
+
+    /// Hard-links files unchanged in the `link_dest` snapshot directory instead of
+    /// copying them. A relative path is resolved from the target directory.
+    pub fn link_dest<P: AsRef<Path>>(mut self, link_dest: Option<P>) -> Self {
+        self.options.link_dest = link_dest.map(|link_dest| self.target.join(link_dest));
+        self
+    }
+
+    /// Keeps the previous version of overridden files as `file~`.
+    pub fn backup(mut self, flag: bool) -> Self {
+        self.options.backup = flag;
+        self
+    }
+
+    /// Keeps the previous version of overridden files in the `backup_dir` directory,
+    /// preserving their relative paths. A relative path is resolved from the target
+    /// directory.
+    pub fn backup_dir<P: AsRef<Path>>(mut self, backup_dir: Option<P>) -> Self {
+        self.options.backup_dir = backup_dir.map(|backup_dir| self.target.join(backup_dir));
+        self
+    }
+
+    /// Deletes the target files replicated by a previous run whose source file no
+    /// longer exists. Files never replicated into the target are left untouched.
+    pub fn delete(mut self, flag: bool) -> Self {
+        self.options.delete = flag;
+        self
+    }
+
+    /// Moves the deleted target files to a timestamped directory of the trash directory
+    /// instead of removing them, preserving their relative paths. Implies
+    /// [`Replicator::delete`].
+    pub fn delete_to_trash(mut self, flag: bool) -> Self {
+        self.options.delete_to_trash = flag;
+        self
+    }
+
+    /// Fails with [`SyncError::TooManyDeletions`] before replicating anything when more
+    /// than `count` files would be deleted, e.g. from an origin accidentally left empty.
+    pub fn max_delete(mut self, count: Option<u64>) -> Self {
+        self.options.max_delete = count;
+        self
+    }
+
+    /// Fails with [`SyncError::TooManyDeletions`] before replicating anything when more
+    /// than `percent` % of the files replicated by the previous run would be deleted.
+    pub fn max_delete_percent(mut self, percent: Option<u8>) -> Self {
+        self.options.max_delete_percent = percent;
+        self
+    }
+
+    /// Hashes the data of every file as it's copied, then reads the copy back and
+    /// compares their checksums before renaming it over the target, failing the file with
+    /// [`SyncError::ChecksumMismatch`] and removing the copy on mismatch, e.g. on flaky
+    /// USB drives or network file systems. The previous target is kept, except under
+    /// [`Replicator::inplace`] where it may be left corrupt. Files are then copied
+    /// through userspace buffers, without reflinks, patches of their changed blocks or
+    /// holes.
+    pub fn verify(mut self, flag: bool) -> Self {
+        self.options.verify = flag;
+        self
+    }
+
+    /// Reads back about `percent` % of the files copied, picked at random, right after
+    /// writing them and compares their checksum against the source, failing with
+    /// [`SyncError::ChecksumMismatch`] and removing the copy on silent corruption, as
+    /// [`Replicator::verify`] does. The copy is likely read from the page cache,
+    /// catching corruption on the way rather than on the disk. Compressed origins and
+    /// destinations aren't sampled.
+    pub fn verify_sample(mut self, percent: Option<u8>) -> Self {
+        self.options.verify_sample = percent;
+        self
+    }
+
+    /// Retries up to `count` times the copies failing with `EIO`, a timeout, an
+    /// interruption or a checksum mismatch, as network file systems and USB drives
+    /// intermittently do, before counting the file as failed.
+    pub fn retries(mut self, count: u32) -> Self {
+        self.options.retries = count;
+        self
+    }
+
+    /// Delay before the first retry of [`Replicator::retries`], doubled before every
+    /// other one, [`DEFAULT_RETRY_DELAY`] by default.
+    pub fn retry_delay(mut self, delay: Option<Duration>) -> Self {
+        self.options.retry_delay = delay;
+        self
+    }
+
+    /// Gives up on the copies of files taking longer than `timeout`, e.g. with a read
+    /// hung on a stale NFS handle, failing them with [`SyncError::TimedOut`] and going on
+    /// with the next files, even without [`Replicator::ignore_errors`]. Copies are then
+    /// run on a thread of their own, left behind when hung.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.timeout = timeout;
+        self
+    }
+
+    /// Copies the files whose size or modification time changed while being copied again,
+    /// up to `count` times, then fails them with [`SyncError::SourceChanged`] instead of
+    /// keeping a torn copy. Without it, such copies are kept with a warning.
+    pub fn recopies(mut self, count: Option<u32>) -> Self {
+        self.options.recopies = count;
+        self
+    }
+
+    /// Stops the run once `token` is cancelled, e.g. from another thread of an embedding
+    /// application, as it stops on Ctrl-C: the file being copied is given up on between
+    /// two chunks and the run returns the stats of the files replicated so far, flagged
+    /// [`SyncStats::interrupted`], recording an interrupted [`Replicator::report`].
+    pub fn cancellation(mut self, token: Option<CancellationToken>) -> Self {
+        self.options.cancellation = token;
+        self
+    }
+
+    /// Reads the files from a read-only snapshot of the source taken at the start of the
+    /// run, so that they're all copied as they were at the same instant. The source must
+    /// be a Btrfs subvolume.
+    pub fn snapshot(mut self, flag: bool) -> Self {
+        self.options.snapshot = flag;
+        self
+    }
+
+    /// Trash directory used by [`Replicator::delete_to_trash`], the [`TRASH_DIR_NAME`]
+    /// directory of the target by default. A relative path is resolved from the target
+    /// directory.
+    pub fn trash_dir<P: AsRef<Path>>(mut self, trash_dir: Option<P>) -> Self {
+        self.options.trash_dir = trash_dir.map(|trash_dir| self.target.join(trash_dir));
+        self
+    }
+
+    /// Only performs the actions on the `approved` relative paths, e.g. those picked
+    /// from [`Replicator::plan`]. Conflicts of approved paths are overridden without
+    /// asking under [`ConflictPolicy::Prompt`].
+    pub fn approved(mut self, approved: Option<HashSet<PathBuf>>) -> Self {
+        self.options.approved = approved;
+        self
+    }
+
+    /// Only replicates the `files_from` paths, relative to the source directory, instead
+    /// of walking it. Their missing parent directories are created, but listed
+    /// directories are not descended into.
+    pub fn files_from(mut self, files_from: Option<Vec<PathBuf>>) -> Self {
+        self.options.files_from = files_from;
+        self
+    }
+
+    /// Asks the questions of [`ConflictPolicy::Prompt`] through `prompter`, instead of
+    /// the terminal by default.
+    pub fn prompter<T: Prompter + 'static>(mut self, prompter: T) -> Self {
+        self.options.prompter = Some(Arc::new(prompter));
+        self
+    }
+
+    /// Stores files compressed in the target directory, keeping an index of the
+    /// original paths. Replicating from such a directory decompresses them back.
+    pub fn compress(mut self, compress: Option<Compression>) -> Self {
+        self.options.compress = compress;
+        self
+    }
+
+    /// Stores the source files into the target directory as a [`ContentStore`], each
+    /// distinct content once, recording a snapshot of the source per run.
+    pub fn store(mut self, flag: bool) -> Self {
+        self.options.store = flag;
+        self
+    }
+
+    /// Draws a progress line on the standard error while going through the source
+    /// files.
+    pub fn progress(mut self, flag: bool) -> Self {
+        self.options.progress = flag;
+        self
+    }
+
+    /// Scans the source directory before replicating it, so that the progress shows the
+    /// percentage done and an ETA. Implies [`Replicator::progress`].
+    pub fn prescan(mut self, flag: bool) -> Self {
+        self.options.prescan = flag;
+        self
+    }
+
+    /// Hashes the upcoming source files of a store run on `count` reader threads and
+    /// `count` hasher threads while the current one is stored, 0 hashing them on the
+    /// copying thread instead. Defaults to the available parallelism.
+    pub fn hash_threads(mut self, count: Option<usize>) -> Self {
+        self.options.hash_threads = count;
+        self
+    }
+
+    /// Hashes every file again when looking for moved files, instead of reusing the
+    /// checksums cached in the target.
+    pub fn ignore_cache(mut self, flag: bool) -> Self {
+        self.options.ignore_cache = flag;
+        self
+    }
+
+    /// Waits up to `wait` for another run replicating into the target to release its
+    /// lock, instead of failing right away with [`SyncError::Locked`].
+    pub fn wait_lock(mut self, wait: Duration) -> Self {
+        self.options.wait_lock = wait;
+        self
+    }
+
+    /// Fails with [`SyncError::InsufficientSpace`] before copying anything when the files
+    /// a run would write don't fit in the free space of the target, as estimated by a
+    /// dry run walking both directories before the run, which is why it's disabled by
+    /// default. Dry runs and store runs, whose deduplication is only known once the
+    /// files are hashed, aren't checked.
+    pub fn check_space(mut self, flag: bool) -> Self {
+        self.options.check_space = flag;
+        self
+    }
+
+    /// Only warns when a safety check such as [`Replicator::check_space`] or
+    /// [`Replicator::max_delete`] fails, replicating anyway.
+    ///
+    /// Without it, runs also refuse to replicate into a target marked with a
+    /// [`PROTECT_FILE_NAME`] file, or whose state manifest records another origin.
+    pub fn force(mut self, flag: bool) -> Self {
+        self.options.force = flag;
+        self
+    }
+
+    /// Writes a [`RunReport`] into the
+    /// [`REPORTS_DIR_NAME`](crate::report::REPORTS_DIR_NAME) directory of the target
+    /// after each run that isn't a dry run.
+    pub fn report(mut self, flag: bool) -> Self {
+        self.options.report = flag;
+        self
+    }
+
+    /// Command line arguments recorded in the reports of [`Replicator::report`].
+    pub fn arguments(mut self, arguments: Vec<String>) -> Self {
+        self.options.arguments = arguments;
+        self
+    }
+
+    /// Returns the count and total size of the source files a run goes through, with a
+    /// metadata-only walk.
+    pub fn scan(&self) -> (u64, u64) {
+        self.source_entries()
+            .filter_map(|result| result.ok())
+            .filter(|entry| entry.is_file())
+            .fold((0, 0), |(file_count, file_size), entry| {
+                (file_count + 1, file_size + entry.metadata().len())
+            })
+    }
+
+    pub fn run(&self) -> Result<SyncStats, SyncError> {
+        self.run_observed(None, None)
+    }
+
+    /// Runs the replication like [`Replicator::run`], calling `on_action` with every
+    /// action as it is performed, e.g. to print them or feed a user interface.
+    pub fn run_itemized<F: FnMut(&PlannedAction)>(
+        &self,
+        mut on_action: F,
+    ) -> Result<SyncStats, SyncError> {
+        self.run_observed(Some(&mut on_action), None)
+    }
+
+    /// Runs the replication like [`Replicator::run`], reporting its progress to the
+    /// `observer` with typed [`SyncEvent`]s, e.g. to build a user interface on top of it.
+    /// Pass `&mut observer` to keep using it after the run.
+    pub fn run_with_observer<O: SyncObserver>(
+        &self,
+        mut observer: O,
+    ) -> Result<SyncStats, SyncError> {
+        self.run_observed(None, Some(&mut observer))
+    }
+
+    fn run_observed<'a>(
+        &self,
+        on_action: Option<&'a mut dyn FnMut(&PlannedAction)>,
+        observer: Option<&'a mut dyn SyncObserver>,
+    ) -> Result<SyncStats, SyncError> {
+        if self.options.snapshot && !self.options.dryrun {
+            let frozen = FrozenSource::create(&self.source)?;
+            return self.frozen(&frozen).run_observed(on_action, observer);
+        }
+        self.replicate(false, on_action, observer)
+            .map(|context| context.stats)
+    }
+
+    /// Returns a replicator reading the source files from the `frozen` snapshot of the
+    /// source, still recorded as the origin of the target.
+    fn frozen(&self, frozen: &FrozenSource) -> Replicator {
+        Replicator {
+            source: frozen.path().to_path_buf(),
+            target: self.target.clone(),
+            options: ReplicatorOptions {
+                snapshot: false,
+                origin: Some(self.source.clone()),
+                ..self.options.clone()
+            },
+        }
+    }
+
+    /// Whether the token of [`Replicator::cancellation`] was cancelled.
+    fn cancelled(&self) -> bool {
+        self.options
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Returns the source directory the target is replicated from, even when read from a
+    /// snapshot.
+    fn origin(&self) -> &Path {
+        self.options.origin.as_deref().unwrap_or(&self.source)
+    }
+
+    /// Returns the actions a run would perform, without performing them. Conflicts
+    /// under [`ConflictPolicy::Prompt`] are planned as overrides instead of asked.
+    pub fn plan(&self) -> Result<Vec<PlannedAction>, SyncError> {
+        let replicator = Replicator {
+            source: self.source.clone(),
+            target: self.target.clone(),
+            options: ReplicatorOptions {
+                dryrun: true,
+                ..self.options.clone()
+            },
+        };
+        replicator
+            .replicate(true, None, None)
+            .map(|context| context.plan.unwrap_or_default())
+    }
+
+    fn replicate<'a>(
+        &self,
+        planning: bool,
+        on_action: Option<&'a mut dyn FnMut(&PlannedAction)>,
+        observer: Option<&'a mut dyn SyncObserver>,
+    ) -> Result<ReplicateContext<'a>, SyncError> {
+        let source = &self.source;
+        let origin = self.origin();
+        let target = &self.target;
+        let ReplicatorOptions {
+            dryrun,
+            ignore_errors,
+            bwlimit,
+            link_dest,
+            delete,
+            delete_to_trash,
+            compress,
+            store,
+            progress,
+            prescan,
+            ..
+        } = &self.options;
+        let (dryrun, ignore_errors) = (*dryrun, *ignore_errors);
+        let delete = *delete || *delete_to_trash;
+        let (started, clock) = (SystemTime::now(), Instant::now());
+        // Fails early instead of replicating nothing from a missing source.
+        let source_metadata = source.metadata().with_path(source)?;
+        // Created before anything else, so that its lock covers the checks of the run.
+        let target_created = source_metadata.is_dir() && !target.exists();
+        if target_created {
+            debug!("Creating target directory {} ...", target.display());
+            if !dryrun {
+                std::fs::DirBuilder::new()
+                    .create(target)
+                    .with_path(target)?;
+
+                std::fs::set_permissions(target, source_metadata.permissions())
+                    .with_path(target)?;
+                if self.options.acls {
+                    copy_acl(source, target)?;
+                }
+            }
+        }
+        // Held until the state of the target is saved, released when dropped.
+        let lock = match !dryrun && target.is_dir() {
+            true => Some(DestinationLock::acquire(target, self.options.wait_lock)?),
+            false => None,
+        };
+        let checked = if planning {
+            Ok(())
+        } else if dryrun {
+            self.check_destination()
+        } else {
+            self.check_destination().and_then(|_| self.preflight())
+        };
+        if let Err(error) = checked {
+            drop(lock);
+            // Leaves no trace of a run refused before replicating anything.
+            if target_created && !dryrun {
+                let _ = std::fs::remove_dir(target);
+            }
+            return Err(error);
+        }
+
+        let source_index = ArchiveIndex::load(source)?;
+        let target_index = match (compress, ArchiveIndex::load(target)?) {
+            (Some(_), _) if source_index.is_some() => {
+                return Err(SyncError::InvalidArgument {
+                    name: "compress".to_string(),
+                    reason: format!("{} is already compressed", source.display()),
+                });
+            }
+            (Some(compress), Some(index)) if index.compression != *compress => {
+                return Err(SyncError::InvalidArgument {
+                    name: "compress".to_string(),
+                    reason: format!(
+                        "{} is compressed with {}",
+                        target.display(),
+                        index.compression
+                    ),
+                });
+            }
+            (Some(compress), None) => Some(ArchiveIndex::new(*compress)),
+            (_, index) => index,
+        };
+        if target_index.is_some() && self.options.reflink == Reflink::Always {
+            return Err(SyncError::InvalidArgument {
+                name: "reflink".to_string(),
+                reason: "can't be combined with a compressed destination".to_string(),
+            });
+        }
+        if target_index.is_some() && link_dest.is_some() {
+            return Err(SyncError::InvalidArgument {
+                name: "link_dest".to_string(),
+                reason: "can't be combined with a compressed destination".to_string(),
+            });
+        }
+        if target_index.is_some() && delete {
+            return Err(SyncError::InvalidArgument {
+                name: "delete".to_string(),
+                reason: "can't be combined with a compressed destination".to_string(),
+            });
+        }
+        if self.options.metadata_only
+            && (source_index.is_some() || target_index.is_some() || *store || delete)
+        {
+            return Err(SyncError::InvalidArgument {
+                name: "metadata_only".to_string(),
+                reason: "can't be combined with --delete, --store or a compressed origin or \
+                    destination"
+                    .to_string(),
+            });
+        }
+        if (source_index.is_some() || target_index.is_some() || *store) && self.options.verify {
+            return Err(SyncError::InvalidArgument {
+                name: "verify".to_string(),
+                reason: "can't be combined with --store or a compressed origin or destination"
+                    .to_string(),
+            });
+        }
+        if (source_index.is_some() || target_index.is_some()) && self.options.acls {
+            return Err(SyncError::InvalidArgument {
+                name: "acls".to_string(),
+                reason: "can't be combined with a compressed origin or destination".to_string(),
+            });
+        }
+        if target_index.is_some() && self.options.invalid_names == InvalidNames::Transcode {
+            return Err(SyncError::InvalidArgument {
+                name: "invalid_names".to_string(),
+                reason: "can't transcode names into a compressed destination".to_string(),
+            });
+        }
+        for (name, map) in [
+            ("usermap", &self.options.usermap),
+            ("groupmap", &self.options.groupmap),
+        ] {
+            if map.is_some() && !self.options.perms && !self.options.metadata_only {
+                return Err(SyncError::InvalidArgument {
+                    name: name.to_string(),
+                    reason: "only applies to the ownership fixed with --perms or --metadata_only"
+                        .to_string(),
+                });
+            }
+        }
+        if *store && (compress.is_some() || link_dest.is_some() || delete) {
+            return Err(SyncError::InvalidArgument {
+                name: "store".to_string(),
+                reason: "can't be combined with --compress, --link_dest or --delete".to_string(),
+            });
+        }
+        let mut state = None;
+        let mut cache = None;
+        let mut moved_candidates: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        let mut previous_file_count = 0;
+        let mut target_names = None;
+        if *store {
+            if !self.options.ignore_cache {
+                cache = Some(ChecksumCache::load(target)?);
+            }
+        } else if source_index.is_none() && target_index.is_none() {
+            let mut previous_state = StateManifest::load(target)?;
+            let names = NameMap::load(target)?;
+            let mut source_normalized = NormalizedNames::new(self.options.normalize);
+            let mut vanished_paths = vec![];
+            for (path, size) in previous_state.iter() {
+                match target.join(path).metadata() {
+                    Ok(metadata) if metadata.is_file() && metadata.len() == size => {
+                        let original_path = names.original(path).unwrap_or(path);
+                        let source_exists = match self.options.normalize {
+                            Normalization::Off => source.join(original_path).exists(),
+                            _ => source_normalized.find(source, original_path).is_some(),
+                        };
+                        if !source_exists {
+                            moved_candidates
+                                .entry(size)
+                                .or_default()
+                                .push(path.to_path_buf());
+                        }
+                    }
+                    _ => vanished_paths.push(path.to_path_buf()),
+                }
+            }
+            for path in vanished_paths {
+                previous_state.remove(path);
+            }
+            previous_state.set_origin(std::fs::canonicalize(origin).with_path(origin)?);
+            previous_file_count = previous_state.iter().count() as u64;
+            state = Some(previous_state);
+            target_names = Some(names);
+            if !self.options.ignore_cache {
+                cache = Some(ChecksumCache::load(target)?);
+            }
+        }
+        let folded_paths = self.checks_case_collisions().then(HashMap::new);
+        let target_fs = match self.options.target_fs {
+            _ if *store => None,
+            TargetFs::Auto => Some(TargetFs::detect(target)),
+            target_fs => Some(target_fs),
+        };
+        let source_names = Some(NameMap::load(source)?).filter(|names| !names.is_empty());
+        let target_normalized = (self.options.normalize != Normalization::Off
+            && target_index.is_none())
+        .then(|| NormalizedNames::new(self.options.normalize));
+        let mut context = ReplicateContext {
+            limiter: bwlimit.map(RateLimiter::new),
+            source_index,
+            target_index,
+            state,
+            moved_candidates,
+            previous_file_count,
+            cache,
+            snapshot: store.then(Snapshot::now),
+            plan: planning.then(Vec::new),
+            on_action,
+            observer,
+            folded_paths,
+            target_fs,
+            target_names,
+            source_names,
+            target_normalized,
+            ..ReplicateContext::default()
+        };
+        observe(&mut context, SyncEvent::ScanStarted { source: origin });
+        let stats = &mut context.stats;
+
+        let mut progress = (!planning && (*progress || *prescan)).then(|| {
+            Progress::new(prescan.then(|| {
+                debug!("Scanning source directory {} ...", source.display());
+                self.scan()
+            }))
+        });
+        let mut paths_iter = self.ordered_entries();
+        let hash_threads = self
+            .options
+            .hash_threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |count| count.get()));
+        let pipeline = (*store && hash_threads > 0).then(|| HashPipeline::new(hash_threads));
+        let lookahead = if pipeline.is_some() { LOOKAHEAD } else { 1 };
+        let mut upcoming = VecDeque::new();
+
+        if target_created {
+            stats.directory_created_count += 1;
+        }
+
+        let mut result = Ok(());
+        loop {
+            // Submits the upcoming files to the pipeline ahead of the one being stored.
+            while upcoming.len() < lookahead
+                && let Some(source_entry) = paths_iter.next()
+            {
+                if let (Some(pipeline), Ok(entry)) = (&pipeline, &source_entry)
+                    && entry.is_file()
+                    && context
+                        .cache
+                        .as_ref()
+                        .is_none_or(|cache| cache.get(entry.path(), entry.metadata()).is_none())
+                {
+                    let pending = pipeline.submit(entry.path());
+                    context.hashes.insert(entry.path().to_path_buf(), pending);
+                }
+                upcoming.push_back(source_entry);
+            }
+            let Some(source_entry) = upcoming.pop_front() else {
+                break;
+            };
+            if signal::interrupted() || self.cancelled() {
+                info!("Interrupted, saving the state of the files replicated so far ...");
+                context.stats.interrupted = true;
+                break;
+            }
+            let relative_path = match &source_entry {
+                Ok(entry) => Some(entry.path()),
+                Err(SyncError::WalkError { path, .. } | SyncError::Unreadable { path, .. }) => {
+                    Some(path.as_path())
+                }
+                Err(_) => None,
+            }
+            .and_then(|path| path.strip_prefix(source).ok())
+            .map(Path::to_path_buf);
+            let replicated = source_entry.and_then(|source_entry| {
+                let replicated = self.replicate_path(&source_entry, &mut context);
+                if let Some(progress) = &mut progress
+                    && source_entry.is_file()
+                {
+                    progress.advance(source_entry.metadata().len());
+                }
+                replicated
+            });
+            if let Err(error) = replicated {
+                // A copy cancelled halfway, its partial file already removed.
+                if matches!(error, SyncError::Interrupted) {
+                    info!("Interrupted, saving the state of the files replicated so far ...");
+                    context.stats.interrupted = true;
+                    break;
+                }
+                observe(
+                    &mut context,
+                    SyncEvent::Error {
+                        path: relative_path.as_deref(),
+                        error: &error,
+                    },
+                );
+                if !ignore_errors
+                    && !matches!(
+                        error,
+                        SyncError::TimedOut { .. } | SyncError::Unreadable { .. }
+                    )
+                {
+                    result = Err(error);
+                    break;
+                }
+                error!("{error}");
+                context.errors.push(error.to_string());
+                context.stats.file_failed_count += 1;
+            }
+        }
+        if let Some(progress) = &mut progress {
+            progress.finish();
+        }
+
+        if delete && result.is_ok() && !context.stats.interrupted {
+            result = self.delete_files(&mut context);
+        }
+
+        if !dryrun && let Some(index) = &context.target_index {
+            index.save(target)?;
+        }
+        if !dryrun
+            && target.is_dir()
+            && let Some(state) = &context.state
+        {
+            state.save(target)?;
+        }
+        if !dryrun
+            && target.is_dir()
+            && let Some(names) = &context.target_names
+        {
+            names.save(target)?;
+        }
+        if !dryrun && target.is_dir() {
+            if let Some(cache) = &context.cache {
+                cache.save(target)?;
+            }
+            // A snapshot missing some files would be restored as if they were deleted.
+            if let Some(snapshot) = &context.snapshot
+                && result.is_ok()
+                && !context.stats.interrupted
+            {
+                ContentStore::new(target).save_snapshot(snapshot)?;
+            }
+        }
+        context.stats.duration = clock.elapsed();
+        context.stats.dryrun = dryrun;
+        if self.options.report && !dryrun && target.is_dir() {
+            let outcome = match &result {
+                Ok(()) if context.stats.interrupted => Outcome::Interrupted,
+                Ok(()) if context.stats.file_failed_count == 0 => Outcome::Succeeded,
+                _ => Outcome::Failed,
+            };
+            let mut report = RunReport {
+                started,
+                duration: context.stats.duration,
+                origin: std::fs::canonicalize(origin).with_path(origin)?,
+                destination: std::fs::canonicalize(target).with_path(target)?,
+                arguments: self.options.arguments.clone(),
+                outcome,
+                failure: result.as_ref().err().map(|error| error.to_string()),
+                errors: context.errors.clone(),
+                stats: context.stats.clone(),
+                changes: std::mem::take(&mut context.changes),
+                ..RunReport::default()
+            };
+            report.save(target)?;
+            debug!("Wrote report {} of the run", report.id);
+        }
+        drop(lock);
+        // Last, since every file written in a directory bumps its time, those of the
+        // target root included.
+        if !dryrun && context.state.is_some() {
+            self.restore_directory_times(&mut context);
+        }
+        if let Some(observer) = &mut context.observer {
+            observer.on_event(&SyncEvent::Finished {
+                stats: &context.stats,
+            });
+        }
+        result?;
+
+        Ok(context)
+    }
+
+    /// Gives the target directories changed by the run the modification time of their
+    /// source directory.
+    fn restore_directory_times(&self, context: &mut ReplicateContext<'_>) {
+        let source = self.source.clone();
+        context.directory_sources.insert(PathBuf::new(), source);
+        context.changed_directories.insert(PathBuf::new());
+        for relative_path in &context.changed_directories {
+            let Some(source_path) = context.directory_sources.get(relative_path) else {
+                continue;
+            };
+            let target_path = self.target.join(relative_path);
+            let result = platform::short_path(source_path)
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .and_then(|modified| {
+                    platform::set_modified(&platform::short_path(&target_path), modified)
+                });
+            if let Err(error) = result {
+                warn!(
+                    "Can't set the modification time of {}: {error}",
+                    target_path.display()
+                );
+            }
+        }
+    }
+
+    /// Refuses a target marked with a [`PROTECT_FILE_NAME`] file, or holding the files of
+    /// another origin according to its state manifest.
+    fn check_destination(&self) -> Result<(), SyncError> {
+        let error = if self.target.join(PROTECT_FILE_NAME).exists() {
+            SyncError::ProtectedDestination {
+                path: self.target.clone(),
+            }
+        } else {
+            let origin = self.origin();
+            let source = std::fs::canonicalize(origin).with_path(origin)?;
+            match StateManifest::load(&self.target)?.origin() {
+                Some(origin) if !platform::same_path(origin, &source) => {
+                    SyncError::ForeignDestination {
+                        path: self.target.clone(),
+                        origin: origin.to_path_buf(),
+                    }
+                }
+                _ => return Ok(()),
+            }
+        };
+        if !self.options.force {
+            return Err(error);
+        }
+        warn!("{error}, replicating anyway");
+        Ok(())
+    }
+
+    /// Runs the safety checks needing a dry run of the replication, if any is enabled.
+    fn preflight(&self) -> Result<(), SyncError> {
+        let ReplicatorOptions {
+            check_space,
+            store,
+            delete,
+            delete_to_trash,
+            max_delete,
+            max_delete_percent,
+            ..
+        } = &self.options;
+        let check_space = *check_space && !*store;
+        let check_deletions =
+            (*delete || *delete_to_trash) && (max_delete.is_some() || max_delete_percent.is_some());
+        // Collisions fail the dry run before any file is copied.
+        let check_case =
+            self.options.case_collisions != CaseCollisions::Rename && self.checks_case_collisions();
+        if !check_space && !check_deletions && !check_case {
+            return Ok(());
+        }
+        let replicator = Replicator {
+            source: self.source.clone(),
+            target: self.target.clone(),
+            options: ReplicatorOptions {
+                dryrun: true,
+                ..self.options.clone()
+            },
+        };
+        let context = replicator.replicate(true, None, None)?;
+        if check_deletions {
+            self.check_deletions(
+                context.stats.file_deleted_count,
+                context.previous_file_count,
+            )?;
+        }
+        if check_space {
+            self.check_space_left(context.stats.total_file_copied_size)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true when the source files colliding by case are looked for, according to
+    /// [`Replicator::case_collisions`] and the file system of the target.
+    fn checks_case_collisions(&self) -> bool {
+        match self.options.case_collisions {
+            _ if self.options.store => false,
+            CaseCollisions::Auto => self
+                .target
+                .ancestors()
+                .find(|ancestor| ancestor.is_dir())
+                .is_some_and(|directory| platform::case_insensitive(directory).unwrap_or(false)),
+            CaseCollisions::Error | CaseCollisions::Rename => true,
+            CaseCollisions::Ignore => false,
+        }
+    }
+
+    /// Compares the `count` files to delete with the limits of [`Replicator::max_delete`]
+    /// and [`Replicator::max_delete_percent`], out of the `total` files replicated by the
+    /// previous run.
+    fn check_deletions(&self, count: u64, total: u64) -> Result<(), SyncError> {
+        let ReplicatorOptions {
+            max_delete,
+            max_delete_percent,
+            ..
+        } = &self.options;
+        let over_count = max_delete.is_some_and(|max_delete| count > max_delete);
+        let over_percent =
+            max_delete_percent.is_some_and(|percent| count * 100 > u64::from(percent) * total);
+        if !over_count && !over_percent {
+            return Ok(());
+        }
+        let error = SyncError::TooManyDeletions { count, total };
+        if !self.options.force {
+            return Err(error);
+        }
+        warn!("{error}, deleting anyway");
+        Ok(())
+    }
+
+    /// Compares the `needed` bytes of the files to copy with the free space of the
+    /// target, or of its nearest existing ancestor.
+    fn check_space_left(&self, needed: u64) -> Result<(), SyncError> {
+        let Some(directory) = self.target.ancestors().find(|ancestor| ancestor.is_dir()) else {
+            return Ok(());
+        };
+        let available = match platform::available_space(directory) {
+            Ok(available) => available,
+            Err(error) => {
+                debug!(
+                    "Can't tell the free space of {}: {error}",
+                    directory.display()
+                );
+                return Ok(());
+            }
+        };
+        debug!(
+            "{} KBs to write, {} KBs available",
+            (needed / 1024) as f64,
+            (available / 1024) as f64
+        );
+        if needed <= available {
+            return Ok(());
+        }
+        let error = SyncError::InsufficientSpace {
+            path: directory.to_path_buf(),
+            needed,
+            available,
+        };
+        if !self.options.force {
+            return Err(error);
+        }
+        warn!("{error}, replicating anyway");
+        Ok(())
+    }
+
+    /// Returns the source entries in the [`Replicator::order`] they are replicated in.
+    fn ordered_entries(&self) -> Box<dyn Iterator<Item = Result<SearchEntry, SyncError>>> {
+        let order = self.options.order;
+        if order == TransferOrder::Walk {
+            return self.source_entries();
+        }
+        let mut entries: Vec<_> = self.source_entries().collect();
+        // Stable, so files of the same rank keep their walk order.
+        entries.sort_by_key(|result| {
+            let Ok(entry) = result else {
+                return (false, 0, None);
+            };
+            let metadata = entry.metadata();
+            let rank = match order {
+                TransferOrder::SmallestFirst => metadata.len(),
+                TransferOrder::LargestFirst => u64::MAX - metadata.len(),
+                _ => 0,
+            };
+            let modified = (order == TransferOrder::NewestFirst)
+                .then(|| metadata.modified().ok().map(std::cmp::Reverse))
+                .flatten();
+            (entry.is_file(), rank, modified)
+        });
+        Box::new(entries.into_iter())
+    }
+
+    /// Returns the source entries a run goes through: the walk of the source directory,
+    /// or the paths of [`Replicator::files_from`] sorted by name, failing for those
+    /// that are missing or outside the source directory.
+    fn source_entries(&self) -> Box<dyn Iterator<Item = Result<SearchEntry, SyncError>>> {
+        let Some(files_from) = &self.options.files_from else {
+            let mut walk = self.searcher().on_error(ErrorPolicy::Collect).into_iter();
+            let mut unreadable = VecDeque::new();
+            let source = self.source.clone();
+            // Reports the directories that couldn't be read as the walk goes.
+            return Box::new(std::iter::from_fn(move || {
+                if unreadable.is_empty() {
+                    let result = walk.next();
+                    unreadable.extend(walk.take_skipped());
+                    if let Some(result) = result {
+                        return Some(result.map_err(|error| SyncError::WalkError {
+                            path: source.clone(),
+                            source: error,
+                        }));
+                    }
+                }
+                let (path, error) = unreadable.pop_front()?;
+                Some(Err(SyncError::Unreadable {
+                    path,
+                    source: error,
+                }))
+            }));
+        };
+        let relative_paths: BTreeSet<PathBuf> = files_from
+            .iter()
+            .map(|path| {
+                path.components()
+                    .filter(|component| *component != Component::CurDir)
+                    .collect()
+            })
+            .collect();
+        let source = self.source.clone();
+        Box::new(relative_paths.into_iter().map(move |relative_path| {
+            let path = source.join(&relative_path);
+            let is_relative = relative_path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+            if !is_relative || relative_path.as_os_str().is_empty() {
+                return Err(SyncError::WalkError {
+                    path,
+                    source: std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "not a relative path inside the origin",
+                    ),
+                });
+            }
+            let depth = relative_path.components().count();
+            SearchEntry::new(path.clone(), depth).map_err(|error| SyncError::WalkError {
+                path,
+                source: error,
+            })
+        }))
+    }
+
+    /// Returns the searcher walking the source files selected by the options.
+    fn searcher(&self) -> FileSearcher {
+        let ReplicatorOptions {
+            hidden,
+            specials,
+            devices,
+            one_file_system,
+            min_size,
+            max_size,
+            newer_than,
+            older_than,
+            ..
+        } = &self.options;
+        let includes = read_patterns(&self.source, ".acsync_includes");
+
+        FileSearcher::new(&self.source)
+            .sort_by(SortOrder::Name)
+            .hidden(*hidden)
+            .specials(*specials || *devices)
+            .same_file_system(*one_file_system)
+            .min_size(*min_size)
+            .max_size(*max_size)
+            .newer_than(*newer_than)
+            .older_than(*older_than)
+            .includes(&includes)
+            .excludes(&self.options.excludes)
+            .excludes_file(Some(".acsync_excludes"))
+    }
+
+    fn transfer_file(
+        &self,
+        source_path: &Path,
+        target_path: &Path,
+        relative_path: &Path,
+        source_size: u64,
+        context: &mut ReplicateContext<'_>,
+    ) -> Result<(), SyncError> {
+        let delay = self.options.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY);
+        let mut recopies = 0;
+        loop {
+            let (result, retried) = with_retries(self.options.retries, delay, || {
+                self.transfer_file_once(
+                    source_path,
+                    target_path,
+                    relative_path,
+                    source_size,
+                    context,
+                )
+            });
+            if retried && result.is_ok() {
+                context.stats.file_retried_count += 1;
+            }
+            if let Err(SyncError::SourceChanged { .. }) = result
+                && self.options.recopies.is_some_and(|max| recopies < max)
+            {
+                recopies += 1;
+                debug!(
+                    "Copying file {} again, changed while being copied ...",
+                    relative_path.display()
+                );
+                continue;
+            }
+            return result;
+        }
+    }
+
+    fn transfer_file_once(
+        &self,
+        source_path: &Path,
+        target_path: &Path,
+        relative_path: &Path,
+        source_size: u64,
+        context: &mut ReplicateContext<'_>,
+    ) -> Result<(), SyncError> {
+        let write_path = if self.options.inplace {
+            target_path.to_path_buf()
+        } else {
+            copy::temporary_path(target_path)
+        };
+        let ReplicatorOptions {
+            inplace,
+            whole_file,
+            reflink,
+            sparse,
+            verify,
+            timeout,
+            ..
+        } = self.options;
+        let source_version = file_version(source_path);
+        let job = CopyJob {
+            source_path: source_path.to_path_buf(),
+            target_path: target_path.to_path_buf(),
+            write_path: write_path.clone(),
+            relative_path: relative_path.to_path_buf(),
+            source_size,
+            decompress: context.source_index.as_ref().map(|index| index.compression),
+            compress: context.target_index.as_ref().map(|index| index.compression),
+            verify,
+            reflink,
+            delta: !whole_file && source_size >= copy::DELTA_MIN_SIZE && target_path.is_file(),
+            inplace,
+            sparse,
+            limiter: context.limiter.take(),
+            cancellation: self.options.cancellation.clone(),
+        };
+        let outcome = match timeout {
+            Some(timeout) => match job.run_within(timeout) {
+                Ok(outcome) => outcome,
+                Err(error) => {
+                    context.limiter = self.options.bwlimit.map(RateLimiter::new);
+                    if !inplace {
+                        let _ = std::fs::remove_file(&write_path);
+                    }
+                    return Err(error);
+                }
+            },
+            None => job.run(),
+        };
+        context.limiter = outcome.limiter;
+        if let Err(error) = outcome.result {
+            if !inplace {
+                let _ = std::fs::remove_file(&write_path);
+            }
+            return Err(error);
+        }
+        let (delta_saved_size, source_hash) = (outcome.delta_saved_size, outcome.source_hash);
+        if file_version(source_path) != source_version {
+            if self.options.recopies.is_some() {
+                if !inplace {
+                    let _ = std::fs::remove_file(&write_path);
+                }
+                return Err(SyncError::SourceChanged {
+                    path: source_path.to_path_buf(),
+                });
+            }
+            warn!(
+                "{}, its copy may be torn",
+                SyncError::SourceChanged {
+                    path: source_path.to_path_buf()
+                }
+            );
+        }
+        // Checked before the rename, so that a mismatch leaves the previous target alone.
+        let sampled = source_hash.is_none()
+            && self.options.verify_sample.is_some_and(|percent| {
+                context.source_index.is_none()
+                    && context.target_index.is_none()
+                    && context.sampler.hash_one(relative_path) % 100 < u64::from(percent)
+            });
+        let expected = match source_hash {
+            Some(source_hash) => Some(source_hash),
+            None if sampled => Some(hash::hash_file(source_path)?),
+            None => None,
+        };
+        if let Some(expected) = expected {
+            if let Err(error) = verify_copy(expected, &write_path) {
+                if !inplace {
+                    let _ = std::fs::remove_file(&write_path);
+                }
+                return Err(error);
+            }
+            if sampled {
+                context.stats.file_sampled_count += 1;
+            }
+        }
+        if !self.options.inplace {
+            std::fs::rename(&write_path, target_path).with_path(target_path)?;
+        }
+        if self.options.acls {
+            copy_acl(source_path, target_path)?;
+        }
+        context.stats.total_delta_saved_size += delta_saved_size;
+        if let Some(index) = &mut context.target_index {
+            index.insert(relative_path, source_size);
+        }
+        Ok(())
+    }
+
+    /// Moves the `target_path` file about to be overridden to its backup location, when
+    /// backups are enabled, returning the backup path.
+    fn backup_file(
+        &self,
+        target_path: &Path,
+        context: &mut ReplicateContext<'_>,
+    ) -> Result<Option<PathBuf>, SyncError> {
+        let ReplicatorOptions {
+            backup,
+            backup_dir,
+            dryrun,
+            ..
+        } = &self.options;
+        let backup_path = match backup_dir {
+            Some(backup_dir) => backup_dir.join(
+                target_path
+                    .strip_prefix(&self.target)
+                    .unwrap_or(target_path),
+            ),
+            None if *backup => backup_path(target_path),
+            None => return Ok(None),
+        };
+        debug!(
+            "Backing up file {} to {} ...",
+            target_path.display(),
+            backup_path.display()
+        );
+        if !*dryrun {
+            if let Some(parent) = backup_path.parent() {
+                std::fs::create_dir_all(parent).with_path(parent)?;
+            }
+            copy::move_file(target_path, &backup_path)?;
+        }
+        context.stats.file_backed_up_count += 1;
+        Ok(Some(backup_path))
+    }
+
+    /// Deletes the files of the previous run left as moved candidates, i.e. not found in
+    /// the source nor matched by a moved file.
+    fn delete_files(&self, context: &mut ReplicateContext<'_>) -> Result<(), SyncError> {
+        let ReplicatorOptions {
+            dryrun,
+            delete_to_trash,
+            trash_dir,
+            ..
+        } = &self.options;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let trash_dir = trash_dir
+            .clone()
+            .unwrap_or_else(|| self.target.join(TRASH_DIR_NAME))
+            .join(timestamp.to_string());
+
+        let mut deleted_paths: Vec<(u64, PathBuf)> = context
+            .moved_candidates
+            .drain()
+            .flat_map(|(size, paths)| paths.into_iter().map(move |path| (size, path)))
+            .collect();
+        deleted_paths.retain(|(_, path)| self.is_approved(path));
+        deleted_paths.sort_by(|(_, path), (_, other_path)| path.cmp(other_path));
+        // Real runs are checked before replicating anything by the preflight.
+        if *dryrun && context.plan.is_none() {
+            self.check_deletions(deleted_paths.len() as u64, context.previous_file_count)?;
+        }
+        for (size, relative_path) in deleted_paths {
+            record(context, ActionKind::Delete, &relative_path);
+            let target_path = self.target.join(&relative_path);
+            if *delete_to_trash {
+                let trash_path = trash_dir.join(&relative_path);
+                debug!(
+                    "Moving deleted file {} to {} ...",
+                    target_path.display(),
+                    trash_path.display()
+                );
+                if !*dryrun {
+                    if let Some(parent) = trash_path.parent() {
+                        std::fs::create_dir_all(parent).with_path(parent)?;
+                    }
+                    copy::move_file(&target_path, &trash_path)?;
+                }
+            } else {
+                debug!("Deleting file {} ...", target_path.display());
+                if !*dryrun {
+                    std::fs::remove_file(&target_path).with_path(&target_path)?;
+                }
+            }
+            let trash = delete_to_trash.then(|| trash_dir.join(&relative_path));
+            if let Some(state) = &mut context.state {
+                state.remove(&relative_path);
+            }
+            if let Some(names) = &mut context.target_names {
+                names.remove(&relative_path);
+            }
+            track(
+                context,
+                Change::Deleted {
+                    path: relative_path,
+                    trash,
+                },
+            );
+            context.stats.file_deleted_count += 1;
+            context.stats.total_file_deleted_size += size;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the actions on `relative_path` can be performed.
+    fn is_approved(&self, relative_path: &Path) -> bool {
+        self.options
+            .approved
+            .as_ref()
+            .is_none_or(|approved| approved.contains(relative_path))
+    }
+
+    /// Returns the relative path of a file of the previous run with the same content as
+    /// `source_path`, which was moved in the source since then.
+    fn find_moved_path(
+        &self,
+        source_path: &Path,
+        source_size: u64,
+        context: &mut ReplicateContext<'_>,
+    ) -> Result<Option<PathBuf>, SyncError> {
+        let Some(candidates) = context.moved_candidates.get_mut(&source_size) else {
+            return Ok(None);
+        };
+        let source_hash = cache::hash_file(&mut context.cache, source_path)?;
+        for (position, candidate) in candidates.iter().enumerate() {
+            if cache::hash_file(&mut context.cache, self.target.join(candidate))? == source_hash {
+                return Ok(Some(candidates.swap_remove(position)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn replicate_path(
+        &self,
+        source_entry: &SearchEntry,
+        context: &mut ReplicateContext<'_>,
+    ) -> Result<(), SyncError> {
+        let (source_path, source_metadata) = (source_entry.path(), source_entry.metadata());
+        let source = &self.source;
+        let target = &self.target;
+        let ReplicatorOptions {
+            on_conflict,
+            dryrun,
+            link_dest,
+            ..
+        } = &self.options;
+        let (on_conflict, dryrun) = (*on_conflict, *dryrun);
+
+        let relative_path =
+            source_path
+                .strip_prefix(source)
+                .map_err(|error| SyncError::WalkError {
+                    path: source_path.to_path_buf(),
+                    source: std::io::Error::other(error),
+                })?;
+        let mut relative_path = relative_path.to_path_buf();
+        let mut source_size = source_metadata.len();
+        if let Some(index) = &context.source_index
+            && source_metadata.is_file()
+        {
+            let Some((original_path, original_size)) = index.original(&relative_path) else {
+                return Ok(());
+            };
+            relative_path = original_path.to_path_buf();
+            source_size = original_size;
+        }
+        if let Some(original_path) = context
+            .source_names
+            .as_ref()
+            .and_then(|names| names.original(&relative_path))
+        {
+            relative_path = original_path.to_path_buf();
+        }
+        let relative_path = relative_path.as_path();
+        if is_metadata_path(relative_path)
+            || context.snapshot.is_some() && !self.is_approved(relative_path)
+        {
+            return Ok(());
+        }
+        if context.snapshot.is_some() {
+            return self.store_path(source_entry, relative_path, context);
+        }
+        let normalized_path = (self.options.normalize != Normalization::Off)
+            .then(|| self.options.normalize.normalize(relative_path));
+        let relative_path = normalized_path.as_deref().unwrap_or(relative_path);
+        let renamed_path = match &mut context.folded_paths {
+            Some(folded_paths) if !source_metadata.is_dir() => {
+                let folded_path = relative_path.to_string_lossy().to_lowercase();
+                match folded_paths.get_mut(&folded_path) {
+                    Some((first_path, count)) => {
+                        if self.options.case_collisions != CaseCollisions::Rename {
+                            return Err(SyncError::CaseCollision {
+                                path: source_path.to_path_buf(),
+                                other: source.join(first_path),
+                            });
+                        }
+                        *count += 1;
+                        let renamed_path = case_collision_path(relative_path, *count);
+                        // Planned actions already show the renamed path.
+                        if context.plan.is_none() {
+                            warn!(
+                                "{} collides by case with {}, replicating it as {}",
+                                relative_path.display(),
+                                first_path.display(),
+                                renamed_path.display()
+                            );
+                        }
+                        Some(renamed_path)
+                    }
+                    None => {
+                        folded_paths.insert(folded_path, (relative_path.to_path_buf(), 1));
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let relative_path = renamed_path.as_deref().unwrap_or(relative_path);
+        let invalid_name = context.target_fs.and_then(|target_fs| {
+            let reason = target_fs.check(relative_path)?;
+            Some((target_fs, reason))
+        });
+        let invalid_name_error = |file_system, reason| SyncError::InvalidName {
+            path: source_path.to_path_buf(),
+            file_system,
+            reason,
+        };
+        let transcoded_path = match invalid_name {
+            Some((target_fs, reason)) if self.options.invalid_names == InvalidNames::Error => {
+                return Err(invalid_name_error(target_fs, reason));
+            }
+            Some((target_fs, reason)) if self.options.invalid_names == InvalidNames::Skip => {
+                if context.plan.is_none() {
+                    warn!(
+                        "Skipping {}, which can't be named as is on a {target_fs} destination: {reason}",
+                        relative_path.display()
+                    );
+                }
+                context.stats.path_skipped_count += 1;
+                return Ok(());
+            }
+            Some((target_fs, _)) => {
+                let transcoded_path = target_fs.transcode(relative_path);
+                // Paths too long stay too long once transcoded.
+                if let Some(reason) = target_fs.check(&transcoded_path) {
+                    return Err(invalid_name_error(target_fs, reason));
+                }
+                debug!(
+                    "Transcoding {} to {} ...",
+                    relative_path.display(),
+                    transcoded_path.display()
+                );
+                if let Some(names) = &mut context.target_names {
+                    names.insert(&transcoded_path, relative_path);
+                }
+                context.stats.path_transcoded_count += 1;
+                Some(transcoded_path)
+            }
+            None => None,
+        };
+        let relative_path = transcoded_path.as_deref().unwrap_or(relative_path);
+        // A target file named in another normalization form is updated in place.
+        let existing_path = context
+            .target_normalized
+            .as_mut()
+            .and_then(|names| names.find(target, relative_path))
+            .filter(|existing_path| existing_path != relative_path);
+        let relative_path = existing_path.as_deref().unwrap_or(relative_path);
+        // Actions are approved by the path they have in the target.
+        if !self.is_approved(relative_path) {
+            return Ok(());
+        }
+        let target_path = match &context.target_index {
+            Some(_) if platform::same_path(relative_path, Path::new(INDEX_FILE_NAME)) => {
+                return Ok(());
+            }
+            Some(index) if source_metadata.is_file() => {
+                target.join(index.stored_path(relative_path))
+            }
+            _ => target.join(relative_path),
+        };
+        let target_existed = platform::short_path(&target_path)
+            .symlink_metadata()
+            .is_ok();
+        match self.options.existing_policy {
+            ExistingPolicy::Existing if !target_existed => return Ok(()),
+            ExistingPolicy::IgnoreExisting if target_existed && !source_metadata.is_dir() => {
+                return Ok(());
+            }
+            _ => {}
+        }
+        if self.options.metadata_only {
+            if target_existed {
+                let target_path = platform::short_path(&target_path);
+                self.fix_metadata(source_entry, &target_path, relative_path, context)?;
+            }
+            return Ok(());
+        }
+
+        // Several levels are missing when the path comes from `files_from`, created
+        // from the outermost one.
+        let mut missing_parents = vec![];
+        let mut check_parent_directory = target_path.as_path();
+        while let Some(parent) = check_parent_directory.parent()
+            && !platform::short_path(parent).exists()
+        {
+            check_parent_directory = parent;
+            missing_parents.push(parent);
+        }
+        for parent in missing_parents.into_iter().rev() {
+            let Ok(check_relative_path_directory) = parent.strip_prefix(target) else {
+                continue;
+            };
+            // The source directory at the same depth, named otherwise when transcoded.
+            let Some(check_source_path_directory) = target_path
+                .strip_prefix(parent)
+                .ok()
+                .and_then(|below| source_path.ancestors().nth(below.components().count()))
+            else {
+                continue;
+            };
+            if let Ok(directory_metadata) =
+                platform::short_path(check_source_path_directory).metadata()
+                && directory_metadata.is_dir()
+            {
+                debug!("Creating directory {} ...", parent.display());
+                if !dryrun {
+                    let short_parent = platform::short_path(parent);
+                    std::fs::DirBuilder::new()
+                        .create(&short_parent)
+                        .with_path(parent)?;
+
+                    std::fs::set_permissions(&short_parent, directory_metadata.permissions())
+                        .with_path(parent)?;
+                    if self.options.acls {
+                        copy_acl(check_source_path_directory, &short_parent)?;
+                    }
+                }
+                track(
+                    context,
+                    Change::Created(check_relative_path_directory.to_path_buf()),
+                );
+                context.stats.directory_created_count += 1;
+            }
+        }
+
+        if source_metadata.is_dir() && context.state.is_some() {
+            context
+                .directory_sources
+                .insert(relative_path.to_path_buf(), source_path.to_path_buf());
+        }
+        // Paths deeper than the system calls allow are reached from an open ancestor
+        // directory from here on, the parents of the target existing by now.
+        let (short_source_path, short_target_path) = (
+            platform::short_path(source_path),
+            platform::short_path(&target_path),
+        );
+        let (source_path, target_path) = (&*short_source_path, short_target_path.to_path_buf());
+
+        let source_file_type = source_metadata.file_type();
+        if !source_file_type.is_file() && !source_file_type.is_dir() {
+            let is_device = platform::is_device(&source_file_type);
+            if (is_device && self.options.devices || !is_device && self.options.specials)
+                && target_path.symlink_metadata().is_err()
+            {
+                debug!("Creating special file {} ...", relative_path.display());
+                record(context, ActionKind::Special, relative_path);
+                if !dryrun {
+                    copy::create_special(source_path, &target_path)?;
+                }
+                track(context, Change::Created(relative_path.to_path_buf()));
+                context.stats.file_special_count += 1;
+            }
+        } else if target_path.exists() && target_path.is_file() && source_metadata.is_file() {
+            let source_modified_date = source_metadata.modified().with_path(source_path)?;
+            let target_metadata = target_path.metadata().with_path(&target_path)?;
+            let target_modified_date = target_metadata.modified().with_path(&target_path)?;
+            let target_size = match &context.target_index {
+                Some(index) => index.size(relative_path).unwrap_or(target_metadata.len()),
+                None => target_metadata.len(),
+            };
+            let is_conflict = match self.options.change_detection {
+                ChangeDetection::SizeAndTime => {
+                    !same_time(
+                        source_modified_date,
+                        target_modified_date,
+                        self.options.modify_window,
+                    ) && source_size != target_size
+                }
+                ChangeDetection::SizeOnly => source_size != target_size,
+                ChangeDetection::IgnoreTimes => true,
+            };
+            if is_conflict {
+                context.stats.file_conflict_count += 1;
+                let source_is_newer = source_modified_date > target_modified_date;
+                let conflict_description = if source_is_newer {
+                    context.stats.file_dated_count += 1;
+                    context.stats.total_file_dated_size += target_size;
+                    format!(
+                        "File {} is dated in {:?} ({} KBs != {} KBs)",
+                        target_path.display(),
+                        source_modified_date
+                            .duration_since(target_modified_date)
+                            .unwrap_or_default(),
+                        (source_size / 1024) as f64,
+                        (target_size / 1024) as f64
+                    )
+                } else {
+                    format!(
+                        "File {} is newer than origin in {:?} ({} KBs != {} KBs)",
+                        target_path.display(),
+                        target_modified_date
+                            .duration_since(source_modified_date)
+                            .unwrap_or_default(),
+                        (source_size / 1024) as f64,
+                        (target_size / 1024) as f64
+                    )
+                };
+                debug!("{conflict_description}");
+                let to_override = match on_conflict {
+                    ConflictPolicy::Prompt
+                        if context.plan.is_some() || self.options.approved.is_some() =>
+                    {
+                        true
+                    }
+                    ConflictPolicy::Prompt => {
+                        let mut question = String::new();
+                        if !log::enabled(Level::Debug) {
+                            question += &format!("{conflict_description}\n");
+                        }
+                        question += "Do you want to override the file content?";
+                        match &self.options.prompter {
+                            Some(prompter) => prompter.confirm(&question),
+                            None => TerminalPrompter.confirm(&question),
+                        }
+                        .with_path(&target_path)?
+                    }
+                    ConflictPolicy::Newer => source_is_newer,
+                    ConflictPolicy::Older => !source_is_newer,
+                    ConflictPolicy::Larger => source_size > target_size,
+                    ConflictPolicy::Skip => false,
+                    ConflictPolicy::Rename => true,
+                };
+                if on_conflict == ConflictPolicy::Rename {
+                    let timestamp = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+                    let renamed_path = conflict_path(&target_path, timestamp);
+                    debug!(
+                        "Renaming file {} to {} ...",
+                        target_path.display(),
+                        renamed_path.display()
+                    );
+                    if !dryrun {
+                        std::fs::rename(&target_path, &renamed_path).with_path(&target_path)?;
+                    }
+                    if let Ok(renamed_relative_path) = renamed_path.strip_prefix(target) {
+                        track(
+                            context,
+                            Change::Moved {
+                                from: relative_path.to_path_buf(),
+                                to: renamed_relative_path.to_path_buf(),
+                            },
+                        );
+                    }
+                    context.stats.file_renamed_count += 1;
+                }
+                let mut backup = None;
+                if to_override && on_conflict != ConflictPolicy::Rename {
+                    backup = self.backup_file(&target_path, context)?;
+                }
+                if to_override {
+                    let kind = if on_conflict == ConflictPolicy::Rename {
+                        ActionKind::Rename
+                    } else {
+                        ActionKind::Override
+                    };
+                    record(context, kind, relative_path);
+                    let change = if on_conflict == ConflictPolicy::Rename {
+                        Change::Created(relative_path.to_path_buf())
+                    } else {
+                        Change::Overridden {
+                            path: relative_path.to_path_buf(),
+                            backup,
+                        }
+                    };
+                    track(context, change);
+                    debug!(
+                        "Copying file {} ({} KBs)...",
+                        relative_path.display(),
+                        (source_size / 1024) as f64
+                    );
+                    if !dryrun {
+                        self.transfer_file(
+                            source_path,
+                            &target_path,
+                            relative_path,
+                            source_size,
+                            context,
+                        )?;
+                    }
+                    context.stats.file_overrided_count += 1;
+                    context.stats.total_file_overrided_size += source_size;
+                    observe(
+                        context,
+                        SyncEvent::FileCopied {
+                            path: relative_path,
+                            bytes: source_size,
+                        },
+                    );
+                }
+            }
+        } else if source_metadata.is_file()
+            && !target_path.exists()
+            && let Some(moved_path) = self.find_moved_path(source_path, source_size, context)?
+        {
+            let moved_target_path = target.join(&moved_path);
+            debug!(
+                "Moving file {} to {} ...",
+                moved_target_path.display(),
+                target_path.display()
+            );
+            if !dryrun {
+                std::fs::rename(&*platform::short_path(&moved_target_path), &target_path)
+                    .with_path(&moved_target_path)?;
+            }
+            if let Some(state) = &mut context.state {
+                state.remove(&moved_path);
+            }
+            if let Some(names) = &mut context.target_names {
+                names.remove(&moved_path);
+            }
+            record(context, ActionKind::Move, relative_path);
+            track(
+                context,
+                Change::Moved {
+                    from: moved_path,
+                    to: relative_path.to_path_buf(),
+                },
+            );
+            context.stats.file_moved_count += 1;
+            context.stats.total_file_moved_size += source_size;
+        } else if source_metadata.is_file()
+            && let Some(link_path) = link_dest.as_ref().map(|path| path.join(relative_path))
+            && let Ok(link_metadata) = link_path.metadata()
+            && link_metadata.is_file()
+            && link_metadata.len() == source_size
+            && link_metadata.modified().with_path(&link_path)? + self.options.modify_window
+                >= source_path
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .with_path(source_path)?
+        {
+            debug!(
+                "Linking file {} ({} KBs)...",
+                relative_path.display(),
+                (source_size / 1024) as f64
+            );
+            if !dryrun {
+                std::fs::hard_link(&link_path, &target_path).with_path(&target_path)?;
+            }
+            record(context, ActionKind::Link, relative_path);
+            track(context, Change::Created(relative_path.to_path_buf()));
+            context.stats.file_linked_count += 1;
+            context.stats.total_file_linked_size += source_size;
+        } else if source_metadata.is_file() {
+            debug!(
+                "Copying file {} ({} KBs)...",
+                relative_path.display(),
+                (source_size / 1024) as f64
+            );
+            if !dryrun {
+                self.transfer_file(
+                    source_path,
+                    &target_path,
+                    relative_path,
+                    source_size,
+                    context,
+                )?;
+            }
+            record(context, ActionKind::Copy, relative_path);
+            track(context, Change::Created(relative_path.to_path_buf()));
+            context.stats.file_copied_count += 1;
+            context.stats.total_file_copied_size += source_size;
+            observe(
+                context,
+                SyncEvent::FileCopied {
+                    path: relative_path,
+                    bytes: source_size,
+                },
+            );
+        }
+        if self.options.perms
+            && target_existed
+            && context.source_index.is_none()
+            && context.target_index.is_none()
+        {
+            self.fix_metadata(source_entry, &target_path, relative_path, context)?;
+        }
+        if source_metadata.is_file() {
+            if let Some(state) = &mut context.state {
+                state.insert(relative_path, source_size);
+            }
+            context.stats.file_count += 1;
+            context.stats.total_file_size += source_size;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the source file to the snapshot of the store, storing its content unless an
+    /// identical one already is.
+    fn store_path(
+        &self,
+        source_entry: &SearchEntry,
+        relative_path: &Path,
+        context: &mut ReplicateContext<'_>,
+    ) -> Result<(), SyncError> {
+        if !source_entry.is_file() {
+            return Ok(());
+        }
+        let (source_path, source_metadata) = (source_entry.path(), source_entry.metadata());
+        let source_size = source_metadata.len();
+        let store = ContentStore::new(&self.target);
+        let hash = match context.hashes.remove(source_path) {
+            Some(pending) => {
+                let hash = pending.wait()?;
+                if let Some(cache) = &mut context.cache {
+                    cache.insert(source_path, source_metadata, &hash)?;
+                }
+                hash
+            }
+            None => cache::hash_file(&mut context.cache, source_path)?,
+        };
+        if store.contains(&hash) {
+            record(context, ActionKind::Link, relative_path);
+            context.stats.file_deduplicated_count += 1;
+        } else {
+            debug!(
+                "Storing file {} ({} KBs)...",
+                relative_path.display(),
+                (source_size / 1024) as f64
+            );
+            record(context, ActionKind::Copy, relative_path);
+            if !self.options.dryrun {
+                store.insert(source_path, &hash, context.limiter.as_mut())?;
+            }
+            context.stats.file_copied_count += 1;
+            context.stats.total_file_copied_size += source_size;
+            observe(
+                context,
+                SyncEvent::FileCopied {
+                    path: relative_path,
+                    bytes: source_size,
+                },
+            );
+        }
+        let modified = source_metadata
+            .modified()
+            .with_path(source_path)?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if let Some(snapshot) = &mut context.snapshot {
+            snapshot.entries.push(SnapshotEntry {
+                path: relative_path.to_path_buf(),
+                hash,
+                size: source_size,
+                mode: platform::mode(source_metadata),
+                modified: modified.as_nanos() as u64,
+            });
+        }
+        context.stats.file_count += 1;
+        context.stats.total_file_size += source_size;
+        Ok(())
+    }
+
+    /// Fixes the symlink target, permissions, ownership and ACL of `target_path` that
+    /// differ from those of the source entry, and its modification time with
+    /// [`Replicator::metadata_only`].
+    fn fix_metadata(
+        &self,
+        source_entry: &SearchEntry,
+        target_path: &Path,
+        relative_path: &Path,
+        context: &mut ReplicateContext<'_>,
+    ) -> Result<(), SyncError> {
+        let short_source_path = platform::short_path(source_entry.path());
+        let source_path: &Path = &short_source_path;
+        let dryrun = self.options.dryrun;
+        let target_link_metadata = target_path.symlink_metadata().with_path(target_path)?;
+        if target_link_metadata.is_symlink() && platform::is_link(source_path) {
+            let source_link = std::fs::read_link(source_path).with_path(source_path)?;
+            let target_link = std::fs::read_link(target_path).with_path(target_path)?;
+            if source_link != target_link {
+                debug!(
+                    "Pointing symlink {} to {} ...",
+                    relative_path.display(),
+                    source_link.display()
+                );
+                record(context, ActionKind::Metadata, relative_path);
+                if !dryrun {
+                    std::fs::remove_file(target_path).with_path(target_path)?;
+                    platform::symlink(&source_link, target_path).with_path(target_path)?;
+                }
+                // Replacing the link bumped the time of its directory.
+                if let Some(parent) = relative_path.parent() {
+                    context.changed_directories.insert(parent.to_path_buf());
+                }
+                context.stats.file_metadata_fixed_count += 1;
+            }
+            return Ok(());
+        }
+
+        let source_metadata = source_entry.metadata();
+        let target_metadata = target_path.metadata().with_path(target_path)?;
+        let mode_drifted = platform::mode(source_metadata) != platform::mode(&target_metadata);
+        let translate = |map: &Option<IdMap>, id| map.as_ref().map_or(id, |map| map.map(id));
+        let source_owner = platform::owner(source_metadata).map(|(uid, gid)| {
+            (
+                translate(&self.options.usermap, uid),
+                translate(&self.options.groupmap, gid),
+            )
+        });
+        let owner_drifted = source_owner != platform::owner(&target_metadata);
+        let source_modified = source_metadata.modified().with_path(source_path)?;
+        // The time of the root, bumped by the lock of the run, is restored once it ends.
+        let time_drifted = self.options.metadata_only
+            && !relative_path.as_os_str().is_empty()
+            && !same_time(
+                source_modified,
+                target_metadata.modified().with_path(target_path)?,
+                self.options.modify_window,
+            );
+        let source_acl = match self.options.acls {
+            true => Some(platform::acl(source_path).with_path(source_path)?),
+            false => None,
+        };
+        let acl_drifted = match &source_acl {
+            Some(source_acl) => *source_acl != platform::acl(target_path).with_path(target_path)?,
+            None => false,
+        };
+        if !mode_drifted && !owner_drifted && !acl_drifted && !time_drifted {
+            return Ok(());
+        }
+        debug!("Fixing metadata of {} ...", relative_path.display());
+        record(context, ActionKind::Metadata, relative_path);
+        if !dryrun {
+            if owner_drifted && let Some(source_owner) = source_owner {
+                platform::set_owner(target_path, source_owner).with_path(target_path)?;
+            }
+            if mode_drifted {
+                std::fs::set_permissions(target_path, source_metadata.permissions())
+                    .with_path(target_path)?;
+            }
+            // Last, since changing the permission bits changes the mask of the ACL.
+            if acl_drifted && let Some(source_acl) = &source_acl {
+                platform::set_acl(target_path, source_acl).with_path(target_path)?;
+            }
+            if time_drifted {
+                platform::set_modified(target_path, source_modified).with_path(target_path)?;
+            }
+        }
+        context.stats.file_metadata_fixed_count += 1;
+        Ok(())
+    }
+}
+
+/// Gives `target_path` the ACL of `source_path`, replacing the one it may have inherited.
+fn copy_acl(source_path: &Path, target_path: &Path) -> Result<(), SyncError> {
+    let source_acl = platform::acl(source_path).with_path(source_path)?;
+    platform::set_acl(target_path, &source_acl).with_path(target_path)
+}
+
+/// Returns the size and modification time of the `path` file, changed by any write.
+fn file_version(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = path.metadata().ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Data copy of [`Replicator::transfer_file`], owning what it needs to run on a thread
+/// of its own under [`Replicator::timeout`].
+struct CopyJob {
+    source_path: PathBuf,
+    target_path: PathBuf,
+    /// Either the target path or its temporary path, renamed to it once written.
+    write_path: PathBuf,
+    relative_path: PathBuf,
+    source_size: u64,
+    /// Compression of the origin when decompressing from it.
+    decompress: Option<Compression>,
+    /// Compression of the destination when compressing into it.
+    compress: Option<Compression>,
+    verify: bool,
+    reflink: Reflink,
+    /// Whether only the changed blocks of the existing target may be written.
+    delta: bool,
+    inplace: bool,
+    sparse: bool,
+    limiter: Option<RateLimiter>,
+    /// Token checked between the chunks of the copy.
+    cancellation: Option<CancellationToken>,
+}
+
+/// Result of a [`CopyJob`], handing its limiter back.
+struct CopyOutcome {
+    /// Bytes written.
+    result: Result<u64, SyncError>,
+    delta_saved_size: u64,
+    /// Checksum of the data read from the source, when verified.
+    source_hash: Option<String>,
+    limiter: Option<RateLimiter>,
+}
+
+impl CopyJob {
+    /// Runs the copy on a thread of its own, failing with [`SyncError::TimedOut`] if it
+    /// didn't complete within `timeout`. The thread is then cancelled and left behind,
+    /// as a read hung on a stale network file system can't be interrupted, but it stops
+    /// before writing anything once that read returns.
+    fn run_within(mut self, timeout: Duration) -> Result<CopyOutcome, SyncError> {
+        let source_path = self.source_path.clone();
+        let cancellation = self
+            .cancellation
+            .as_ref()
+            .map_or_else(CancellationToken::new, CancellationToken::child);
+        self.cancellation = Some(cancellation.clone());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(self.run());
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(outcome) => Ok(outcome),
+            Err(RecvTimeoutError::Timeout) => {
+                cancellation.cancel();
+                Err(SyncError::TimedOut {
+                    path: source_path,
+                    timeout,
+                })
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(SyncError::Io {
+                path: source_path,
+                source: std::io::Error::other("the copy thread panicked"),
+            }),
+        }
+    }
+
+    fn run(self) -> CopyOutcome {
+        let CopyJob {
+            source_path,
+            target_path,
+            write_path,
+            relative_path,
+            source_size,
+            decompress,
+            compress,
+            verify,
+            reflink,
+            delta,
+            inplace,
+            sparse,
+            mut limiter,
+            cancellation,
+        } = self;
+        let mut delta_saved_size = 0;
+        let mut source_hash = None;
+        let result = copy::with_cancellation(cancellation, || {
+            if let Some(compression) = decompress {
+                archive::decompress_file(&source_path, &write_path, compression, limiter.as_mut())
+            } else if let Some(compression) = compress {
+                archive::compress_file(&source_path, &write_path, compression, limiter.as_mut())
+            } else if verify {
+                copy::copy_file_hashed(&source_path, &write_path, limiter.as_mut()).map(
+                    |(copied, hash)| {
+                        source_hash = Some(hash);
+                        copied
+                    },
+                )
+            } else if reflink == Reflink::Always {
+                copy::reflink_file(&source_path, &write_path).map(|_| source_size)
+            } else if reflink == Reflink::Auto
+            // Cloning in place would truncate the target before comparing its blocks.
+            && !(delta && inplace)
+            && copy::reflink_file(&source_path, &write_path).is_ok()
+            {
+                Ok(source_size)
+            } else if delta
+                && (inplace
+                    || reflink != Reflink::Never
+                        && copy::reflink_file(&target_path, &write_path).is_ok())
+            {
+                debug!(
+                    "Patching the changed blocks of {} ...",
+                    relative_path.display()
+                );
+                copy::patch_file(&source_path, &write_path, limiter.as_mut()).inspect(|written| {
+                    delta_saved_size = source_size.saturating_sub(*written);
+                })
+            } else if sparse {
+                copy::copy_file_sparse(&source_path, &write_path, limiter.as_mut())
+            } else {
+                copy::copy_file(&source_path, &write_path, limiter.as_mut())
+            }
+        });
+        CopyOutcome {
+            result,
+            delta_saved_size,
+            source_hash,
+            limiter,
+        }
+    }
+}
+
+/// Compares the checksum of the `written_path` copy against the `expected` one of its
+/// source.
+fn verify_copy(expected: String, written_path: &Path) -> Result<(), SyncError> {
+    let actual = hash::hash_file(written_path)?;
+    if actual != expected {
+        return Err(SyncError::ChecksumMismatch {
+            path: written_path.to_path_buf(),
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Runs `operation` until it succeeds or fails with an error other than a checksum
+/// mismatch or an I/O error that may be transient, i.e. `EIO`, a timeout or an
+/// interruption, retrying it up to `retries` times, waiting `delay` before the first
+/// retry and twice as long before every other one, unless interrupted. Returns its last
+/// result along with whether it was retried.
+fn with_retries<T>(
+    retries: u32,
+    mut delay: Duration,
+    mut operation: impl FnMut() -> Result<T, SyncError>,
+) -> (Result<T, SyncError>, bool) {
+    let mut attempt = 0;
+    loop {
+        let result = operation();
+        let transient = match &result {
+            Err(SyncError::Io { source, .. }) => {
+                matches!(
+                    source.kind(),
+                    std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted
+                ) || source.raw_os_error() == Some(EIO)
+            }
+            Err(SyncError::ChecksumMismatch { .. }) => true,
+            _ => false,
+        };
+        if let Err(error) = &result
+            && transient
+            && attempt < retries
+        {
+            attempt += 1;
+            warn!("{error}, retrying in {delay:?} ({attempt}/{retries})");
+            if signal::sleep(delay) {
+                delay = delay.saturating_mul(2);
+                continue;
+            }
+        }
+        return (result, attempt > 0);
+    }
+}
+
+/// Tells the observer of the run about the `event`, if any.
+fn observe(context: &mut ReplicateContext<'_>, event: SyncEvent<'_>) {
+    if let Some(observer) = &mut context.observer {
+        observer.on_event(&event);
+    }
+}
+
+/// Records the `kind` action on `relative_path` when planning.
+fn record(context: &mut ReplicateContext<'_>, kind: ActionKind, relative_path: &Path) {
+    if context.plan.is_none() && context.on_action.is_none() {
+        return;
+    }
+    let action = PlannedAction {
+        kind,
+        path: relative_path.to_path_buf(),
+    };
+    if let Some(on_action) = &mut context.on_action {
+        on_action(&action);
+    }
+    if let Some(plan) = &mut context.plan {
+        plan.push(action);
+    }
+}
+
+/// Records a change made to the target for its report, unless the target has no state
+/// manifest, i.e. is compressed or a store, whose changes can't be undone.
+fn track(context: &mut ReplicateContext<'_>, change: Change) {
+    if context.state.is_some() {
+        let paths = match &change {
+            Change::Created(path) => {
+                context.changed_directories.insert(path.clone());
+                vec![path]
+            }
+            Change::Overridden { path, .. } | Change::Deleted { path, .. } => vec![path],
+            Change::Moved { from, to } => vec![from, to],
+        };
+        let parents = paths.into_iter().filter_map(|path| path.parent());
+        context
+            .changed_directories
+            .extend(parents.map(Path::to_path_buf));
+        context.changes.push(change);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::{prompt::MockPrompter, verify::Verifier};
+
+    #[test]
+    fn it_parses_conflict_policies() {
+        assert_eq!("newer".parse(), Ok(ConflictPolicy::Newer));
+        assert_eq!("Rename".parse(), Ok(ConflictPolicy::Rename));
+        assert!("latest".parse::<ConflictPolicy>().is_err());
+    }
+
+    #[test]
+    fn it_moves_files_renamed_in_the_source() {
+        let root = testing::test_dir("it_moves_files_renamed_in_the_source");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+        std::fs::write(source.join("other.txt"), "other").unwrap();
+        Replicator::new(&source, &target).run().unwrap();
+
+        std::fs::rename(source.join("notes.txt"), source.join("docs/notes.txt")).unwrap();
+        let stats = Replicator::new(&source, &target).run().unwrap();
+        assert_eq!(stats.file_moved_count, 1);
+        assert_eq!(stats.file_copied_count, 0);
+        assert!(!target.join("notes.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(target.join("docs/notes.txt")).unwrap(),
+            "notes"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_hard_links_unchanged_files_to_the_link_dest() {
+        use std::os::unix::fs::MetadataExt;
+        let root = testing::test_dir("it_hard_links_unchanged_files_to_the_link_dest");
+        let source = root.join("source");
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::write(source.join("docs/unchanged.txt"), "unchanged").unwrap();
+        std::fs::write(source.join("changed.txt"), "old").unwrap();
+        let (first, second) = (root.join("snapshots/1"), root.join("snapshots/2"));
+        std::fs::create_dir_all(root.join("snapshots")).unwrap();
+        Replicator::new(&source, &first).run().unwrap();
+        std::fs::write(source.join("changed.txt"), "changed").unwrap();
+
+        let stats = Replicator::new(&source, &second)
+            .link_dest(Some("../1"))
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_linked_count, 1);
+        assert_eq!(stats.file_copied_count, 1);
+        let inode = |path: PathBuf| {
+            let metadata = path.metadata().unwrap();
+            (metadata.dev(), metadata.ino())
+        };
+        assert_eq!(
+            inode(second.join("docs/unchanged.txt")),
+            inode(first.join("docs/unchanged.txt"))
+        );
+        assert_ne!(
+            inode(second.join("changed.txt")),
+            inode(first.join("changed.txt"))
+        );
+        assert_eq!(std::fs::read(first.join("changed.txt")).unwrap(), b"old");
+        assert_eq!(
+            std::fs::read(second.join("changed.txt")).unwrap(),
+            b"changed"
+        );
+    }
+
+    #[test]
+    fn it_leaves_the_throughput_out_of_dry_runs() {
+        let root = testing::test_dir("it_leaves_the_throughput_out_of_dry_runs");
+        let source = root.join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+
+        let replicator = Replicator::new(&source, root.join("target"));
+        let stats = replicator.clone().dryrun(true).run().unwrap();
+        assert!(stats.dryrun);
+        assert_eq!(stats.file_copied_count, 1);
+        assert!(!stats.to_string().contains("Average throughput"));
+        let stats = SyncStats {
+            dryrun: false,
+            duration: Duration::from_secs(1),
+            ..stats
+        };
+        assert!(stats.to_string().contains("Average throughput: 5 B/s"));
+    }
+
+    #[test]
+    fn it_backs_up_overridden_files() {
+        let root = testing::test_dir("it_backs_up_overridden_files");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::create_dir_all(target.join("docs")).unwrap();
+        std::fs::write(source.join("docs/notes.txt"), "new notes").unwrap();
+        std::fs::write(target.join("docs/notes.txt"), "old").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(target.join("docs/notes.txt"))
+            .unwrap()
+            .set_modified(UNIX_EPOCH)
+            .unwrap();
+
+        let stats = Replicator::new(&source, &target)
+            .on_conflict(ConflictPolicy::Newer)
+            .backup_dir(Some("../backups"))
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_backed_up_count, 1);
+        assert_eq!(
+            std::fs::read_to_string(root.join("backups/docs/notes.txt")).unwrap(),
+            "old"
+        );
+        assert_eq!(
+            std::fs::read_to_string(target.join("docs/notes.txt")).unwrap(),
+            "new notes"
+        );
+    }
+
+    #[test]
+    fn it_moves_deleted_files_to_the_trash() {
+        let root = testing::test_dir("it_moves_deleted_files_to_the_trash");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(source.join("docs/notes.txt"), "notes").unwrap();
+        std::fs::write(source.join("other.txt"), "other").unwrap();
+        std::fs::write(target.join("unknown.txt"), "unknown").unwrap();
+        Replicator::new(&source, &target).run().unwrap();
+
+        std::fs::remove_file(source.join("docs/notes.txt")).unwrap();
+        let stats = Replicator::new(&source, &target)
+            .delete_to_trash(true)
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_deleted_count, 1);
+        assert!(!target.join("docs/notes.txt").exists());
+        assert!(target.join("unknown.txt").exists());
+        let trash_dirs: Vec<_> = std::fs::read_dir(target.join(TRASH_DIR_NAME))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(trash_dirs.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(trash_dirs[0].join("docs/notes.txt")).unwrap(),
+            "notes"
+        );
+    }
+
+    #[test]
+    fn it_aborts_runs_deleting_more_files_than_the_limits() {
+        let root = testing::test_dir("it_aborts_runs_deleting_more_files_than_the_limits");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            std::fs::write(source.join(name), name).unwrap();
+        }
+        Replicator::new(&source, &target).run().unwrap();
+
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::remove_file(source.join(name)).unwrap();
+        }
+        std::fs::write(source.join("e.txt"), "e.txt").unwrap();
+        let result = Replicator::new(&source, &target)
+            .delete(true)
+            .max_delete(Some(2))
+            .run();
+        assert!(matches!(
+            result,
+            Err(SyncError::TooManyDeletions { count: 3, total: 4 })
+        ));
+        assert!(target.join("a.txt").exists());
+        assert!(!target.join("e.txt").exists());
+
+        let result = Replicator::new(&source, &target)
+            .delete(true)
+            .max_delete_percent(Some(50))
+            .run();
+        assert!(matches!(result, Err(SyncError::TooManyDeletions { .. })));
+
+        let stats = Replicator::new(&source, &target)
+            .delete(true)
+            .max_delete_percent(Some(50))
+            .force(true)
+            .run()
+            .unwrap();
+        assert_eq!((stats.file_deleted_count, stats.file_copied_count), (3, 1));
+    }
+
+    #[test]
+    fn it_refuses_protected_or_foreign_targets_unless_forced() {
+        let root = testing::test_dir("it_refuses_protected_or_foreign_targets_unless_forced");
+        let (source, other, target) =
+            (root.join("source"), root.join("other"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+        std::fs::write(other.join("other.txt"), "other").unwrap();
+        Replicator::new(&source, &target).run().unwrap();
+        Replicator::new(source.join("."), &target).run().unwrap();
+
+        let result = Replicator::new(&other, &target).dryrun(true).run();
+        assert!(matches!(result, Err(SyncError::ForeignDestination { .. })));
+        assert!(!target.join("other.txt").exists());
+        Replicator::new(&other, &target).force(true).run().unwrap();
+        assert!(target.join("other.txt").exists());
+
+        std::fs::write(target.join(PROTECT_FILE_NAME), "").unwrap();
+        let result = Replicator::new(&other, &target).run();
+        assert!(matches!(
+            result,
+            Err(SyncError::ProtectedDestination { .. })
+        ));
+    }
+
+    #[test]
+    fn it_writes_a_report_of_each_run() {
+        let root = testing::test_dir("it_writes_a_report_of_each_run");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+
+        let replicator = Replicator::new(&source, &target)
+            .report(true)
+            .arguments(vec!["replicate".to_string()]);
+        replicator.clone().dryrun(true).run().unwrap();
+        assert!(RunReport::list(&target).unwrap().is_empty());
+        replicator.run().unwrap();
+
+        let ids = RunReport::list(&target).unwrap();
+        assert_eq!(ids.len(), 1);
+        let report = RunReport::load(&target, ids[0]).unwrap();
+        assert_eq!(report.outcome, Outcome::Succeeded);
+        assert_eq!(report.arguments, vec!["replicate"]);
+        assert_eq!(report.stats.file_copied_count, 1);
+        assert_eq!(report.origin, std::fs::canonicalize(&source).unwrap());
+
+        // Replicating the target elsewhere leaves its reports behind, hidden or not.
+        let copy = root.join("copy");
+        let stats = Replicator::new(&target, &copy).hidden(true).run().unwrap();
+        assert_eq!(stats.file_copied_count, 1);
+        assert!(!copy.join(ACSYNC_DIR_NAME).exists());
+    }
+
+    #[test]
+    fn it_only_applies_approved_actions() {
+        let root = testing::test_dir("it_only_applies_approved_actions");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::write(source.join("docs/notes.txt"), "notes").unwrap();
+        std::fs::write(source.join("other.txt"), "other").unwrap();
+
+        let replicator = Replicator::new(&source, &target);
+        let plan = replicator.plan().unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                PlannedAction {
+                    kind: ActionKind::Copy,
+                    path: PathBuf::from("docs/notes.txt")
+                },
+                PlannedAction {
+                    kind: ActionKind::Copy,
+                    path: PathBuf::from("other.txt")
+                },
+            ]
+        );
+        assert!(!target.exists());
+
+        let stats = replicator
+            .approved(Some(HashSet::from([PathBuf::from("docs/notes.txt")])))
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_copied_count, 1);
+        assert!(target.join("docs/notes.txt").exists());
+        assert!(!target.join("other.txt").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_repairs_metadata_only() {
+        let root = testing::test_dir("it_repairs_metadata_only");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(source.join("script.sh"), "echo").unwrap();
+        std::fs::set_permissions(
+            source.join("script.sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        std::fs::write(source.join("missing.txt"), "missing").unwrap();
+        platform::symlink(Path::new("script.sh"), &source.join("link")).unwrap();
+        // Copied by another tool, which lost the permissions, times and link target.
+        std::fs::write(target.join("script.sh"), "echo").unwrap();
+        platform::set_modified(&target.join("script.sh"), UNIX_EPOCH).unwrap();
+        std::fs::set_permissions(
+            target.join("script.sh"),
+            std::fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        platform::symlink(Path::new("missing.txt"), &target.join("link")).unwrap();
+
+        let stats = Replicator::new(&source, &target)
+            .metadata_only(true)
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_metadata_fixed_count, 2);
+        assert_eq!(stats.file_copied_count, 0);
+        assert!(!target.join("missing.txt").exists());
+        let (source_metadata, target_metadata) = (
+            source.join("script.sh").metadata().unwrap(),
+            target.join("script.sh").metadata().unwrap(),
+        );
+        assert_eq!(platform::mode(&target_metadata), 0o755);
+        assert_eq!(
+            target_metadata.modified().unwrap(),
+            source_metadata.modified().unwrap()
+        );
+        assert_eq!(
+            std::fs::read_link(target.join("link")).unwrap(),
+            Path::new("script.sh")
+        );
+        let stats = Replicator::new(&source, &target)
+            .metadata_only(true)
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_metadata_fixed_count, 0);
+    }
+
+    #[test]
+    fn it_restores_the_times_of_the_directories_changed() {
+        let root = testing::test_dir("it_restores_the_times_of_the_directories_changed");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(source.join("docs/drafts")).unwrap();
+        std::fs::write(source.join("docs/drafts/draft.txt"), "draft").unwrap();
+        std::fs::write(source.join("docs/notes.txt"), "notes").unwrap();
+        std::fs::write(source.join("plan.txt"), "plan").unwrap();
+        let set_times = || {
+            for (path, days) in [("", 1), ("docs", 2), ("docs/drafts", 3)] {
+                let modified = UNIX_EPOCH + Duration::from_secs(days * 86_400);
+                platform::set_modified(&source.join(path), modified).unwrap();
+            }
+        };
+        let modified = |path: &Path| path.metadata().unwrap().modified().unwrap();
+        let assert_same_times = || {
+            for path in ["", "docs", "docs/drafts"] {
+                assert_eq!(modified(&target.join(path)), modified(&source.join(path)));
+            }
+        };
+        set_times();
+
+        Replicator::new(&source, &target).run().unwrap();
+        assert_same_times();
+
+        std::fs::write(source.join("docs/other.txt"), "other").unwrap();
+        std::fs::remove_file(source.join("plan.txt")).unwrap();
+        set_times();
+        Replicator::new(&source, &target)
+            .delete(true)
+            .run()
+            .unwrap();
+        assert!(target.join("docs/other.txt").exists() && !target.join("plan.txt").exists());
+        assert_same_times();
+    }
+
+    #[test]
+    fn it_verifies_a_sample_of_the_copied_files() {
+        let root = testing::test_dir("it_verifies_a_sample_of_the_copied_files");
+        let source = root.join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        for index in 0..10 {
+            std::fs::write(source.join(format!("{index}.txt")), index.to_string()).unwrap();
+        }
+
+        for (percent, sampled) in [(0, 0), (100, 10)] {
+            let stats = Replicator::new(&source, root.join(format!("target_{percent}")))
+                .verify_sample(Some(percent))
+                .run()
+                .unwrap();
+            assert_eq!(
+                (stats.file_copied_count, stats.file_sampled_count),
+                (10, sampled)
+            );
+        }
+
+        let corrupted = root.join("target_100/0.txt");
+        std::fs::write(&corrupted, "corrupted").unwrap();
+        let result = verify_copy(hash::hash_file(source.join("0.txt")).unwrap(), &corrupted);
+        assert!(matches!(result, Err(SyncError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn it_verifies_every_copied_file() {
+        let root = testing::test_dir("it_verifies_every_copied_file");
+        let source = root.join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        for index in 0..10 {
+            std::fs::write(source.join(format!("{index}.txt")), index.to_string()).unwrap();
+        }
+
+        let stats = Replicator::new(&source, root.join("target"))
+            .verify(true)
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_copied_count, 10);
+        assert_eq!(std::fs::read(root.join("target/9.txt")).unwrap(), b"9");
+        assert!(!copy::temporary_path(root.join("target/9.txt")).exists());
+        let result = Replicator::new(&source, root.join("target_compressed"))
+            .verify(true)
+            .compress(Some(Compression::Gzip))
+            .run();
+        assert!(matches!(result, Err(SyncError::InvalidArgument { .. })));
+    }
+
+    #[test]
+    fn it_retries_transient_errors_with_backoff() {
+        let failure = |kind| SyncError::Io {
+            path: PathBuf::from("notes.txt"),
+            source: std::io::Error::from(kind),
+        };
+        let mut calls = 0;
+        let (result, retried) = with_retries(3, Duration::from_millis(1), || {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(failure(std::io::ErrorKind::TimedOut)),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!((result.unwrap(), retried), (3, true));
+
+        let mut calls = 0;
+        let (result, retried) = with_retries(3, Duration::from_millis(1), || {
+            calls += 1;
+            Err::<(), _>(failure(std::io::ErrorKind::NotFound))
+        });
+        assert!(result.is_err() && !retried);
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let (result, retried) = with_retries(3, Duration::from_millis(1), || {
+            calls += 1;
+            Err::<(), _>(failure(std::io::ErrorKind::StorageFull))
+        });
+        assert!(result.is_err() && !retried);
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let started = Instant::now();
+        let (result, retried) = with_retries(2, Duration::from_millis(20), || {
+            calls += 1;
+            Err::<(), _>(SyncError::Io {
+                path: PathBuf::from("notes.txt"),
+                source: std::io::Error::from_raw_os_error(EIO),
+            })
+        });
+        assert!(result.is_err() && retried);
+        assert_eq!(calls, 3);
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn it_copies_files_changed_while_being_copied_again() {
+        let root = testing::test_dir("it_copies_files_changed_while_being_copied_again");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("log.txt"), vec![b'a'; 288 * 1024]).unwrap();
+
+        // Throttled, the copy takes a quarter of a second, while the file is appended to.
+        let writer = std::thread::spawn({
+            let path = source.join("log.txt");
+            move || {
+                std::thread::sleep(Duration::from_millis(50));
+                let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+                std::io::Write::write_all(&mut file, b"appended").unwrap();
+            }
+        });
+        let stats = Replicator::new(&source, &target)
+            .bwlimit(Some(256 * 1024))
+            .recopies(Some(1))
+            .run()
+            .unwrap();
+        writer.join().unwrap();
+        assert_eq!(stats.file_copied_count, 1);
+        assert_eq!(
+            std::fs::read(target.join("log.txt")).unwrap(),
+            std::fs::read(source.join("log.txt")).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_gives_up_on_copies_over_the_timeout() {
+        let root = testing::test_dir("it_gives_up_on_copies_over_the_timeout");
+        std::fs::write(root.join("notes.txt"), "notes").unwrap();
+        // Opening a FIFO without a writer blocks, like a read on a stale NFS handle.
+        let status = std::process::Command::new("mkfifo")
+            .arg(root.join("hung"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let job = |source: &str, target: &str| CopyJob {
+            source_path: root.join(source),
+            target_path: root.join(target),
+            write_path: root.join(target),
+            relative_path: PathBuf::from(source),
+            source_size: 5,
+            decompress: None,
+            compress: None,
+            verify: false,
+            reflink: Reflink::Never,
+            delta: false,
+            inplace: true,
+            sparse: false,
+            limiter: None,
+            cancellation: None,
+        };
+
+        let outcome = job("notes.txt", "copy.txt")
+            .run_within(Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(outcome.result.unwrap(), 5);
+        assert_eq!(std::fs::read(root.join("copy.txt")).unwrap(), b"notes");
+        std::fs::write(root.join("hung.txt"), "old").unwrap();
+        let started = Instant::now();
+        assert!(matches!(
+            job("hung", "hung.txt").run_within(Duration::from_millis(100)),
+            Err(SyncError::TimedOut { .. })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        // Unblocks the abandoned copy, which mustn't write into the target anymore, and
+        // may close the FIFO before it's written.
+        let _ = std::fs::write(root.join("hung"), "late");
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(std::fs::read(root.join("hung.txt")).unwrap(), b"old");
+    }
+
+    #[test]
+    fn it_leaves_the_target_untouched_after_a_failed_copy() {
+        let root = testing::test_dir("it_leaves_the_target_untouched_after_a_failed_copy");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(source.join("log.txt"), vec![b'a'; 288 * 1024]).unwrap();
+        std::fs::write(target.join("log.txt"), "old").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(target.join("log.txt"))
+            .unwrap()
+            .set_modified(UNIX_EPOCH)
+            .unwrap();
+
+        // Throttled, the copy takes a quarter of a second, while the run is cancelled.
+        let token = CancellationToken::new();
+        let canceller = std::thread::spawn({
+            let token = token.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(50));
+                token.cancel();
+            }
+        });
+        let stats = Replicator::new(&source, &target)
+            .on_conflict(ConflictPolicy::Newer)
+            .bwlimit(Some(256 * 1024))
+            .cancellation(Some(token))
+            .run()
+            .unwrap();
+        canceller.join().unwrap();
+        assert!(stats.interrupted);
+        assert_eq!(stats.file_copied_count, 0);
+        assert_eq!(std::fs::read(target.join("log.txt")).unwrap(), b"old");
+        assert!(!copy::temporary_path(target.join("log.txt")).exists());
+
+        // In place, a copy failing before writing doesn't truncate the target either.
+        let outcome = CopyJob {
+            source_path: source.join("missing.txt"),
+            target_path: target.join("log.txt"),
+            write_path: target.join("log.txt"),
+            relative_path: PathBuf::from("missing.txt"),
+            source_size: 5,
+            decompress: None,
+            compress: None,
+            verify: false,
+            reflink: Reflink::Never,
+            delta: false,
+            inplace: true,
+            sparse: false,
+            limiter: None,
+            cancellation: None,
+        }
+        .run();
+        assert!(outcome.result.is_err());
+        assert_eq!(std::fs::read(target.join("log.txt")).unwrap(), b"old");
+        assert!(!copy::temporary_path(target.join("log.txt")).exists());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_preserves_acls() {
+        let root = testing::test_dir("it_preserves_acls");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(source.join("shared")).unwrap();
+        std::fs::write(source.join("shared/notes.txt"), "notes").unwrap();
+        // user::rw-, user:1000:rw-, group::r--, mask::rw-, other::r-- in the binary
+        // format of the extended attributes.
+        let mut acl = 2u32.to_le_bytes().to_vec();
+        for (tag, permissions, id) in [
+            (0x01u16, 6u16, u32::MAX),
+            (0x02, 6, 1000),
+            (0x04, 4, u32::MAX),
+            (0x10, 6, u32::MAX),
+            (0x20, 4, u32::MAX),
+        ] {
+            acl.extend([tag.to_le_bytes(), permissions.to_le_bytes()].concat());
+            acl.extend(id.to_le_bytes());
+        }
+        for (path, name) in [
+            ("shared", "system.posix_acl_default"),
+            ("shared/notes.txt", "system.posix_acl_access"),
+        ] {
+            platform::set_xattr(&source.join(path), name, &acl).unwrap();
+        }
+        let acl = |path: &Path| platform::acl(path).unwrap();
+
+        Replicator::new(&source, &target).acls(true).run().unwrap();
+        for path in ["shared", "shared/notes.txt"] {
+            assert!(!acl(&source.join(path)).is_empty());
+            assert_eq!(acl(&target.join(path)), acl(&source.join(path)));
+        }
+
+        let notes_path = target.join("shared/notes.txt");
+        platform::set_acl(&notes_path, &platform::Acl::default()).unwrap();
+        assert!(acl(&notes_path).is_empty());
+        let stats = Replicator::new(&source, &target)
+            .acls(true)
+            .perms(true)
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_metadata_fixed_count, 1);
+        assert_eq!(acl(&notes_path), acl(&source.join("shared/notes.txt")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_fixes_drifted_permissions_with_perms() {
+        let root = testing::test_dir("it_fixes_drifted_permissions_with_perms");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(source.join("script.sh"), "echo").unwrap();
+        std::fs::set_permissions(
+            source.join("script.sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        Replicator::new(&source, &target).run().unwrap();
+        std::fs::set_permissions(
+            target.join("script.sh"),
+            std::fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+
+        let stats = Replicator::new(&source, &target).run().unwrap();
+        assert_eq!(stats.file_metadata_fixed_count, 0);
+        let stats = Replicator::new(&source, &target).perms(true).run().unwrap();
+        assert_eq!(stats.file_metadata_fixed_count, 1);
+        assert_eq!(stats.file_copied_count, 0);
+        let metadata = target.join("script.sh").metadata().unwrap();
+        assert_eq!(platform::mode(&metadata), 0o755);
+    }
+
+    #[test]
+    fn it_reports_itemized_actions() {
+        let root = testing::test_dir("it_reports_itemized_actions");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+        std::fs::write(source.join("other.txt"), "other").unwrap();
+        Replicator::new(&source, &target).run().unwrap();
+
+        std::fs::rename(source.join("notes.txt"), source.join("moved.txt")).unwrap();
+        std::fs::write(source.join("new.txt"), "new").unwrap();
+        let mut lines = vec![];
+        Replicator::new(&source, &target)
+            .run_itemized(|action| lines.push(action.to_string()))
+            .unwrap();
+        lines.sort();
+        assert_eq!(lines, [">+++ new.txt", "m... moved.txt"]);
+    }
+
+    #[test]
+    fn it_asks_the_prompter_about_conflicts() {
+        let root = testing::test_dir("it_asks_the_prompter_about_conflicts");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+        Replicator::new(&source, &target).run().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(target.join("notes.txt"), "edited notes").unwrap();
+
+        let stats = Replicator::new(&source, &target)
+            .on_conflict(ConflictPolicy::Prompt)
+            .prompter(MockPrompter::new([true]))
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_overrided_count, 1);
+        assert_eq!(
+            std::fs::read_to_string(target.join("notes.txt")).unwrap(),
+            "notes"
+        );
+    }
+
+    #[test]
+    fn it_selects_files_by_their_existence_in_the_target() {
+        let root = testing::test_dir("it_selects_files_by_their_existence_in_the_target");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(source.join("notes.txt"), "new notes").unwrap();
+        std::fs::write(source.join("other.txt"), "other").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(target.join("notes.txt"), "notes").unwrap();
+
+        let stats = Replicator::new(&source, &target)
+            .existing_policy(ExistingPolicy::IgnoreExisting)
+            .on_conflict(ConflictPolicy::Larger)
+            .run()
+            .unwrap();
+        assert_eq!(
+            (stats.file_copied_count, stats.file_overrided_count),
+            (1, 0)
+        );
+
+        std::fs::remove_file(target.join("other.txt")).unwrap();
+        let stats = Replicator::new(&source, &target)
+            .existing_policy(ExistingPolicy::Existing)
+            .on_conflict(ConflictPolicy::Larger)
+            .run()
+            .unwrap();
+        assert_eq!(
+            (stats.file_copied_count, stats.file_overrided_count),
+            (0, 1)
+        );
+        assert!(!target.join("other.txt").exists());
+    }
+
+    #[test]
+    fn it_detects_conflicts_by_size_only_or_always() {
+        let root = testing::test_dir("it_detects_conflicts_by_size_only_or_always");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("notes.txt"), "notes").unwrap();
+        std::fs::write(target.join("other.txt"), "other").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(source.join("notes.txt"), "new notes").unwrap();
+        std::fs::write(source.join("other.txt"), "OTHER").unwrap();
+        std::fs::write(source.join("same_time.txt"), "longer").unwrap();
+        std::fs::write(target.join("same_time.txt"), "short").unwrap();
+        let modified = source.join("same_time.txt").metadata().unwrap().modified();
+        std::fs::File::options()
+            .write(true)
+            .open(target.join("same_time.txt"))
+            .unwrap()
+            .set_modified(modified.unwrap())
+            .unwrap();
+
+        for (change_detection, conflict_count) in [
+            (ChangeDetection::SizeAndTime, 1),
+            (ChangeDetection::SizeOnly, 2),
+            (ChangeDetection::IgnoreTimes, 3),
+        ] {
+            let stats = Replicator::new(&source, &target)
+                .change_detection(change_detection)
+                .dryrun(true)
+                .run()
+                .unwrap();
+            assert_eq!(stats.file_conflict_count, conflict_count);
+        }
+        let stats = Replicator::new(&source, &target)
+            .modify_window(std::time::Duration::from_secs(3600))
+            .dryrun(true)
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_conflict_count, 0);
+    }
+
+    #[test]
+    fn it_patches_the_changed_blocks_of_large_files() {
+        let root = testing::test_dir("it_patches_the_changed_blocks_of_large_files");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        let mut content = vec![0; 2 * copy::DELTA_MIN_SIZE as usize];
+        std::fs::write(target.join("disk.img"), &content).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        content[42] = 1;
+        content.push(1);
+        std::fs::write(source.join("disk.img"), &content).unwrap();
+
+        let stats = Replicator::new(&source, &target)
+            .on_conflict(ConflictPolicy::Newer)
+            .inplace(true)
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_overrided_count, 1);
+        assert!(stats.total_delta_saved_size >= copy::DELTA_MIN_SIZE);
+        assert_eq!(std::fs::read(target.join("disk.img")).unwrap(), content);
+    }
+
+    #[test]
+    fn it_deduplicates_files_into_a_store_and_restores_them() {
+        let root = testing::test_dir("it_deduplicates_files_into_a_store_and_restores_them");
+        let (source, store, restored) = (
+            root.join("source"),
+            root.join("store"),
+            root.join("restored"),
+        );
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::write(source.join("docs/notes.txt"), "notes").unwrap();
+        std::fs::write(source.join("copy.txt"), "notes").unwrap();
+
+        let stats = Replicator::new(&source, &store).store(true).run().unwrap();
+        assert_eq!(
+            (stats.file_copied_count, stats.file_deduplicated_count),
+            (1, 1)
+        );
+        std::thread::sleep(std::time::Duration::from_millis(1000));
+        let stats = Replicator::new(&source, &store).store(true).run().unwrap();
+        assert_eq!(
+            (stats.file_copied_count, stats.file_deduplicated_count),
+            (0, 2)
+        );
+        let content_store = ContentStore::new(&store);
+        assert_eq!(content_store.snapshots().unwrap().len(), 2);
+
+        let stats = content_store.restore(None, &restored, false).unwrap();
+        assert_eq!(stats.file_copied_count, 2);
+        assert_eq!(
+            std::fs::read_to_string(restored.join("docs/notes.txt")).unwrap(),
+            "notes"
+        );
+        assert_eq!(
+            restored
+                .join("copy.txt")
+                .metadata()
+                .unwrap()
+                .modified()
+                .unwrap(),
+            source
+                .join("copy.txt")
+                .metadata()
+                .unwrap()
+                .modified()
+                .unwrap()
+        );
+        let stats = content_store.restore(None, &restored, false).unwrap();
+        assert_eq!(stats.file_copied_count, 0);
+    }
+
+    #[test]
+    fn it_keeps_going_past_failed_files_with_ignore_errors() {
+        let root = testing::test_dir("it_keeps_going_past_failed_files_with_ignore_errors");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+        let replicator = Replicator::new(&source, &target)
+            .files_from(Some(vec![
+                PathBuf::from("missing.txt"),
+                PathBuf::from("notes.txt"),
+            ]))
+            .report(true);
+
+        assert!(replicator.clone().run().is_err());
+        assert!(!target.join("notes.txt").exists());
+
+        let stats = replicator.ignore_errors(true).run().unwrap();
+        assert_eq!(stats.file_copied_count, 1);
+        assert_eq!(stats.file_failed_count, 1);
+        assert_eq!(std::fs::read(target.join("notes.txt")).unwrap(), b"notes");
+        assert!(stats.to_string().contains("Failed files: 1"));
+        let ids = RunReport::list(&target).unwrap();
+        let report = RunReport::load(&target, *ids.last().unwrap()).unwrap();
+        assert_eq!(report.outcome, Outcome::Failed);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].contains("missing.txt"));
+        // As the CLI fails the run once it's over.
+        assert_eq!(
+            SyncError::FilesFailed(stats.file_failed_count).exit_code(),
+            2
+        );
+    }
+
+    #[test]
+    fn it_reports_the_events_of_a_run_to_its_observer() {
+        #[derive(Default)]
+        struct Recorder(Vec<String>);
+
+        impl SyncObserver for Recorder {
+            fn on_event(&mut self, event: &SyncEvent<'_>) {
+                self.0.push(match event {
+                    SyncEvent::ScanStarted { .. } => "started".to_string(),
+                    SyncEvent::FileCopied { path, bytes } => {
+                        format!("copied {} {bytes}", path.display())
+                    }
+                    SyncEvent::Error { path, .. } => format!("failed {path:?}"),
+                    SyncEvent::Finished { stats } => {
+                        format!("finished {}", stats.file_failed_count)
+                    }
+                });
+            }
+        }
+
+        let root = testing::test_dir("it_reports_the_events_of_a_run_to_its_observer");
+        let source = root.join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+
+        let mut recorder = Recorder::default();
+        Replicator::new(&source, root.join("target"))
+            .files_from(Some(vec![
+                PathBuf::from("notes.txt"),
+                PathBuf::from("missing.txt"),
+            ]))
+            .ignore_errors(true)
+            .run_with_observer(&mut recorder)
+            .unwrap();
+        assert_eq!(
+            recorder.0,
+            [
+                "started",
+                "failed Some(\"missing.txt\")",
+                "copied notes.txt 5",
+                "finished 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_stops_the_run_once_cancelled() {
+        struct Canceller(CancellationToken);
+
+        impl SyncObserver for Canceller {
+            fn on_event(&mut self, event: &SyncEvent<'_>) {
+                if let SyncEvent::FileCopied { .. } = event {
+                    self.0.cancel();
+                }
+            }
+        }
+
+        let root = testing::test_dir("it_stops_the_run_once_cancelled");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.txt"), "a").unwrap();
+        std::fs::write(source.join("b.txt"), "bb").unwrap();
+        std::fs::write(source.join("c.txt"), "ccc").unwrap();
+
+        let token = CancellationToken::new();
+        let stats = Replicator::new(&source, &target)
+            .order(TransferOrder::SmallestFirst)
+            .cancellation(Some(token.clone()))
+            .report(true)
+            .run_with_observer(Canceller(token))
+            .unwrap();
+        assert!(stats.interrupted);
+        assert_eq!(stats.file_copied_count, 1);
+        assert!(target.join("a.txt").is_file());
+        assert!(!target.join("b.txt").exists());
+        let ids = RunReport::list(&target).unwrap();
+        let report = RunReport::load(&target, ids[0]).unwrap();
+        assert_eq!(report.outcome, Outcome::Interrupted);
+    }
+
+    #[test]
+    fn it_saves_the_state_of_an_interrupted_run() {
+        struct Canceller(CancellationToken);
+
+        impl SyncObserver for Canceller {
+            fn on_event(&mut self, event: &SyncEvent<'_>) {
+                if let SyncEvent::FileCopied { .. } = event {
+                    self.0.cancel();
+                }
+            }
+        }
+
+        let root = testing::test_dir("it_saves_the_state_of_an_interrupted_run");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.txt"), "a").unwrap();
+        std::fs::write(source.join("b.txt"), "bb").unwrap();
+
+        let token = CancellationToken::new();
+        let stats = Replicator::new(&source, &target)
+            .order(TransferOrder::SmallestFirst)
+            .cancellation(Some(token.clone()))
+            .run_with_observer(Canceller(token))
+            .unwrap();
+        assert!(stats.interrupted);
+        assert!(stats.to_string().contains("Interrupted before the end"));
+        let state = StateManifest::load(&target).unwrap();
+        assert_eq!(state.iter().collect::<Vec<_>>(), [(Path::new("a.txt"), 1)]);
+        assert!(!copy::temporary_path(target.join("b.txt")).exists());
+        // As the CLI fails the run once it's over.
+        assert_eq!(SyncError::Interrupted.exit_code(), 4);
+
+        let stats = Replicator::new(&source, &target).run().unwrap();
+        assert_eq!(stats.file_copied_count, 1);
+        assert_eq!(StateManifest::load(&target).unwrap().iter().count(), 2);
+    }
+
+    #[test]
+    fn it_reports_the_unreadable_source_directories() {
+        let root = testing::test_dir("it_reports_the_unreadable_source_directories");
+        let source = root.join("source");
+        std::fs::create_dir_all(source.join("a")).unwrap();
+        std::fs::write(source.join("a/x.txt"), "x").unwrap();
+        std::fs::write(source.join("c.txt"), "c").unwrap();
+
+        let replicator = Replicator::new(&source, root.join("target"));
+        let mut entries = replicator.source_entries();
+        assert!(entries.nth(1).unwrap().unwrap().path().ends_with("a"));
+        // Removed once walked into, as if it couldn't be read.
+        std::fs::remove_dir_all(source.join("a")).unwrap();
+        let rest: Vec<_> = entries.collect();
+        assert!(rest[0].as_ref().unwrap().path().ends_with("c.txt"));
+        assert!(matches!(
+            &rest[1],
+            Err(SyncError::Unreadable { path, .. }) if *path == source.join("a")
+        ));
+        assert_eq!(rest.len(), 2);
+    }
+
+    #[test]
+    fn it_replicates_only_the_listed_files() {
+        let root = testing::test_dir("it_replicates_only_the_listed_files");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(source.join("docs/drafts")).unwrap();
+        std::fs::write(source.join("docs/drafts/notes.txt"), "notes").unwrap();
+        std::fs::write(source.join("docs/other.txt"), "other").unwrap();
+        std::fs::write(source.join("readme.txt"), "readme").unwrap();
+
+        let stats = Replicator::new(&source, &target)
+            .files_from(Some(vec![
+                PathBuf::from("./docs/drafts/notes.txt"),
+                PathBuf::from("readme.txt"),
+            ]))
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_copied_count, 2);
+        assert!(target.join("docs/drafts/notes.txt").exists());
+        assert!(!target.join("docs/other.txt").exists());
+
+        let result = Replicator::new(&source, &target)
+            .files_from(Some(vec![PathBuf::from("../outside.txt")]))
+            .run();
+        assert!(matches!(result, Err(SyncError::WalkError { .. })));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn it_fails_before_copying_files_larger_than_the_free_space() {
+        let root = testing::test_dir("it_fails_before_copying_files_larger_than_the_free_space");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+        let available = platform::available_space(&root).unwrap();
+        // Sparse, so the source takes no room itself.
+        std::fs::File::create(source.join("disk.img"))
+            .unwrap()
+            .set_len(available + 1)
+            .unwrap();
+
+        let result = Replicator::new(&source, &target).check_space(true).run();
+        assert!(matches!(
+            result,
+            Err(SyncError::InsufficientSpace { needed, .. }) if needed == available + 6
+        ));
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn it_fails_or_renames_files_colliding_by_case() {
+        let root = testing::test_dir("it_fails_or_renames_files_colliding_by_case");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::write(source.join("docs/README.md"), "upper").unwrap();
+        std::fs::write(source.join("docs/Readme.md"), "mixed").unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+
+        let result = Replicator::new(&source, &target)
+            .case_collisions(CaseCollisions::Error)
+            .run();
+        assert!(matches!(
+            result,
+            Err(SyncError::CaseCollision { path, .. }) if path.ends_with("Readme.md")
+        ));
+        assert!(!target.exists());
+
+        Replicator::new(&source, &target)
+            .case_collisions(CaseCollisions::Rename)
+            .run()
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(target.join("docs/README.md")).unwrap(),
+            "upper"
+        );
+        assert_eq!(
+            std::fs::read_to_string(target.join("docs/Readme.md.case-2")).unwrap(),
+            "mixed"
+        );
+        assert!(target.join("notes.txt").exists());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_replicates_trees_deeper_than_path_max() {
+        let root = testing::test_dir("it_replicates_trees_deeper_than_path_max");
+        let source = root.join("source");
+        let mut directory = source.clone();
+        std::fs::create_dir_all(&directory).unwrap();
+        let name = "d".repeat(200);
+        for _ in 0..30 {
+            directory.push(&name);
+            std::fs::create_dir(&*platform::short_path(&directory)).unwrap();
+        }
+        let file_path = directory.join("notes.txt");
+        std::fs::write(&*platform::short_path(&file_path), "deep").unwrap();
+        assert!(file_path.as_os_str().len() > 4096);
+
+        let target = root.join("target");
+        std::fs::create_dir(&target).unwrap();
+        let stats = Replicator::new(&source, &target).run().unwrap();
+        assert_eq!(
+            (stats.file_copied_count, stats.directory_created_count),
+            (1, 30)
+        );
+        let relative_path = file_path.strip_prefix(&source).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&*platform::short_path(&target.join(relative_path))).unwrap(),
+            "deep"
+        );
+        let stats = Replicator::new(&source, &target).run().unwrap();
+        assert_eq!(stats.file_copied_count, 0);
+    }
+
+    #[test]
+    fn it_compares_names_by_their_normalization_form() {
+        let root = testing::test_dir("it_compares_names_by_their_normalization_form");
+        let source = root.join("source");
+        // Decomposed names, as APFS hands them out.
+        let decomposed_path = Path::new("re\u{301}sume\u{301}/cafe\u{301}.txt");
+        std::fs::create_dir_all(source.join("re\u{301}sume\u{301}")).unwrap();
+        std::fs::write(source.join(decomposed_path), "menu").unwrap();
+        let replicator = |target: &Path| {
+            Replicator::new(&source, target)
+                .normalize(Normalization::Nfc)
+                .delete(true)
+        };
+
+        let target = root.join("target");
+        replicator(&target).run().unwrap();
+        let composed_path = Path::new("r\u{e9}sum\u{e9}/caf\u{e9}.txt");
+        assert!(target.join(composed_path).is_file());
+        assert!(!target.join(decomposed_path).exists());
+        let stats = replicator(&target).run().unwrap();
+        assert_eq!((stats.file_copied_count, stats.file_deleted_count), (0, 0));
+        assert!(target.join(composed_path).is_file());
+
+        // Replicated before names were normalized.
+        let previous = root.join("previous");
+        Replicator::new(&source, &previous).run().unwrap();
+        std::fs::write(source.join(decomposed_path), "new menu").unwrap();
+        let stats = replicator(&previous)
+            .on_conflict(ConflictPolicy::Larger)
+            .run()
+            .unwrap();
+        assert_eq!(
+            (stats.file_overrided_count, stats.file_deleted_count),
+            (1, 0)
+        );
+        assert_eq!(
+            std::fs::read_to_string(previous.join(decomposed_path)).unwrap(),
+            "new menu"
+        );
+        assert!(!previous.join(composed_path).exists());
+    }
+
+    #[test]
+    fn it_skips_or_transcodes_names_invalid_on_the_target_file_system() {
+        let root =
+            testing::test_dir("it_skips_or_transcodes_names_invalid_on_the_target_file_system");
+        let source = root.join("source");
+        std::fs::create_dir_all(source.join("Q1: plans")).unwrap();
+        std::fs::write(source.join("Q1: plans/notes?.txt"), "notes").unwrap();
+        std::fs::write(source.join("aux.c"), "aux").unwrap();
+        std::fs::write(source.join("ok.txt"), "ok").unwrap();
+        let replicator = |target: &Path| Replicator::new(&source, target).target_fs(TargetFs::Fat);
+
+        assert!(matches!(
+            replicator(&root.join("failed")).run(),
+            Err(SyncError::InvalidName { .. })
+        ));
+        let skipped = root.join("skipped");
+        let stats = replicator(&skipped)
+            .invalid_names(InvalidNames::Skip)
+            .run()
+            .unwrap();
+        assert_eq!((stats.path_skipped_count, stats.file_copied_count), (3, 1));
+        assert!(skipped.join("ok.txt").exists());
+
+        let target = root.join("target");
+        let stats = replicator(&target)
+            .invalid_names(InvalidNames::Transcode)
+            .run()
+            .unwrap();
+        assert_eq!(
+            (stats.path_transcoded_count, stats.file_copied_count),
+            (3, 3)
+        );
+        assert!(target.join("Q1%3A plans/notes%3F.txt").is_file());
+        assert!(target.join("%61ux.c").is_file());
+        assert!(
+            Verifier::new(&source, &target)
+                .run()
+                .unwrap()
+                .is_consistent()
+        );
+
+        let back = root.join("back");
+        Replicator::new(&target, &back).run().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(back.join("Q1: plans/notes?.txt")).unwrap(),
+            "notes"
+        );
+        assert!(back.join("aux.c").is_file() && !back.join(NAMES_FILE_NAME).exists());
+
+        std::fs::remove_file(source.join("aux.c")).unwrap();
+        replicator(&target)
+            .invalid_names(InvalidNames::Transcode)
+            .delete(true)
+            .run()
+            .unwrap();
+        assert!(!target.join("%61ux.c").exists());
+        let names = NameMap::load(&target).unwrap();
+        assert_eq!(names.original("%61ux.c"), None);
+        assert_eq!(
+            names.original("Q1%3A plans/notes%3F.txt"),
+            Some(Path::new("Q1: plans/notes?.txt"))
+        );
+    }
+
+    #[test]
+    fn it_replicates_files_in_the_given_order() {
+        let root = testing::test_dir("it_replicates_files_in_the_given_order");
+        let source = root.join("source");
+        std::fs::create_dir_all(source.join("a/b")).unwrap();
+        for (path, size, days) in [
+            ("a/b/large.bin", 300, 1),
+            ("a/tiny.txt", 1, 3),
+            ("medium.txt", 20, 2),
+        ] {
+            std::fs::write(source.join(path), vec![b'x'; size]).unwrap();
+            let modified = UNIX_EPOCH + Duration::from_secs(days * 86_400);
+            platform::set_modified(&source.join(path), modified).unwrap();
+        }
+
+        for (order, expected) in [
+            (
+                TransferOrder::SmallestFirst,
+                ["a/tiny.txt", "medium.txt", "a/b/large.bin"],
+            ),
+            (
+                TransferOrder::LargestFirst,
+                ["a/b/large.bin", "medium.txt", "a/tiny.txt"],
+            ),
+            (
+                TransferOrder::NewestFirst,
+                ["a/tiny.txt", "medium.txt", "a/b/large.bin"],
+            ),
+        ] {
+            let mut copied = vec![];
+            Replicator::new(&source, root.join(order.to_string()))
+                .order(order)
+                .run_itemized(|action| copied.push(action.path.clone()))
+                .unwrap();
+            assert_eq!(copied, expected.map(PathBuf::from));
+            assert_eq!(order.to_string().parse(), Ok(order));
+        }
+    }
+
+    #[test]
+    fn it_displays_conflict_policies_as_parsed() {
+        for policy in [
+            ConflictPolicy::Prompt,
+            ConflictPolicy::Newer,
+            ConflictPolicy::Older,
+            ConflictPolicy::Larger,
+            ConflictPolicy::Skip,
+            ConflictPolicy::Rename,
+        ] {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+    }
+}
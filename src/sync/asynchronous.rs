@@ -0,0 +1,187 @@
+//! **asynchronous** contains a thread-backed future adapter of the [`Replicator`], for
+//! async applications to await its runs, behind the `async` feature. It doesn't do any
+//! async I/O: the files are still walked and copied with blocking calls.
+
+use super::{Replicator, SyncStats};
+use crate::{cancel::CancellationToken, error::SyncError};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// Thread-backed future adapter of a [`Replicator`], whose runs are awaited rather than
+/// blocking the calling thread, e.g. from the handler of a web dashboard triggering
+/// syncs.
+///
+/// It isn't async I/O: the run goes through the files with blocking calls on a thread
+/// of its own and wakes the awaiting task once done, so it fits any executor, tokio
+/// included, without tying up one of its worker threads nor requiring a
+/// `spawn_blocking`.
+///
+/// Each run still takes an OS thread until it ends, as copying files blocks on the file
+/// system: the threads aren't pooled, so an application starting many runs at once
+/// should bound them itself, e.g. with a semaphore around [`AsyncReplicator::run`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # use acsync::sync::{AsyncReplicator, Replicator};
+/// #
+/// # async fn sync() -> Result<(), acsync::error::SyncError> {
+/// let replicator = AsyncReplicator::new(Replicator::new("photos", "backup/photos"));
+/// let stats = replicator.run().await?;
+/// println!("{stats}");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AsyncReplicator {
+    replicator: Replicator,
+}
+
+impl AsyncReplicator {
+    /// Wraps the configured `replicator`.
+    pub fn new(replicator: Replicator) -> Self {
+        AsyncReplicator { replicator }
+    }
+
+    /// Starts a run as [`Replicator::run`] does, returning the future of its stats.
+    ///
+    /// Dropping the future before it completes cancels the run, and only it, through a
+    /// [`CancellationToken::child`] of the [`Replicator::cancellation`] token, if any,
+    /// which still cancels the run too.
+    pub fn run(&self) -> Replication {
+        let token = match &self.replicator.options.cancellation {
+            Some(token) => token.child(),
+            None => CancellationToken::new(),
+        };
+        let replicator = self.replicator.clone().cancellation(Some(token.clone()));
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let completed = Arc::clone(&shared);
+        std::thread::spawn(move || {
+            let result = replicator.run();
+            let mut shared = completed.lock().unwrap();
+            shared.result = Some(result);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        });
+        Replication {
+            shared,
+            token,
+            done: false,
+        }
+    }
+}
+
+impl From<Replicator> for AsyncReplicator {
+    fn from(replicator: Replicator) -> Self {
+        AsyncReplicator::new(replicator)
+    }
+}
+
+/// Run started by [`AsyncReplicator::run`], resolving to its stats.
+#[derive(Debug)]
+#[must_use = "the run is cancelled when its future is dropped"]
+pub struct Replication {
+    shared: Arc<Mutex<Shared>>,
+    token: CancellationToken,
+    done: bool,
+}
+
+/// State shared between a [`Replication`] and the thread running it.
+#[derive(Debug, Default)]
+struct Shared {
+    result: Option<Result<SyncStats, SyncError>>,
+    /// Waker of the task awaiting the run, as of its last poll.
+    waker: Option<Waker>,
+}
+
+impl Future for Replication {
+    type Output = Result<SyncStats, SyncError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => {
+                drop(shared);
+                self.done = true;
+                Poll::Ready(result)
+            }
+            None => {
+                shared.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Replication {
+    fn drop(&mut self) {
+        if !self.done {
+            self.token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::{task::Wake, time::Duration};
+
+    /// Polls `future` to completion on the current thread, parking it in between.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unparker(std::thread::Thread);
+
+        impl Wake for Unparker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unparker(std::thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+
+    #[test]
+    fn it_awaits_the_run() {
+        let root = testing::test_dir("it_awaits_the_run");
+        let source = root.join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+
+        let token = CancellationToken::new();
+        // The run of a dropped future may still hold the lock of the target a moment.
+        let replicator = AsyncReplicator::new(
+            Replicator::new(&source, root.join("target"))
+                .cancellation(Some(token.clone()))
+                .wait_lock(Duration::from_secs(10)),
+        );
+        let stats = block_on(replicator.run()).unwrap();
+        assert_eq!(stats.file_copied_count, 1);
+        assert_eq!(
+            std::fs::read(root.join("target").join("notes.txt")).unwrap(),
+            b"notes"
+        );
+
+        let replication = replicator.run();
+        let run_token = replication.token.clone();
+        drop(replication);
+        assert!(run_token.is_cancelled());
+        assert!(!token.is_cancelled());
+        assert!(block_on(replicator.run()).is_ok_and(|stats| !stats.interrupted));
+
+        token.cancel();
+        assert!(block_on(replicator.run()).is_ok_and(|stats| stats.interrupted));
+    }
+}
@@ -0,0 +1,127 @@
+//! **testing** contains the fixtures shared by the tests of the modules.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Returns an empty directory named after the test `name` in the temporary directory,
+/// removing what a previous run of the test left there.
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join("acsync_tests").join(name);
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Request received by the server of [`serve_http`].
+#[derive(Debug, Clone)]
+pub(crate) struct HttpRequest {
+    pub method: String,
+    /// Path and query of the request, still percent-encoded.
+    pub target: String,
+    /// Headers with lowercase names.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the path of the target, without its query.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    /// Returns the value of the `name` parameter of the query, still percent-encoded.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.target.split_once('?')?;
+        query.split('&').find_map(|parameter| {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            (key == name).then_some(value)
+        })
+    }
+}
+
+/// Serves HTTP/1.1 on a loopback port until the test process ends, answering every
+/// request with the status, headers and body returned by the `handler`, and returns
+/// the address of the server.
+pub(crate) fn serve_http<F>(handler: F) -> String
+where
+    F: Fn(HttpRequest) -> (u16, Vec<(String, String)>, Vec<u8>) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let handler = Arc::new(handler);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let handler = Arc::clone(&handler);
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let mut parts = line.split_whitespace();
+                    let method = parts.next().unwrap_or_default().to_string();
+                    let target = parts.next().unwrap_or_default().to_string();
+                    let mut headers = vec![];
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let line = line.trim_end();
+                        let Some((name, value)) = line.split_once(':') else {
+                            break;
+                        };
+                        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+                    }
+                    let mut request = HttpRequest {
+                        method,
+                        target,
+                        headers,
+                        body: vec![],
+                    };
+                    let length = request
+                        .header("content-length")
+                        .and_then(|length| length.parse().ok())
+                        .unwrap_or(0);
+                    (&mut reader)
+                        .take(length)
+                        .read_to_end(&mut request.body)
+                        .unwrap();
+
+                    let head = request.method == "HEAD";
+                    let (status, headers, body) = handler(request);
+                    let mut answer = format!("HTTP/1.1 {status} Status\r\n");
+                    for (name, value) in headers {
+                        answer += &format!("{name}: {value}\r\n");
+                    }
+                    if !head {
+                        answer += &format!("Content-Length: {}\r\n", body.len());
+                    }
+                    answer += "\r\n";
+                    let mut answer = answer.into_bytes();
+                    if !head {
+                        answer.extend(body);
+                    }
+                    if writer.write_all(&answer).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    address
+}
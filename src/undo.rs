@@ -0,0 +1,284 @@
+//! **undo** contains the reversal of the last replicate run of a destination, from the
+//! changes recorded in its report.
+
+use crate::{
+    copy, debug,
+    error::{IoResultExt, SyncError},
+    lock::DestinationLock,
+    report::{Change, RunReport},
+    state::StateManifest,
+    warn,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Counters of what an [`Undoer`] run reversed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UndoStats {
+    /// Identifier of the report of the undone run.
+    pub id: u64,
+    pub file_removed_count: u64,
+    pub directory_removed_count: u64,
+    /// Overridden files whose previous version was restored from its backup.
+    pub file_restored_count: u64,
+    pub file_moved_back_count: u64,
+    /// Deleted files moved back from the trash.
+    pub file_recovered_count: u64,
+    /// Overridden or deleted files that were neither backed up nor moved to the trash.
+    pub file_lost_count: u64,
+}
+
+impl fmt::Display for UndoStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#^80}", format!(" Undo of run {} ", self.id))?;
+        writeln!(f, "Removed files: {}", self.file_removed_count)?;
+        writeln!(f, "Removed directories: {}", self.directory_removed_count)?;
+        writeln!(f, "Restored backups: {}", self.file_restored_count)?;
+        writeln!(f, "Moved back files: {}", self.file_moved_back_count)?;
+        writeln!(f, "Recovered deleted files: {}", self.file_recovered_count)?;
+        writeln!(f, "Unrecoverable files: {}", self.file_lost_count)?;
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+/// Reverses the last replicate run of a destination, removing the paths it created,
+/// moving back the files it moved and restoring the files it overrode or deleted from
+/// their backups or the trash.
+///
+/// Files overridden without [`Replicator::backup`](crate::sync::Replicator::backup)
+/// nor deleted without
+/// [`Replicator::delete_to_trash`](crate::sync::Replicator::delete_to_trash) can't be
+/// restored and are only counted.
+#[derive(Debug)]
+pub struct Undoer {
+    destination: PathBuf,
+    dryrun: bool,
+}
+
+impl Undoer {
+    pub fn new<P: AsRef<Path>>(destination: P) -> Self {
+        Undoer {
+            destination: destination.as_ref().to_path_buf(),
+            dryrun: false,
+        }
+    }
+
+    /// Counts what would be reversed without changing anything.
+    pub fn dryrun(mut self, flag: bool) -> Self {
+        self.dryrun = flag;
+        self
+    }
+
+    pub fn run(&self) -> Result<UndoStats, SyncError> {
+        let destination = &self.destination;
+        let invalid_destination = |reason: String| SyncError::InvalidArgument {
+            name: "destination".to_string(),
+            reason,
+        };
+        let _lock = match self.dryrun {
+            true => None,
+            false => Some(DestinationLock::acquire(destination, Duration::ZERO)?),
+        };
+        let Some(&id) = RunReport::list(destination)?.last() else {
+            return Err(invalid_destination(format!(
+                "{} has no report of a run to undo",
+                destination.display()
+            )));
+        };
+        let mut report = RunReport::load(destination, id)?;
+        if report.undone.is_some() {
+            return Err(invalid_destination(format!(
+                "its last run {id} was already undone"
+            )));
+        }
+        if report.changes.is_empty() && report.changed_file_count() > 0 {
+            return Err(invalid_destination(format!(
+                "its last run {id} recorded no changes to undo, e.g. into a store or a compressed destination"
+            )));
+        }
+
+        let mut state = StateManifest::load(destination)?;
+        let mut stats = UndoStats {
+            id,
+            ..UndoStats::default()
+        };
+        for change in report.changes.iter().rev() {
+            self.undo_change(change, &mut state, &mut stats)?;
+        }
+        if !self.dryrun {
+            state.save(destination)?;
+            report.undone = Some(SystemTime::now());
+            report.update(destination)?;
+        }
+        Ok(stats)
+    }
+
+    fn undo_change(
+        &self,
+        change: &Change,
+        state: &mut StateManifest,
+        stats: &mut UndoStats,
+    ) -> Result<(), SyncError> {
+        let destination = &self.destination;
+        match change {
+            Change::Created(relative_path) => {
+                let path = destination.join(relative_path);
+                let Ok(metadata) = path.symlink_metadata() else {
+                    debug!("{} is already gone", path.display());
+                    return Ok(());
+                };
+                if metadata.is_dir() {
+                    debug!("Removing directory {} ...", path.display());
+                    // Files added since the run are left in place along with their
+                    // directory.
+                    if !self.dryrun
+                        && let Err(error) = std::fs::remove_dir(&path)
+                    {
+                        warn!("Keeping directory {}: {error}", path.display());
+                        return Ok(());
+                    }
+                    stats.directory_removed_count += 1;
+                } else {
+                    debug!("Removing file {} ...", path.display());
+                    if !self.dryrun {
+                        std::fs::remove_file(&path).with_path(&path)?;
+                    }
+                    state.remove(relative_path);
+                    stats.file_removed_count += 1;
+                }
+            }
+            Change::Overridden {
+                path: relative_path,
+                backup: Some(backup),
+            } => {
+                self.move_back(backup, relative_path, state)?;
+                stats.file_restored_count += 1;
+            }
+            Change::Moved { from, to } => {
+                self.move_back(&destination.join(to), from, state)?;
+                state.remove(to);
+                stats.file_moved_back_count += 1;
+            }
+            Change::Deleted {
+                path: relative_path,
+                trash: Some(trash),
+            } => {
+                self.move_back(trash, relative_path, state)?;
+                stats.file_recovered_count += 1;
+            }
+            Change::Overridden {
+                path: relative_path,
+                backup: None,
+            }
+            | Change::Deleted {
+                path: relative_path,
+                trash: None,
+            } => {
+                warn!(
+                    "Can't restore {}, it was neither backed up nor moved to the trash",
+                    destination.join(relative_path).display()
+                );
+                stats.file_lost_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the `path` file back to the `relative_path` of the destination, replacing
+    /// the file there if any.
+    fn move_back(
+        &self,
+        path: &Path,
+        relative_path: &Path,
+        state: &mut StateManifest,
+    ) -> Result<(), SyncError> {
+        let target_path = self.destination.join(relative_path);
+        debug!(
+            "Moving {} back to {} ...",
+            path.display(),
+            target_path.display()
+        );
+        let size = path.metadata().with_path(path)?.len();
+        if !self.dryrun {
+            if let Some(parent) = target_path.parent() {
+                std::fs::create_dir_all(parent).with_path(parent)?;
+            }
+            if target_path.is_file() {
+                std::fs::remove_file(&target_path).with_path(&target_path)?;
+            }
+            copy::move_file(path, &target_path)?;
+        }
+        state.insert(relative_path, size);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Replicator;
+    use crate::testing;
+
+    #[test]
+    fn it_undoes_the_last_run() {
+        let root = testing::test_dir("it_undoes_the_last_run");
+        let (source, target) = (root.join("source"), root.join("target"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("notes.txt"), "notes").unwrap();
+        std::fs::write(source.join("old.txt"), "old").unwrap();
+        std::fs::write(source.join("gone.txt"), "gone").unwrap();
+        Replicator::new(&source, &target).run().unwrap();
+
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(source.join("notes.txt"), "new notes").unwrap();
+        std::fs::rename(source.join("old.txt"), source.join("new.txt")).unwrap();
+        std::fs::remove_file(source.join("gone.txt")).unwrap();
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::write(source.join("docs/added.txt"), "added").unwrap();
+        Replicator::new(&source, &target)
+            .on_conflict(crate::sync::ConflictPolicy::Newer)
+            .backup(true)
+            .delete_to_trash(true)
+            .report(true)
+            .run()
+            .unwrap();
+
+        let stats = Undoer::new(&target).run().unwrap();
+        assert_eq!(
+            (
+                stats.file_removed_count,
+                stats.directory_removed_count,
+                stats.file_restored_count,
+                stats.file_moved_back_count,
+                stats.file_recovered_count,
+                stats.file_lost_count
+            ),
+            (1, 1, 1, 1, 1, 0)
+        );
+        assert_eq!(
+            std::fs::read_to_string(target.join("notes.txt")).unwrap(),
+            "notes"
+        );
+        assert!(target.join("old.txt").exists() && !target.join("new.txt").exists());
+        assert!(target.join("gone.txt").exists());
+        assert!(!target.join("docs").exists());
+        let mut paths: Vec<PathBuf> = StateManifest::load(&target)
+            .unwrap()
+            .iter()
+            .map(|(path, _)| path.to_path_buf())
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            ["gone.txt", "notes.txt", "old.txt"].map(PathBuf::from)
+        );
+
+        assert!(matches!(
+            Undoer::new(&target).run(),
+            Err(SyncError::InvalidArgument { .. })
+        ));
+    }
+}
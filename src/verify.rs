@@ -0,0 +1,315 @@
+//! **verify** contains the audit of a destination directory against its origin,
+//! reporting every difference without copying anything.
+
+use crate::{
+    archive::{ArchiveIndex, INDEX_FILE_NAME},
+    cache::{self, ChecksumCache},
+    debug,
+    error::{IoResultExt, SyncError},
+    fs::{FileSearcher, SortOrder},
+    info,
+    names::NameMap,
+    platform,
+    sync::{is_metadata_path, read_patterns},
+    warn,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// A difference found between the origin and the destination directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The path exists in the origin but not in the destination.
+    Missing(PathBuf),
+    /// The path exists in the destination but not in the origin.
+    Extra(PathBuf),
+    /// The path is a file on one side and a directory on the other.
+    TypeMismatch(PathBuf),
+    SizeMismatch {
+        path: PathBuf,
+        origin_size: u64,
+        destination_size: u64,
+    },
+    /// The files have the same size but a different content.
+    HashMismatch(PathBuf),
+    PermissionMismatch {
+        path: PathBuf,
+        origin_mode: u32,
+        destination_mode: u32,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Missing(path) => write!(f, "Missing: {}", path.display()),
+            Divergence::Extra(path) => write!(f, "Extra: {}", path.display()),
+            Divergence::TypeMismatch(path) => write!(f, "Type differs: {}", path.display()),
+            Divergence::SizeMismatch {
+                path,
+                origin_size,
+                destination_size,
+            } => write!(
+                f,
+                "Size differs: {} ({origin_size} bytes != {destination_size} bytes)",
+                path.display()
+            ),
+            Divergence::HashMismatch(path) => write!(f, "Content differs: {}", path.display()),
+            Divergence::PermissionMismatch {
+                path,
+                origin_mode,
+                destination_mode,
+            } => write!(
+                f,
+                "Permissions differ: {} ({origin_mode:04o} != {destination_mode:04o})",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Result of a [`Verifier`] run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub divergences: Vec<Divergence>,
+    pub file_checked_count: u64,
+    pub directory_checked_count: u64,
+}
+
+impl VerifyReport {
+    /// Returns true when no divergence was found.
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |predicate: fn(&Divergence) -> bool| {
+            self.divergences
+                .iter()
+                .filter(|divergence| predicate(divergence))
+                .count()
+        };
+        writeln!(f, "{:#^80}", " Verify ")?;
+        writeln!(f, "Checked files: {}", self.file_checked_count)?;
+        writeln!(f, "Checked directories: {}", self.directory_checked_count)?;
+        writeln!(
+            f,
+            "Missing paths: {}",
+            count(|divergence| matches!(divergence, Divergence::Missing(..)))
+        )?;
+        writeln!(
+            f,
+            "Extra paths: {}",
+            count(|divergence| matches!(divergence, Divergence::Extra(..)))
+        )?;
+        writeln!(
+            f,
+            "Mismatched paths: {}",
+            count(|divergence| {
+                !matches!(divergence, Divergence::Missing(..) | Divergence::Extra(..))
+            })
+        )?;
+        writeln!(f, "{:#^80}", "")
+    }
+}
+
+#[derive(Debug)]
+struct VerifierOptions {
+    checksum: bool,
+    hidden: bool,
+    ignore_cache: bool,
+}
+
+/// Compares a destination directory with its origin.
+///
+/// Compressed destinations are compared through their index, checking sizes and
+/// permissions but not contents.
+#[derive(Debug)]
+pub struct Verifier {
+    origin: PathBuf,
+    destination: PathBuf,
+    options: VerifierOptions,
+}
+
+impl Verifier {
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(origin: P, destination: Q) -> Self {
+        Verifier {
+            origin: platform::long_path(origin.as_ref()),
+            destination: platform::long_path(destination.as_ref()),
+            options: VerifierOptions {
+                checksum: true,
+                hidden: true,
+                ignore_cache: false,
+            },
+        }
+    }
+
+    /// Compares the content hash of files with the same size, enabled by default.
+    pub fn checksum(mut self, flag: bool) -> Self {
+        self.options.checksum = flag;
+        self
+    }
+
+    /// Whether dot-files and dot-directories are compared, enabled by default.
+    pub fn hidden(mut self, flag: bool) -> Self {
+        self.options.hidden = flag;
+        self
+    }
+
+    /// Hashes every file again instead of reusing the checksums cached in the
+    /// destination.
+    pub fn ignore_cache(mut self, flag: bool) -> Self {
+        self.options.ignore_cache = flag;
+        self
+    }
+
+    pub fn run(&self) -> Result<VerifyReport, SyncError> {
+        let origin = &self.origin;
+        let destination = &self.destination;
+        let includes = read_patterns(origin, ".acsync_includes");
+        let index = ArchiveIndex::load(destination)?;
+        let names = match &index {
+            Some(_) => NameMap::new(),
+            None => NameMap::load(destination)?,
+        };
+        let mut report = VerifyReport::default();
+        let mut cache = match self.options.checksum && !self.options.ignore_cache {
+            true if index.is_none() => Some(ChecksumCache::load(destination)?),
+            _ => None,
+        };
+
+        let searcher = |root: &Path| {
+            FileSearcher::new(root)
+                .sort_by(SortOrder::Name)
+                .hidden(self.options.hidden)
+                .includes(&includes)
+                .excludes_file(Some(".acsync_excludes"))
+        };
+
+        for result in searcher(origin) {
+            let origin_entry = result.map_err(|source| SyncError::WalkError {
+                path: origin.clone(),
+                source,
+            })?;
+            let origin_path = origin_entry.path();
+            let relative_path = origin_path.strip_prefix(origin).unwrap_or(origin_path);
+            let destination_path = match &index {
+                Some(index) if origin_entry.is_file() => {
+                    destination.join(index.stored_path(relative_path))
+                }
+                _ => destination.join(names.transcoded(relative_path).unwrap_or(relative_path)),
+            };
+            debug!("Checking {} ...", relative_path.display());
+            if let Some(divergence) = self.compare(
+                origin_path,
+                &destination_path,
+                relative_path,
+                &index,
+                &mut cache,
+            )? {
+                info!("{divergence}");
+                report.divergences.push(divergence);
+            }
+            if origin_entry.is_dir() {
+                report.directory_checked_count += 1;
+            } else {
+                report.file_checked_count += 1;
+            }
+        }
+
+        for result in searcher(destination) {
+            let destination_entry = result.map_err(|source| SyncError::WalkError {
+                path: destination.clone(),
+                source,
+            })?;
+            let relative_path = destination_entry
+                .path()
+                .strip_prefix(destination)
+                .unwrap_or(destination_entry.path());
+            if is_metadata_path(relative_path) {
+                continue;
+            }
+            let relative_path = match &index {
+                Some(_) if platform::same_path(relative_path, Path::new(INDEX_FILE_NAME)) => {
+                    continue;
+                }
+                Some(index) if destination_entry.is_file() => index
+                    .original(relative_path)
+                    .map(|(original_path, _)| original_path)
+                    .unwrap_or(relative_path),
+                _ => names.original(relative_path).unwrap_or(relative_path),
+            };
+            if !origin.join(relative_path).exists() {
+                let divergence = Divergence::Extra(relative_path.to_path_buf());
+                info!("{divergence}");
+                report.divergences.push(divergence);
+            }
+        }
+
+        if let Some(cache) = &cache
+            && destination.is_dir()
+            && let Err(error) = cache.save(destination)
+        {
+            warn!("Can't save the checksum cache: {error}");
+        }
+
+        Ok(report)
+    }
+
+    fn compare(
+        &self,
+        origin_path: &Path,
+        destination_path: &Path,
+        relative_path: &Path,
+        index: &Option<ArchiveIndex>,
+        cache: &mut Option<ChecksumCache>,
+    ) -> Result<Option<Divergence>, SyncError> {
+        let relative = relative_path.to_path_buf();
+        let Ok(destination_metadata) = destination_path.metadata() else {
+            return Ok(Some(Divergence::Missing(relative)));
+        };
+        let origin_metadata = origin_path.metadata().with_path(origin_path)?;
+        if origin_metadata.is_dir() != destination_metadata.is_dir() {
+            return Ok(Some(Divergence::TypeMismatch(relative)));
+        }
+
+        if origin_metadata.is_file() {
+            let destination_size = match index {
+                Some(index) => index
+                    .size(relative_path)
+                    .unwrap_or(destination_metadata.len()),
+                None => destination_metadata.len(),
+            };
+            if origin_metadata.len() != destination_size {
+                return Ok(Some(Divergence::SizeMismatch {
+                    path: relative,
+                    origin_size: origin_metadata.len(),
+                    destination_size,
+                }));
+            }
+            if self.options.checksum
+                && index.is_none()
+                && cache::hash_file(cache, origin_path)?
+                    != cache::hash_file(cache, destination_path)?
+            {
+                return Ok(Some(Divergence::HashMismatch(relative)));
+            }
+        }
+
+        let origin_mode = platform::mode(&origin_metadata);
+        let destination_mode = platform::mode(&destination_metadata);
+        if origin_mode != destination_mode {
+            return Ok(Some(Divergence::PermissionMismatch {
+                path: relative,
+                origin_mode,
+                destination_mode,
+            }));
+        }
+
+        Ok(None)
+    }
+}
@@ -0,0 +1,644 @@
+//! **webdav** contains the WebDAV transport of
+//! [`RemoteReplicator`](crate::remote::RemoteReplicator), replicating from or to a
+//! collection of a WebDAV share, such as one of Nextcloud or ownCloud, given as a
+//! `dav://HOST[:PORT]/PATH` URL.
+//!
+//! Collections are listed a level at a time with `PROPFIND` and created with `MKCOL`.
+//! Files are read with ranged `GET` requests, written to a local temporary file and
+//! sent whole with a `PUT` once complete, and removed with `DELETE`, collections
+//! along with their content. The modification date of a file is stored in its
+//! `mtime` dead property, of the `urn:acsync` namespace, in nanoseconds since the
+//! epoch, and sent in the `X-OC-Mtime` header understood by Nextcloud and ownCloud,
+//! the `getlastmodified` date being the time it was uploaded on other servers.
+//!
+//! Requests go over plain HTTP, see [`crate::http`].
+
+use crate::{
+    cli_helper,
+    error::{IoResultExt, SyncError},
+    http::{self, Body, HttpClient, HttpUrl, Response},
+    remote::{self, CHUNK_SIZE, EntryInfo},
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Namespace of the dead properties set by acsync.
+const NAMESPACE: &str = "urn:acsync";
+
+/// Properties asked for by the listings.
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:" xmlns:a="urn:acsync"><d:prop><d:resourcetype/><d:getcontentlength/><d:getlastmodified/><a:mtime/></d:prop></d:propfind>"#;
+
+/// Count of the uploads of the process, naming their temporary files.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// Parses a `dav://HOST[:PORT]/PATH` URL into the `http://` URL of its collection,
+/// returning `None` for any other value.
+///
+/// # Examples
+///
+/// ```
+/// # use acsync::webdav;
+/// #
+/// let url = webdav::parse_url("dav://cloud.local/remote.php/dav/files/ana/Photos/").unwrap();
+/// assert_eq!(url.address, "cloud.local:80");
+/// assert_eq!(url.path, "/remote.php/dav/files/ana/Photos");
+/// assert!(webdav::parse_url("davs://cloud.local/dav").is_none());
+/// assert!(webdav::parse_url("/media/backup").is_none());
+/// ```
+pub fn parse_url(value: &str) -> Option<HttpUrl> {
+    HttpUrl::from_parts(value.strip_prefix("dav://")?, 80)
+}
+
+/// Credentials of the HTTP Basic authentication to a WebDAV share, such as an app
+/// password of Nextcloud.
+#[derive(Clone)]
+pub struct DavConfig {
+    pub user: String,
+    pub password: String,
+}
+
+impl DavConfig {
+    /// Returns the credentials given by the `ACSYNC_DAV_USER` and `ACSYNC_DAV_PASSWORD`
+    /// environment variables, or `None` for shares open to anonymous users when neither
+    /// is set.
+    pub fn from_env() -> Result<Option<Self>, SyncError> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        match (var("ACSYNC_DAV_USER"), var("ACSYNC_DAV_PASSWORD")) {
+            (Some(user), Some(password)) => Ok(Some(DavConfig { user, password })),
+            (Some(_), None) => Err(SyncError::MissingArgument(
+                "ACSYNC_DAV_PASSWORD".to_string(),
+            )),
+            (None, Some(_)) => Err(SyncError::MissingArgument("ACSYNC_DAV_USER".to_string())),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+impl fmt::Debug for DavConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DavConfig")
+            .field("user", &self.user)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+/// File being written by [`Share::write_chunk`], into a temporary file removed once
+/// dropped.
+struct Upload {
+    path: PathBuf,
+    temporary_path: PathBuf,
+    file: File,
+    length: u64,
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.temporary_path);
+    }
+}
+
+/// Connection to the collection of a `dav://` URL.
+pub(crate) struct Share {
+    url: HttpUrl,
+    authorization: Option<String>,
+    client: HttpClient,
+    /// Whether the collection exists, created along with its first file or directory
+    /// otherwise.
+    exists: bool,
+    upload: Option<Upload>,
+}
+
+impl Share {
+    pub fn open(url: &HttpUrl, config: Option<&DavConfig>) -> Self {
+        Share {
+            url: url.clone(),
+            authorization: config
+                .map(|config| http::basic_authorization(&config.user, &config.password)),
+            client: HttpClient::new(&url.address),
+            exists: true,
+            upload: None,
+        }
+    }
+
+    /// Returns the `dav://` URL of `path`, for errors.
+    fn name(&self, path: &Path) -> String {
+        format!(
+            "dav://{}{}/{}",
+            self.url.host,
+            self.url.path,
+            remote::wire_path(path)
+        )
+    }
+
+    /// Returns the percent-encoded request target of `path`, that of the collection
+    /// itself if empty.
+    fn target(&self, path: &Path) -> String {
+        format!(
+            "{}/{}",
+            self.url.path,
+            http::percent_encode(&remote::wire_path(path), "/")
+        )
+    }
+
+    /// Sends the `method` request of the `target` with the `headers` and the `body`,
+    /// authenticated if the share has credentials.
+    fn request(
+        &mut self,
+        method: &str,
+        target: &str,
+        headers: &[(&str, String)],
+        body: Body<'_>,
+    ) -> io::Result<Response> {
+        let mut all_headers = vec![("Host".to_string(), self.url.host.clone())];
+        if let Some(authorization) = &self.authorization {
+            all_headers.push(("Authorization".to_string(), authorization.clone()));
+        }
+        all_headers.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone())),
+        );
+        self.client.send(method, target, &all_headers, body)
+    }
+
+    /// Returns the files and directories under the collection, keyed by their path
+    /// relative to it, or nothing if it doesn't exist yet.
+    pub fn list(&mut self) -> Result<BTreeMap<PathBuf, EntryInfo>, SyncError> {
+        let base_path = http::percent_decode(&self.url.path);
+        let mut entries = BTreeMap::new();
+        let mut collections = VecDeque::from([PathBuf::new()]);
+        while let Some(collection) = collections.pop_front() {
+            let target = format!("{}/", self.target(&collection).trim_end_matches('/'));
+            let response = self
+                .request(
+                    "PROPFIND",
+                    &target,
+                    &[
+                        ("Depth", "1".to_string()),
+                        ("Content-Type", "application/xml".to_string()),
+                    ],
+                    Body::Bytes(PROPFIND_BODY.as_bytes()),
+                )
+                .with_path(self.name(&collection))?;
+            if response.status == 404 && collection.as_os_str().is_empty() {
+                self.exists = false;
+                return Ok(entries);
+            }
+            let response = response.success().with_path(self.name(&collection))?;
+            let xml = String::from_utf8_lossy(&response.body);
+            for member in http::xml_elements(&xml, "response") {
+                let Some(href) = http::xml_element(member, "href") else {
+                    continue;
+                };
+                let href = http::percent_decode(&http::xml_text(href));
+                // Hrefs are either absolute paths or absolute URLs.
+                let href = match href.split_once("://") {
+                    Some((_, rest)) => rest.find('/').map_or("", |index| &rest[index..]),
+                    None => href.as_str(),
+                };
+                let Some(relative_path) = href.strip_prefix(&base_path) else {
+                    continue;
+                };
+                let path = PathBuf::from(relative_path.trim_matches('/'));
+                if path == collection {
+                    continue;
+                }
+
+                let properties: Vec<&str> = http::xml_elements(member, "propstat")
+                    .into_iter()
+                    .filter(|propstat| {
+                        http::xml_element(propstat, "status")
+                            .is_some_and(|status| status.split_whitespace().nth(1) == Some("200"))
+                    })
+                    .collect();
+                let property = |name: &str| {
+                    properties
+                        .iter()
+                        .find_map(|propstat| http::xml_element(propstat, name))
+                };
+                let is_dir = property("collection").is_some();
+                let size = property("getcontentlength")
+                    .and_then(|size| size.trim().parse().ok())
+                    .unwrap_or(0);
+                let modified = property("mtime")
+                    .and_then(|nanos| nanos.trim().parse().ok())
+                    .map(remote::from_nanos)
+                    .or_else(|| property("getlastmodified").and_then(parse_http_date))
+                    .unwrap_or(UNIX_EPOCH);
+                if is_dir {
+                    collections.push_back(path.clone());
+                }
+                entries.insert(
+                    path,
+                    EntryInfo {
+                        is_dir,
+                        size: if is_dir { 0 } else { size },
+                        modified,
+                    },
+                );
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Reads up to [`CHUNK_SIZE`] bytes of the `path` file from `offset`.
+    pub fn read_chunk(&mut self, path: &Path, offset: u64) -> Result<Vec<u8>, SyncError> {
+        let range = format!("bytes={offset}-{}", offset + CHUNK_SIZE as u64 - 1);
+        let target = self.target(path);
+        let response = self
+            .request("GET", &target, &[("Range", range)], Body::Bytes(&[]))
+            .with_path(self.name(path))?;
+        match response.status {
+            206 => Ok(response.body),
+            // Servers ignoring the range send the whole file.
+            200 => Ok(response
+                .body
+                .into_iter()
+                .skip(offset as usize)
+                .take(CHUNK_SIZE)
+                .collect()),
+            // Past the end, or an empty file.
+            416 => Ok(vec![]),
+            _ => Err(response.error()).with_path(self.name(path)),
+        }
+    }
+
+    /// Writes the `chunk` at `offset` of the `path` file into a temporary file, started
+    /// anew from the first chunk and sent by [`Share::commit`].
+    pub fn write_chunk(&mut self, path: &Path, offset: u64, chunk: &[u8]) -> Result<(), SyncError> {
+        if offset == 0 {
+            self.upload = None;
+            let temporary_path = std::env::temp_dir().join(format!(
+                "acsync_dav_{}_{}.acsync_tmp",
+                std::process::id(),
+                UPLOADS.fetch_add(1, Ordering::Relaxed)
+            ));
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&temporary_path)
+                .with_path(&temporary_path)?;
+            self.upload = Some(Upload {
+                path: path.to_path_buf(),
+                temporary_path,
+                file,
+                length: 0,
+            });
+        }
+        let Some(upload) = self
+            .upload
+            .as_mut()
+            .filter(|upload| upload.path == path && upload.length == offset)
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "chunk written out of order",
+            ))
+            .with_path(self.name(path));
+        };
+        upload
+            .file
+            .write_all(chunk)
+            .with_path(&upload.temporary_path)?;
+        upload.length += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Sends the file written to `path`, with the `modified` date.
+    pub fn commit(&mut self, path: &Path, modified: SystemTime) -> Result<(), SyncError> {
+        let name = self.name(path);
+        let Some(mut upload) = self.upload.take().filter(|upload| upload.path == path) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no file written to commit",
+            ))
+            .with_path(&name);
+        };
+        self.create_collection()?;
+        let target = self.target(path);
+        let seconds = modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.request(
+            "PUT",
+            &target,
+            &[("X-OC-Mtime", seconds.to_string())],
+            Body::File(&mut upload.file, upload.length),
+        )
+        .and_then(Response::success)
+        .with_path(&name)?;
+
+        let update = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><d:propertyupdate xmlns:d="DAV:" xmlns:a="{NAMESPACE}"><d:set><d:prop><a:mtime>{}</a:mtime></d:prop></d:set></d:propertyupdate>"#,
+            remote::to_nanos(modified)
+        );
+        let response = self
+            .request(
+                "PROPPATCH",
+                &target,
+                &[("Content-Type", "application/xml".to_string())],
+                Body::Bytes(update.as_bytes()),
+            )
+            .and_then(Response::success)
+            .with_path(&name)?;
+        let xml = String::from_utf8_lossy(&response.body);
+        let refused = http::xml_elements(&xml, "status")
+            .into_iter()
+            .any(|status| status.split_whitespace().nth(1) != Some("200"));
+        if refused {
+            return Err(io::Error::other(
+                "the share refused to store the modification date in a property",
+            ))
+            .with_path(&name);
+        }
+        Ok(())
+    }
+
+    /// Creates the collection itself if it didn't exist when listed, its parent
+    /// having to exist.
+    fn create_collection(&mut self) -> Result<(), SyncError> {
+        if !self.exists {
+            self.create_dir(Path::new(""))?;
+            self.exists = true;
+        }
+        Ok(())
+    }
+
+    /// Creates the `path` collection, doing nothing if it exists.
+    pub fn create_dir(&mut self, path: &Path) -> Result<(), SyncError> {
+        if !path.as_os_str().is_empty() {
+            self.create_collection()?;
+        }
+        let target = format!("{}/", self.target(path).trim_end_matches('/'));
+        let response = self
+            .request("MKCOL", &target, &[], Body::Bytes(&[]))
+            .with_path(self.name(path))?;
+        // 405 Method Not Allowed: the collection exists.
+        if !response.is_success() && response.status != 405 {
+            return Err(response.error()).with_path(self.name(path));
+        }
+        Ok(())
+    }
+
+    /// Deletes the `path` file, or the `path` collection with its content.
+    pub fn remove(&mut self, path: &Path) -> Result<(), SyncError> {
+        let target = self.target(path);
+        let response = self
+            .request("DELETE", &target, &[], Body::Bytes(&[]))
+            .with_path(self.name(path))?;
+        if !response.is_success() && response.status != 404 {
+            return Err(response.error()).with_path(self.name(path));
+        }
+        Ok(())
+    }
+}
+
+/// Parses an HTTP date such as `Mon, 01 Jan 2024 00:00:00 GMT`.
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let mut fields = value.split_whitespace().skip(1);
+    let (day, month, year, time) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    let month = MONTHS.iter().position(|name| *name == month)? + 1;
+    cli_helper::parse_time(&format!("{year}-{month:02}-{day}T{time}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        remote::RemoteReplicator,
+        testing::{self, HttpRequest},
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    /// Files and collections of the fake share, by decoded path, with their content,
+    /// `None` for collections, and their `mtime` property.
+    type Nodes = Arc<Mutex<BTreeMap<String, (Option<Vec<u8>>, Option<String>)>>>;
+
+    fn config() -> DavConfig {
+        DavConfig {
+            user: "ana".to_string(),
+            password: "secret".to_string(),
+        }
+    }
+
+    /// Serves a fake WebDAV share, requiring the credentials of [`config`].
+    fn serve_share(nodes: Nodes) -> String {
+        testing::serve_http(move |request: HttpRequest| {
+            assert_eq!(
+                request.header("authorization"),
+                Some(http::basic_authorization("ana", "secret").as_str())
+            );
+            let path = http::percent_decode(request.path())
+                .trim_end_matches('/')
+                .to_string();
+            let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+            let mut nodes = nodes.lock().unwrap();
+            let parent_exists = nodes.get(parent).is_some_and(|(data, _)| data.is_none());
+            match request.method.as_str() {
+                "PROPFIND" => {
+                    let Some((data, _)) = nodes.get(&path) else {
+                        return (404, vec![], vec![]);
+                    };
+                    assert_eq!(request.header("depth"), Some("1"));
+                    let is_collection = data.is_none();
+                    let members: String = nodes
+                        .iter()
+                        .filter(|(key, _)| {
+                            **key == path
+                                || (is_collection
+                                    && key.rsplit_once('/').map(|(parent, _)| parent)
+                                        == Some(path.as_str()))
+                        })
+                        .map(|(key, (data, mtime))| propfind_member(key, data, mtime))
+                        .collect();
+                    let xml = format!(
+                        r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:" xmlns:a="urn:acsync">{members}</d:multistatus>"#
+                    );
+                    (207, vec![], xml.into_bytes())
+                }
+                "GET" => {
+                    let data = nodes[&path].0.as_ref().unwrap();
+                    let range = request
+                        .header("range")
+                        .unwrap()
+                        .trim_start_matches("bytes=");
+                    let (start, end) = range.split_once('-').unwrap();
+                    let start: usize = start.parse().unwrap();
+                    if start >= data.len() {
+                        return (416, vec![], vec![]);
+                    }
+                    let end = (end.parse::<usize>().unwrap() + 1).min(data.len());
+                    (206, vec![], data[start..end].to_vec())
+                }
+                "PUT" if parent_exists => {
+                    assert!(request.header("x-oc-mtime").is_some());
+                    nodes.insert(path, (Some(request.body.clone()), None));
+                    (201, vec![], vec![])
+                }
+                "PROPPATCH" => {
+                    let xml = String::from_utf8(request.body.clone()).unwrap();
+                    nodes.get_mut(&path).unwrap().1 =
+                        http::xml_element(&xml, "mtime").map(str::to_string);
+                    let xml = "<d:multistatus xmlns:d=\"DAV:\"><d:response><d:propstat><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>";
+                    (207, vec![], xml.as_bytes().to_vec())
+                }
+                "MKCOL" if nodes.contains_key(&path) => (405, vec![], vec![]),
+                "MKCOL" if parent_exists => {
+                    nodes.insert(path, (None, None));
+                    (201, vec![], vec![])
+                }
+                "PUT" | "MKCOL" => (409, vec![], vec![]),
+                "DELETE" => {
+                    if nodes.remove(&path).is_none() {
+                        return (404, vec![], vec![]);
+                    }
+                    nodes.retain(|key, _| !key.starts_with(&format!("{path}/")));
+                    (204, vec![], vec![])
+                }
+                _ => (400, vec![], vec![]),
+            }
+        })
+    }
+
+    /// Returns the `response` element of a `PROPFIND` listing of the `key` node, whose
+    /// collections are given as absolute URLs.
+    fn propfind_member(key: &str, data: &Option<Vec<u8>>, mtime: &Option<String>) -> String {
+        let (href, properties) = match data {
+            Some(data) => (
+                http::percent_encode(key, "/"),
+                format!(
+                    "<d:resourcetype/><d:getcontentlength>{}</d:getcontentlength>",
+                    data.len()
+                ),
+            ),
+            None => (
+                format!("http://localhost{}/", http::percent_encode(key, "/")),
+                "<d:resourcetype><d:collection/></d:resourcetype>".to_string(),
+            ),
+        };
+        let mtime = match mtime {
+            Some(nanos) => format!(
+                "<d:propstat><d:prop><a:mtime>{nanos}</a:mtime></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>"
+            ),
+            None => "<d:propstat><d:prop><a:mtime/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>"
+                .to_string(),
+        };
+        format!(
+            "<d:response><d:href>{href}</d:href><d:propstat><d:prop>{properties}<d:getlastmodified>Mon, 01 Jan 2024 00:00:00 GMT</d:getlastmodified></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>{mtime}</d:response>"
+        )
+    }
+
+    #[test]
+    fn it_replicates_to_and_from_a_share() {
+        let root = testing::test_dir("it_replicates_to_and_from_a_share");
+        let (local, restored) = (root.join("local"), root.join("restored"));
+        std::fs::create_dir_all(local.join("docs/empty")).unwrap();
+        std::fs::write(local.join("docs/notes & todo.txt"), "notes").unwrap();
+        let large: Vec<u8> = (0..CHUNK_SIZE + 10).map(|index| index as u8).collect();
+        std::fs::write(local.join("large.bin"), &large).unwrap();
+        std::fs::write(local.join("empty.txt"), "").unwrap();
+        let nodes = Nodes::default();
+        nodes
+            .lock()
+            .unwrap()
+            .insert("/dav".to_string(), (None, None));
+        let url = format!("dav://{}/dav/photos", serve_share(nodes.clone()));
+
+        let stats = RemoteReplicator::new(&local, &url)
+            .dav_config(Some(config()))
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_copied_count, 3);
+        {
+            let mut nodes = nodes.lock().unwrap();
+            assert_eq!(
+                nodes.keys().collect::<Vec<_>>(),
+                vec![
+                    "/dav",
+                    "/dav/photos",
+                    "/dav/photos/docs",
+                    "/dav/photos/docs/empty",
+                    "/dav/photos/docs/notes & todo.txt",
+                    "/dav/photos/empty.txt",
+                    "/dav/photos/large.bin"
+                ]
+            );
+            assert_eq!(nodes["/dav/photos/large.bin"].0.as_ref(), Some(&large));
+            let modified = local
+                .join("large.bin")
+                .metadata()
+                .unwrap()
+                .modified()
+                .unwrap();
+            assert_eq!(
+                nodes["/dav/photos/large.bin"].1,
+                Some(remote::to_nanos(modified).to_string())
+            );
+            nodes.insert("/dav/photos/old".to_string(), (None, None));
+            nodes.insert(
+                "/dav/photos/old/extra.txt".to_string(),
+                (Some(b"extra".to_vec()), None),
+            );
+        }
+
+        let stats = RemoteReplicator::new(&local, &url)
+            .dav_config(Some(config()))
+            .delete(true)
+            .run()
+            .unwrap();
+        assert_eq!(stats.file_copied_count + stats.file_conflict_count, 0);
+        assert_eq!(stats.file_deleted_count, 1);
+        assert!(!nodes.lock().unwrap().contains_key("/dav/photos/old"));
+
+        RemoteReplicator::new(&url, &restored)
+            .dav_config(Some(config()))
+            .run()
+            .unwrap();
+        assert_eq!(
+            std::fs::read(restored.join("docs/notes & todo.txt")).unwrap(),
+            b"notes"
+        );
+        assert_eq!(std::fs::read(restored.join("large.bin")).unwrap(), large);
+        assert_eq!(std::fs::read(restored.join("empty.txt")).unwrap(), b"");
+        assert!(restored.join("docs/empty").is_dir());
+        assert_eq!(
+            restored
+                .join("large.bin")
+                .metadata()
+                .unwrap()
+                .modified()
+                .unwrap(),
+            local
+                .join("large.bin")
+                .metadata()
+                .unwrap()
+                .modified()
+                .unwrap()
+        );
+        assert_eq!(
+            parse_http_date("Mon, 01 Jan 2024 00:00:00 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(1704067200))
+        );
+    }
+}